@@ -1,26 +1,406 @@
-use crate::buffer::{BufferEvent, EventHandlerOutcome};
-use crate::{Buffer, Layout};
+use crate::buffer::output::SharedLog;
+use crate::buffer::text_buffer::{FontConfig, TextBuffer};
+use crate::buffer::{BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, ViewOptions};
+use crate::command::{CommandRegistry, Invocation};
+use crate::git;
+use crate::keymap::Keymap;
+use crate::layout::{FrameId, Layout};
+use crate::macros::MacroRegisters;
+use crate::scripting::ScriptHost;
+use crate::state::StateEvent;
+use crate::swap;
+use crate::tasks::Tasks;
+use crate::theme::Theme;
+use crate::watcher::FileWatcher;
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
 use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
 
 pub type SharedState = Arc<RwLock<AppState>>;
 
-#[derive(Default)]
+pub type SharedBuffer = Arc<Mutex<dyn Buffer + Send + Sync + 'static>>;
+
 pub struct AppState {
-    pub buffers: Vec<Arc<Mutex<dyn Buffer + Send + Sync + 'static>>>,
+    pub buffers: Vec<SharedBuffer>,
+    /// Buffer shown in the active frame
     pub active_buffer: usize,
+    /// Frame the user types in
+    pub active_frame: FrameId,
+    /// Frame focused before the active one, where the explorer opens files
+    pub previous_frame: FrameId,
+    /// Frames on screen, replaced with `set_layout` to keep the focus on one of them
     pub layout: Layout,
+    pub commands: Arc<CommandRegistry>,
+    pub keymap: Keymap,
+    pub macros: MacroRegisters,
+    /// Invocations queued by commands, dispatched by the state loop after the current one.
+    pub pending: VecDeque<Invocation>,
+    pub scripts: ScriptHost,
+    /// Font used for newly opened buffers.
+    pub font: FontConfig,
+    pub theme: Arc<Theme>,
+    /// Font zoom applied to every buffer.
+    pub zoom: f32,
+    pub view: ViewOptions,
+    /// Lets buffers and background tasks feed events back into the state loop.
+    pub state_tx: Sender<StateEvent>,
+    /// Reports open files changed by other programs, where inotify is available.
+    pub watcher: Option<FileWatcher>,
+    /// Tasks that can be run, and the problems found by the last one.
+    pub tasks: Tasks,
+    /// Task keeping unsaved changes in swap files
+    pub swap_writer: Option<JoinHandle<()>>,
+    /// Buffer asking what to do with unsaved changes before quitting, once asked, and
+    /// the list it shows
+    pub quit_dialog: Option<(usize, SharedLog)>,
+    /// Quitting was settled, so the state loop shuts down after the current command
+    pub exiting: bool,
 }
 
 impl AppState {
-    pub async fn handle_character(&self, c: char) -> EventHandlerOutcome {
-        let mutex = self
-            .buffers
+    pub fn new(font: FontConfig, state_tx: Sender<StateEvent>) -> Self {
+        let mut state = Self {
+            buffers: Vec::new(),
+            active_buffer: 0,
+            active_frame: FrameId::default(),
+            previous_frame: FrameId::default(),
+            layout: Layout::single(0),
+            commands: Arc::default(),
+            keymap: Keymap::default(),
+            macros: MacroRegisters::default(),
+            pending: VecDeque::new(),
+            scripts: ScriptHost::default(),
+            font,
+            theme: Arc::default(),
+            zoom: 1.0,
+            view: ViewOptions::default(),
+            state_tx,
+            watcher: None,
+            tasks: Tasks::default(),
+            swap_writer: None,
+            quit_dialog: None,
+            exiting: false,
+        };
+
+        state.set_layout(Layout::default());
+        state
+    }
+
+    /// Replaces the layout, focusing its first frame.
+    pub fn set_layout(&mut self, layout: Layout) {
+        let (frame, index) = layout.frames()[0];
+
+        self.layout = layout;
+        self.active_frame = frame;
+        self.previous_frame = frame;
+        self.active_buffer = index;
+    }
+
+    pub fn active_buffer(&self) -> &SharedBuffer {
+        self.buffers
             .get(self.active_buffer)
-            .expect("get active buffer");
+            .expect("get active buffer")
+    }
+
+    pub async fn send_to_active_buffer(
+        &self,
+        event: BufferEvent,
+    ) -> anyhow::Result<EventHandlerOutcome> {
+        let mut buffer = self.active_buffer().lock().await;
+
+        Ok(buffer.handle_events(event))
+    }
+
+    /// Moves focus `step` frames forward (or backward when negative) in layout order.
+    pub fn cycle_focus(&mut self, step: isize) -> EventHandlerOutcome {
+        let frames = self.layout.frames();
+
+        if frames.is_empty() {
+            return EventHandlerOutcome::None;
+        }
+
+        let current = frames
+            .iter()
+            .position(|&(frame, _)| frame == self.active_frame)
+            .unwrap_or(0) as isize;
+        let next = (current + step).rem_euclid(frames.len() as isize) as usize;
+
+        self.focus_frame(frames[next].0);
+
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Focuses the frame under `position` and places its cursor there.
+    pub async fn click(
+        &mut self,
+        position: (f32, f32),
+        window: BoundingBox,
+    ) -> EventHandlerOutcome {
+        let (x, y) = position;
+        let frame = self
+            .layout
+            .build_bounding_boxes(window)
+            .into_iter()
+            .find(|(bb, ..)| {
+                (bb.left..bb.left + bb.width).contains(&x)
+                    && (bb.top..bb.top + bb.height).contains(&y)
+            });
+
+        let Some((bb, frame, index)) = frame else {
+            return EventHandlerOutcome::None;
+        };
+
+        let focus_changed = self.active_frame != frame;
+        self.focus_frame(frame);
+
+        let click = BufferEvent::Click {
+            x: x - bb.left,
+            y: y - bb.top,
+        };
+        match self.buffers[index].lock().await.handle_events(click) {
+            EventHandlerOutcome::None if !focus_changed => EventHandlerOutcome::None,
+            _ => EventHandlerOutcome::Redraw,
+        }
+    }
+
+    /// Shows the file in the active frame, reusing its buffer if it's already open.
+    pub async fn open_file(&mut self, path: PathBuf) -> anyhow::Result<usize> {
+        if let Some(index) = self.find_buffer(&path).await {
+            self.show_buffer(index);
+            return Ok(index);
+        }
+
+        let index = self.load_file(path).await?;
+        self.show_buffer(index);
+
+        Ok(index)
+    }
+
+    /// Reads the file into a new buffer, without showing it. Returns the buffer's index.
+    pub async fn load_file(&mut self, path: PathBuf) -> anyhow::Result<usize> {
+        let text = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => text,
+            // Opening a path that doesn't exist yet starts a new file
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).with_context(|| format!("Can't open {}", path.display())),
+        };
+        self.watch(&path, &text);
+
+        let recovered = swap::take_leftover(&path).await;
+        let mut buffer = TextBuffer::from_file(self.font.clone(), path.clone(), text);
+        // Changes a crash left unsaved come back as unsaved changes, to compare with the file
+        if let Some(recovered) =
+            recovered.filter(|recovered| Some(recovered.as_str()) != buffer.text())
+        {
+            tracing::info!("Recovered unsaved changes to {}", path.display());
+            buffer.recover(recovered);
+        }
+        buffer.set_problems(self.tasks.problems_at(&path));
+        let buffer: SharedBuffer = Arc::new(Mutex::new(buffer));
+        git::refresh(buffer.clone(), self.state_tx.clone());
+        self.buffers.push(buffer);
+
+        Ok(self.buffers.len() - 1)
+    }
+
+    /// Writes the active buffer to its file, or to `path` if given. Returns the path written.
+    pub async fn save_active_buffer(&mut self, path: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+        self.save_buffer(self.active_buffer, path).await
+    }
+
+    /// Writes buffer `index` to its file, or to `path` if given. Returns the path written.
+    pub async fn save_buffer(
+        &mut self,
+        index: usize,
+        path: Option<PathBuf>,
+    ) -> anyhow::Result<PathBuf> {
+        let buffer = self.buffers[index].clone();
+        let mut buffer = buffer.lock().await;
+
+        // Saving elsewhere may move the file in or out of a repository
+        if let Some(path) = path {
+            if let Some(old) = buffer.path().filter(|old| *old != path) {
+                self.unwatch(old);
+            }
+            buffer.set_path(path);
+            git::refresh(self.buffers[index].clone(), self.state_tx.clone());
+        }
+
+        let path = buffer
+            .path()
+            .context("Buffer has no file to save to")?
+            .to_path_buf();
+        let text = buffer
+            .text()
+            .context("Buffer has no text to save")?
+            .to_string();
+
+        tokio::fs::write(&path, &text)
+            .await
+            .with_context(|| format!("Can't write {}", path.display()))?;
+        buffer.mark_saved();
+        buffer.set_conflict(None);
+        self.watch(&path, &text);
+
+        Ok(path)
+    }
+
+    /// Index of the buffer editing the file at `path`.
+    pub async fn find_buffer(&self, path: &Path) -> Option<usize> {
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            if buffer.lock().await.path() == Some(path) {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+
+    /// Watches `path` for changes made by other programs. `text` is what was just read from
+    /// or written to it.
+    pub fn watch(&mut self, path: &Path, text: &str) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+
+        if let Err(err) = watcher.watch(path, text) {
+            tracing::warn!("{:#}", err);
+        }
+    }
+
+    /// Stops watching `path`, whose buffer is gone or edits another file.
+    pub fn unwatch(&mut self, path: &Path) {
+        if let Some(watcher) = &mut self.watcher {
+            watcher.unwatch(path);
+        }
+    }
+
+    /// Stops watching the folders in `dirs` that no buffer lists anymore.
+    pub async fn unwatch_folders(&mut self, dirs: Vec<PathBuf>) {
+        for dir in dirs {
+            let mut listed = false;
+            for buffer in &self.buffers {
+                listed |= buffer.lock().await.watched_folders().contains(&dir);
+            }
+
+            if let (false, Some(watcher)) = (listed, &mut self.watcher) {
+                watcher.unwatch_folder(&dir);
+            }
+        }
+    }
+
+    /// Focuses buffer `index`: the active frame if it shows it, else the first frame that
+    /// does, else the active frame made to show it.
+    pub fn show_buffer(&mut self, index: usize) {
+        if self.active_buffer == index {
+            return;
+        }
+
+        match self.layout.frame_showing(index) {
+            Some(frame) => self.focus_frame(frame),
+            None => {
+                self.layout.set_buffer(self.active_frame, index);
+                self.active_buffer = index;
+            }
+        }
+    }
+
+    /// Shows buffer `index` in a new frame below the active one, unless a frame shows it
+    /// already. The focus stays where it is.
+    pub fn show_below(&mut self, index: usize) {
+        if self.layout.frame_showing(index).is_some() {
+            return;
+        }
+
+        if let Some(below) = self.layout.split_frame(self.active_frame, true) {
+            self.layout.set_buffer(below, index);
+        }
+    }
+
+    /// Focuses the frame used before the active one, or any other frame. Lists such as the
+    /// explorer open files there, so they stay on screen.
+    pub fn focus_other_frame(&mut self) {
+        let frames = self.layout.frames();
+        let open = |frame: &FrameId| frames.iter().any(|(id, _)| id == frame);
+        let target = Some(self.previous_frame)
+            .filter(|frame| *frame != self.active_frame && open(frame))
+            .or_else(|| {
+                frames
+                    .iter()
+                    .map(|&(frame, _)| frame)
+                    .find(|frame| *frame != self.active_frame)
+            });
+
+        if let Some(target) = target {
+            self.focus_frame(target);
+        }
+    }
+
+    /// Closes `frame`, releasing its buffer when it goes away with the last frame showing
+    /// it. The last frame can't be closed.
+    pub async fn close_frame(&mut self, frame: FrameId) -> bool {
+        let Some(index) = self.layout.buffer(frame) else {
+            return false;
+        };
+        if !self.layout.close_frame(frame) {
+            return false;
+        }
+
+        if self.active_frame == frame {
+            self.focus_other_frame();
+        }
+        if self.layout.frame_showing(index).is_none()
+            && self.buffers[index].lock().await.closes_with_frame()
+        {
+            self.release_buffer(index).await;
+        }
+
+        true
+    }
+
+    /// Removes buffer `index`, which no frame shows, stopping what it runs.
+    async fn release_buffer(&mut self, index: usize) {
+        let buffer = self.buffers.remove(index);
+        let (path, folders) = {
+            let mut buffer = buffer.lock().await;
+            buffer.shutdown();
+            (
+                buffer.path().map(Path::to_path_buf),
+                buffer.watched_folders(),
+            )
+        };
+        // A file's buffer is the only one editing it, but other explorers may list the
+        // same folders
+        if !folders.is_empty() {
+            self.unwatch_folders(folders).await;
+        } else if let Some(path) = path {
+            self.unwatch(&path);
+        }
+
+        self.layout.buffer_removed(index);
+        self.active_buffer -= usize::from(self.active_buffer > index);
+        self.tasks.buffer_removed(index);
+        self.quit_dialog = self
+            .quit_dialog
+            .take()
+            .filter(|(dialog, _)| *dialog != index)
+            .map(|(dialog, log)| (dialog - usize::from(dialog > index), log));
+    }
 
-        let mut buffer = mutex.lock().await;
+    /// Makes `frame` the active one, remembering the one focused before.
+    pub fn focus_frame(&mut self, frame: FrameId) {
+        let Some(index) = self.layout.buffer(frame) else {
+            return;
+        };
 
-        buffer.handle_events(BufferEvent::Input(c))
+        if self.active_frame != frame {
+            self.previous_frame = self.active_frame;
+            self.active_frame = frame;
+        }
+        self.active_buffer = index;
     }
 }
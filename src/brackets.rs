@@ -0,0 +1,79 @@
+use crate::syntax::SyntaxSpans;
+
+/// Pairs inserted together. Single quotes aren't paired: in Rust they start lifetimes far
+/// more often than character literals.
+const PAIRS: [(char, char); 4] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')];
+
+/// Brackets matched with each other, as bytes since they're all ASCII.
+const BRACKETS: [(u8, u8); 3] = [(b'(', b')'), (b'[', b']'), (b'{', b'}')];
+
+/// Closer auto-inserted after typing `c`.
+pub fn closer(c: char) -> Option<char> {
+    PAIRS
+        .iter()
+        .find(|(open, _)| *open == c)
+        .map(|(_, close)| *close)
+}
+
+pub fn is_closer(c: char) -> bool {
+    PAIRS.iter().any(|(_, close)| *close == c)
+}
+
+/// Whether typing `open` at `offset` should insert its closer too: in code, and when the
+/// next character doesn't continue a word.
+pub fn should_pair(text: &str, offset: usize, open: char, syntax: &SyntaxSpans) -> bool {
+    if syntax.kind_at_caret(offset).is_some() {
+        return false;
+    }
+
+    let next = text[offset..].chars().next();
+    let previous = text[..offset].chars().next_back();
+    let next_allows =
+        next.is_none_or(|c| c.is_whitespace() || is_closer(c) || matches!(c, ',' | ';'));
+    // Quotes right after words are more likely to end something than to start a string
+    let previous_allows = open != '"' || previous.is_none_or(|c| !c.is_alphanumeric());
+
+    next_allows && previous_allows
+}
+
+/// Offset of the bracket matching the one at `offset`. Brackets inside strings and
+/// comments only match brackets in the same kind of span.
+pub fn find_match(text: &str, offset: usize, syntax: &SyntaxSpans) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let bracket = *bytes.get(offset)?;
+    let kind = syntax.kind_at(offset);
+
+    // Openers search forward for their closer, closers backward for their opener
+    let (same, other, forward) = BRACKETS.iter().find_map(|&(open, close)| match bracket {
+        b if b == open => Some((open, close, true)),
+        b if b == close => Some((close, open, false)),
+        _ => None,
+    })?;
+    let offsets: Box<dyn Iterator<Item = usize>> = match forward {
+        true => Box::new(offset..bytes.len()),
+        false => Box::new((0..=offset).rev()),
+    };
+
+    let mut depth = 0;
+    for i in offsets.filter(|&i| syntax.kind_at(i) == kind) {
+        if bytes[i] == same {
+            depth += 1;
+        } else if bytes[i] == other {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+
+    None
+}
+
+/// Bracket pair around the caret at `cursor`: the bracket right after it, else the one
+/// right before it.
+pub fn pair_at_caret(text: &str, cursor: usize, syntax: &SyntaxSpans) -> Option<(usize, usize)> {
+    [Some(cursor), cursor.checked_sub(1)]
+        .into_iter()
+        .flatten()
+        .find_map(|offset| Some((offset, find_match(text, offset, syntax)?)))
+}
@@ -0,0 +1,510 @@
+use crate::app_state::AppState;
+use crate::buffer::diff::rows::{Line, Row};
+use crate::buffer::text_buffer::FontConfig;
+use crate::buffer::{BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, Motion, RenderContext};
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult,
+};
+use crate::fonts::FontStyle;
+use crate::grid::GridMetrics;
+use crate::scene::{Label, Quad, Scene, Span};
+use anyhow::Context;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use unicode_width::UnicodeWidthChar;
+
+mod rows;
+
+pub use rows::{Diff, Mode, SharedDiff, Version};
+
+/// Which part of a diff a view shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pane {
+    /// Left half of the side by side mode
+    Old,
+    /// Right half of the side by side mode
+    New,
+    Inline,
+}
+
+/// Read-only view of a diff, browsed with the cursor keys.
+///
+/// The side by side mode is a pair of views in frames next to each other, one per
+/// version, sharing the diff so they scroll together. The first row names the versions,
+/// and the changed words of changed lines are highlighted over their line's color.
+pub struct DiffView {
+    diff: SharedDiff,
+    pane: Pane,
+    config: FontConfig,
+    /// Row size the view was last drawn with, to map clicks to rows
+    metrics: Option<GridMetrics>,
+}
+
+impl DiffView {
+    pub fn new(config: FontConfig, diff: SharedDiff, pane: Pane) -> Self {
+        Self {
+            diff,
+            pane,
+            config,
+            metrics: None,
+        }
+    }
+
+    fn move_cursor(&mut self, motion: Motion) -> EventHandlerOutcome {
+        let mut diff = self.diff.lock().unwrap();
+        let rows = diff.rows().len() as isize;
+
+        let moved = match motion {
+            Motion::Up => diff.move_cursor(-1),
+            Motion::Down => diff.move_cursor(1),
+            Motion::LineStart => diff.move_cursor(-rows),
+            Motion::LineEnd => diff.move_cursor(rows),
+            Motion::Left | Motion::Right => false,
+        };
+
+        redraw_if(moved)
+    }
+
+    fn click(&mut self, y: f32) -> EventHandlerOutcome {
+        let Some(metrics) = self.metrics else {
+            return EventHandlerOutcome::None;
+        };
+
+        // The first row is the title
+        let Some(row) = ((y.max(0.0) / metrics.line_height) as usize).checked_sub(1) else {
+            return EventHandlerOutcome::None;
+        };
+
+        let mut diff = self.diff.lock().unwrap();
+        let target = diff.scroll + row;
+        if target >= diff.rows().len() {
+            return EventHandlerOutcome::None;
+        }
+
+        diff.cursor = target;
+        EventHandlerOutcome::Redraw
+    }
+
+    /// The line of `row` this view shows, with the text it's from.
+    fn line<'a>(&self, diff: &'a Diff, row: &'a Row) -> Option<(&'a Line, &'a str)> {
+        match self.pane {
+            Pane::Old => row.old.as_ref().map(|line| (line, diff.old.text.as_str())),
+            Pane::New => row.new.as_ref().map(|line| (line, diff.new.text.as_str())),
+            Pane::Inline => match (&row.old, &row.new) {
+                (_, Some(line)) => Some((line, diff.new.text.as_str())),
+                (Some(line), None) => Some((line, diff.old.text.as_str())),
+                (None, None) => None,
+            },
+        }
+    }
+
+    fn title(&self, diff: &Diff) -> String {
+        let title = match self.pane {
+            Pane::Old => diff.old.title.clone(),
+            Pane::New => diff.new.title.clone(),
+            Pane::Inline => format!("{} → {}", diff.old.title, diff.new.title),
+        };
+
+        match diff.is_empty() {
+            true => format!("{} (no changes)", title),
+            false => title,
+        }
+    }
+}
+
+fn redraw_if(changed: bool) -> EventHandlerOutcome {
+    match changed {
+        true => EventHandlerOutcome::Redraw,
+        false => EventHandlerOutcome::None,
+    }
+}
+
+/// `line` with its tabs expanded, and the column each of its bytes starts at.
+fn expand_tabs(line: &str, tab_width: usize) -> (String, Vec<usize>) {
+    let mut expanded = String::with_capacity(line.len());
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+
+    for c in line.chars() {
+        columns.extend(std::iter::repeat_n(column, c.len_utf8()));
+
+        if c == '\t' {
+            let width = tab_width - column % tab_width;
+            expanded.extend(std::iter::repeat_n(' ', width));
+            column += width;
+        } else {
+            expanded.push(c);
+            column += c.width().unwrap_or(0);
+        }
+    }
+    columns.push(column);
+
+    (expanded, columns)
+}
+
+fn digits(number: usize) -> usize {
+    number.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+impl Buffer for DiffView {
+    fn draw(&mut self, bb: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        let scale = ctx.font_scale(self.config.scale);
+        let fonts = self.config.fonts.clone();
+        let font_id = fonts.primary(FontStyle::Regular);
+        let metrics = GridMetrics::new(&fonts, scale);
+        self.metrics = Some(metrics);
+
+        let tab_width = self.config.grid.tab_width.max(1);
+        let theme = ctx.theme;
+        let diff = self.diff.clone();
+        let mut diff = diff.lock().unwrap();
+        let visible_rows = ((bb.height / metrics.line_height) as usize)
+            .saturating_sub(1)
+            .max(1);
+
+        // Keep the cursor in view. Views of the same diff have the same height, so they
+        // scroll to the same row.
+        diff.scroll = diff
+            .scroll
+            .min(diff.cursor)
+            .max((diff.cursor + 1).saturating_sub(visible_rows));
+
+        let number_width = digits(
+            diff.old
+                .text
+                .lines()
+                .count()
+                .max(diff.new.text.lines().count()),
+        );
+        // Inline rows show both line numbers and a `-` or `+`
+        let gutter_columns = match self.pane {
+            Pane::Old | Pane::New => number_width + 1,
+            Pane::Inline => number_width * 2 + 4,
+        };
+        let gutter_width = gutter_columns as f32 * metrics.cell_width;
+        let text_left = bb.left + gutter_width;
+
+        let mut quads = Vec::new();
+        let mut sections = Vec::new();
+        let row_top = |row: usize| bb.top + (row + 1) as f32 * metrics.line_height;
+        let rect = |left: f32, right: f32, top: f32| {
+            [
+                left.min(bb.left + bb.width),
+                top,
+                right.min(bb.left + bb.width),
+                top + metrics.line_height,
+            ]
+        };
+
+        // Title row
+        quads.push(Quad {
+            aabb: rect(bb.left, bb.left + bb.width, bb.top),
+            z_pos: 0.0,
+            color: theme.gutter.0,
+        });
+        sections.push((
+            (bb.left, bb.top),
+            vec![(self.title(&diff), theme.foreground.0)],
+        ));
+
+        quads.push(Quad {
+            aabb: [
+                bb.left,
+                bb.top + metrics.line_height,
+                (bb.left + gutter_width).min(bb.left + bb.width),
+                bb.top + bb.height,
+            ],
+            z_pos: 0.0,
+            color: theme.gutter.0,
+        });
+
+        let first = diff.scroll;
+        for (i, row) in diff
+            .rows()
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible_rows)
+        {
+            let top = row_top(i - first);
+
+            if i == diff.cursor {
+                quads.push(Quad {
+                    aabb: rect(bb.left, text_left, top),
+                    z_pos: 0.0,
+                    color: theme.selection.0,
+                });
+            }
+
+            let Some((line, text)) = self.line(&diff, row) else {
+                // Padding for lines only the other version has
+                quads.push(Quad {
+                    aabb: rect(text_left, bb.left + bb.width, top),
+                    z_pos: 0.0,
+                    color: theme.gutter.0,
+                });
+                continue;
+            };
+
+            let removed = match self.pane {
+                Pane::Old => true,
+                Pane::New => false,
+                Pane::Inline => row.new.is_none(),
+            };
+            if row.changed {
+                let (line_color, word_color) = match removed {
+                    true => (theme.diff_deleted, theme.diff_deleted_word),
+                    false => (theme.diff_added, theme.diff_added_word),
+                };
+                quads.push(Quad {
+                    aabb: rect(text_left, bb.left + bb.width, top),
+                    z_pos: 0.0,
+                    color: line_color.0,
+                });
+
+                let (_, columns) = expand_tabs(&text[line.bytes.clone()], tab_width);
+                for word in &line.words {
+                    let cell = |column: usize| text_left + column as f32 * metrics.cell_width;
+                    quads.push(Quad {
+                        aabb: rect(cell(columns[word.start]), cell(columns[word.end]), top),
+                        z_pos: 0.0,
+                        color: word_color.0,
+                    });
+                }
+            }
+
+            let gutter = match self.pane {
+                Pane::Old | Pane::New => {
+                    format!("{:>width$} ", line.number + 1, width = number_width)
+                }
+                Pane::Inline => {
+                    let number = |line: &Option<Line>| {
+                        line.as_ref()
+                            .map_or(String::new(), |line| (line.number + 1).to_string())
+                    };
+                    let sign = match (&row.old, &row.new) {
+                        (Some(_), None) => '-',
+                        (None, Some(_)) => '+',
+                        _ => ' ',
+                    };
+                    format!(
+                        "{:>width$} {:>width$} {} ",
+                        number(&row.old),
+                        number(&row.new),
+                        sign,
+                        width = number_width
+                    )
+                }
+            };
+            let (expanded, _) = expand_tabs(&text[line.bytes.clone()], tab_width);
+            sections.push((
+                (bb.left, top),
+                vec![
+                    (gutter, theme.line_number.0),
+                    (expanded, theme.foreground.0),
+                ],
+            ));
+        }
+        drop(diff);
+
+        scene.backgrounds.extend(quads);
+
+        for (position, texts) in sections {
+            let bounds = (bb.left + bb.width - position.0, metrics.line_height);
+            let label =
+                texts
+                    .into_iter()
+                    .fold(Label::new(position, bounds), |label, (text, color)| {
+                        label.add_span(
+                            Span::new(text)
+                                .with_scale(scale)
+                                .with_color(color)
+                                .with_font_id(font_id),
+                        )
+                    });
+            scene.labels.push(label);
+        }
+    }
+
+    fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome {
+        match event {
+            BufferEvent::MoveCursor { motion, .. } => self.move_cursor(motion),
+            BufferEvent::JumpToHunk { forward } => {
+                redraw_if(self.diff.lock().unwrap().jump_to_change(forward))
+            }
+            BufferEvent::Click { y, .. } => self.click(y),
+            _ => EventHandlerOutcome::None,
+        }
+    }
+
+    fn diff(&self) -> Option<(SharedDiff, Pane)> {
+        Some((self.diff.clone(), self.pane))
+    }
+}
+
+/// Index of the buffer showing `pane` of `diff`, which is added if there's none.
+async fn pane_buffer(state: &mut AppState, diff: &SharedDiff, pane: Pane) -> usize {
+    for (index, buffer) in state.buffers.iter().enumerate() {
+        if let Some((shown, shown_pane)) = buffer.lock().await.diff() {
+            if Arc::ptr_eq(&shown, diff) && shown_pane == pane {
+                return index;
+            }
+        }
+    }
+
+    let view = DiffView::new(state.font.clone(), diff.clone(), pane);
+    state.buffers.push(Arc::new(tokio::sync::Mutex::new(view)));
+    state.buffers.len() - 1
+}
+
+/// A diff none of whose views are on screen.
+async fn unused_diff(state: &AppState) -> Option<SharedDiff> {
+    let mut diffs: Vec<(SharedDiff, bool)> = Vec::new();
+
+    for (index, buffer) in state.buffers.iter().enumerate() {
+        let Some((diff, _)) = buffer.lock().await.diff() else {
+            continue;
+        };
+
+        let shown = state.layout.frame_showing(index).is_some();
+        match diffs
+            .iter_mut()
+            .find(|(known, _)| Arc::ptr_eq(known, &diff))
+        {
+            Some((_, any_shown)) => *any_shown |= shown,
+            None => diffs.push((diff, shown)),
+        }
+    }
+
+    diffs
+        .into_iter()
+        .find(|(_, shown)| !shown)
+        .map(|(diff, _)| diff)
+}
+
+/// Shows `old` and `new` compared left of the active frame, side by side or inline as
+/// diffs were last shown, and focuses the comparison.
+pub async fn open(state: &mut AppState, old: Version, new: Version) {
+    let mode = match state.view.inline_diff {
+        true => Mode::Inline,
+        false => Mode::SideBySide,
+    };
+    let diff = Diff::new(old, new, mode);
+    // Views of a comparison no frame shows anymore take the new one, rather than piling up
+    let diff = match unused_diff(state).await {
+        Some(unused) => {
+            *unused.lock().unwrap() = diff;
+            unused
+        }
+        None => Arc::new(Mutex::new(diff)),
+    };
+
+    let pane = match mode {
+        Mode::SideBySide => Pane::New,
+        Mode::Inline => Pane::Inline,
+    };
+    let index = pane_buffer(state, &diff, pane).await;
+    let frame = state.active_frame;
+    state.layout.split_frame(frame, false);
+    state.layout.set_buffer(frame, index);
+
+    let mut focused = frame;
+    if mode == Mode::SideBySide {
+        let old = pane_buffer(state, &diff, Pane::Old).await;
+        if let Some(right) = state.layout.split_frame(frame, false) {
+            state.layout.set_buffer(frame, old);
+            focused = right;
+        }
+    }
+
+    state.focus_frame(focused);
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "diff.files",
+            "Compare two files, in a new frame",
+            diff_files,
+        )
+        .with_arg(ArgSpec::required("old", ArgKind::Text))
+        .with_arg(ArgSpec::required("new", ArgKind::Text)),
+    );
+    registry.register(Command::new(
+        "diff.toggle_inline",
+        "Show the active diff in one column, or side by side again",
+        toggle_inline,
+    ));
+}
+
+fn diff_files(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let mut versions = Vec::new();
+        for arg in &args {
+            let path = PathBuf::from(arg.as_text().expect("checked by registry"));
+            let text = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("Can't read {}", path.display()))?;
+
+            versions.push(Version {
+                title: path.display().to_string(),
+                text,
+            });
+        }
+
+        let new = versions.pop().expect("checked by registry");
+        let old = versions.pop().expect("checked by registry");
+        open(state, old, new).await;
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn toggle_inline(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let (diff, pane) = state
+            .active_buffer()
+            .lock()
+            .await
+            .diff()
+            .context("Buffer isn't a diff")?;
+        let mode = match pane {
+            Pane::Old | Pane::New => Mode::Inline,
+            Pane::Inline => Mode::SideBySide,
+        };
+
+        diff.lock().unwrap().set_mode(mode);
+        // Diffs opened later follow suit
+        state.view.inline_diff = mode == Mode::Inline;
+
+        let focused = match mode {
+            Mode::Inline => {
+                let old = pane_buffer(state, &diff, Pane::Old).await;
+                let new = pane_buffer(state, &diff, Pane::New).await;
+                let inline = pane_buffer(state, &diff, Pane::Inline).await;
+
+                // The new version's frame takes the space of both halves
+                state.layout.close_frames_showing(old);
+                let frame = state
+                    .layout
+                    .frame_showing(new)
+                    .unwrap_or(state.active_frame);
+                state.layout.set_buffer(frame, inline);
+                frame
+            }
+            Mode::SideBySide => {
+                let frame = state.active_frame;
+                let old = pane_buffer(state, &diff, Pane::Old).await;
+                let new = pane_buffer(state, &diff, Pane::New).await;
+
+                let right = state.layout.split_frame(frame, false).unwrap_or(frame);
+                state.layout.set_buffer(frame, old);
+                state.layout.set_buffer(right, new);
+                right
+            }
+        };
+        state.focus_frame(focused);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
@@ -0,0 +1,274 @@
+use crate::grid;
+use similar::{Algorithm, DiffOp, DiffTag, TextDiff};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Longest a diff may take before settling for a coarser result, for huge files.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Diff shared by the views showing it, so they scroll together.
+pub type SharedDiff = Arc<Mutex<Diff>>;
+
+/// One of the two texts compared.
+pub struct Version {
+    /// Where the text comes from, such as `main.rs (staged)`
+    pub title: String,
+    pub text: String,
+}
+
+/// A line of one version shown on a row.
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// Line number, from 0
+    pub number: usize,
+    /// Byte range in the version's text, without the line break
+    pub bytes: Range<usize>,
+    /// Byte ranges within the line of the words that changed
+    pub words: Vec<Range<usize>>,
+}
+
+/// What a row shows of each version. Rows of unchanged lines have both, rows of removed
+/// lines only the old one and rows of added lines only the new one.
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub old: Option<Line>,
+    pub new: Option<Line>,
+    pub changed: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Versions next to each other, changed lines paired up and padded to stay aligned
+    SideBySide,
+    /// Removed lines above the added lines that replace them, in one column
+    Inline,
+}
+
+/// Lines of two texts aligned for comparison, with the row at the cursor.
+///
+/// Lines are matched with the patience algorithm, which keeps unique lines such as
+/// function signatures together. Changed lines paired up are then compared word by word
+/// with Myers' algorithm, to highlight what changed inside them.
+pub struct Diff {
+    pub old: Version,
+    pub new: Version,
+    pub mode: Mode,
+    side_by_side: Vec<Row>,
+    inline: Vec<Row>,
+    /// Row at the cursor, in the rows of the current mode
+    pub cursor: usize,
+    /// First row drawn
+    pub scroll: usize,
+}
+
+impl Diff {
+    pub fn new(old: Version, new: Version, mode: Mode) -> Self {
+        let (side_by_side, inline) = rows(&old.text, &new.text);
+
+        Self {
+            old,
+            new,
+            mode,
+            side_by_side,
+            inline,
+            cursor: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        match self.mode {
+            Mode::SideBySide => &self.side_by_side,
+            Mode::Inline => &self.inline,
+        }
+    }
+
+    /// Switches to `mode`, keeping the cursor on the same line.
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode == self.mode {
+            return;
+        }
+
+        let line = self.rows().get(self.cursor).map(|row| (&row.old, &row.new));
+        let target = match line {
+            Some((Some(old), None)) => (Some(old.number), None),
+            Some((_, Some(new))) => (None, Some(new.number)),
+            _ => (None, None),
+        };
+
+        self.mode = mode;
+        self.cursor = self
+            .rows()
+            .iter()
+            .position(|row| {
+                target.0.is_some() && row.old.as_ref().map(|line| line.number) == target.0
+                    || target.1.is_some() && row.new.as_ref().map(|line| line.number) == target.1
+            })
+            .unwrap_or(0);
+    }
+
+    /// Moves the cursor by `step` rows, staying within the rows.
+    pub fn move_cursor(&mut self, step: isize) -> bool {
+        let last = self.rows().len().saturating_sub(1);
+        let target = self.cursor.saturating_add_signed(step).min(last);
+
+        std::mem::replace(&mut self.cursor, target) != target
+    }
+
+    /// Moves the cursor to the first row of the next or previous block of changed rows.
+    pub fn jump_to_change(&mut self, forward: bool) -> bool {
+        let rows = self.rows();
+        let starts =
+            |&index: &usize| rows[index].changed && (index == 0 || !rows[index - 1].changed);
+
+        let target = match forward {
+            true => (self.cursor + 1..rows.len()).find(starts),
+            false => (0..self.cursor).rev().find(starts),
+        };
+
+        match target {
+            Some(target) => {
+                self.cursor = target;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the texts are the same.
+    pub fn is_empty(&self) -> bool {
+        !self.side_by_side.iter().any(|row| row.changed)
+    }
+}
+
+/// Rows of the side by side and inline modes.
+fn rows(old: &str, new: &str) -> (Vec<Row>, Vec<Row>) {
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Patience)
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(old, new);
+    let old_lines = line_bytes(old);
+    let new_lines = line_bytes(new);
+    let line = |lines: &[Range<usize>], number: usize| Line {
+        number,
+        bytes: lines[number].clone(),
+        words: Vec::new(),
+    };
+
+    let mut side_by_side = Vec::new();
+    let mut inline = Vec::new();
+
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+
+        if tag == DiffTag::Equal {
+            for (o, n) in old_range.zip(new_range) {
+                let row = Row {
+                    old: Some(line(&old_lines, o)),
+                    new: Some(line(&new_lines, n)),
+                    changed: false,
+                };
+                side_by_side.push(row.clone());
+                inline.push(row);
+            }
+            continue;
+        }
+
+        let mut removed: Vec<_> = old_range.map(|o| line(&old_lines, o)).collect();
+        let mut added: Vec<_> = new_range.map(|n| line(&new_lines, n)).collect();
+
+        // Removed lines are compared word by word with the added lines they pair up with
+        if let DiffOp::Replace { .. } = op {
+            for (old_line, new_line) in removed.iter_mut().zip(added.iter_mut()) {
+                (old_line.words, new_line.words) =
+                    changed_words(&old[old_line.bytes.clone()], &new[new_line.bytes.clone()]);
+            }
+        }
+
+        let pairs = removed.len().max(added.len());
+        for i in 0..pairs {
+            side_by_side.push(Row {
+                old: removed.get(i).cloned(),
+                new: added.get(i).cloned(),
+                changed: true,
+            });
+        }
+
+        let removed = removed.into_iter().map(|line| Row {
+            old: Some(line),
+            new: None,
+            changed: true,
+        });
+        let added = added.into_iter().map(|line| Row {
+            old: None,
+            new: Some(line),
+            changed: true,
+        });
+        inline.extend(removed.chain(added));
+    }
+
+    (side_by_side, inline)
+}
+
+/// Byte ranges of the changed words of `old` and of `new`.
+fn changed_words(old: &str, new: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Myers)
+        .timeout(DIFF_TIMEOUT)
+        .diff_words(old, new);
+    let offsets = |words: &[&str]| {
+        let mut offsets = vec![0];
+        for word in words {
+            offsets.push(offsets.last().unwrap() + word.len());
+        }
+        offsets
+    };
+    let old_offsets = offsets(diff.old_slices());
+    let new_offsets = offsets(diff.new_slices());
+
+    let mut old_words: Vec<Range<usize>> = Vec::new();
+    let mut new_words: Vec<Range<usize>> = Vec::new();
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+
+        push_merged(
+            &mut old_words,
+            old_offsets[old_range.start]..old_offsets[old_range.end],
+        );
+        push_merged(
+            &mut new_words,
+            new_offsets[new_range.start]..new_offsets[new_range.end],
+        );
+    }
+
+    (old_words, new_words)
+}
+
+/// Adds `range` to `ranges`, extending the last one if they touch.
+fn push_merged(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+/// Byte ranges of the lines of `text`, without line breaks.
+fn line_bytes(text: &str) -> Vec<Range<usize>> {
+    let mut start = 0;
+
+    grid::lines(text)
+        .map(|line| {
+            let bytes = start..start + line.len();
+            start += text[start..].find('\n').map_or(0, |end| end + 1);
+            bytes
+        })
+        .collect()
+}
@@ -0,0 +1,773 @@
+use crate::buffer::explorer::tree::{Row, SharedTree, Tree};
+use crate::buffer::text_buffer::FontConfig;
+use crate::buffer::{
+    BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, FileOperation, Motion, RenderContext,
+};
+use crate::command::{CommandArg, Invocation};
+use crate::fonts::FontStyle;
+use crate::grid::GridMetrics;
+use crate::scene::{Label, Quad, Scene, Span};
+use crate::state::StateEvent;
+use anyhow::{bail, Context};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+use tokio::sync::mpsc::Sender;
+
+mod tree;
+
+const EXPANDED_MARKER: char = '▾';
+const COLLAPSED_MARKER: char = '▸';
+/// Columns each level of the tree is indented by
+const INDENT_COLUMNS: usize = 2;
+
+/// Files and folders under a root folder, as a tree browsed with the cursor keys.
+///
+/// Enter opens files in the editor frame focused before the explorer, and expands or
+/// collapses folders. Folders are read when first expanded, skipping what `.gitignore`
+/// files ignore, and the expanded ones are watched for changes.
+pub struct Explorer {
+    root: PathBuf,
+    tree: SharedTree,
+    /// Selected entry, by path so it stays selected while the tree changes
+    selected: Option<PathBuf>,
+    /// First row drawn
+    scroll: usize,
+    prompt: Option<Prompt>,
+    /// Outcome of the last file operation, shown until the next key
+    message: Option<String>,
+    config: FontConfig,
+    state_tx: Sender<StateEvent>,
+    /// Row size the explorer was last drawn with, to map clicks to rows
+    metrics: Option<GridMetrics>,
+    /// Outcome of file operations running in the background, taken on the next key or draw
+    report: Arc<Mutex<Report>>,
+}
+
+/// What a file operation carried out in the background tells the explorer.
+#[derive(Default)]
+struct Report {
+    /// Entry created or moved, to select
+    select: Option<PathBuf>,
+    /// Confirmation to ask for before going on
+    prompt: Option<Prompt>,
+    /// Why the operation failed
+    message: Option<String>,
+}
+
+/// What carrying out a prompt needs from the explorer, to do it in a task of its own.
+struct Operation {
+    root: PathBuf,
+    tree: SharedTree,
+    state_tx: Sender<StateEvent>,
+    report: Arc<Mutex<Report>>,
+}
+
+/// Question asked on the last row before changing files.
+enum Prompt {
+    Create {
+        dir: PathBuf,
+        folder: bool,
+        input: String,
+    },
+    Rename {
+        path: PathBuf,
+        input: String,
+    },
+    /// Destination folder, relative to the root
+    Move {
+        path: PathBuf,
+        input: String,
+    },
+    Delete {
+        path: PathBuf,
+    },
+    /// Renaming or moving onto an existing file
+    Overwrite {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl Explorer {
+    /// Browses `root`, watching the folders it expands.
+    pub fn new(config: FontConfig, root: PathBuf, state_tx: Sender<StateEvent>) -> Self {
+        let tree = Arc::new(Mutex::new(Tree::new(root.clone())));
+        watch(&state_tx, &root);
+
+        Self {
+            root,
+            tree,
+            selected: None,
+            scroll: 0,
+            prompt: None,
+            message: None,
+            config,
+            state_tx,
+            metrics: None,
+            report: Arc::default(),
+        }
+    }
+
+    /// Applies what file operations reported since the last key or draw.
+    fn take_report(&mut self) {
+        let mut report = self.report.lock().unwrap();
+
+        if let Some(path) = report.select.take() {
+            self.selected = Some(path);
+        }
+        if let Some(prompt) = report.prompt.take() {
+            self.prompt = Some(prompt);
+        }
+        if let Some(message) = report.message.take() {
+            self.message = Some(message);
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        self.tree.lock().unwrap().rows()
+    }
+
+    /// Index of the selected row, the first one if the selection is gone.
+    fn selected_index(&self, rows: &[Row]) -> usize {
+        rows.iter()
+            .position(|row| Some(&row.entry.path) == self.selected.as_ref())
+            .unwrap_or(0)
+    }
+
+    fn selected_row(&self) -> Option<Row> {
+        let rows = self.rows();
+        let index = self.selected_index(&rows);
+
+        rows.into_iter().nth(index)
+    }
+
+    fn select(&mut self, path: PathBuf) -> EventHandlerOutcome {
+        if self.selected.as_ref() == Some(&path) {
+            return EventHandlerOutcome::None;
+        }
+
+        self.selected = Some(path);
+        EventHandlerOutcome::Redraw
+    }
+
+    fn move_selection(&mut self, motion: Motion) -> EventHandlerOutcome {
+        let rows = self.rows();
+        let Some(last) = rows.len().checked_sub(1) else {
+            return EventHandlerOutcome::None;
+        };
+        let index = self.selected_index(&rows);
+        let row = &rows[index];
+
+        let target = match motion {
+            Motion::Up => index.saturating_sub(1),
+            Motion::Down => (index + 1).min(last),
+            Motion::LineStart => 0,
+            Motion::LineEnd => last,
+            // Left collapses the folder, or goes up to the parent
+            Motion::Left if row.expanded => {
+                self.collapse(&row.entry.path);
+                return EventHandlerOutcome::Redraw;
+            }
+            Motion::Left => match rows[..index].iter().rposition(|r| r.depth < row.depth) {
+                Some(parent) => parent,
+                None => return EventHandlerOutcome::None,
+            },
+            // Right expands the folder, or goes down to its first entry
+            Motion::Right if row.entry.is_dir && !row.expanded => {
+                self.tree.lock().unwrap().expand(&row.entry.path);
+                watch(&self.state_tx, &row.entry.path);
+                return EventHandlerOutcome::Redraw;
+            }
+            Motion::Right if row.expanded && index < last => index + 1,
+            Motion::Right => return EventHandlerOutcome::None,
+        };
+
+        self.select(rows[target].entry.path.clone())
+    }
+
+    /// Opens the selected file, or expands or collapses the selected folder.
+    fn activate(&mut self) -> EventHandlerOutcome {
+        let Some(row) = self.selected_row() else {
+            return EventHandlerOutcome::None;
+        };
+
+        self.selected = Some(row.entry.path.clone());
+
+        if !row.entry.is_dir {
+            open(&self.state_tx, &row.entry.path);
+            return EventHandlerOutcome::Redraw;
+        }
+
+        match row.expanded {
+            true => self.collapse(&row.entry.path),
+            false => {
+                self.tree.lock().unwrap().expand(&row.entry.path);
+                watch(&self.state_tx, &row.entry.path);
+            }
+        }
+
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Collapses `dir`, no longer watching the folders hidden.
+    fn collapse(&mut self, dir: &Path) {
+        let collapsed = self.tree.lock().unwrap().collapse(dir);
+
+        for dir in collapsed {
+            unwatch(&self.state_tx, &dir);
+        }
+    }
+
+    fn click(&mut self, y: f32) -> EventHandlerOutcome {
+        let Some(metrics) = self.metrics else {
+            return EventHandlerOutcome::None;
+        };
+
+        let index = self.scroll + (y.max(0.0) / metrics.line_height) as usize;
+        match self.rows().into_iter().nth(index) {
+            Some(row) => {
+                self.selected = Some(row.entry.path);
+                self.activate()
+            }
+            None => EventHandlerOutcome::None,
+        }
+    }
+
+    /// Asks for what `operation` needs, or for a confirmation.
+    fn start(&mut self, operation: FileOperation) -> EventHandlerOutcome {
+        let selected = self.selected_row().map(|row| row.entry);
+        // New entries go in the selected folder, or next to the selected file
+        let dir = match &selected {
+            Some(entry) if entry.is_dir => entry.path.clone(),
+            Some(entry) => entry.path.parent().unwrap_or(&self.root).to_path_buf(),
+            None => self.root.clone(),
+        };
+
+        self.prompt = match (operation, selected) {
+            (FileOperation::NewFile | FileOperation::NewFolder, _) => Some(Prompt::Create {
+                dir,
+                folder: matches!(operation, FileOperation::NewFolder),
+                input: String::new(),
+            }),
+            (FileOperation::Rename, Some(entry)) => Some(Prompt::Rename {
+                path: entry.path,
+                input: entry.name,
+            }),
+            (FileOperation::Move, Some(entry)) => Some(Prompt::Move {
+                input: self.relative(entry.path.parent().unwrap_or(&self.root)),
+                path: entry.path,
+            }),
+            (FileOperation::Delete, Some(entry)) => Some(Prompt::Delete { path: entry.path }),
+            (_, None) => return EventHandlerOutcome::None,
+        };
+
+        self.message = None;
+        EventHandlerOutcome::Redraw
+    }
+
+    fn type_text(&mut self, text: &str) -> EventHandlerOutcome {
+        match &mut self.prompt {
+            Some(
+                Prompt::Create { input, .. }
+                | Prompt::Rename { input, .. }
+                | Prompt::Move { input, .. },
+            ) => {
+                input.push_str(text);
+                EventHandlerOutcome::Redraw
+            }
+            // Confirmations take `y` for yes and anything else for no
+            Some(Prompt::Delete { .. } | Prompt::Overwrite { .. }) if text == "y" => self.submit(),
+            Some(_) => self.cancel(),
+            None => EventHandlerOutcome::None,
+        }
+    }
+
+    fn delete_backward(&mut self) -> EventHandlerOutcome {
+        match &mut self.prompt {
+            Some(
+                Prompt::Create { input, .. }
+                | Prompt::Rename { input, .. }
+                | Prompt::Move { input, .. },
+            ) => {
+                input.pop();
+                EventHandlerOutcome::Redraw
+            }
+            _ => EventHandlerOutcome::None,
+        }
+    }
+
+    fn cancel(&mut self) -> EventHandlerOutcome {
+        if self.prompt.take().is_none() && self.message.take().is_none() {
+            return EventHandlerOutcome::None;
+        }
+
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Carries out the prompt in the background, reporting failures on the last row.
+    fn submit(&mut self) -> EventHandlerOutcome {
+        let Some(prompt) = self.prompt.take() else {
+            return EventHandlerOutcome::None;
+        };
+
+        let operation = Operation {
+            root: self.root.clone(),
+            tree: self.tree.clone(),
+            state_tx: self.state_tx.clone(),
+            report: self.report.clone(),
+        };
+        tokio::spawn(async move {
+            let outcome = operation.carry_out(prompt).await;
+
+            {
+                let mut report = operation.report.lock().unwrap();
+                match outcome {
+                    Ok(prompt) => report.prompt = prompt,
+                    Err(err) => report.message = Some(format!("{:#}", err)),
+                }
+            }
+            let _ = operation.state_tx.send(StateEvent::Redraw).await;
+        });
+
+        EventHandlerOutcome::Redraw
+    }
+
+    /// `path` relative to the root, as shown in prompts.
+    fn relative(&self, path: &Path) -> String {
+        relative(&self.root, path)
+    }
+
+    /// Text of the last row: the prompt with its input, or the last message.
+    fn status(&self) -> Option<String> {
+        let prompt = match &self.prompt {
+            None => return self.message.clone(),
+            Some(prompt) => prompt,
+        };
+
+        Some(match prompt {
+            Prompt::Create { dir, folder, input } => format!(
+                "New {} in {}: {}",
+                if *folder { "folder" } else { "file" },
+                self.relative(dir),
+                input
+            ),
+            Prompt::Rename { input, .. } => format!("Rename to: {}", input),
+            Prompt::Move { input, .. } => format!("Move to folder: {}", input),
+            Prompt::Delete { path } => format!("Delete {}? (y/n)", self.relative(path)),
+            Prompt::Overwrite { to, .. } => format!("Replace {}? (y/n)", self.relative(to)),
+        })
+    }
+}
+
+impl Buffer for Explorer {
+    fn draw(&mut self, bb: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        self.take_report();
+
+        let scale = ctx.font_scale(self.config.scale);
+        let fonts = self.config.fonts.clone();
+        let metrics = GridMetrics::new(&fonts, scale);
+        self.metrics = Some(metrics);
+
+        let status = self.status();
+        let rows = self.rows();
+        let selected = self.selected_index(&rows);
+        let visible_rows = ((bb.height / metrics.line_height) as usize)
+            .saturating_sub(usize::from(status.is_some()))
+            .max(1);
+
+        // Keep the selection in view
+        self.scroll = self
+            .scroll
+            .min(selected)
+            .max((selected + 1).saturating_sub(visible_rows));
+
+        let mut quads = Vec::new();
+        let row_top = |row: usize| bb.top + row as f32 * metrics.line_height;
+        let row_quad = |row: usize, color: [f32; 4]| Quad {
+            aabb: [
+                bb.left,
+                row_top(row),
+                bb.left + bb.width,
+                row_top(row) + metrics.line_height,
+            ],
+            z_pos: 0.0,
+            color,
+        };
+
+        for (i, row) in rows.iter().enumerate().skip(self.scroll).take(visible_rows) {
+            let top = row_top(i - self.scroll);
+            let left = bb.left + (row.depth * INDENT_COLUMNS) as f32 * metrics.cell_width;
+
+            if i == selected {
+                quads.push(row_quad(i - self.scroll, ctx.theme.selection.0));
+            }
+
+            let marker = match (row.entry.is_dir, row.expanded) {
+                (false, _) => None,
+                (true, true) => Some(EXPANDED_MARKER.to_string()),
+                (true, false) => Some(COLLAPSED_MARKER.to_string()),
+            };
+            let mut label = Label::new(
+                (left, top),
+                (bb.left + bb.width - left, metrics.line_height),
+            );
+            if let Some(marker) = &marker {
+                label = label.add_span(
+                    Span::new(marker.as_str())
+                        .with_scale(scale)
+                        .with_color(ctx.theme.fold_marker.0)
+                        .with_font_id(fonts.resolve(EXPANDED_MARKER, FontStyle::Regular)),
+                );
+            }
+            let name = format!(
+                "{}{}",
+                " ".repeat(INDENT_COLUMNS - usize::from(marker.is_some())),
+                row.entry.name
+            );
+            label = label.add_span(
+                Span::new(name)
+                    .with_scale(scale)
+                    .with_color(ctx.theme.foreground.0)
+                    .with_font_id(fonts.primary(FontStyle::Regular)),
+            );
+
+            scene.labels.push(label);
+        }
+
+        if let Some(status) = status {
+            let row = visible_rows;
+            quads.push(row_quad(row, ctx.theme.gutter.0));
+
+            let label = Label::new((bb.left, row_top(row)), (bb.width, metrics.line_height))
+                .add_span(
+                    Span::new(status.as_str())
+                        .with_scale(scale)
+                        .with_color(ctx.theme.foreground.0)
+                        .with_font_id(fonts.primary(FontStyle::Regular)),
+                );
+            scene.labels.push(label);
+
+            // Prompts get a cursor after their input
+            if self.prompt.is_some() {
+                let x = bb.left + status.chars().count() as f32 * metrics.cell_width;
+                scene.cursor = Some((x, row_top(row)));
+                quads.push(Quad {
+                    aabb: [
+                        x,
+                        row_top(row),
+                        x + scale * 0.1,
+                        row_top(row) + metrics.line_height,
+                    ],
+                    z_pos: 0.0,
+                    color: ctx.theme.cursor.0,
+                });
+            }
+        }
+
+        scene.backgrounds.extend(quads);
+    }
+
+    fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome {
+        self.take_report();
+        if self.prompt.is_none() {
+            self.message = None;
+        }
+
+        match event {
+            BufferEvent::Insert(text) => self.type_text(&text),
+            BufferEvent::InsertChar(c) => self.type_text(&c.to_string()),
+            BufferEvent::Newline if self.prompt.is_some() => self.submit(),
+            BufferEvent::Newline => self.activate(),
+            BufferEvent::DeleteBackward => self.delete_backward(),
+            BufferEvent::DeleteForward if self.prompt.is_none() => {
+                self.start(FileOperation::Delete)
+            }
+            BufferEvent::MoveCursor { motion, .. } if self.prompt.is_none() => {
+                self.move_selection(motion)
+            }
+            BufferEvent::Click { y, .. } => {
+                self.prompt = None;
+                self.click(y)
+            }
+            // Escape drops extra cursors in editors, and cancels prompts here
+            BufferEvent::SingleCursor => self.cancel(),
+            BufferEvent::FileOperation(operation) => self.start(operation),
+            _ => EventHandlerOutcome::None,
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+
+    fn folder_changed(&mut self, dir: &Path) -> bool {
+        let mut tree = self.tree.lock().unwrap();
+
+        tree.is_expanded(dir) && tree.reload(dir)
+    }
+
+    fn watched_folders(&self) -> Vec<PathBuf> {
+        self.tree.lock().unwrap().expanded()
+    }
+}
+
+impl Operation {
+    /// Changes files as `prompt` asked, returning a prompt to confirm overwriting.
+    async fn carry_out(&self, prompt: Prompt) -> anyhow::Result<Option<Prompt>> {
+        match prompt {
+            Prompt::Create { dir, folder, input } => {
+                let name = input.trim();
+                if name.is_empty() {
+                    bail!("Names can't be empty");
+                }
+
+                let path = self.under_root(&dir, name)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("Can't create {}", parent.display()))?;
+                }
+
+                if folder {
+                    fs::create_dir(&path).await
+                } else {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .await
+                        .map(drop)
+                }
+                .with_context(|| format!("Can't create {}", self.relative(&path)))?;
+
+                self.refresh(&path).await;
+                if !folder {
+                    open(&self.state_tx, &path);
+                }
+            }
+            Prompt::Rename { path, input } => {
+                let name = input.trim();
+                if name.is_empty() || name.contains(std::path::is_separator) {
+                    bail!("`{}` isn't a valid name", name);
+                }
+
+                let to = path.with_file_name(name);
+                return self.rename(path, to).await;
+            }
+            Prompt::Move { path, input } => {
+                let dir = self.under_root(&self.root, input.trim())?;
+                if !fs::metadata(&dir).await.is_ok_and(|dir| dir.is_dir()) {
+                    bail!("{} isn't a folder", self.relative(&dir));
+                }
+
+                let name = path.file_name().context("Can't move the root")?;
+                let to = dir.join(name);
+                return self.rename(path, to).await;
+            }
+            Prompt::Delete { path } => {
+                match fs::metadata(&path).await {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path).await,
+                    _ => fs::remove_file(&path).await,
+                }
+                .with_context(|| format!("Can't delete {}", self.relative(&path)))?;
+
+                self.refresh(&path).await;
+                // Buffers of deleted files keep their text, to be saved again
+                self.notify(Invocation::new("file.deleted").with_arg(path_arg(&path)));
+            }
+            Prompt::Overwrite { from, to } => self.move_file(&from, &to).await?,
+        }
+
+        Ok(None)
+    }
+
+    /// `input` from the prompt, as a path in `dir`. It has to stay under the root, so it
+    /// can't be absolute or go up with `..`.
+    fn under_root(&self, dir: &Path, input: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(input)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !relative {
+            bail!("`{}` isn't under {}", input, self.root.display());
+        }
+
+        Ok(dir.join(input))
+    }
+
+    /// Renames `from` to `to` unless that replaces something, which needs confirming.
+    async fn rename(&self, from: PathBuf, to: PathBuf) -> anyhow::Result<Option<Prompt>> {
+        if from == to {
+            return Ok(None);
+        }
+
+        if fs::try_exists(&to).await.unwrap_or(false) {
+            return Ok(Some(Prompt::Overwrite { from, to }));
+        }
+
+        self.move_file(&from, &to).await?;
+        Ok(None)
+    }
+
+    /// Moves `from` to `to`, and the buffers editing files there along.
+    async fn move_file(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        fs::rename(from, to).await.with_context(|| {
+            format!(
+                "Can't move {} to {}",
+                self.relative(from),
+                self.relative(to)
+            )
+        })?;
+
+        self.refresh(from).await;
+        self.refresh(to).await;
+        self.notify(
+            Invocation::new("file.moved")
+                .with_arg(path_arg(from))
+                .with_arg(path_arg(to)),
+        );
+
+        Ok(())
+    }
+
+    /// Reads the folders leading to `path` again. If `path` still exists, they're expanded
+    /// to show it and it gets selected.
+    async fn refresh(&self, path: &Path) {
+        let exists = fs::try_exists(path).await.unwrap_or(false);
+        let mut tree = self.tree.lock().unwrap();
+
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+
+            if exists || tree.is_expanded(dir) {
+                tree.expand(dir);
+                watch(&self.state_tx, dir);
+            }
+        }
+
+        if exists {
+            self.report.lock().unwrap().select = Some(path.to_path_buf());
+        }
+    }
+
+    /// Tells the state loop about files changed under open buffers.
+    fn notify(&self, invocation: Invocation) {
+        if let Err(err) = self.state_tx.try_send(StateEvent::Command(invocation)) {
+            tracing::warn!("Can't update buffers: {}", err);
+        }
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        relative(&self.root, path)
+    }
+}
+
+/// Asks the state loop to show `path` in the editor frame.
+fn open(state_tx: &Sender<StateEvent>, path: &Path) {
+    let open = Invocation::new("explorer.open_file").with_arg(path_arg(path));
+
+    if let Err(err) = state_tx.try_send(StateEvent::Command(open)) {
+        tracing::warn!("Can't open {}: {}", path.display(), err);
+    }
+}
+
+/// Asks the state loop to tell the explorers when entries of `dir` change.
+fn watch(state_tx: &Sender<StateEvent>, dir: &Path) {
+    let watch = Invocation::new("explorer.watch_folder").with_arg(path_arg(dir));
+
+    if let Err(err) = state_tx.try_send(StateEvent::Command(watch)) {
+        tracing::warn!("Can't watch {}: {}", dir.display(), err);
+    }
+}
+
+/// Asks the state loop to stop watching `dir`, unless another explorer lists it.
+fn unwatch(state_tx: &Sender<StateEvent>, dir: &Path) {
+    let unwatch = Invocation::new("explorer.unwatch_folder").with_arg(path_arg(dir));
+
+    if let Err(err) = state_tx.try_send(StateEvent::Command(unwatch)) {
+        tracing::warn!("Can't stop watching {}: {}", dir.display(), err);
+    }
+}
+
+fn path_arg(path: &Path) -> CommandArg {
+    CommandArg::Text(path.to_string_lossy().into_owned())
+}
+
+/// `path` relative to `root`, as shown in prompts.
+fn relative(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    match relative.as_os_str().is_empty() {
+        true => ".".to_string(),
+        false => relative.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+    use tokio::sync::mpsc;
+
+    fn operation(root: &Path) -> Operation {
+        let (state_tx, _) = mpsc::channel(16);
+
+        Operation {
+            root: root.to_path_buf(),
+            tree: Arc::new(Mutex::new(Tree::new(root.to_path_buf()))),
+            state_tx,
+            report: Arc::default(),
+        }
+    }
+
+    fn create(dir: &Path, input: &str) -> Prompt {
+        Prompt::Create {
+            dir: dir.to_path_buf(),
+            folder: true,
+            input: input.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_entries_under_the_root_only() {
+        let dir = paths::scratch_dir("explorer-create");
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        let operation = operation(&root);
+
+        operation.carry_out(create(&root, "a/b")).await.unwrap();
+        assert!(root.join("a/b").is_dir());
+
+        let outside = dir.join("outside");
+        for input in ["../outside", "a/../../outside", outside.to_str().unwrap()] {
+            let Err(err) = operation.carry_out(create(&root, input)).await else {
+                panic!("created {}", input);
+            };
+            assert!(err.to_string().contains("isn't under"), "{}", err);
+        }
+        assert!(!outside.exists());
+    }
+
+    #[tokio::test]
+    async fn moves_entries_under_the_root_only() {
+        let dir = paths::scratch_dir("explorer-move");
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("file"), "").unwrap();
+        let operation = operation(&root);
+        let to = |input: &str| Prompt::Move {
+            path: root.join("file"),
+            input: input.to_string(),
+        };
+
+        for input in ["..", "sub/../..", dir.to_str().unwrap()] {
+            assert!(operation.carry_out(to(input)).await.is_err());
+        }
+        assert!(!dir.join("file").exists());
+
+        operation.carry_out(to("sub")).await.unwrap();
+        assert!(root.join("sub/file").exists());
+    }
+}
@@ -0,0 +1,151 @@
+use ignore::WalkBuilder;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// A visible line of the tree.
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub entry: Entry,
+    pub depth: usize,
+    pub expanded: bool,
+}
+
+/// Directory listings, read as directories get expanded.
+#[derive(Debug, Default)]
+pub struct Tree {
+    pub root: PathBuf,
+    listings: HashMap<PathBuf, Vec<Entry>>,
+    expanded: HashSet<PathBuf>,
+}
+
+pub type SharedTree = Arc<Mutex<Tree>>;
+
+impl Tree {
+    pub fn new(root: PathBuf) -> Self {
+        let mut tree = Self {
+            root: root.clone(),
+            ..Self::default()
+        };
+
+        tree.expand(&root);
+        tree
+    }
+
+    pub fn is_expanded(&self, dir: &Path) -> bool {
+        self.expanded.contains(dir)
+    }
+
+    /// Shows the contents of `dir`, read again in case it changed while collapsed.
+    pub fn expand(&mut self, dir: &Path) {
+        self.reload(dir);
+        self.expanded.insert(dir.to_path_buf());
+    }
+
+    /// Hides the contents of `dir` and of the folders expanded in it. Returns the folders
+    /// collapsed.
+    pub fn collapse(&mut self, dir: &Path) -> Vec<PathBuf> {
+        if dir == self.root {
+            return Vec::new();
+        }
+
+        let collapsed: Vec<_> = self
+            .expanded
+            .iter()
+            .filter(|expanded| expanded.starts_with(dir))
+            .cloned()
+            .collect();
+        for dir in &collapsed {
+            self.expanded.remove(dir);
+        }
+
+        collapsed
+    }
+
+    /// Folders showing their contents, the root included.
+    pub fn expanded(&self) -> Vec<PathBuf> {
+        self.expanded.iter().cloned().collect()
+    }
+
+    /// Reads `dir` again, dropping it if it's gone. Returns whether its listing changed.
+    pub fn reload(&mut self, dir: &Path) -> bool {
+        match read_dir(dir) {
+            Ok(entries) => self.update(dir, entries),
+            Err(err) => {
+                tracing::debug!("Can't list {}: {}", dir.display(), err);
+                self.expanded.remove(dir);
+                self.listings.remove(dir).is_some()
+            }
+        }
+    }
+
+    fn update(&mut self, dir: &Path, entries: Vec<Entry>) -> bool {
+        if self.listings.get(dir) == Some(&entries) {
+            return false;
+        }
+
+        self.listings.insert(dir.to_path_buf(), entries);
+        true
+    }
+
+    /// Entries of the expanded directories, depth first.
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        self.push_rows(&self.root, 0, &mut rows);
+        rows
+    }
+
+    fn push_rows(&self, dir: &Path, depth: usize, rows: &mut Vec<Row>) {
+        for entry in self.listings.get(dir).into_iter().flatten() {
+            let expanded = entry.is_dir && self.expanded.contains(&entry.path);
+
+            rows.push(Row {
+                entry: entry.clone(),
+                depth,
+                expanded,
+            });
+
+            if expanded {
+                self.push_rows(&entry.path, depth + 1, rows);
+            }
+        }
+    }
+}
+
+/// Entries of `dir` that aren't ignored by `.gitignore` files, folders first.
+pub fn read_dir(dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} isn't a folder", dir.display());
+    }
+
+    let walk = WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut entries = Vec::new();
+    // Entries that can't be read are left out rather than hiding the whole folder
+    for entry in walk.flatten() {
+        if entry.depth() == 0 {
+            continue;
+        }
+
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: entry.file_type().is_some_and(|kind| kind.is_dir()),
+            path: entry.into_path(),
+        });
+    }
+
+    entries.sort_by_cached_key(|entry| (!entry.is_dir, entry.name.to_lowercase()));
+    Ok(entries)
+}
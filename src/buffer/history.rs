@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+
+/// Steps kept to undo, the oldest dropped past it
+const MAX_STEPS: usize = 1000;
+
+/// Undo history of a text, kept as the edits made to it.
+///
+/// Each step undoes in one go: the edits of a group, or a run of typing. Callers record
+/// every edit as it's made, with the cursor from before it.
+pub struct History {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+    group_depth: usize,
+    group_recorded: bool,
+    /// The last step is a run of typing that the next insertion can extend
+    typing: bool,
+}
+
+/// `removed` at `start` replaced by `inserted`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+/// Edits undone or redone together, with where the cursor goes then.
+struct Step {
+    edits: Vec<Edit>,
+    cursor: usize,
+}
+
+impl Edit {
+    /// The edit that takes the text back to before this one.
+    fn inverse(&self) -> Self {
+        Self {
+            start: self.start,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+
+    /// Whether this is a typed insertion that can join a run of typing ending with
+    /// `previous`: right after it, on the same line and the same word.
+    fn continues(&self, previous: &Edit) -> bool {
+        let starts_word = previous.inserted.ends_with(char::is_whitespace)
+            && !self.inserted.starts_with(char::is_whitespace);
+
+        self.removed.is_empty()
+            && !self.inserted.contains('\n')
+            && self.start == previous.start + previous.inserted.len()
+            && !starts_word
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            group_depth: 0,
+            group_recorded: false,
+            typing: false,
+        }
+    }
+
+    /// Records `edit`, made with the cursor at `cursor`.
+    pub fn record(&mut self, edit: Edit, cursor: usize) {
+        self.redo.clear();
+
+        if self.group_depth > 0 && self.group_recorded {
+            let step = self.undo.back_mut().expect("group step recorded");
+            step.edits.push(edit);
+            return;
+        }
+
+        if self.group_depth == 0 && self.typing {
+            let step = self.undo.back_mut().expect("typing step recorded");
+            let last = step.edits.last_mut().expect("steps have edits");
+            if edit.continues(last) {
+                last.inserted.push_str(&edit.inserted);
+                return;
+            }
+        }
+
+        self.group_recorded = self.group_depth > 0;
+        self.typing = self.group_depth == 0 && edit.removed.is_empty();
+        self.typing &= !edit.inserted.contains('\n');
+        if self.undo.len() == MAX_STEPS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(Step {
+            edits: vec![edit],
+            cursor,
+        });
+    }
+
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group_recorded = false;
+            self.typing = false;
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+    }
+
+    /// Edits that undo the last step, in the order to apply them, and the cursor from
+    /// before it. `cursor` is where redoing the step puts the cursor back.
+    pub fn undo(&mut self, cursor: usize) -> Option<(Vec<Edit>, usize)> {
+        let step = self.undo.pop_back()?;
+        let edits = step.edits.iter().rev().map(Edit::inverse).collect();
+
+        self.typing = false;
+        self.redo.push(Step {
+            edits: step.edits,
+            cursor,
+        });
+        Some((edits, step.cursor))
+    }
+
+    /// Edits that redo the last step undone, and the cursor from after it.
+    pub fn redo(&mut self, cursor: usize) -> Option<(Vec<Edit>, usize)> {
+        let step = self.redo.pop()?;
+        let edits = step.edits.clone();
+
+        self.typing = false;
+        self.undo.push_back(Step {
+            edits: step.edits,
+            cursor,
+        });
+        Some((edits, step.cursor))
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(start: usize, text: &str) -> Edit {
+        Edit {
+            start,
+            removed: String::new(),
+            inserted: text.to_string(),
+        }
+    }
+
+    /// Applies `edit` to `text`, recording it.
+    fn make(history: &mut History, text: &mut String, edit: Edit) {
+        let range = edit.start..edit.start + edit.removed.len();
+        assert_eq!(&text[range.clone()], edit.removed);
+
+        text.replace_range(range, &edit.inserted);
+        history.record(edit, 0);
+    }
+
+    fn apply(text: &mut String, (edits, _): (Vec<Edit>, usize)) {
+        for edit in edits {
+            let range = edit.start..edit.start + edit.removed.len();
+            assert_eq!(&text[range.clone()], edit.removed);
+            text.replace_range(range, &edit.inserted);
+        }
+    }
+
+    #[test]
+    fn typing_undoes_a_word_at_a_time() {
+        let mut history = History::new();
+        let mut text = String::new();
+
+        for (i, c) in "ab cd".char_indices() {
+            make(&mut history, &mut text, insert(i, &c.to_string()));
+        }
+        assert_eq!(history.undo.len(), 2);
+
+        apply(&mut text, history.undo(5).unwrap());
+        assert_eq!(text, "ab ");
+        apply(&mut text, history.undo(3).unwrap());
+        assert_eq!(text, "");
+        assert!(history.undo(0).is_none());
+
+        apply(&mut text, history.redo(0).unwrap());
+        apply(&mut text, history.redo(3).unwrap());
+        assert_eq!(text, "ab cd");
+    }
+
+    #[test]
+    fn line_breaks_and_deletions_end_runs_of_typing() {
+        let mut history = History::new();
+        let mut text = String::new();
+
+        make(&mut history, &mut text, insert(0, "a"));
+        make(&mut history, &mut text, insert(1, "\n"));
+        make(&mut history, &mut text, insert(2, "b"));
+        let delete = Edit {
+            start: 2,
+            removed: "b".to_string(),
+            inserted: String::new(),
+        };
+        make(&mut history, &mut text, delete);
+        make(&mut history, &mut text, insert(2, "c"));
+        assert_eq!(history.undo.len(), 5);
+
+        // Typing elsewhere starts a run of its own
+        make(&mut history, &mut text, insert(0, "d"));
+        assert_eq!(text, "da\nc");
+        assert_eq!(history.undo.len(), 6);
+    }
+
+    #[test]
+    fn groups_undo_in_one_step() {
+        let mut history = History::new();
+        let mut text = "ab".to_string();
+
+        history.begin_group();
+        make(&mut history, &mut text, insert(2, "\n"));
+        history.begin_group();
+        make(&mut history, &mut text, insert(0, "\n"));
+        history.end_group();
+        history.end_group();
+        make(&mut history, &mut text, insert(4, "c"));
+
+        apply(&mut text, history.undo(0).unwrap());
+        assert_eq!(text, "\nab\n");
+        apply(&mut text, history.undo(0).unwrap());
+        assert_eq!(text, "ab");
+        assert!(history.undo(0).is_none());
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_cursor() {
+        let mut history = History::new();
+
+        history.record(insert(0, "\n"), 0);
+        assert_eq!(history.undo(1).unwrap().1, 0);
+        assert_eq!(history.redo(0).unwrap().1, 1);
+    }
+
+    #[test]
+    fn new_edits_drop_what_was_undone() {
+        let mut history = History::new();
+
+        history.record(insert(0, "a"), 0);
+        history.undo(1);
+        history.record(insert(0, "b"), 0);
+        assert!(history.redo(1).is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_steps() {
+        let mut history = History::new();
+
+        for i in 0..MAX_STEPS + 10 {
+            history.record(insert(i, "\n"), i);
+        }
+
+        assert_eq!(history.undo.len(), MAX_STEPS);
+        assert_eq!(history.undo.front().unwrap().cursor, 10);
+    }
+}
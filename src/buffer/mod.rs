@@ -1,10 +1,22 @@
-use crate::WindowData;
-use wgpu::util::StagingBelt;
-use wgpu::{Adapter, CommandEncoder, Device, TextureView};
+use crate::buffer::diff::{Pane, SharedDiff};
+use crate::git::hunks::Hunk;
+use crate::git::GitFile;
+use crate::indent::IndentStyle;
+use crate::scene::Scene;
+use crate::tasks::messages::Problem;
+use crate::theme::Theme;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub mod dummy_buffer;
+pub mod diff;
+pub mod explorer;
+pub mod history;
+pub mod output;
+pub mod terminal;
+pub mod text_buffer;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct BoundingBox {
     pub left: f32,
     pub top: f32,
@@ -12,26 +24,251 @@ pub struct BoundingBox {
     pub height: f32,
 }
 
+/// Window wide parameters every buffer draws with.
+pub struct RenderContext<'a> {
+    pub theme: &'a Theme,
+    /// Physical pixels per logical pixel of the window's monitor.
+    pub scale_factor: f32,
+    /// Global font zoom, on top of each buffer's own zoom.
+    pub zoom: f32,
+    pub view: ViewOptions,
+    /// Font size of all text, whatever the size and zoom of buffers, on surfaces of fixed
+    /// cells such as terminals.
+    pub fixed_scale: Option<f32>,
+}
+
+impl RenderContext<'_> {
+    /// Size in pixels of text of font size `size`, zoomed and scaled for the display.
+    pub fn font_scale(&self, size: f32) -> f32 {
+        self.fixed_scale
+            .unwrap_or(size * self.zoom * self.scale_factor)
+    }
+}
+
+/// Optional decorations drawn over the text.
+#[derive(Copy, Clone, Debug)]
+pub struct ViewOptions {
+    /// Dots for spaces, arrows for tabs and a highlight behind trailing whitespace
+    pub whitespace: bool,
+    /// Vertical lines at each indentation level, brighter for the scope of the cursor
+    pub indent_guides: bool,
+    /// Author and summary of the commit that last changed the cursor line, after it
+    pub blame: bool,
+    /// Diffs in one column rather than side by side
+    pub inline_diff: bool,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        Self {
+            whitespace: false,
+            indent_guides: true,
+            blame: true,
+            inline_diff: false,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum EventHandlerOutcome {
     Redraw,
     None,
 }
 
 pub enum BufferEvent {
-    Input(char),
+    Insert(String),
+    /// Typed character, which may trigger auto-indentation
+    InsertChar(char),
+    Newline,
+    Indent,
+    Outdent,
+    /// Move the cursor to the bracket matching the one at the caret
+    JumpToMatch,
+    /// Fold or unfold the region at the cursor
+    ToggleFold,
+    FoldAll,
+    UnfoldAll,
+    /// Comment out the selected lines, or uncomment them if they all are
+    ToggleLineComment,
+    /// Wrap the selection in a block comment, or unwrap it
+    ToggleBlockComment,
+    /// Swap the selected lines with the line above, or below
+    MoveLines {
+        down: bool,
+    },
+    DuplicateLines,
+    JoinLines,
+    DeleteLines,
+    SortLines,
+    /// Swap the graphemes around the cursor
+    Transpose,
+    DeleteBackward,
+    DeleteForward,
+    MoveCursor {
+        motion: Motion,
+        /// Extend the selection instead of dropping it
+        select: bool,
+    },
+    /// Add a cursor above the first one, or below the last one
+    AddCursor {
+        below: bool,
+    },
+    /// Drop all cursors but the primary one
+    SingleCursor,
+    /// Move the cursor to the next or previous block of lines changed since staging
+    JumpToHunk {
+        forward: bool,
+    },
+    /// Put back the staged lines of the changed block at the cursor
+    RevertHunk,
+    /// Mouse click, in pixels from the top left corner of the buffer's frame
+    Click {
+        x: f32,
+        y: f32,
+    },
+    Undo,
+    Redo,
+    /// Ask for what a file operation needs in buffers that browse files
+    FileOperation(FileOperation),
+    /// Key without a character, by its keymap name such as `pageup`, for buffers that
+    /// take raw input
+    Key(String),
+    /// Show the page above or below, without moving the cursor
+    ScrollPage {
+        up: bool,
+    },
+    /// Move the cursor to a line and column, both from 0
+    GoTo {
+        line: usize,
+        column: usize,
+    },
+}
+
+/// Changes to files made from the explorer, confirmed before they happen.
+#[derive(Copy, Clone, Debug)]
+pub enum FileOperation {
+    NewFile,
+    NewFolder,
+    Rename,
+    Move,
+    Delete,
+}
+
+/// Cursor movements. Horizontal motions step over whole graphemes, so the cursor can sit
+/// between the letters of a ligature but never inside a combined character.
+#[derive(Copy, Clone, Debug)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
 }
 
 pub trait Buffer {
-    fn init_rendering(&mut self, window_data: &WindowData, device: &Device, adapter: &Adapter);
-    fn enqueue(&mut self, bb: BoundingBox);
-    fn draw_queued(
-        &mut self,
-        device: &Device,
-        staging_belt: &mut StagingBelt,
-        encoder: &mut CommandEncoder,
-        view: &TextureView,
-        target_width: u32,
-        target_height: u32,
-    );
+    /// Adds what the buffer shows in the frame `bb` to `scene`.
+    fn draw(&mut self, bb: BoundingBox, ctx: &RenderContext, scene: &mut Scene);
     fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome;
+
+    /// Edits made until the matching `end_undo_group` are undone as a single step.
+    fn begin_undo_group(&mut self) {}
+    fn end_undo_group(&mut self) {}
+
+    /// Font zoom of this buffer alone.
+    fn zoom(&self) -> f32 {
+        1.0
+    }
+    fn set_zoom(&mut self, _zoom: f32) {}
+
+    /// Indentation inserted by Tab, Enter and the indent commands.
+    fn indent_style(&self) -> Option<IndentStyle> {
+        None
+    }
+    fn set_indent_style(&mut self, _style: IndentStyle) {}
+
+    /// Editable text of the buffer, if it has any.
+    fn text(&self) -> Option<&str> {
+        None
+    }
+
+    /// File backing the buffer, if any.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+    fn set_path(&mut self, _path: PathBuf) {}
+
+    /// Line and column of the cursor, both from 0, for buffers that have one.
+    fn cursor_position(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Whether the text has changed since it was last loaded or saved.
+    fn is_dirty(&self) -> bool {
+        false
+    }
+    fn mark_saved(&mut self) {}
+    /// Marks the text as changed, as when its file is deleted, so it gets saved again.
+    fn mark_unsaved(&mut self) {}
+
+    /// Replaces the text with the file's new contents as one undoable edit, keeping the
+    /// cursors where they were in the text around the change.
+    fn reload(&mut self, _text: String) {}
+
+    /// Contents the file was changed to on disk while the buffer had unsaved changes,
+    /// until the user picks which to keep.
+    fn conflict(&self) -> Option<&str> {
+        None
+    }
+    fn set_conflict(&mut self, _text: Option<String>) {}
+
+    /// Replaces the text with changes recovered from a swap file, as unsaved changes in
+    /// conflict with the file.
+    fn recover(&mut self, _text: String) {}
+
+    /// What the repository knows about the file, once read.
+    fn git_file(&self) -> Option<Arc<GitFile>> {
+        None
+    }
+    fn set_git(&mut self, _file: Option<Arc<GitFile>>) {}
+
+    /// Block of lines changed since staging at the cursor.
+    fn hunk_at_cursor(&mut self) -> Option<Hunk> {
+        None
+    }
+
+    /// Diff shown by the buffer, and which part of it.
+    fn diff(&self) -> Option<(SharedDiff, Pane)> {
+        None
+    }
+
+    /// Reads the folder `dir` again where the buffer lists it, after its entries changed.
+    /// Returns whether what the buffer shows changed.
+    fn folder_changed(&mut self, _dir: &Path) -> bool {
+        false
+    }
+
+    /// Folders the buffer lists, whose changes the watcher reports to `folder_changed`.
+    fn watched_folders(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Marks the problems tasks found in the file, until the next run.
+    fn set_problems(&mut self, _problems: Vec<Arc<Problem>>) {}
+
+    /// Whether typed text and keys go to the buffer as they are, instead of through the
+    /// keymap, as for terminals.
+    fn takes_raw_input(&self) -> bool {
+        false
+    }
+
+    /// Whether the buffer is dropped once no frame shows it, as nothing could show it
+    /// again. Terminals go with their frame rather than keep their shell running.
+    fn closes_with_frame(&self) -> bool {
+        false
+    }
+
+    /// Stops what the buffer runs in the background, before Kami exits or the buffer is
+    /// dropped.
+    fn shutdown(&mut self) {}
 }
@@ -0,0 +1,142 @@
+use crate::tasks::messages::Problem;
+use std::sync::{Arc, Mutex};
+
+/// Log shared by the task writing it and the view showing it.
+pub type SharedLog = Arc<Mutex<Log>>;
+
+/// Line of output, and the problem it belongs to.
+pub struct Line {
+    pub text: String,
+    pub problem: Option<Arc<Problem>>,
+}
+
+/// Lines printed by a task, or problems found by one.
+pub struct Log {
+    /// What the lines come from, such as the task's command
+    pub title: String,
+    /// State of the task, such as `running` or `exit status: 1`
+    pub status: Option<String>,
+    lines: Vec<Line>,
+    /// Selected line, or `None` to follow the last line as more are added
+    pub cursor: Option<usize>,
+    /// The log was drawn since it last changed, so changes need a redraw
+    pub shown: bool,
+    /// Runs the log was cleared for, so a stopped run can't write to the next one's
+    run: usize,
+}
+
+impl Log {
+    pub fn new(title: String) -> Self {
+        Self {
+            title,
+            status: None,
+            lines: Vec::new(),
+            cursor: None,
+            shown: false,
+            run: 0,
+        }
+    }
+
+    pub fn run(&self) -> usize {
+        self.run
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Problems of the lines, once each.
+    pub fn problems(&self) -> impl Iterator<Item = &Arc<Problem>> {
+        let mut previous: Option<&Arc<Problem>> = None;
+
+        self.lines.iter().filter_map(move |line| {
+            let problem = line.problem.as_ref()?;
+            let repeated = previous.is_some_and(|previous| Arc::ptr_eq(previous, problem));
+            previous = Some(problem);
+
+            (!repeated).then_some(problem)
+        })
+    }
+
+    /// Adds the lines of `text`, all pointing at `problem`. Returns whether the log has
+    /// to be drawn again.
+    pub fn push(&mut self, text: &str, problem: Option<Arc<Problem>>) -> bool {
+        for line in text.split('\n') {
+            self.lines.push(Line {
+                text: printable(line),
+                problem: problem.clone(),
+            });
+        }
+
+        std::mem::take(&mut self.shown)
+    }
+
+    /// Starts over for another run.
+    pub fn clear(&mut self, title: String) {
+        *self = Self {
+            shown: self.shown,
+            run: self.run + 1,
+            ..Self::new(title)
+        };
+    }
+
+    /// Moves the cursor by `step` lines, staying within the lines.
+    pub fn move_cursor(&mut self, step: isize) -> bool {
+        let Some(last) = self.lines.len().checked_sub(1) else {
+            return false;
+        };
+        let current = self.cursor.unwrap_or(last);
+        let target = current.saturating_add_signed(step).min(last);
+
+        self.cursor.replace(target) != Some(target)
+    }
+
+    /// Moves the cursor to the first line of the next or previous problem, wrapping
+    /// around, and returns it.
+    pub fn jump_to_problem(&mut self, forward: bool) -> Option<Arc<Problem>> {
+        let problem_at = |index: usize| self.lines.get(index)?.problem.as_ref();
+        let starts = |index: &usize| {
+            let problem = problem_at(*index);
+            problem.is_some()
+                && match (problem, index.checked_sub(1).and_then(problem_at)) {
+                    (Some(problem), Some(previous)) => !Arc::ptr_eq(problem, previous),
+                    _ => true,
+                }
+        };
+
+        let count = self.lines.len();
+        let target = match (self.cursor, forward) {
+            (Some(cursor), true) => (cursor + 1..count).chain(0..=cursor).find(starts),
+            (None, true) => (0..count).find(starts),
+            (Some(cursor), false) => (0..cursor).rev().chain((cursor..count).rev()).find(starts),
+            (None, false) => (0..count).rev().find(starts),
+        }?;
+
+        self.cursor = Some(target);
+        self.lines[target].problem.clone()
+    }
+}
+
+/// `line` without escape sequences and with tabs expanded, as drawn.
+fn printable(line: &str) -> String {
+    let mut printable = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Colors and other control sequences end with a letter
+            '\x1b' => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            '\t' => printable.push_str("    "),
+            c if c.is_control() => {}
+            c => printable.push(c),
+        }
+    }
+
+    printable
+}
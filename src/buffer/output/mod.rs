@@ -0,0 +1,248 @@
+use crate::buffer::text_buffer::FontConfig;
+use crate::buffer::{BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, Motion, RenderContext};
+use crate::command::{CommandArg, Invocation};
+use crate::fonts::FontStyle;
+use crate::grid::GridMetrics;
+use crate::scene::{Label, Quad, Scene, Span};
+use crate::state::StateEvent;
+use crate::tasks::messages::{Problem, Severity};
+use crate::theme::Theme;
+use tokio::sync::mpsc::Sender;
+
+pub use log::{Log, SharedLog};
+
+mod log;
+
+/// Lines of a log below its title and status, scrolled to follow new lines until one is
+/// selected.
+///
+/// Lines that belong to a problem are marked with its severity, and Enter or a click opens
+/// its file at the problem in the frame focused before the log.
+pub struct OutputView {
+    log: SharedLog,
+    /// First line drawn
+    scroll: usize,
+    /// Lines that fit below the title, as last drawn
+    page: usize,
+    config: FontConfig,
+    state_tx: Sender<StateEvent>,
+    /// Row size the log was last drawn with, to map clicks to lines
+    metrics: Option<GridMetrics>,
+}
+
+impl OutputView {
+    pub fn new(config: FontConfig, log: SharedLog, state_tx: Sender<StateEvent>) -> Self {
+        Self {
+            log,
+            scroll: 0,
+            page: 1,
+            config,
+            state_tx,
+            metrics: None,
+        }
+    }
+
+    fn move_cursor(&mut self, motion: Motion) -> EventHandlerOutcome {
+        let mut log = self.log.lock().unwrap();
+        let moved = match motion {
+            Motion::Up => log.move_cursor(-1),
+            Motion::Down => log.move_cursor(1),
+            Motion::LineStart => log.move_cursor(isize::MIN),
+            Motion::LineEnd => log.move_cursor(isize::MAX),
+            Motion::Left | Motion::Right => false,
+        };
+
+        match moved {
+            true => EventHandlerOutcome::Redraw,
+            false => EventHandlerOutcome::None,
+        }
+    }
+
+    /// Pages up or down, going back to following the log past its last line.
+    fn scroll_page(&mut self, up: bool) -> EventHandlerOutcome {
+        let page = self.page as isize;
+        let mut log = self.log.lock().unwrap();
+        let last = log.lines().len().saturating_sub(1);
+        match up {
+            true => log.move_cursor(-page),
+            false
+                if log
+                    .cursor
+                    .is_some_and(|cursor| cursor + page as usize > last) =>
+            {
+                log.cursor = None;
+                true
+            }
+            false => log.move_cursor(page),
+        };
+
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Opens the file of the problem at the cursor.
+    fn activate(&mut self) -> EventHandlerOutcome {
+        let log = self.log.lock().unwrap();
+        let problem = log
+            .cursor
+            .and_then(|cursor| log.lines().get(cursor))
+            .and_then(|line| line.problem.clone());
+        drop(log);
+
+        if let Some(problem) = problem {
+            open(&problem, &self.state_tx);
+        }
+
+        EventHandlerOutcome::None
+    }
+
+    fn click(&mut self, y: f32) -> EventHandlerOutcome {
+        let Some(metrics) = self.metrics else {
+            return EventHandlerOutcome::None;
+        };
+
+        // The first row is the title
+        let Some(row) = ((y.max(0.0) / metrics.line_height) as usize).checked_sub(1) else {
+            return EventHandlerOutcome::None;
+        };
+
+        let index = self.scroll + row;
+        {
+            let mut log = self.log.lock().unwrap();
+            if index >= log.lines().len() {
+                return EventHandlerOutcome::None;
+            }
+            log.cursor = Some(index);
+        }
+
+        self.activate();
+        EventHandlerOutcome::Redraw
+    }
+}
+
+/// Asks the state loop to show the file of `problem` at its start.
+pub fn open(problem: &Problem, state_tx: &Sender<StateEvent>) {
+    let (line, column) = problem.start;
+    let open = Invocation::new("problems.open_location")
+        .with_arg(CommandArg::Text(
+            problem.path.to_string_lossy().into_owned(),
+        ))
+        .with_arg(CommandArg::Integer(line as i64 + 1))
+        .with_arg(CommandArg::Integer(column as i64 + 1));
+
+    if let Err(err) = state_tx.try_send(StateEvent::Command(open)) {
+        tracing::warn!("Can't open {}: {}", problem.path.display(), err);
+    }
+}
+
+/// Color problems of `severity` are marked with.
+pub fn severity_color(severity: Severity, theme: &Theme) -> [f32; 4] {
+    match severity {
+        Severity::Error => theme.error.0,
+        Severity::Warning => theme.warning.0,
+        Severity::Note => theme.note.0,
+    }
+}
+
+impl Buffer for OutputView {
+    fn draw(&mut self, bb: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        let scale = ctx.font_scale(self.config.scale);
+        let fonts = self.config.fonts.clone();
+        let metrics = GridMetrics::new(&fonts, scale);
+        self.metrics = Some(metrics);
+        let theme = ctx.theme;
+
+        let log = self.log.clone();
+        let mut log = log.lock().unwrap();
+        log.shown = true;
+
+        let visible_rows = ((bb.height / metrics.line_height) as usize)
+            .saturating_sub(1)
+            .max(1);
+        self.page = visible_rows;
+        let count = log.lines().len();
+
+        // Follow the last line, or keep the cursor in view
+        self.scroll = match log.cursor {
+            None => count.saturating_sub(visible_rows),
+            Some(cursor) => self
+                .scroll
+                .min(cursor)
+                .max((cursor + 1).saturating_sub(visible_rows)),
+        };
+
+        let row_top = |row: usize| bb.top + row as f32 * metrics.line_height;
+        let row_quad = |row: usize, left: f32, right: f32, color: [f32; 4]| Quad {
+            aabb: [
+                left,
+                row_top(row),
+                right,
+                row_top(row) + metrics.line_height,
+            ],
+            z_pos: 0.0,
+            color,
+        };
+        let marker_width = ctx.scale_factor.max(1.0) * 3.0;
+        let mut quads = vec![row_quad(0, bb.left, bb.left + bb.width, theme.gutter.0)];
+        let mut sections = Vec::new();
+
+        let title = match &log.status {
+            Some(status) => format!("{} · {}", log.title, status),
+            None => log.title.clone(),
+        };
+        sections.push((title, 0, theme.foreground.0));
+
+        let lines = log.lines().iter().enumerate();
+        for (i, line) in lines.skip(self.scroll).take(visible_rows) {
+            let row = i - self.scroll + 1;
+
+            if log.cursor == Some(i) {
+                quads.push(row_quad(
+                    row,
+                    bb.left,
+                    bb.left + bb.width,
+                    theme.selection.0,
+                ));
+            }
+            if let Some(problem) = &line.problem {
+                let color = severity_color(problem.severity, theme);
+                quads.push(row_quad(row, bb.left, bb.left + marker_width, color));
+            }
+
+            sections.push((line.text.clone(), row, theme.foreground.0));
+        }
+        drop(log);
+
+        // Row highlights go under the text
+        scene.backgrounds.extend(quads);
+
+        for (text, row, color) in sections {
+            scene.labels.push(
+                Label::new(
+                    (bb.left + metrics.cell_width, row_top(row)),
+                    (bb.width - metrics.cell_width, metrics.line_height),
+                )
+                .add_span(
+                    Span::new(text)
+                        .with_scale(scale)
+                        .with_color(color)
+                        .with_font_id(fonts.primary(FontStyle::Regular)),
+                ),
+            );
+        }
+    }
+
+    fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome {
+        match event {
+            BufferEvent::MoveCursor { motion, .. } => self.move_cursor(motion),
+            BufferEvent::ScrollPage { up } => self.scroll_page(up),
+            BufferEvent::Newline => self.activate(),
+            BufferEvent::Click { y, .. } => self.click(y),
+            // Escape goes back to following the log
+            BufferEvent::SingleCursor => match self.log.lock().unwrap().cursor.take() {
+                Some(_) => EventHandlerOutcome::Redraw,
+                None => EventHandlerOutcome::None,
+            },
+            _ => EventHandlerOutcome::None,
+        }
+    }
+}
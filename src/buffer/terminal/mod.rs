@@ -0,0 +1,509 @@
+use crate::app_state::AppState;
+use crate::buffer::terminal::screen::{Color, Screen, SPACER};
+use crate::buffer::text_buffer::FontConfig;
+use crate::buffer::{BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, Motion, RenderContext};
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult,
+};
+use crate::fonts::FontStyle;
+use crate::grid::GridMetrics;
+use crate::scene::{Glyph, Label, Quad, Scene, Span};
+use crate::state::StateEvent;
+use crate::theme::{Rgba, Theme};
+use ab_glyph::{point, Font};
+use anyhow::Context;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+
+mod screen;
+
+const READ_BUFFER_SIZE: usize = 4096;
+/// Size of the terminal until its frame is first drawn
+const INITIAL_SIZE: (usize, usize) = (24, 80);
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Shell running in a pseudo terminal, drawn from the cell grid its output builds.
+///
+/// Typed text and keys go to the shell rather than the keymap while the terminal is
+/// focused, except for chords that don't make a control character. The grid follows the
+/// size of the frame, and lines scrolled off the top are kept to page back through.
+pub struct Terminal {
+    screen: Arc<Mutex<Screen>>,
+    /// Locked only to be shareable, as buffers must be
+    master: Mutex<Box<dyn MasterPty + Send>>,
+    writer: SharedWriter,
+    child: Box<dyn Child + Send + Sync>,
+    /// Lines scrolled back into the scrollback, 0 when following the output
+    offset: usize,
+    /// Output arrived since the last draw, so a redraw was already asked for
+    redraw_pending: Arc<AtomicBool>,
+    /// The shell exited and closed the terminal
+    exited: Arc<AtomicBool>,
+    config: FontConfig,
+}
+
+impl Terminal {
+    /// Starts `command` in a shell, or an interactive shell, in `cwd`. Output is read on a
+    /// thread of its own until the shell exits.
+    pub fn spawn(
+        config: FontConfig,
+        cwd: PathBuf,
+        command: Option<&str>,
+        state_tx: Sender<StateEvent>,
+    ) -> anyhow::Result<Self> {
+        let (rows, cols) = INITIAL_SIZE;
+        let pair = native_pty_system()
+            .openpty(pty_size(rows, cols))
+            .context("Can't open a pseudo terminal")?;
+
+        let mut builder = CommandBuilder::new_default_prog();
+        if let Some(command) = command {
+            builder = CommandBuilder::new(builder.get_shell());
+            builder.args(["-c", command]);
+        }
+        builder.cwd(&cwd);
+        builder.env("TERM", "xterm-256color");
+
+        let child = pair
+            .slave
+            .spawn_command(builder)
+            .context("Can't start the shell")?;
+        let reader = pair.master.try_clone_reader()?;
+        let writer: SharedWriter = Arc::new(Mutex::new(pair.master.take_writer()?));
+
+        let screen = Arc::new(Mutex::new(Screen::new(rows, cols)));
+        let redraw_pending = Arc::new(AtomicBool::new(false));
+        let exited = Arc::new(AtomicBool::new(false));
+
+        let output = Output {
+            screen: screen.clone(),
+            writer: writer.clone(),
+            redraw_pending: redraw_pending.clone(),
+            exited: exited.clone(),
+            state_tx,
+        };
+        std::thread::Builder::new()
+            .name("terminal".to_string())
+            .spawn(move || output.read(reader))?;
+
+        Ok(Self {
+            screen,
+            master: Mutex::new(pair.master),
+            writer,
+            child,
+            offset: 0,
+            redraw_pending,
+            exited,
+            config,
+        })
+    }
+
+    /// Sends `input` to the shell, going back to following its output.
+    fn write(&mut self, input: &str) -> EventHandlerOutcome {
+        if self.exited.load(Ordering::Relaxed) {
+            return EventHandlerOutcome::None;
+        }
+
+        if let Err(err) = write_all(&self.writer, input.as_bytes()) {
+            tracing::warn!("Can't write to the terminal: {}", err);
+        }
+
+        match std::mem::take(&mut self.offset) {
+            0 => EventHandlerOutcome::None,
+            _ => EventHandlerOutcome::Redraw,
+        }
+    }
+
+    /// Sends the escape sequence of a key without a character, by its keymap name.
+    fn key(&mut self, name: &str) -> EventHandlerOutcome {
+        let app_cursor = self.screen.lock().unwrap().app_cursor;
+        let cursor = |code: &str| match app_cursor {
+            true => format!("\x1bO{}", code),
+            false => format!("\x1b[{}", code),
+        };
+
+        let sequence = match name {
+            "up" => cursor("A"),
+            "down" => cursor("B"),
+            "right" => cursor("C"),
+            "left" => cursor("D"),
+            "home" => cursor("H"),
+            "end" => cursor("F"),
+            "insert" => "\x1b[2~".to_string(),
+            "delete" => "\x1b[3~".to_string(),
+            "pageup" => "\x1b[5~".to_string(),
+            "pagedown" => "\x1b[6~".to_string(),
+            "f1" => "\x1bOP".to_string(),
+            "f2" => "\x1bOQ".to_string(),
+            "f3" => "\x1bOR".to_string(),
+            "f4" => "\x1bOS".to_string(),
+            "f5" => "\x1b[15~".to_string(),
+            "f6" => "\x1b[17~".to_string(),
+            "f7" => "\x1b[18~".to_string(),
+            "f8" => "\x1b[19~".to_string(),
+            "f9" => "\x1b[20~".to_string(),
+            "f10" => "\x1b[21~".to_string(),
+            "f11" => "\x1b[23~".to_string(),
+            "f12" => "\x1b[24~".to_string(),
+            _ => return EventHandlerOutcome::None,
+        };
+
+        self.write(&sequence)
+    }
+
+    fn move_cursor(&mut self, motion: Motion) -> EventHandlerOutcome {
+        let name = match motion {
+            Motion::Left => "left",
+            Motion::Right => "right",
+            Motion::Up => "up",
+            Motion::Down => "down",
+            Motion::LineStart => "home",
+            Motion::LineEnd => "end",
+        };
+
+        self.key(name)
+    }
+
+    /// Pages back through the scrollback, or forward to the live output.
+    fn scroll_page(&mut self, up: bool) -> EventHandlerOutcome {
+        let screen = self.screen.lock().unwrap();
+        let (rows, _) = screen.size();
+        let offset = match up {
+            true => (self.offset + rows).min(screen.scrollback_len()),
+            false => self.offset.saturating_sub(rows),
+        };
+        drop(screen);
+
+        match std::mem::replace(&mut self.offset, offset) == offset {
+            true => EventHandlerOutcome::None,
+            false => EventHandlerOutcome::Redraw,
+        }
+    }
+
+    /// Fits the grid and the pseudo terminal to `rows` and `cols`.
+    fn resize(&mut self, rows: usize, cols: usize) {
+        let mut screen = self.screen.lock().unwrap();
+        if screen.size() == (rows, cols) {
+            return;
+        }
+
+        screen.resize(rows, cols);
+        if self.exited.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = self.master.lock().unwrap().resize(pty_size(rows, cols)) {
+            tracing::warn!("Can't resize the terminal: {:#}", err);
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn pty_size(rows: usize, cols: usize) -> PtySize {
+    PtySize {
+        rows: rows.min(u16::MAX as usize) as u16,
+        cols: cols.min(u16::MAX as usize) as u16,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+fn write_all(writer: &SharedWriter, bytes: &[u8]) -> std::io::Result<()> {
+    let mut writer = writer.lock().unwrap();
+
+    writer.write_all(bytes)?;
+    writer.flush()
+}
+
+/// What the reading thread needs to feed the shell's output into the screen.
+struct Output {
+    screen: Arc<Mutex<Screen>>,
+    writer: SharedWriter,
+    redraw_pending: Arc<AtomicBool>,
+    exited: Arc<AtomicBool>,
+    state_tx: Sender<StateEvent>,
+}
+
+impl Output {
+    fn read(self, mut reader: Box<dyn Read + Send>) {
+        let mut parser = vte::Parser::new();
+        let mut buf = [0; READ_BUFFER_SIZE];
+
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+
+            let replies = {
+                let mut screen = self.screen.lock().unwrap();
+                parser.advance(&mut *screen, &buf[..read]);
+                std::mem::take(&mut screen.replies)
+            };
+            if !replies.is_empty() {
+                let _ = write_all(&self.writer, &replies);
+            }
+
+            // A single redraw covers all output until the terminal is drawn again
+            if !self.redraw_pending.swap(true, Ordering::Relaxed)
+                && self.state_tx.blocking_send(StateEvent::Redraw).is_err()
+            {
+                return;
+            }
+        }
+
+        self.exited.store(true, Ordering::Relaxed);
+        let _ = self.state_tx.blocking_send(StateEvent::Redraw);
+    }
+}
+
+/// Color of `color` as drawn with `theme`.
+fn rgba(color: Color, theme: &Theme, default: Rgba) -> [f32; 4] {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let rgb = |r: u8, g: u8, b: u8| [r, g, b].map(|c| c as f32 / 255.0);
+
+    let [r, g, b] = match color {
+        Color::Default => return default.0,
+        Color::Indexed(i) if i < 16 => {
+            return theme
+                .terminal_colors
+                .get(i as usize)
+                .map_or(default.0, |color| color.0)
+        }
+        // 6×6×6 color cube, then a gray ramp
+        Color::Indexed(i) if i < 232 => {
+            let i = (i - 16) as usize;
+            rgb(
+                CUBE_LEVELS[i / 36],
+                CUBE_LEVELS[i / 6 % 6],
+                CUBE_LEVELS[i % 6],
+            )
+        }
+        Color::Indexed(i) => {
+            let level = 8 + (i - 232) * 10;
+            rgb(level, level, level)
+        }
+        Color::Rgb(r, g, b) => rgb(r, g, b),
+    };
+
+    [r, g, b, 1.0]
+}
+
+impl Buffer for Terminal {
+    fn draw(&mut self, bb: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        self.redraw_pending.store(false, Ordering::Relaxed);
+
+        let scale = ctx.font_scale(self.config.scale);
+        let fonts = self.config.fonts.clone();
+        let metrics = GridMetrics::new(&fonts, scale);
+        let theme = ctx.theme;
+
+        let fit = ((bb.height / metrics.line_height) as usize).max(1);
+        let cols = ((bb.width / metrics.cell_width) as usize).max(1);
+
+        // Once the shell is gone, the last row says so and the output moves up to make room
+        let exited = self.exited.load(Ordering::Relaxed);
+        let rows = match exited {
+            true => fit.saturating_sub(1).max(1),
+            false => fit,
+        };
+        self.resize(rows, cols);
+
+        let status = match (exited, self.offset) {
+            (true, _) => Some("The shell exited".to_string()),
+            (false, 0) => None,
+            (false, offset) => Some(format!("{} lines back", offset)),
+        };
+        let shown_rows = match self.offset {
+            0 => rows,
+            _ => fit - 1,
+        };
+
+        let cell_rect = |row: usize, col: usize, width: usize| {
+            let left = bb.left + col as f32 * metrics.cell_width;
+            let top = bb.top + row as f32 * metrics.line_height;
+            [
+                left,
+                top,
+                (left + width as f32 * metrics.cell_width).min(bb.left + bb.width),
+                top + metrics.line_height,
+            ]
+        };
+
+        let mut quads = Vec::new();
+        let mut glyphs = Vec::new();
+
+        let screen = self.screen.lock().unwrap();
+        for (row, line) in screen.visible(self.offset).take(shown_rows).enumerate() {
+            for (col, cell) in line.iter().enumerate().take(cols) {
+                let style = cell.style;
+                let (mut fg, mut bg) = (
+                    rgba(style.fg, theme, theme.foreground),
+                    rgba(style.bg, theme, theme.background),
+                );
+                if style.inverse {
+                    std::mem::swap(&mut fg, &mut bg);
+                }
+
+                let width = match line.get(col + 1) {
+                    Some(next) if next.c == SPACER => 2,
+                    _ => 1,
+                };
+                if style.bg != Color::Default || style.inverse {
+                    quads.push(Quad {
+                        aabb: cell_rect(row, col, width),
+                        z_pos: 0.0,
+                        color: bg,
+                    });
+                }
+                if style.underline {
+                    let [left, _, right, bottom] = cell_rect(row, col, width);
+                    quads.push(Quad {
+                        aabb: [left, bottom - ctx.scale_factor.max(1.0), right, bottom],
+                        z_pos: 0.0,
+                        color: fg,
+                    });
+                }
+
+                if cell.c == ' ' || cell.c == SPACER {
+                    continue;
+                }
+
+                let font_style = match (style.bold, style.italic) {
+                    (false, false) => FontStyle::Regular,
+                    (true, false) => FontStyle::Bold,
+                    (false, true) => FontStyle::Italic,
+                    (true, true) => FontStyle::BoldItalic,
+                };
+                let font_id = fonts.resolve(cell.c, font_style);
+                let glyph_id = fonts.font(font_id).glyph_id(cell.c);
+                let [left, top, ..] = cell_rect(row, col, width);
+
+                glyphs.push(Glyph {
+                    glyph: glyph_id
+                        .with_scale_and_position(scale, point(left, top + metrics.ascent)),
+                    font_id,
+                    color: fg,
+                    text: cell.c.to_string(),
+                });
+            }
+        }
+
+        if screen.cursor_visible && self.offset == 0 && !exited {
+            let (row, col) = screen.cursor();
+            let [left, top, ..] = cell_rect(row, col, 1);
+            scene.cursor = Some((left, top));
+            quads.push(Quad {
+                aabb: [left, top, left + scale * 0.1, top + metrics.line_height],
+                z_pos: 0.0,
+                color: theme.cursor.0,
+            });
+        }
+        drop(screen);
+
+        if let Some(status) = status {
+            let row = fit - 1;
+            quads.push(Quad {
+                aabb: cell_rect(row, 0, cols),
+                z_pos: 0.0,
+                color: theme.gutter.0,
+            });
+            let [left, top, ..] = cell_rect(row, 0, cols);
+            scene.labels.push(
+                Label::new((left, top), (bb.width, metrics.line_height)).add_span(
+                    Span::new(status)
+                        .with_scale(scale)
+                        .with_color(theme.foreground.0)
+                        .with_font_id(fonts.primary(FontStyle::Regular)),
+                ),
+            );
+        }
+
+        scene.backgrounds.extend(quads);
+        scene.glyphs.extend(glyphs);
+    }
+
+    fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome {
+        match event {
+            BufferEvent::Insert(text) => self.write(&text),
+            BufferEvent::InsertChar(c) => self.write(c.encode_utf8(&mut [0; 4])),
+            BufferEvent::Newline => self.write("\r"),
+            BufferEvent::Indent => self.write("\t"),
+            BufferEvent::Outdent => self.write("\x1b[Z"),
+            BufferEvent::DeleteBackward => self.write("\x7f"),
+            BufferEvent::DeleteForward => self.key("delete"),
+            BufferEvent::MoveCursor { motion, .. } => self.move_cursor(motion),
+            BufferEvent::Key(name) => self.key(&name),
+            BufferEvent::ScrollPage { up } => self.scroll_page(up),
+            _ => EventHandlerOutcome::None,
+        }
+    }
+
+    fn takes_raw_input(&self) -> bool {
+        true
+    }
+
+    fn closes_with_frame(&self) -> bool {
+        true
+    }
+
+    fn shutdown(&mut self) {
+        if self.exited.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // Hangs up on the shell, as closing its terminal would
+        if let Err(err) = self.child.kill() {
+            tracing::warn!("Can't stop the shell: {}", err);
+        }
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "terminal.open",
+            "Open a terminal running a shell, or a command in the shell, in the active frame",
+            open,
+        )
+        .with_arg(ArgSpec::optional("command", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "terminal.key",
+            "Send a key without a character, such as `up` or `f5`, to the active terminal",
+            |state, args| {
+                Box::pin(async move {
+                    let name = args[0].as_text().expect("checked by registry").to_string();
+
+                    state.send_to_active_buffer(BufferEvent::Key(name)).await
+                })
+            },
+        )
+        .with_arg(ArgSpec::required("key", ArgKind::Text)),
+    );
+}
+
+fn open(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let command = args.first().and_then(CommandArg::as_text);
+        let cwd = std::env::current_dir().context("Can't read the working directory")?;
+
+        let terminal = Terminal::spawn(state.font.clone(), cwd, command, state.state_tx.clone())?;
+        state
+            .buffers
+            .push(Arc::new(tokio::sync::Mutex::new(terminal)));
+        state.show_buffer(state.buffers.len() - 1);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
@@ -0,0 +1,720 @@
+use std::collections::VecDeque;
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, ParamsIter, Perform};
+
+/// Lines kept after they scroll off the top of the primary screen
+const SCROLLBACK_LINES: usize = 10_000;
+const TAB_STOP: usize = 8;
+/// Second half of a double width character
+pub const SPACER: char = '\0';
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Color {
+    #[default]
+    Default,
+    /// One of the 256 xterm colors, the first 16 of which come from the theme
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    /// Foreground and background swapped
+    pub inverse: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub style: Style,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct SavedCursor {
+    row: usize,
+    col: usize,
+    style: Style,
+}
+
+/// Cell grid of a terminal, updated by the escape sequences programs write.
+///
+/// Covers what shells, cargo and common full screen programs use of VT100 and xterm:
+/// cursor motion, erasing, scroll regions, SGR colors and the alternate screen.
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    lines: Vec<Vec<Cell>>,
+    scrollback: VecDeque<Vec<Cell>>,
+    /// Lines of the primary screen while full screen programs draw on the alternate one
+    primary: Option<Vec<Vec<Cell>>>,
+    row: usize,
+    col: usize,
+    /// A character was printed in the last column, so the next one goes on the next line
+    wrap_pending: bool,
+    style: Style,
+    saved: Option<SavedCursor>,
+    /// Rows that scroll, from `top` to just before `bottom`
+    top: usize,
+    bottom: usize,
+    autowrap: bool,
+    /// Arrow keys are sent as application sequences (`ESC O A` rather than `ESC [ A`)
+    pub app_cursor: bool,
+    pub cursor_visible: bool,
+    /// Answers to queries such as the cursor position, to write back to the program
+    pub replies: Vec<u8>,
+}
+
+impl Cell {
+    fn blank(style: Style) -> Self {
+        // Erased cells keep the background color, as xterm does
+        Self {
+            c: ' ',
+            style: Style {
+                bg: style.bg,
+                ..Style::default()
+            },
+        }
+    }
+}
+
+impl Screen {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+
+        Self {
+            rows,
+            cols,
+            lines: vec![vec![Cell::blank(Style::default()); cols]; rows],
+            scrollback: VecDeque::new(),
+            primary: None,
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            style: Style::default(),
+            saved: None,
+            top: 0,
+            bottom: rows,
+            autowrap: true,
+            app_cursor: false,
+            cursor_visible: true,
+            replies: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Rows on screen when scrolled `offset` lines back into the scrollback.
+    pub fn visible(&self, offset: usize) -> impl Iterator<Item = &[Cell]> {
+        let start = self.scrollback.len() - offset.min(self.scrollback.len());
+
+        self.scrollback
+            .iter()
+            .chain(&self.lines)
+            .skip(start)
+            .take(self.rows)
+            .map(Vec::as_slice)
+    }
+
+    /// Fits the screen to `rows` and `cols`. Lines above the cursor that no longer fit go
+    /// to the scrollback.
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+        if (rows, cols) == (self.rows, self.cols) {
+            return;
+        }
+
+        let blank = Cell::blank(Style::default());
+        for line in self
+            .lines
+            .iter_mut()
+            .chain(self.primary.iter_mut().flatten())
+        {
+            line.resize(cols, blank);
+        }
+
+        while self.lines.len() > rows {
+            if self.row + 1 < self.lines.len() {
+                self.lines.pop();
+            } else {
+                let line = self.lines.remove(0);
+                self.push_scrollback(line);
+                self.row -= 1;
+            }
+        }
+        self.lines.resize(rows, vec![blank; cols]);
+
+        if let Some(primary) = &mut self.primary {
+            primary.truncate(rows);
+            primary.resize(rows, vec![blank; cols]);
+        }
+
+        self.rows = rows;
+        self.cols = cols;
+        self.top = 0;
+        self.bottom = rows;
+        self.row = self.row.min(rows - 1);
+        self.col = self.col.min(cols - 1);
+        self.wrap_pending = false;
+    }
+
+    fn push_scrollback(&mut self, line: Vec<Cell>) {
+        if self.scrollback.len() == SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        }
+
+        self.scrollback.push_back(line);
+    }
+
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![Cell::blank(self.style); self.cols]
+    }
+
+    /// Scrolls the scroll region up by `count` lines. Lines leaving the top of the primary
+    /// screen go to the scrollback.
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count.min(self.bottom - self.top) {
+            let line = self.lines.remove(self.top);
+            if self.top == 0 && self.primary.is_none() {
+                self.push_scrollback(line);
+            }
+
+            let blank = self.blank_line();
+            self.lines.insert(self.bottom - 1, blank);
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count.min(self.bottom - self.top) {
+            self.lines.remove(self.bottom - 1);
+
+            let blank = self.blank_line();
+            self.lines.insert(self.top, blank);
+        }
+    }
+
+    fn linefeed(&mut self) {
+        if self.row + 1 == self.bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    /// Blanks columns `columns` of line `row`.
+    fn erase(&mut self, row: usize, columns: std::ops::Range<usize>) {
+        let blank = Cell::blank(self.style);
+        let end = columns.end.min(self.cols);
+
+        self.lines[row][columns.start.min(end)..end].fill(blank);
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        match mode {
+            0 => {
+                self.erase(self.row, self.col..self.cols);
+                for row in self.row + 1..self.rows {
+                    self.erase(row, 0..self.cols);
+                }
+            }
+            1 => {
+                for row in 0..self.row {
+                    self.erase(row, 0..self.cols);
+                }
+                self.erase(self.row, 0..self.col + 1);
+            }
+            2 => {
+                for row in 0..self.rows {
+                    self.erase(row, 0..self.cols);
+                }
+            }
+            3 => self.scrollback.clear(),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        match mode {
+            0 => self.erase(self.row, self.col..self.cols),
+            1 => self.erase(self.row, 0..self.col + 1),
+            2 => self.erase(self.row, 0..self.cols),
+            _ => {}
+        }
+    }
+
+    /// Inserts `count` blank lines at the cursor, or deletes `count` lines there when
+    /// `insert` is false, within the scroll region.
+    fn shift_lines(&mut self, count: usize, insert: bool) {
+        if !(self.top..self.bottom).contains(&self.row) {
+            return;
+        }
+
+        let top = self.top;
+        self.top = self.row;
+        match insert {
+            true => self.scroll_down(count),
+            false => {
+                // Lines deleted from the middle of the screen don't go to the scrollback
+                for _ in 0..count.min(self.bottom - self.top) {
+                    self.lines.remove(self.top);
+                    let blank = self.blank_line();
+                    self.lines.insert(self.bottom - 1, blank);
+                }
+            }
+        }
+        self.top = top;
+        self.col = 0;
+    }
+
+    /// Inserts `count` blank cells at the cursor, or deletes `count` cells there when
+    /// `insert` is false, shifting the rest of the line.
+    fn shift_cells(&mut self, count: usize, insert: bool) {
+        let count = count.min(self.cols - self.col);
+        let blank = Cell::blank(self.style);
+        let line = &mut self.lines[self.row];
+
+        match insert {
+            true => {
+                line.splice(self.col..self.col, std::iter::repeat_n(blank, count));
+                line.truncate(self.cols);
+            }
+            false => {
+                line.drain(self.col..self.col + count);
+                line.resize(self.cols, blank);
+            }
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Some(SavedCursor {
+            row: self.row,
+            col: self.col,
+            style: self.style,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved.unwrap_or(SavedCursor {
+            row: 0,
+            col: 0,
+            style: Style::default(),
+        });
+
+        self.row = saved.row.min(self.rows - 1);
+        self.col = saved.col.min(self.cols - 1);
+        self.style = saved.style;
+        self.wrap_pending = false;
+    }
+
+    fn set_alternate_screen(&mut self, alternate: bool) {
+        match (alternate, self.primary.is_some()) {
+            (true, false) => {
+                let blank = vec![vec![Cell::blank(Style::default()); self.cols]; self.rows];
+                self.primary = Some(std::mem::replace(&mut self.lines, blank));
+            }
+            (false, true) => self.lines = self.primary.take().expect("on the alternate screen"),
+            _ => {}
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, set: bool) {
+        match mode {
+            1 => self.app_cursor = set,
+            7 => self.autowrap = set,
+            25 => self.cursor_visible = set,
+            47 | 1047 => self.set_alternate_screen(set),
+            1049 => {
+                // Saves the cursor of the primary screen as well
+                if set {
+                    self.save_cursor();
+                    self.set_alternate_screen(true);
+                    self.erase_display(2);
+                } else {
+                    self.set_alternate_screen(false);
+                    self.restore_cursor();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Select Graphic Rendition: colors and text attributes.
+    fn sgr(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.style = Style::default();
+            return;
+        }
+
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param[0] {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = true,
+                7 => self.style.inverse = true,
+                22 => self.style.bold = false,
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                n @ 30..=37 => self.style.fg = Color::Indexed((n - 30) as u8),
+                38 => self.style.fg = extended_color(param, &mut params).unwrap_or_default(),
+                39 => self.style.fg = Color::Default,
+                n @ 40..=47 => self.style.bg = Color::Indexed((n - 40) as u8),
+                48 => self.style.bg = extended_color(param, &mut params).unwrap_or_default(),
+                49 => self.style.bg = Color::Default,
+                n @ 90..=97 => self.style.fg = Color::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.style.bg = Color::Indexed((n - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Color of `38` and `48` parameters, either `5;n` for a palette color or `2;r;g;b`, with
+/// semicolons or colons between them.
+fn extended_color(param: &[u16], params: &mut ParamsIter) -> Option<Color> {
+    let mut values: Vec<u16> = match param.len() {
+        1 => Vec::new(),
+        _ => param[1..].to_vec(),
+    };
+    let separate = values.is_empty();
+    let mut next = |values: &mut Vec<u16>| {
+        if separate {
+            values.push(params.next()?[0]);
+        }
+        Some(())
+    };
+
+    next(&mut values)?;
+    match values[0] {
+        5 => {
+            next(&mut values)?;
+            Some(Color::Indexed(*values.get(1)? as u8))
+        }
+        2 => {
+            for _ in 0..3 {
+                next(&mut values)?;
+            }
+            // Colon separated values may have a color space before the components
+            let rgb = &values[values.len().checked_sub(3)?..];
+            Some(Color::Rgb(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
+        }
+        _ => None,
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0).min(self.cols);
+        // Combining characters aren't drawn
+        if width == 0 {
+            return;
+        }
+
+        if self.wrap_pending || self.col + width > self.cols {
+            if self.autowrap {
+                self.col = 0;
+                self.linefeed();
+            } else {
+                self.col = self.cols - width;
+            }
+            self.wrap_pending = false;
+        }
+
+        let line = &mut self.lines[self.row];
+        line[self.col] = Cell {
+            c,
+            style: self.style,
+        };
+        if width == 2 {
+            line[self.col + 1] = Cell {
+                c: SPACER,
+                style: self.style,
+            };
+        }
+
+        self.col += width;
+        if self.col >= self.cols {
+            self.col = self.cols - 1;
+            self.wrap_pending = true;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // Backspace
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\t' => self.col = ((self.col / TAB_STOP + 1) * TAB_STOP).min(self.cols - 1),
+            // Line feed, vertical tab and form feed
+            0x0a..=0x0c => self.linefeed(),
+            b'\r' => self.col = 0,
+            _ => return,
+        }
+
+        self.wrap_pending = false;
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        let args: Vec<u16> = params.iter().map(|param| param[0]).collect();
+        let raw = |i: usize| args.get(i).copied().unwrap_or(0);
+        // Counts and positions of 0 mean 1
+        let arg = |i: usize| usize::from(raw(i).max(1));
+        let private = intermediates.first() == Some(&b'?');
+
+        match (action, private) {
+            ('h' | 'l', true) => {
+                for &mode in &args {
+                    self.set_private_mode(mode, action == 'h');
+                }
+                return;
+            }
+            ('m', false) => {
+                self.sgr(params);
+                return;
+            }
+            (_, true) => return,
+            ('@', _) => self.shift_cells(arg(0), true),
+            ('A', _) => self.row = self.row.saturating_sub(arg(0)),
+            ('B' | 'e', _) => self.row = (self.row + arg(0)).min(self.rows - 1),
+            ('C' | 'a', _) => self.col = (self.col + arg(0)).min(self.cols - 1),
+            ('D', _) => self.col = self.col.saturating_sub(arg(0)),
+            ('E', _) => {
+                self.row = (self.row + arg(0)).min(self.rows - 1);
+                self.col = 0;
+            }
+            ('F', _) => {
+                self.row = self.row.saturating_sub(arg(0));
+                self.col = 0;
+            }
+            ('G' | '`', _) => self.col = (arg(0) - 1).min(self.cols - 1),
+            ('H' | 'f', _) => {
+                self.row = (arg(0) - 1).min(self.rows - 1);
+                self.col = (arg(1) - 1).min(self.cols - 1);
+            }
+            ('J', _) => self.erase_display(raw(0)),
+            ('K', _) => self.erase_line(raw(0)),
+            ('L', _) => self.shift_lines(arg(0), true),
+            ('M', _) => self.shift_lines(arg(0), false),
+            ('P', _) => self.shift_cells(arg(0), false),
+            ('S', _) => self.scroll_up(arg(0)),
+            ('T', _) => self.scroll_down(arg(0)),
+            ('X', _) => self.erase(self.row, self.col..self.col + arg(0)),
+            ('d', _) => self.row = (arg(0) - 1).min(self.rows - 1),
+            ('r', _) => {
+                let top = arg(0) - 1;
+                let bottom = match raw(1) {
+                    0 => self.rows,
+                    bottom => usize::from(bottom).min(self.rows),
+                };
+                if top + 1 < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.row = 0;
+                    self.col = 0;
+                }
+            }
+            ('s', _) => self.save_cursor(),
+            ('u', _) => self.restore_cursor(),
+            // Device status reports
+            ('n', _) => match raw(0) {
+                5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                6 => self
+                    .replies
+                    .extend(format!("\x1b[{};{}R", self.row + 1, self.col + 1).bytes()),
+                _ => {}
+            },
+            // Device attributes: a VT102
+            ('c', _) => self.replies.extend_from_slice(b"\x1b[?6c"),
+            _ => return,
+        }
+
+        self.wrap_pending = false;
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        // Character set designations have intermediates, and only UTF-8 is supported
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.col = 0;
+                self.linefeed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => {
+                let scrollback = std::mem::take(&mut self.scrollback);
+                *self = Self::new(self.rows, self.cols);
+                self.scrollback = scrollback;
+            }
+            _ => return,
+        }
+
+        self.wrap_pending = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rows: usize, cols: usize, output: &str) -> Screen {
+        let mut screen = Screen::new(rows, cols);
+
+        write(&mut screen, output);
+        screen
+    }
+
+    fn write(screen: &mut Screen, output: &str) {
+        vte::Parser::new().advance(screen, output.as_bytes());
+    }
+
+    /// Text of the rows on screen, scrolled `offset` lines back.
+    fn rows(screen: &Screen, offset: usize) -> Vec<String> {
+        screen
+            .visible(offset)
+            .map(|line| {
+                let text: String = line
+                    .iter()
+                    .map(|cell| cell.c)
+                    .filter(|c| *c != SPACER)
+                    .collect();
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_the_last_column() {
+        let mut screen = screen(3, 5, "abcde");
+        // The cursor stays on the last column until the next character
+        assert_eq!(screen.cursor(), (0, 4));
+
+        write(&mut screen, "fg");
+        assert_eq!(rows(&screen, 0), ["abcde", "fg", ""]);
+        assert_eq!(screen.cursor(), (1, 2));
+
+        // Wide characters don't get split over two lines
+        let screen = self::screen(2, 3, "ab界");
+        assert_eq!(rows(&screen, 0), ["ab", "界"]);
+        assert_eq!(screen.visible(0).nth(1).unwrap()[1].c, SPACER);
+        assert_eq!(screen.cursor(), (1, 2));
+
+        let screen = self::screen(2, 5, "\x1b[?7labcdefg");
+        assert_eq!(rows(&screen, 0), ["abcdg", ""]);
+        assert_eq!(screen.cursor(), (0, 4));
+    }
+
+    #[test]
+    fn scrolls_lines_off_the_top_into_the_scrollback() {
+        let screen = screen(2, 5, "1\r\n2\r\n3");
+
+        assert_eq!(rows(&screen, 0), ["2", "3"]);
+        assert_eq!(screen.scrollback_len(), 1);
+        assert_eq!(rows(&screen, 1), ["1", "2"]);
+        assert_eq!(rows(&screen, 5), ["1", "2"]);
+    }
+
+    #[test]
+    fn scrolls_only_the_scroll_region() {
+        let mut screen = screen(4, 5, "1\r\n2\r\n3\r\n4\x1b[2;3r");
+        assert_eq!(screen.cursor(), (0, 0));
+
+        write(&mut screen, "\x1b[3;1H\n");
+        assert_eq!(rows(&screen, 0), ["1", "3", "", "4"]);
+        assert_eq!(screen.scrollback_len(), 0);
+
+        write(&mut screen, "\x1b[2;1H\x1bM");
+        assert_eq!(rows(&screen, 0), ["1", "", "3", "4"]);
+    }
+
+    #[test]
+    fn inserts_and_deletes_lines_and_cells() {
+        let mut screen = screen(3, 5, "abc\r\nde\r\nfg\x1b[2;2H\x1b[L");
+        assert_eq!(rows(&screen, 0), ["abc", "", "de"]);
+        assert_eq!(screen.cursor(), (1, 0));
+
+        write(&mut screen, "\x1b[M");
+        assert_eq!(rows(&screen, 0), ["abc", "de", ""]);
+
+        write(&mut screen, "\x1b[1;2H\x1b[2@");
+        assert_eq!(rows(&screen, 0)[0], "a  bc");
+        write(&mut screen, "\x1b[2P");
+        assert_eq!(rows(&screen, 0)[0], "abc");
+    }
+
+    #[test]
+    fn resizing_keeps_the_cursor_line() {
+        let mut screen = screen(3, 5, "1\r\n2\r\n345");
+
+        // Lines above the cursor make room
+        screen.resize(2, 5);
+        assert_eq!(rows(&screen, 0), ["2", "345"]);
+        assert_eq!(screen.scrollback_len(), 1);
+        assert_eq!(screen.cursor(), (1, 3));
+
+        screen.resize(2, 2);
+        assert_eq!(rows(&screen, 0), ["2", "34"]);
+        assert_eq!(screen.cursor(), (1, 1));
+
+        // Lines below the cursor are dropped first
+        let mut screen = self::screen(3, 5, "1\x1b[3;1H3\x1b[1;1H");
+        screen.resize(1, 5);
+        assert_eq!(rows(&screen, 0), ["1"]);
+        assert_eq!(screen.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn full_screen_programs_draw_on_the_alternate_screen() {
+        let mut screen = screen(3, 5, "abc\x1b[2;3H");
+
+        write(&mut screen, "\x1b[?1049hxyz\r\n\r\n\r\nw");
+        assert_eq!(rows(&screen, 0), ["", "", "w"]);
+        // What scrolls off the alternate screen isn't kept
+        assert_eq!(screen.scrollback_len(), 0);
+
+        write(&mut screen, "\x1b[?1049l");
+        assert_eq!(rows(&screen, 0), ["abc", "", ""]);
+        assert_eq!(screen.cursor(), (1, 2));
+    }
+
+    #[test]
+    fn answers_status_and_attribute_queries() {
+        let screen = screen(3, 5, "\x1b[2;3H\x1b[6n\x1b[5n\x1b[c");
+
+        assert_eq!(screen.replies, b"\x1b[2;3R\x1b[0n\x1b[?6c");
+    }
+}
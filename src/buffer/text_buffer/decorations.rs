@@ -0,0 +1,431 @@
+use crate::buffer::output::severity_color;
+use crate::buffer::BoundingBox;
+use crate::fonts::{FontCollection, FontId, FontStyle};
+use crate::git::hunks::{ChangeKind, Hunk};
+use crate::grid::{self, Cell, GridMetrics, TextGrid};
+use crate::scene::{Glyph, Quad};
+use crate::tasks::messages::Severity;
+use crate::theme::Theme;
+use ab_glyph::{point, Font, GlyphId, ScaleFont};
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+const SPACE_MARKER: char = '·';
+const TAB_MARKER: char = '→';
+const FOLDABLE_MARKER: char = '▾';
+const FOLDED_MARKER: char = '▸';
+const PLACEHOLDER: char = '⋯';
+
+/// Where the grid is drawn: the frame, its cell size and how many rows fit in it.
+pub struct Placement {
+    /// Area of the text, right of the gutter
+    pub bb: BoundingBox,
+    pub gutter: BoundingBox,
+    pub metrics: GridMetrics,
+    pub visible_rows: usize,
+    /// Width of thin lines such as indent guides, in pixels
+    pub line_width: f32,
+}
+
+impl Placement {
+    pub fn cell_rect(&self, row: usize, cell: Cell) -> [f32; 4] {
+        let left = self.bb.left + cell.column as f32 * self.metrics.cell_width;
+        let top = self.bb.top + row as f32 * self.metrics.line_height;
+
+        [
+            left,
+            top,
+            left + cell.width as f32 * self.metrics.cell_width,
+            top + self.metrics.line_height,
+        ]
+    }
+}
+
+/// Markers for spaces and tabs, drawn in `color`, and highlights behind trailing
+/// whitespace.
+pub fn whitespace(
+    text: &str,
+    grid: &TextGrid,
+    fonts: &FontCollection,
+    scale: f32,
+    color: [f32; 4],
+    place: &Placement,
+    theme: &Theme,
+) -> (Vec<Glyph>, Vec<Quad>) {
+    let mut glyphs = Vec::new();
+    let mut quads = Vec::new();
+
+    let space = marker(fonts, scale, SPACE_MARKER);
+    let tab = marker(fonts, scale, TAB_MARKER);
+
+    for (start, first_row, line_grid) in grid.lines() {
+        if first_row >= place.visible_rows {
+            break;
+        }
+
+        let line = grid::line_at(text, start);
+        let trailing = line.trim_end_matches([' ', '\t']).len();
+
+        for (offset, grapheme) in line.grapheme_indices(true) {
+            let (character, font_id, glyph_id, advance) = match grapheme {
+                " " => space,
+                "\t" => tab,
+                _ => continue,
+            };
+
+            let cell = line_grid.cell(offset);
+            let rect = place.cell_rect(first_row + cell.row, cell);
+
+            if offset >= trailing {
+                quads.push(Quad {
+                    aabb: rect,
+                    z_pos: 0.0,
+                    color: theme.trailing_whitespace.0,
+                });
+            }
+
+            // Dots are centered in their cell, arrows start at the tab
+            let slot = match grapheme {
+                " " => rect[2] - rect[0],
+                _ => place.metrics.cell_width,
+            };
+            let position = point(
+                rect[0] + (slot - advance) / 2.0,
+                rect[1] + place.metrics.ascent,
+            );
+
+            glyphs.push(Glyph {
+                glyph: glyph_id.with_scale_and_position(scale, position),
+                font_id,
+                color,
+                text: character.to_string(),
+            });
+        }
+    }
+
+    (glyphs, quads)
+}
+
+/// The marker character, with its font, glyph and advance.
+fn marker(fonts: &FontCollection, scale: f32, c: char) -> (char, FontId, GlyphId, f32) {
+    let font_id = fonts.resolve(c, FontStyle::Regular);
+    let font = fonts.font(font_id).as_scaled(scale);
+    let glyph_id = font.glyph_id(c);
+
+    (c, font_id, glyph_id, font.h_advance(glyph_id))
+}
+
+/// Arrows in the gutter next to the lines that can be folded, and placeholders after the
+/// lines that are, drawn in `color`. `headers` holds the start of each foldable line,
+/// sorted, and whether it's folded.
+pub fn folds(
+    text: &str,
+    grid: &TextGrid,
+    headers: &[(usize, bool)],
+    fonts: &FontCollection,
+    scale: f32,
+    color: [f32; 4],
+    place: &Placement,
+) -> Vec<Glyph> {
+    let foldable = marker(fonts, scale, FOLDABLE_MARKER);
+    let folded = marker(fonts, scale, FOLDED_MARKER);
+    let placeholder = marker(fonts, scale, PLACEHOLDER);
+    let mut glyphs = Vec::new();
+
+    let mut glyph = |marker: (char, FontId, GlyphId, f32), left, width, row| {
+        let (character, font_id, glyph_id, advance) = marker;
+        let position = point(
+            left + (width - advance) / 2.0,
+            place.bb.top + row as f32 * place.metrics.line_height + place.metrics.ascent,
+        );
+
+        glyphs.push(Glyph {
+            glyph: glyph_id.with_scale_and_position(scale, position),
+            font_id,
+            color,
+            text: character.to_string(),
+        });
+    };
+
+    for (start, first_row, line_grid) in grid.lines() {
+        if first_row >= place.visible_rows {
+            break;
+        }
+
+        let Ok(index) = headers.binary_search_by_key(&start, |&(header, _)| header) else {
+            continue;
+        };
+
+        let gutter = place.gutter;
+        if !headers[index].1 {
+            glyph(foldable, gutter.left, gutter.width, first_row);
+            continue;
+        }
+
+        glyph(folded, gutter.left, gutter.width, first_row);
+
+        // The placeholder takes the cell after the line's end, or two for wide glyphs
+        let end = line_grid.cell(grid::line_at(text, start).len());
+        let cell = Cell {
+            column: end.column + 1,
+            width: 2,
+            ..end
+        };
+        let [left, _, right, _] = place.cell_rect(first_row + end.row, cell);
+        glyph(placeholder, left, right - left, first_row + end.row);
+    }
+
+    glyphs
+}
+
+/// Bars along the left edge of the gutter next to lines changed since staging, and marks
+/// between the lines where others were removed.
+pub fn changes(grid: &TextGrid, hunks: &[Hunk], place: &Placement, theme: &Theme) -> Vec<Quad> {
+    let width = place.line_width * 3.0;
+    let left = place.gutter.left;
+    let y = |row: usize| place.bb.top + row as f32 * place.metrics.line_height;
+    let mut quads = Vec::new();
+
+    for hunk in hunks {
+        let rows = grid.line_rows(hunk.new.start);
+        if rows.start >= place.visible_rows {
+            break;
+        }
+
+        let (aabb, color) = match hunk.kind() {
+            ChangeKind::Deleted => (
+                [
+                    left,
+                    y(rows.start) - width / 2.0,
+                    left + place.gutter.width / 2.0,
+                    y(rows.start) + width / 2.0,
+                ],
+                theme.git_deleted,
+            ),
+            kind => {
+                let end = grid.line_rows(hunk.new.end - 1).end;
+                let color = match kind {
+                    ChangeKind::Added => theme.git_added,
+                    _ => theme.git_modified,
+                };
+                ([left, y(rows.start), left + width, y(end)], color)
+            }
+        };
+
+        quads.push(Quad {
+            aabb,
+            z_pos: 0.0,
+            color: color.0,
+        });
+    }
+
+    quads
+}
+
+/// Wavy lines under the graphemes of problems found by tasks, in the color of their
+/// severity. Each cell gets a low half and a high half.
+pub fn squiggles(
+    text: &str,
+    grid: &TextGrid,
+    problems: &[(Range<usize>, Severity)],
+    place: &Placement,
+    theme: &Theme,
+) -> Vec<Quad> {
+    let mut quads = Vec::new();
+
+    for (range, severity) in problems {
+        let color = severity_color(*severity, theme);
+
+        for (start, first_row, line_grid) in grid.lines() {
+            let line = grid::line_at(text, start);
+            let end = start + line.len();
+
+            if first_row >= place.visible_rows || start >= range.end {
+                break;
+            }
+            if end < range.start {
+                continue;
+            }
+
+            for (offset, _) in line.grapheme_indices(true) {
+                if !range.contains(&(start + offset)) || grid.is_hidden(start + offset) {
+                    continue;
+                }
+
+                let cell = line_grid.cell(offset);
+                let [left, _, right, bottom] = place.cell_rect(first_row + cell.row, cell);
+                let middle = (left + right) / 2.0;
+                let width = place.line_width;
+
+                quads.push(Quad {
+                    aabb: [left, bottom - width, middle, bottom],
+                    z_pos: 0.0,
+                    color,
+                });
+                quads.push(Quad {
+                    aabb: [middle, bottom - width * 2.0, right, bottom - width],
+                    z_pos: 0.0,
+                    color,
+                });
+            }
+        }
+    }
+
+    quads
+}
+
+/// Highlights behind the selected graphemes. Selected line breaks get half a cell.
+pub fn selection(
+    text: &str,
+    grid: &TextGrid,
+    selection: Range<usize>,
+    place: &Placement,
+    theme: &Theme,
+) -> Vec<Quad> {
+    let mut quads = Vec::new();
+
+    for (start, first_row, line_grid) in grid.lines() {
+        let line = grid::line_at(text, start);
+        let end = start + line.len();
+
+        if first_row >= place.visible_rows || start > selection.end {
+            break;
+        }
+        if end < selection.start {
+            continue;
+        }
+
+        for (offset, _) in line.grapheme_indices(true) {
+            if selection.contains(&(start + offset)) {
+                let cell = line_grid.cell(offset);
+                quads.push(Quad {
+                    aabb: place.cell_rect(first_row + cell.row, cell),
+                    z_pos: 0.0,
+                    color: theme.selection.0,
+                });
+            }
+        }
+
+        if selection.contains(&end) && end < text.len() {
+            let cell = Cell {
+                width: 1,
+                ..line_grid.cell(line.len())
+            };
+            let [left, top, right, bottom] = place.cell_rect(first_row + cell.row, cell);
+            quads.push(Quad {
+                aabb: [left, top, (left + right) / 2.0, bottom],
+                z_pos: 0.0,
+                color: theme.selection.0,
+            });
+        }
+    }
+
+    quads
+}
+
+/// Vertical lines at every indentation level. The guide of the innermost block around
+/// the cursor is drawn in the active color.
+pub fn indent_guides(
+    text: &str,
+    grid: &TextGrid,
+    tab_width: usize,
+    cursor: usize,
+    place: &Placement,
+    theme: &Theme,
+) -> Vec<Quad> {
+    let tab_width = tab_width.max(1);
+    let lines: Vec<_> = grid.lines().collect();
+    let indents = effective_indents(text, &lines, tab_width);
+
+    // A line followed by deeper ones opens the block the cursor is considered in
+    let cursor_line = lines.partition_point(|&(start, ..)| start <= cursor) - 1;
+    let own = indents[cursor_line];
+    let active_column = match indents.get(cursor_line + 1) {
+        Some(&next) if next > own => Some(own),
+        _ => own.checked_sub(1).map(|last| last / tab_width * tab_width),
+    };
+    let active_lines = active_column.map(|column| {
+        let inside = |line: &usize| indents[*line] > column;
+        let anchor = if inside(&cursor_line) {
+            cursor_line
+        } else {
+            cursor_line + 1
+        };
+        let first = (0..anchor)
+            .rev()
+            .take_while(inside)
+            .last()
+            .unwrap_or(anchor);
+        let last = (anchor..lines.len())
+            .take_while(inside)
+            .last()
+            .unwrap_or(anchor);
+
+        first..=last
+    });
+
+    let mut quads = Vec::new();
+    for (i, &(_, first_row, line_grid)) in lines.iter().enumerate() {
+        if first_row >= place.visible_rows {
+            break;
+        }
+
+        let top = place.bb.top + first_row as f32 * place.metrics.line_height;
+        let bottom = top + line_grid.row_count() as f32 * place.metrics.line_height;
+
+        for column in (0..indents[i]).step_by(tab_width) {
+            let active = Some(column) == active_column
+                && active_lines
+                    .as_ref()
+                    .is_some_and(|lines| lines.contains(&i));
+            let left = place.bb.left + column as f32 * place.metrics.cell_width;
+
+            quads.push(Quad {
+                aabb: [left, top, left + place.line_width, bottom],
+                z_pos: 0.0,
+                color: if active {
+                    theme.active_indent_guide.0
+                } else {
+                    theme.indent_guide.0
+                },
+            });
+        }
+    }
+
+    quads
+}
+
+/// Indentation of each line. Blank lines take the shallower of the lines around them,
+/// so guides run through the gaps inside a block.
+fn effective_indents(
+    text: &str,
+    lines: &[(usize, usize, &grid::LineGrid)],
+    tab_width: usize,
+) -> Vec<usize> {
+    let own: Vec<Option<usize>> = lines
+        .iter()
+        .map(|&(start, _, _)| {
+            let line = grid::line_at(text, start);
+            (!line.trim().is_empty()).then(|| grid::indent_width(line, tab_width))
+        })
+        .collect();
+
+    let mut previous = vec![0; own.len()];
+    let mut last = 0;
+    for (i, indent) in own.iter().enumerate() {
+        last = indent.unwrap_or(last);
+        previous[i] = last;
+    }
+
+    let mut next = 0;
+    let mut indents = vec![0; own.len()];
+    for (i, indent) in own.iter().enumerate().rev() {
+        indents[i] = match indent {
+            Some(indent) => *indent,
+            None => previous[i].min(next),
+        };
+        next = indent.unwrap_or(next);
+    }
+
+    indents
+}
@@ -0,0 +1,221 @@
+//! Edits at the cursor, and undoing them.
+
+use super::TextBuffer;
+use crate::brackets;
+use crate::buffer::history::Edit;
+use crate::buffer::EventHandlerOutcome;
+use crate::indent;
+use crate::syntax::SpanKind;
+use std::ops::Range;
+
+impl TextBuffer {
+    /// Replaces `range` of the text, recording the edit in the history. Other cursors
+    /// keep their place in the text around it.
+    pub(super) fn edit(&mut self, range: Range<usize>, text: &str) {
+        let edit = Edit {
+            start: range.start,
+            removed: self.text[range.clone()].to_string(),
+            inserted: text.to_string(),
+        };
+        self.history.record(edit, self.cursor);
+        self.replace(range.clone(), text);
+        self.cursor = range.start + text.len();
+        self.anchor = None;
+
+        let map = |offset: usize| match offset {
+            offset if offset <= range.start => offset,
+            offset if offset >= range.end => offset - range.len() + text.len(),
+            _ => range.start + text.len(),
+        };
+        for selection in &mut self.secondary {
+            selection.cursor = map(selection.cursor);
+            selection.anchor = selection.anchor.map(map);
+        }
+
+        self.folds.reveal(&self.text, self.cursor);
+    }
+
+    /// Replaces `range` of the text, dropping what was worked out from it.
+    fn replace(&mut self, range: Range<usize>, text: &str) {
+        self.shift_problems(range.clone(), text.len());
+        self.text.replace_range(range.clone(), text);
+        self.folds.edit(&self.text, range, text.len());
+        self.dirty = true;
+        self.shaped = None;
+        self.grid = None;
+        self.syntax = None;
+        self.regions = None;
+        self.hunks = None;
+        self.origins = None;
+    }
+
+    /// Makes `edits` from the history, leaving a single cursor at `cursor`.
+    fn restore(&mut self, edits: Vec<Edit>, cursor: usize) {
+        for edit in edits {
+            let end = edit.start + edit.removed.len();
+            self.replace(edit.start..end, &edit.inserted);
+        }
+
+        self.cursor = cursor;
+        self.anchor = None;
+        self.secondary.clear();
+        self.folds.reveal(&self.text, self.cursor);
+    }
+
+    /// Moves problems after `range` to where they are once it's replaced by `inserted`
+    /// bytes, and drops the problems it overlaps.
+    fn shift_problems(&mut self, range: Range<usize>, inserted: usize) {
+        self.problems.retain_mut(|(problem, _)| {
+            if problem.end <= range.start {
+                return true;
+            }
+            if problem.start < range.end {
+                return false;
+            }
+
+            let shift = |offset: usize| offset - range.len() + inserted;
+            *problem = shift(problem.start)..shift(problem.end);
+            true
+        });
+    }
+
+    pub(super) fn insert(&mut self, text: &str) -> EventHandlerOutcome {
+        let range = self.selection().unwrap_or(self.cursor..self.cursor);
+
+        self.edit(range, text);
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Whether any selection spans lines, which makes Tab indent them.
+    pub(super) fn spans_lines(&self) -> bool {
+        self.secondary
+            .iter()
+            .chain([&self.primary()])
+            .any(|selection| self.text[selection.range()].contains('\n'))
+    }
+
+    /// Inserts a typed character. Brackets and quotes come in pairs, and closing brackets
+    /// typed on a blank line take one level of indentation away.
+    pub(super) fn insert_char(&mut self, c: char) -> EventHandlerOutcome {
+        if let Some(selection) = self.selection() {
+            // Openers typed over a selection wrap it
+            if let Some(close) = brackets::closer(c) {
+                let wrapped = format!("{}{}{}", c, &self.text[selection.clone()], close);
+                self.edit(selection.clone(), &wrapped);
+                self.anchor = Some(selection.start + c.len_utf8());
+                self.cursor = selection.start + wrapped.len() - close.len_utf8();
+                return EventHandlerOutcome::Redraw;
+            }
+
+            return self.insert(&c.to_string());
+        }
+
+        let cursor = self.cursor;
+
+        // Typing a closer in front of the same closer steps over it. Quotes only do so
+        // when they end a string.
+        if brackets::is_closer(c) && self.text[cursor..].starts_with(c) {
+            let (_, syntax) = self.syntax();
+            if c != '"' || syntax.kind_at_caret(cursor) == Some(SpanKind::String) {
+                return self.place_cursor(cursor + c.len_utf8());
+            }
+        }
+
+        if let Some(close) = brackets::closer(c) {
+            let (text, syntax) = self.syntax();
+            if brackets::should_pair(text, cursor, c, syntax) {
+                self.edit(cursor..cursor, &format!("{}{}", c, close));
+                self.cursor = cursor + c.len_utf8();
+                return EventHandlerOutcome::Redraw;
+            }
+        }
+
+        let start = self.line_start(self.cursor);
+        let before = &self.text[start..self.cursor];
+
+        if indent::dedents(c, before) {
+            let replacement = format!("{}{}", self.indent.dedent(before), c);
+            self.edit(start..self.cursor, &replacement);
+            return EventHandlerOutcome::Redraw;
+        }
+
+        match c {
+            '\t' => self.insert(&self.indent.unit()),
+            c => self.insert(&c.to_string()),
+        }
+    }
+
+    /// Breaks the line, keeping its indentation and adding a level after an opener.
+    pub(super) fn newline(&mut self) -> EventHandlerOutcome {
+        let range = self.selection().unwrap_or(self.cursor..self.cursor);
+        let start = self.line_start(range.start);
+        let end = self.line_end(range.end);
+        let (inserted, after) = indent::newline(
+            &self.text[start..range.start],
+            &self.text[range.end..end],
+            &self.indent,
+        );
+
+        self.edit(range.clone(), &format!("{}{}", inserted, after));
+        self.cursor = range.start + inserted.len();
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn delete_backward(&mut self) -> EventHandlerOutcome {
+        if let Some(selection) = self.selection() {
+            self.edit(selection, "");
+            return EventHandlerOutcome::Redraw;
+        }
+
+        let Some(start) = self.previous_boundary(self.cursor) else {
+            return EventHandlerOutcome::None;
+        };
+
+        // Deleting the opener of an empty pair deletes its closer too
+        let mut end = self.cursor;
+        if let Some(close) = self.text[start..end]
+            .chars()
+            .next()
+            .and_then(brackets::closer)
+        {
+            if self.text[end..].starts_with(close) {
+                end += close.len_utf8();
+            }
+        }
+
+        self.edit(start..end, "");
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn delete_forward(&mut self) -> EventHandlerOutcome {
+        if let Some(selection) = self.selection() {
+            self.edit(selection, "");
+            return EventHandlerOutcome::Redraw;
+        }
+
+        let Some(end) = self.next_boundary(self.cursor) else {
+            return EventHandlerOutcome::None;
+        };
+
+        self.edit(self.cursor..end, "");
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn undo(&mut self) -> EventHandlerOutcome {
+        let Some((edits, cursor)) = self.history.undo(self.cursor) else {
+            return EventHandlerOutcome::None;
+        };
+
+        self.restore(edits, cursor);
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn redo(&mut self) -> EventHandlerOutcome {
+        let Some((edits, cursor)) = self.history.redo(self.cursor) else {
+            return EventHandlerOutcome::None;
+        };
+
+        self.restore(edits, cursor);
+        EventHandlerOutcome::Redraw
+    }
+}
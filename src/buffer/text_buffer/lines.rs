@@ -0,0 +1,627 @@
+//! Commands working on whole lines. They apply to the lines touched by every selection,
+//! merged into blocks, and keep the cursors on the same text.
+
+use super::{Selection, TextBuffer};
+use crate::buffer::EventHandlerOutcome;
+use crate::grid;
+use crate::indent;
+use crate::language::Language;
+use std::ops::Range;
+
+impl TextBuffer {
+    /// Lines touched by each selection, from the start of the first to the end of the
+    /// last before its line break. Blocks that overlap or follow each other are merged.
+    fn line_blocks(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<_> = self
+            .secondary
+            .iter()
+            .chain([&self.primary()])
+            .map(Selection::range)
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut blocks: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            // A selection ending at the start of a line doesn't touch that line
+            let last = match range.end {
+                end if range.start < end && self.line_start(end) == end => end - 1,
+                end => end,
+            };
+            let block = self.line_start(range.start)..self.line_end(last);
+
+            match blocks.last_mut() {
+                Some(previous) if block.start <= previous.end + 1 => {
+                    previous.end = previous.end.max(block.end);
+                }
+                _ => blocks.push(block),
+            }
+        }
+
+        blocks
+    }
+
+    /// Calls `op` on each line block, last first so that the offsets of the others stay
+    /// valid, and undoes all of its edits together. `op` returns whether it edited.
+    fn each_block(&mut self, op: impl Fn(&mut Self, Range<usize>) -> bool) -> EventHandlerOutcome {
+        let mut outcome = EventHandlerOutcome::None;
+
+        self.history.begin_group();
+        for block in self.line_blocks().into_iter().rev() {
+            if op(self, block) {
+                outcome = EventHandlerOutcome::Redraw;
+            }
+        }
+        self.history.end_group();
+        self.merge_selections();
+
+        outcome
+    }
+
+    /// Replaces `range` with `text` like `edit`, moving cursors inside the range, its
+    /// ends included, to where `inside` maps them in the new text.
+    fn edit_mapped(&mut self, range: Range<usize>, text: &str, inside: impl Fn(usize) -> usize) {
+        let map = |offset: usize| match offset {
+            offset if offset < range.start => offset,
+            offset if offset > range.end => offset - range.len() + text.len(),
+            offset => inside(offset),
+        };
+        let map_selection = |selection: Selection| Selection {
+            cursor: map(selection.cursor),
+            anchor: selection.anchor.map(map),
+        };
+
+        let primary = map_selection(self.primary());
+        let secondary: Vec<_> = std::mem::take(&mut self.secondary)
+            .into_iter()
+            .map(map_selection)
+            .collect();
+
+        self.edit(range, text);
+        self.set_primary(primary);
+        self.secondary = secondary;
+    }
+
+    /// Replaces the start of each line of `block`: `prefix` returns how many bytes of a
+    /// line to replace, and with what. Blank lines of a block spanning lines are left
+    /// alone. Returns whether anything changed.
+    fn rewrite_prefixes(
+        &mut self,
+        block: Range<usize>,
+        prefix: impl Fn(&str) -> (usize, String),
+    ) -> bool {
+        let multiline = self.text[block.clone()].contains('\n');
+        let mut replacement = String::new();
+        // Old start, old prefix length, new start and new prefix length of each line
+        let mut lines = Vec::new();
+        let mut start = block.start;
+
+        for line in self.text[block.clone()].split('\n') {
+            let (old, new) = match multiline && line.trim().is_empty() {
+                true => (0, String::new()),
+                false => prefix(line),
+            };
+
+            if !lines.is_empty() {
+                replacement.push('\n');
+            }
+            lines.push((start, old, block.start + replacement.len(), new.len()));
+            replacement.push_str(&new);
+            replacement.push_str(&line[old..]);
+            start += line.len() + 1;
+        }
+
+        if replacement == self.text[block.clone()] {
+            return false;
+        }
+
+        // Offsets inside a prefix stay in the same column as far as the new one reaches
+        let map = |offset: usize| {
+            let &(old_start, old_prefix, new_start, new_prefix) = lines
+                .iter()
+                .rev()
+                .find(|(old_start, ..)| *old_start <= offset)
+                .expect("offset inside the block");
+            let column = offset - old_start;
+
+            match column.checked_sub(old_prefix) {
+                Some(past_prefix) => new_start + new_prefix + past_prefix,
+                None => new_start + column.min(new_prefix),
+            }
+        };
+
+        self.edit_mapped(block, &replacement, map);
+        true
+    }
+
+    pub(super) fn indent_lines(&mut self) -> EventHandlerOutcome {
+        let unit = self.indent.unit();
+
+        self.each_block(|buffer, block| {
+            buffer.rewrite_prefixes(block, |line| {
+                let old = indent::leading_whitespace(line);
+                (old.len(), format!("{}{}", old, unit))
+            })
+        })
+    }
+
+    pub(super) fn outdent_lines(&mut self) -> EventHandlerOutcome {
+        let style = self.indent;
+
+        self.each_block(|buffer, block| {
+            buffer.rewrite_prefixes(block, |line| {
+                let old = indent::leading_whitespace(line);
+                (old.len(), style.dedent(old).to_string())
+            })
+        })
+    }
+
+    /// Comments out the selected lines with the language's line comments, or uncomments
+    /// them if they all are. Languages without line comments wrap each block instead.
+    pub(super) fn toggle_line_comment(&mut self) -> EventHandlerOutcome {
+        let language = Language::from_path(self.path.as_deref());
+        let Some(token) = language.line_comment else {
+            return match language.block_comment {
+                Some(_) => self.each_block(|buffer, block| buffer.toggle_block_comment_in(block)),
+                None => EventHandlerOutcome::None,
+            };
+        };
+
+        let blocks = self.line_blocks();
+        let code_lines = || {
+            blocks
+                .iter()
+                .flat_map(|block| self.text[block.clone()].split('\n'))
+                .filter(|line| !line.trim().is_empty())
+        };
+        let has_code = code_lines().next().is_some();
+        let commented = has_code && code_lines().all(|line| line.trim_start().starts_with(token));
+
+        self.each_block(|buffer, block| {
+            if commented {
+                return buffer.rewrite_prefixes(block, |line| {
+                    if !line.trim_start().starts_with(token) {
+                        return (0, String::new());
+                    }
+                    let indent = indent::leading_whitespace(line);
+                    let rest = &line[indent.len() + token.len()..];
+                    let space = usize::from(rest.starts_with(' '));
+                    (indent.len() + token.len() + space, indent.to_string())
+                });
+            }
+
+            // Comments line up at the shallowest indentation of the block
+            let column = buffer.text[block.clone()]
+                .split('\n')
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| indent::leading_whitespace(line).len())
+                .min()
+                .unwrap_or(0);
+
+            buffer.rewrite_prefixes(block, |line| {
+                // Blank lines only get comments when there's nothing else to comment
+                if has_code && line.trim().is_empty() {
+                    return (0, String::new());
+                }
+                let indent = &line[..column.min(indent::leading_whitespace(line).len())];
+                (indent.len(), format!("{}{} ", indent, token))
+            })
+        })
+    }
+
+    /// Wraps each selection in a block comment, or unwraps it if it already is one.
+    /// Empty selections work on the text of their line.
+    pub(super) fn toggle_block_comment(&mut self) -> EventHandlerOutcome {
+        self.each_selection(false, |buffer| {
+            let range = match buffer.selection() {
+                Some(selection) => selection,
+                None => {
+                    let start = buffer.line_start(buffer.cursor);
+                    let line = grid::line_at(&buffer.text, start);
+                    let indent = indent::leading_whitespace(line).len();
+                    start + indent..start + line.trim_end().len().max(indent)
+                }
+            };
+
+            match buffer.toggle_block_comment_in(range) {
+                true => EventHandlerOutcome::Redraw,
+                false => EventHandlerOutcome::None,
+            }
+        })
+    }
+
+    fn toggle_block_comment_in(&mut self, range: Range<usize>) -> bool {
+        let language = Language::from_path(self.path.as_deref());
+        let Some((open, close)) = language.block_comment else {
+            return false;
+        };
+
+        // Whitespace around the comment stays outside of it
+        let text = &self.text[range.clone()];
+        let start = range.start + (text.len() - text.trim_start().len());
+        let end = (range.start + text.trim_end().len()).max(start);
+        let inner = &self.text[start..end];
+
+        let (replacement, removed_before) = match inner
+            .strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+        {
+            Some(content) => {
+                let before = usize::from(content.starts_with(' '));
+                let after = usize::from(content.len() > before && content.ends_with(' '));
+                let content = &content[before..content.len() - after];
+                (content.to_string(), (open.len() + before) as isize)
+            }
+            None => (
+                format!("{} {} {}", open, inner, close),
+                -((open.len() + 1) as isize),
+            ),
+        };
+
+        let new_end = start + replacement.len();
+        self.edit_mapped(start..end, &replacement, |offset| {
+            match offset {
+                offset if offset == start => start,
+                offset if offset == end => new_end,
+                offset => offset.saturating_add_signed(-removed_before),
+            }
+            .clamp(start, new_end)
+        });
+        true
+    }
+
+    /// Swaps the selected lines with the line above, or below with `down`.
+    pub(super) fn move_lines(&mut self, down: bool) -> EventHandlerOutcome {
+        self.each_block(|buffer, block| {
+            let block_text = buffer.text[block.clone()].to_string();
+
+            if down {
+                if block.end >= buffer.text.len() {
+                    return false;
+                }
+                let next = block.end + 1..buffer.line_end(block.end + 1);
+                let next_text = buffer.text[next.clone()].to_string();
+                let replacement = format!("{}\n{}", next_text, block_text);
+
+                buffer.edit_mapped(block.start..next.end, &replacement, |offset| {
+                    match offset <= block.end {
+                        true => offset + next_text.len() + 1,
+                        false => offset - block_text.len() - 1,
+                    }
+                });
+            } else {
+                if block.start == 0 {
+                    return false;
+                }
+                let previous = buffer.line_start(block.start - 1)..block.start - 1;
+                let previous_text = buffer.text[previous.clone()].to_string();
+                let replacement = format!("{}\n{}", block_text, previous_text);
+
+                buffer.edit_mapped(previous.start..block.end, &replacement, |offset| {
+                    match offset >= block.start {
+                        true => offset - previous_text.len() - 1,
+                        false => offset + block_text.len() + 1,
+                    }
+                });
+            }
+
+            true
+        })
+    }
+
+    /// Copies the selected lines below themselves, moving the cursors to the copy.
+    pub(super) fn duplicate_lines(&mut self) -> EventHandlerOutcome {
+        self.each_block(|buffer, block| {
+            let block_text = buffer.text[block.clone()].to_string();
+            let replacement = format!("{}\n{}", block_text, block_text);
+
+            buffer.edit_mapped(block, &replacement, |offset| offset + block_text.len() + 1);
+            true
+        })
+    }
+
+    /// Deletes the selected lines with their line breaks.
+    pub(super) fn delete_lines(&mut self) -> EventHandlerOutcome {
+        self.each_block(|buffer, block| {
+            let range = match block.end < buffer.text.len() {
+                true => block.start..block.end + 1,
+                false => block.start.saturating_sub(1)..block.end,
+            };
+
+            if range.is_empty() {
+                return false;
+            }
+
+            let target = match range.start == block.start {
+                true => block.start,
+                false => buffer.line_start(range.start),
+            };
+            buffer.edit_mapped(range, "", |_| target);
+            true
+        })
+    }
+
+    /// Joins the selected lines, or the cursor's line with the next one, with a space
+    /// in place of each line break and the indentation after it.
+    pub(super) fn join_lines(&mut self) -> EventHandlerOutcome {
+        self.each_block(|buffer, block| {
+            let block = match buffer.text[block.clone()].contains('\n') {
+                true => block,
+                false if block.end < buffer.text.len() => {
+                    block.start..buffer.line_end(block.end + 1)
+                }
+                false => return false,
+            };
+
+            let mut lines = buffer.text[block.clone()].split('\n');
+            let first = lines.next().unwrap_or_default().trim_end();
+            let mut replacement = first.to_string();
+            for line in lines {
+                let line = line.trim();
+                if !line.is_empty() && !replacement.is_empty() {
+                    replacement.push(' ');
+                }
+                replacement.push_str(line);
+            }
+
+            // Cursors on the first line stay, the others go to the first join
+            let join = block.start + first.len();
+            let new_end = block.start + replacement.len();
+            buffer.edit_mapped(block.clone(), &replacement, |offset| match offset {
+                offset if offset == block.end => new_end,
+                offset => offset.min(join),
+            });
+            true
+        })
+    }
+
+    /// Sorts the selected lines.
+    pub(super) fn sort_lines(&mut self) -> EventHandlerOutcome {
+        self.each_block(|buffer, block| {
+            let mut lines: Vec<_> = buffer.text[block.clone()].split('\n').collect();
+            lines.sort_unstable();
+            let replacement = lines.join("\n");
+
+            if replacement == buffer.text[block.clone()] {
+                return false;
+            }
+
+            buffer.edit_mapped(block.clone(), &replacement, |offset| match offset {
+                offset if offset == block.end => block.end,
+                _ => block.start,
+            });
+            true
+        })
+    }
+
+    /// Swaps the graphemes around each cursor, or the two before it at the end of a
+    /// line, and moves the cursor past them.
+    pub(super) fn transpose(&mut self) -> EventHandlerOutcome {
+        self.each_selection(false, |buffer| {
+            let cursor = buffer.cursor;
+            let line_start = buffer.line_start(cursor);
+            let line_end = line_start + grid::line_at(&buffer.text, line_start).len();
+            let middle = match cursor == line_end {
+                true => buffer.previous_boundary(cursor),
+                false => Some(cursor),
+            };
+
+            let Some(middle) = middle.filter(|&middle| middle > line_start) else {
+                return EventHandlerOutcome::None;
+            };
+            let (Some(start), Some(end)) = (
+                buffer.previous_boundary(middle),
+                buffer.next_boundary(middle),
+            ) else {
+                return EventHandlerOutcome::None;
+            };
+
+            let swapped = format!(
+                "{}{}",
+                &buffer.text[middle..end],
+                &buffer.text[start..middle]
+            );
+            buffer.edit(start..end, &swapped);
+            EventHandlerOutcome::Redraw
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::text_buffer::FontConfig;
+    use crate::buffer::{Buffer, BufferEvent};
+    use crate::fonts::FontCollection;
+    use crate::grid::GridSettings;
+    use crate::indent::IndentStyle;
+    use std::path::PathBuf;
+
+    /// Buffer for a file at `path` with `marked` text: `|` marks cursors, the last of which
+    /// is the primary one, and `<` the anchor of the next cursor's selection.
+    fn buffer(path: &str, marked: &str) -> TextBuffer {
+        let font = FontConfig {
+            scale: 16.0,
+            fonts: FontCollection::embedded(),
+            grid: GridSettings::default(),
+            indent: IndentStyle::default(),
+        };
+        let mut text = String::new();
+        let mut selections = Vec::new();
+        let mut anchor = None;
+
+        for c in marked.chars() {
+            match c {
+                '<' => anchor = Some(text.len()),
+                '|' => selections.push(Selection {
+                    cursor: text.len(),
+                    anchor: anchor.take(),
+                }),
+                c => text.push(c),
+            }
+        }
+
+        let mut buffer = TextBuffer::from_file(font, PathBuf::from(path), text);
+        buffer.set_primary(selections.pop().expect("a cursor"));
+        buffer.secondary = selections;
+        buffer
+    }
+
+    /// Text of `buffer` marked like `buffer` reads it.
+    fn marked(buffer: &TextBuffer) -> String {
+        let mut marks = Vec::new();
+        for selection in buffer.secondary.iter().chain([&buffer.primary()]) {
+            marks.push((selection.cursor, '|'));
+            if let Some(anchor) = selection.anchor.filter(|&a| a != selection.cursor) {
+                marks.push((anchor, '<'));
+            }
+        }
+        marks.sort();
+
+        let mut text = buffer.text.clone();
+        for (offset, mark) in marks.into_iter().rev() {
+            text.insert(offset, mark);
+        }
+        text
+    }
+
+    fn send(buffer: &mut TextBuffer, event: BufferEvent) -> EventHandlerOutcome {
+        buffer.handle_events(event)
+    }
+
+    #[test]
+    fn toggles_line_comments_at_the_shallowest_indentation() {
+        let mut buffer = buffer("a.rs", "fn f() {\n<    a();\n\n  b|();\n}");
+
+        send(&mut buffer, BufferEvent::ToggleLineComment);
+        // Blank lines between code stay blank
+        assert_eq!(marked(&buffer), "fn f() {\n<  //   a();\n\n  // b|();\n}");
+
+        send(&mut buffer, BufferEvent::ToggleLineComment);
+        assert_eq!(marked(&buffer), "fn f() {\n<    a();\n\n  b|();\n}");
+
+        send(&mut buffer, BufferEvent::Undo);
+        assert_eq!(buffer.text, "fn f() {\n  //   a();\n\n  // b();\n}");
+    }
+
+    #[test]
+    fn comments_out_lines_unless_all_of_them_are() {
+        let mut buffer = buffer("a.py", "<# a\nb|");
+
+        send(&mut buffer, BufferEvent::ToggleLineComment);
+        assert_eq!(buffer.text, "# # a\n# b");
+
+        send(&mut buffer, BufferEvent::ToggleLineComment);
+        assert_eq!(buffer.text, "# a\nb");
+    }
+
+    #[test]
+    fn wraps_lines_in_block_comments_without_line_comments() {
+        let mut buffer = buffer("a.css", "a {}|");
+
+        send(&mut buffer, BufferEvent::ToggleLineComment);
+        assert_eq!(buffer.text, "/* a {} */");
+
+        send(&mut buffer, BufferEvent::ToggleBlockComment);
+        assert_eq!(buffer.text, "a {}");
+    }
+
+    #[test]
+    fn moves_lines_with_their_cursors() {
+        let mut buffer = buffer("a.txt", "a\nb|\nc");
+
+        send(&mut buffer, BufferEvent::MoveLines { down: true });
+        assert_eq!(marked(&buffer), "a\nc\nb|");
+
+        send(&mut buffer, BufferEvent::MoveLines { down: false });
+        send(&mut buffer, BufferEvent::MoveLines { down: false });
+        assert_eq!(marked(&buffer), "b|\na\nc");
+
+        let outcome = send(&mut buffer, BufferEvent::MoveLines { down: false });
+        assert!(matches!(outcome, EventHandlerOutcome::None));
+        assert_eq!(marked(&buffer), "b|\na\nc");
+    }
+
+    #[test]
+    fn moves_the_lines_of_every_cursor() {
+        let mut buffer = buffer("a.txt", "a|\nb\nc|\nd");
+
+        send(&mut buffer, BufferEvent::MoveLines { down: true });
+        assert_eq!(marked(&buffer), "b\na|\nd\nc|");
+
+        // All of them undo in one step
+        send(&mut buffer, BufferEvent::Undo);
+        assert_eq!(buffer.text, "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn duplicates_lines_below_themselves() {
+        let mut buffer = buffer("a.txt", "a|b\nc");
+
+        send(&mut buffer, BufferEvent::DuplicateLines);
+        assert_eq!(marked(&buffer), "ab\na|b\nc");
+    }
+
+    #[test]
+    fn deletes_lines_and_merges_the_cursors_left_on_one() {
+        let mut buffer = buffer("a.txt", "a|\nb\nc|");
+
+        send(&mut buffer, BufferEvent::DeleteLines);
+        assert_eq!(marked(&buffer), "|b");
+        assert!(buffer.secondary.is_empty());
+
+        send(&mut buffer, BufferEvent::Undo);
+        assert_eq!(buffer.text, "a\nb\nc");
+    }
+
+    #[test]
+    fn joins_lines() {
+        let mut buffer = buffer("a.txt", "a  \n   b|\nc");
+
+        send(&mut buffer, BufferEvent::JoinLines);
+        assert_eq!(marked(&buffer), "a  \n   b| c");
+
+        let mut buffer = self::buffer("a.txt", "<a\n  b\n  c|");
+        send(&mut buffer, BufferEvent::JoinLines);
+        assert_eq!(marked(&buffer), "<a b c|");
+    }
+
+    #[test]
+    fn sorts_lines() {
+        let mut buffer = buffer("a.txt", "<c\na\nb|");
+
+        send(&mut buffer, BufferEvent::SortLines);
+        assert_eq!(marked(&buffer), "<a\nb\nc|");
+
+        let outcome = send(&mut buffer, BufferEvent::SortLines);
+        assert!(matches!(outcome, EventHandlerOutcome::None));
+    }
+
+    #[test]
+    fn transposes_around_each_cursor() {
+        let mut buffer = buffer("a.txt", "ab|c\nab|");
+
+        send(&mut buffer, BufferEvent::Transpose);
+        // At the end of a line, the two graphemes before the cursor swap
+        assert_eq!(marked(&buffer), "acb|\nba|");
+    }
+
+    #[test]
+    fn types_at_every_cursor_and_undoes_them_together() {
+        let mut buffer = buffer("a.txt", "a|\nb|");
+
+        send(&mut buffer, BufferEvent::InsertChar('x'));
+        assert_eq!(marked(&buffer), "ax|\nbx|");
+
+        send(&mut buffer, BufferEvent::Undo);
+        assert_eq!(buffer.text, "a\nb");
+    }
+
+    #[test]
+    fn merges_cursors_that_run_into_each_other() {
+        let mut buffer = buffer("a.txt", "a|b|c");
+
+        send(&mut buffer, BufferEvent::DeleteBackward);
+        assert_eq!(marked(&buffer), "|c");
+        assert!(buffer.secondary.is_empty());
+    }
+}
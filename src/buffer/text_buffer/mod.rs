@@ -0,0 +1,532 @@
+use crate::buffer::history::History;
+use crate::buffer::{BoundingBox, Buffer, BufferEvent, EventHandlerOutcome, RenderContext};
+use crate::folding::{self, FoldRegion, Folds};
+use crate::fonts::{FontCollection, FontStyle};
+use crate::git::hunks::{self, Hunk};
+use crate::git::GitFile;
+use crate::grid::{self, GridMetrics, GridSettings, TextGrid};
+use crate::indent::{IndentKind, IndentStyle};
+use crate::scene::Scene;
+use crate::shaping::{self, ShapedLine};
+use crate::syntax::SyntaxSpans;
+use crate::tasks::messages::{Problem, Severity};
+use selection::Selection;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+mod decorations;
+mod editing;
+mod lines;
+mod render;
+mod selection;
+
+/// Width of the gutter left of the text, where fold and change markers are drawn
+const GUTTER_COLUMNS: usize = 2;
+/// Blank cells between the end of the cursor line and its blame
+const BLAME_GAP_COLUMNS: usize = 4;
+
+pub struct TextBuffer {
+    text: String,
+    config: FontConfig,
+    /// Byte offset of the cursor, always on a grapheme boundary
+    cursor: usize,
+    /// Other end of the selection, which runs to the cursor
+    anchor: Option<usize>,
+    /// Cursors added next to the primary one, which every edit and motion applies to
+    secondary: Vec<Selection>,
+    indent: IndentStyle,
+    history: History,
+    path: Option<PathBuf>,
+    dirty: bool,
+    /// Contents of the file changed on disk while there were unsaved changes
+    conflict: Option<String>,
+    /// The conflict is with changes recovered from a swap file, rather than a change on disk
+    recovered: bool,
+    zoom: f32,
+    /// Shaped lines of `text`, dropped on every edit
+    shaped: Option<Vec<ShapedLine>>,
+    /// Layout of `shaped` on the cell grid, dropped on every edit and when the wrap width changes
+    grid: Option<TextGrid>,
+    /// Strings and comments of `text`, dropped on every edit
+    syntax: Option<SyntaxSpans>,
+    /// Foldable regions of `text`, dropped on every edit
+    regions: Option<Vec<FoldRegion>>,
+    folds: Folds,
+    git: Option<Arc<GitFile>>,
+    /// Lines changed since staging, dropped on every edit
+    hunks: Option<Vec<Hunk>>,
+    /// Committed line each line is unchanged from, dropped on every edit
+    origins: Option<Vec<Option<usize>>>,
+    /// Byte ranges of the problems found by the last task, moved along with edits around
+    /// them and dropped by edits inside them
+    problems: Vec<(Range<usize>, Severity)>,
+    /// Cell size the buffer was last drawn with, to map clicks to cells
+    metrics: Option<GridMetrics>,
+}
+
+#[derive(Clone)]
+pub struct FontConfig {
+    pub scale: f32,
+    pub fonts: FontCollection,
+    pub grid: GridSettings,
+    /// Indentation of new buffers and of files it can't be detected in
+    pub indent: IndentStyle,
+}
+
+impl TextBuffer {
+    pub fn new(config: FontConfig) -> Self {
+        let mut buffer = Self {
+            text: String::new(),
+            indent: config.indent,
+            config,
+            cursor: 0,
+            anchor: None,
+            secondary: Vec::new(),
+            history: History::new(),
+            path: None,
+            dirty: false,
+            conflict: None,
+            recovered: false,
+            zoom: 1.0,
+            shaped: None,
+            grid: None,
+            syntax: None,
+            regions: None,
+            folds: Folds::default(),
+            git: None,
+            hunks: None,
+            origins: None,
+            problems: Vec::new(),
+            metrics: None,
+        };
+
+        buffer.set_indent_style(buffer.indent);
+        buffer
+    }
+
+    pub fn from_file(config: FontConfig, path: PathBuf, text: String) -> Self {
+        Self {
+            path: Some(path),
+            ..Self::from_text(config, text)
+        }
+    }
+
+    /// Buffer that isn't backed by a file.
+    pub fn from_text(config: FontConfig, text: String) -> Self {
+        let tab_width = config.grid.tab_width;
+        let mut buffer = Self::new(config);
+
+        // Tab indented files keep the configured tab stops
+        if let Some(mut style) = IndentStyle::detect(&text) {
+            if style.kind == IndentKind::Tabs {
+                style.width = tab_width;
+            }
+            buffer.set_indent_style(style);
+        }
+
+        buffer.text = text;
+        buffer
+    }
+
+    /// Visible caret closest to `offset`: past the folds hiding it when moving
+    /// `forward`, else at the end of their header line.
+    fn skip_folds(&self, offset: usize, forward: bool) -> usize {
+        let Some(fold) = self.folds.hiding(&self.text, offset) else {
+            return offset;
+        };
+
+        match forward && self.folds.hiding(&self.text, fold.end).is_none() {
+            true => fold.end,
+            false => folding::header_end(&self.text, &fold),
+        }
+    }
+
+    /// Moves the cursor and the selection's anchor out of folded lines.
+    fn leave_folds(&mut self) {
+        self.cursor = self.skip_folds(self.cursor, false);
+        if self
+            .anchor
+            .is_some_and(|anchor| self.folds.hiding(&self.text, anchor).is_some())
+        {
+            self.anchor = None;
+        }
+    }
+
+    /// Folds or unfolds the region under the line starting at `start`. Returns whether
+    /// the line opens a region.
+    fn toggle_fold_at(&mut self, start: usize) -> bool {
+        let Some(region) = self
+            .fold_regions()
+            .iter()
+            .find(|region| region.header == start)
+            .cloned()
+        else {
+            return false;
+        };
+
+        self.folds.toggle(region.hidden);
+        self.leave_folds();
+        self.grid = None;
+        true
+    }
+
+    /// Folds or unfolds the region opened by the cursor's line, else folds the innermost
+    /// region the cursor is in.
+    fn toggle_fold(&mut self) -> EventHandlerOutcome {
+        let start = self.line_start(self.cursor);
+        if self.toggle_fold_at(start) {
+            return EventHandlerOutcome::Redraw;
+        }
+
+        let enclosing = self
+            .fold_regions()
+            .iter()
+            .rev()
+            .find(|region| region.header < start && region.hidden.contains(&start))
+            .map(|region| region.header);
+
+        match enclosing.is_some_and(|header| self.toggle_fold_at(header)) {
+            true => EventHandlerOutcome::Redraw,
+            false => EventHandlerOutcome::None,
+        }
+    }
+
+    fn fold_all(&mut self) -> EventHandlerOutcome {
+        for region in self.fold_regions().to_vec() {
+            if !self.folds.is_folded(&region.hidden) {
+                self.folds.toggle(region.hidden);
+            }
+        }
+
+        self.leave_folds();
+        self.grid = None;
+        EventHandlerOutcome::Redraw
+    }
+
+    fn unfold_all(&mut self) -> EventHandlerOutcome {
+        if self.folds.ranges().is_empty() {
+            return EventHandlerOutcome::None;
+        }
+
+        self.folds.clear();
+        self.grid = None;
+        EventHandlerOutcome::Redraw
+    }
+
+    /// The text with its strings and comments, scanned again if it changed.
+    fn syntax(&mut self) -> (&str, &SyntaxSpans) {
+        let text = &self.text;
+
+        (
+            text,
+            self.syntax.get_or_insert_with(|| SyntaxSpans::scan(text)),
+        )
+    }
+
+    /// Foldable regions of the text, found again if it changed.
+    fn fold_regions(&mut self) -> &[FoldRegion] {
+        if self.regions.is_none() {
+            let tab_width = self.config.grid.tab_width;
+            let (text, syntax) = self.syntax();
+            self.regions = Some(folding::regions(text, syntax, tab_width));
+        }
+
+        self.regions.as_deref().unwrap()
+    }
+
+    /// Changes since staging, none outside of repositories.
+    fn hunks(&mut self) -> &[Hunk] {
+        if self.hunks.is_none() {
+            self.hunks = Some(match &self.git {
+                Some(git) => hunks::hunks(git.index.as_deref().unwrap_or(""), &self.text),
+                None => Vec::new(),
+            });
+        }
+
+        self.hunks.as_deref().expect("hunks computed above")
+    }
+
+    /// Commit that last changed the cursor line, described on a single line.
+    fn blame(&mut self) -> Option<String> {
+        let git = self.git.clone()?;
+        let head = git.head.as_deref()?;
+        let line = self.cursor_line();
+        let text = &self.text;
+        let origins = self
+            .origins
+            .get_or_insert_with(|| hunks::origins(head, text));
+
+        Some(
+            match origins[line].and_then(|origin| git.blame.get(origin)) {
+                Some(commit) => commit.describe(SystemTime::now()),
+                None => "Not committed yet".to_string(),
+            },
+        )
+    }
+
+    /// Index of the line the cursor is on.
+    fn cursor_line(&self) -> usize {
+        self.text[..self.cursor].matches('\n').count()
+    }
+
+    fn jump_to_hunk(&mut self, forward: bool) -> EventHandlerOutcome {
+        let line = self.cursor_line();
+        let starts: Vec<_> = self.hunks().iter().map(|hunk| hunk.new.start).collect();
+
+        // Jumps wrap around the ends of the text
+        let target = match forward {
+            true => starts
+                .iter()
+                .find(|&&start| start > line)
+                .or(starts.first()),
+            false => starts
+                .iter()
+                .rev()
+                .find(|&&start| start < line)
+                .or(starts.last()),
+        };
+        let Some(&target) = target else {
+            return EventHandlerOutcome::None;
+        };
+
+        let offset = hunks::line_bytes(&self.text, target..target).start;
+        self.secondary.clear();
+        self.place_cursor(offset)
+    }
+
+    fn revert_hunk(&mut self) -> EventHandlerOutcome {
+        let (Some(git), Some(hunk)) = (self.git.clone(), self.hunk_at_cursor()) else {
+            return EventHandlerOutcome::None;
+        };
+
+        let (range, staged) = hunks::revert(git.index.as_deref().unwrap_or(""), &self.text, &hunk);
+        self.secondary.clear();
+        self.edit(range.clone(), staged);
+        self.cursor = range.start;
+        EventHandlerOutcome::Redraw
+    }
+
+    /// The text with its shaped lines and their layout on the grid, redone if stale.
+    fn text_grid(&mut self, wrap_columns: Option<usize>) -> (&str, &[ShapedLine], &TextGrid) {
+        let fonts = &self.config.fonts;
+        let text = &self.text;
+
+        let shaped = self.shaped.get_or_insert_with(|| {
+            grid::lines(text)
+                .map(|line| shaping::shape_line(fonts, line, FontStyle::Regular))
+                .collect()
+        });
+
+        if self.grid.as_ref().map(TextGrid::wrap_columns) != Some(wrap_columns) {
+            self.grid = Some(TextGrid::new(
+                text,
+                shaped,
+                &self.config.grid,
+                wrap_columns,
+                &self.folds,
+            ));
+        }
+
+        (text, shaped, self.grid.as_ref().unwrap())
+    }
+}
+
+impl Buffer for TextBuffer {
+    fn draw(&mut self, frame: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        self.render(frame, ctx, scene);
+    }
+
+    fn handle_events(&mut self, event: BufferEvent) -> EventHandlerOutcome {
+        match event {
+            BufferEvent::Insert(text) => self.each_selection(false, |b| b.insert(&text)),
+            BufferEvent::InsertChar('\t') if self.spans_lines() => self.indent_lines(),
+            BufferEvent::InsertChar(c) => self.each_selection(false, |b| b.insert_char(c)),
+            BufferEvent::Newline => self.each_selection(false, Self::newline),
+            BufferEvent::Indent => self.indent_lines(),
+            BufferEvent::Outdent => self.outdent_lines(),
+            BufferEvent::JumpToMatch => self.each_selection(false, Self::jump_to_match),
+            BufferEvent::ToggleFold => self.toggle_fold(),
+            BufferEvent::FoldAll => self.fold_all(),
+            BufferEvent::UnfoldAll => self.unfold_all(),
+            BufferEvent::ToggleLineComment => self.toggle_line_comment(),
+            BufferEvent::ToggleBlockComment => self.toggle_block_comment(),
+            BufferEvent::MoveLines { down } => self.move_lines(down),
+            BufferEvent::DuplicateLines => self.duplicate_lines(),
+            BufferEvent::JoinLines => self.join_lines(),
+            BufferEvent::DeleteLines => self.delete_lines(),
+            BufferEvent::SortLines => self.sort_lines(),
+            BufferEvent::Transpose => self.transpose(),
+            BufferEvent::DeleteBackward => self.each_selection(false, Self::delete_backward),
+            BufferEvent::DeleteForward => self.each_selection(false, Self::delete_forward),
+            BufferEvent::MoveCursor { motion, select } => {
+                self.each_selection(false, |b| b.move_cursor(motion, select))
+            }
+            BufferEvent::AddCursor { below } => self.add_cursor(below),
+            BufferEvent::SingleCursor => self.single_cursor(),
+            BufferEvent::JumpToHunk { forward } => self.jump_to_hunk(forward),
+            BufferEvent::RevertHunk => self.revert_hunk(),
+            BufferEvent::Click { x, y } => {
+                self.secondary.clear();
+                self.click(x, y)
+            }
+            BufferEvent::GoTo { line, column } => self.go_to(line, column),
+            BufferEvent::Undo => self.undo(),
+            BufferEvent::Redo => self.redo(),
+            BufferEvent::FileOperation(_)
+            | BufferEvent::Key(_)
+            | BufferEvent::ScrollPage { .. } => EventHandlerOutcome::None,
+        }
+    }
+
+    fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom;
+    }
+
+    fn indent_style(&self) -> Option<IndentStyle> {
+        Some(self.indent)
+    }
+
+    fn set_indent_style(&mut self, style: IndentStyle) {
+        self.indent = style;
+
+        // Tabs are as wide as an indentation level
+        if style.kind == IndentKind::Tabs && self.config.grid.tab_width != style.width {
+            self.config.grid.tab_width = style.width;
+            self.grid = None;
+        }
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(&self.text)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    fn cursor_position(&self) -> Option<(usize, usize)> {
+        let start = self.line_start(self.cursor);
+
+        Some((
+            self.cursor_line(),
+            self.text[start..self.cursor].chars().count(),
+        ))
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn mark_saved(&mut self) {
+        self.dirty = false;
+    }
+
+    fn mark_unsaved(&mut self) {
+        self.dirty = true;
+    }
+
+    fn reload(&mut self, text: String) {
+        let (range, inserted) = folding::changed_range(&self.text, &text);
+
+        if !range.is_empty() || inserted > 0 {
+            // The primary cursor moves along with the others through the edit
+            self.secondary.push(self.primary());
+            self.edit(range.clone(), &text[range.start..range.start + inserted]);
+            let primary = self.secondary.pop().expect("pushed above");
+            self.set_primary(primary);
+            self.merge_selections();
+        }
+
+        self.conflict = None;
+        self.dirty = false;
+    }
+
+    fn conflict(&self) -> Option<&str> {
+        self.conflict.as_deref()
+    }
+
+    fn set_conflict(&mut self, text: Option<String>) {
+        self.conflict = text;
+        self.recovered = false;
+    }
+
+    fn recover(&mut self, text: String) {
+        let on_disk = self.text.clone();
+
+        self.reload(text);
+        self.dirty = true;
+        self.conflict = Some(on_disk);
+        self.recovered = true;
+    }
+
+    fn git_file(&self) -> Option<Arc<GitFile>> {
+        self.git.clone()
+    }
+
+    fn set_git(&mut self, file: Option<Arc<GitFile>>) {
+        self.git = file;
+        self.hunks = None;
+        self.origins = None;
+    }
+
+    fn hunk_at_cursor(&mut self) -> Option<Hunk> {
+        let line = self.cursor_line();
+
+        self.hunks()
+            .iter()
+            .find(|hunk| hunk.contains(line))
+            .cloned()
+    }
+
+    fn set_problems(&mut self, problems: Vec<Arc<Problem>>) {
+        self.problems = problems
+            .iter()
+            .map(|problem| {
+                let (line, column) = problem.start;
+                let start = position_offset(&self.text, line, column);
+                let (line, column) = problem.end;
+                let end = position_offset(&self.text, line, column);
+
+                // Problems between two characters get the one after, or the last one
+                let range = match (start < end, self.next_boundary(start)) {
+                    (true, _) => start..end,
+                    (false, Some(next)) => start..next,
+                    (false, None) => self.previous_boundary(start).unwrap_or(start)..start,
+                };
+                (range, problem.severity)
+            })
+            .filter(|(range, _)| !range.is_empty())
+            .collect();
+    }
+}
+
+/// Byte offset of the `column`th character of `line`, both from 0, or of the end of the
+/// line if it's shorter.
+fn position_offset(text: &str, line: usize, column: usize) -> usize {
+    let start = hunks::line_bytes(text, line..line).start;
+    let line = grid::line_at(text, start);
+
+    start
+        + line
+            .char_indices()
+            .nth(column)
+            .map_or(line.len(), |(i, _)| i)
+}
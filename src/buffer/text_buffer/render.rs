@@ -0,0 +1,279 @@
+//! Drawing the text with its cursors, selections and decorations.
+
+use super::decorations::{self, Placement};
+use super::selection::Selection;
+use super::{TextBuffer, BLAME_GAP_COLUMNS, GUTTER_COLUMNS};
+use crate::brackets;
+use crate::buffer::{BoundingBox, RenderContext};
+use crate::fonts::FontStyle;
+use crate::grid::GridMetrics;
+use crate::scene::{Glyph, Label, Quad, Scene, Span};
+use ab_glyph::point;
+use std::path::Path;
+
+impl TextBuffer {
+    pub(super) fn render(&mut self, frame: BoundingBox, ctx: &RenderContext, scene: &mut Scene) {
+        let color = ctx.theme.foreground.0;
+        let scale = ctx.font_scale(self.config.scale * self.zoom);
+        let fonts = self.config.fonts.clone();
+        let metrics = GridMetrics::new(&fonts, scale);
+        self.metrics = Some(metrics);
+
+        // The last row asks what to do about a file changed on disk
+        let banner = self.conflict.is_some().then(|| BoundingBox {
+            top: frame.top + (frame.height - metrics.line_height).max(0.0),
+            height: metrics.line_height.min(frame.height),
+            ..frame
+        });
+        let frame = BoundingBox {
+            height: frame.height - banner.map_or(0.0, |banner| banner.height),
+            ..frame
+        };
+
+        let gutter = BoundingBox {
+            width: (GUTTER_COLUMNS as f32 * metrics.cell_width).min(frame.width),
+            ..frame
+        };
+        let bb = BoundingBox {
+            left: frame.left + gutter.width,
+            width: frame.width - gutter.width,
+            ..frame
+        };
+
+        let wrap_columns = self
+            .config
+            .grid
+            .soft_wrap
+            .then(|| ((bb.width / metrics.cell_width) as usize).max(1));
+        let cursor = self.cursor;
+        let selections: Vec<_> = self
+            .secondary
+            .iter()
+            .chain([&self.primary()])
+            .copied()
+            .collect();
+        let bracket_pair = {
+            let (text, syntax) = self.syntax();
+            brackets::pair_at_caret(text, cursor, syntax)
+        };
+        let tab_width = self.config.grid.tab_width;
+        let folds = self.folds.clone();
+        let headers: Vec<_> = self
+            .fold_regions()
+            .iter()
+            .map(|region| (region.header, folds.is_folded(&region.hidden)))
+            .collect();
+        let hunks = self.hunks().to_vec();
+        let problems = self.problems.clone();
+        let blame = ctx.view.blame.then(|| self.blame()).flatten();
+        let (text, shaped, grid) = self.text_grid(wrap_columns);
+        let visible_rows = (bb.height / metrics.line_height).ceil() as usize;
+
+        let mut glyphs = Vec::new();
+        for (start, first_row, line_grid) in grid.lines() {
+            if first_row >= visible_rows {
+                break;
+            }
+
+            let shaped = &shaped[grid.line_index(start)];
+            let mut cluster_left = 0.0;
+            let mut previous = None;
+
+            for glyph in &shaped.glyphs {
+                // Tabs and other control characters are blank cells rather than the
+                // font's idea of a glyph for them
+                let cluster_text = &text[start + glyph.cluster.start..start + glyph.cluster.end];
+                if cluster_text.chars().all(char::is_control) {
+                    continue;
+                }
+
+                // Glyphs are placed in the cell where their cluster starts, keeping their
+                // offsets within the cluster for combining marks and ligatures
+                let first = previous != Some(&glyph.cluster);
+                if first {
+                    cluster_left = glyph.x;
+                    previous = Some(&glyph.cluster);
+                }
+
+                let cell = line_grid.span_start(glyph.cluster.clone());
+                let position = point(
+                    bb.left
+                        + cell.column as f32 * metrics.cell_width
+                        + (glyph.x - cluster_left + glyph.x_offset) * scale,
+                    bb.top + (first_row + cell.row) as f32 * metrics.line_height + metrics.ascent
+                        - glyph.y_offset * scale,
+                );
+
+                let cluster = start + glyph.cluster.start..start + glyph.cluster.end;
+                glyphs.push(Glyph {
+                    glyph: glyph.glyph_id.with_scale_and_position(scale, position),
+                    font_id: glyph.font_id,
+                    color,
+                    text: match first {
+                        true => text[cluster].to_string(),
+                        false => String::new(),
+                    },
+                });
+            }
+        }
+
+        let carets: Vec<_> = selections
+            .iter()
+            .map(|selection| grid.caret(selection.cursor))
+            .collect();
+
+        let place = Placement {
+            bb,
+            gutter,
+            metrics,
+            visible_rows,
+            line_width: ctx.scale_factor.max(1.0),
+        };
+        let mut quads = vec![Quad {
+            aabb: [
+                gutter.left,
+                gutter.top,
+                gutter.left + gutter.width,
+                gutter.top + gutter.height,
+            ],
+            z_pos: 0.0,
+            color: ctx.theme.gutter.0,
+        }];
+
+        for selection in selections.iter().map(Selection::range) {
+            if !selection.is_empty() {
+                quads.extend(decorations::selection(
+                    text, grid, selection, &place, ctx.theme,
+                ));
+            }
+        }
+
+        if let Some((bracket, matching)) = bracket_pair {
+            for offset in [bracket, matching]
+                .into_iter()
+                .filter(|&o| !grid.is_hidden(o))
+            {
+                let (row, cell) = grid.cell(offset);
+                quads.push(Quad {
+                    aabb: place.cell_rect(row, cell),
+                    z_pos: 0.0,
+                    color: ctx.theme.bracket_match.0,
+                });
+            }
+        }
+
+        quads.extend(decorations::changes(grid, &hunks, &place, ctx.theme));
+        quads.extend(decorations::squiggles(
+            text, grid, &problems, &place, ctx.theme,
+        ));
+
+        if ctx.view.indent_guides {
+            quads.extend(decorations::indent_guides(
+                text, grid, tab_width, cursor, &place, ctx.theme,
+            ));
+        }
+
+        if ctx.view.whitespace {
+            let (markers, highlights) = decorations::whitespace(
+                text,
+                grid,
+                &fonts,
+                scale,
+                ctx.theme.whitespace.0,
+                &place,
+                ctx.theme,
+            );
+            glyphs.extend(markers);
+            quads.extend(highlights);
+        }
+
+        glyphs.extend(decorations::folds(
+            text,
+            grid,
+            &headers,
+            &fonts,
+            scale,
+            ctx.theme.fold_marker.0,
+            &place,
+        ));
+
+        // The commit of the cursor line goes a few cells past its end
+        let blame = blame.and_then(|blame| {
+            let line_end = cursor + text[cursor..].find('\n').unwrap_or(text.len() - cursor);
+            let (row, column) = grid.caret(line_end);
+            let left = bb.left + (column + BLAME_GAP_COLUMNS) as f32 * metrics.cell_width;
+            let top = bb.top + row as f32 * metrics.line_height;
+
+            (row < visible_rows && left < bb.left + bb.width).then_some((blame, left, top))
+        });
+
+        // Text goes under decorations and the cursor, and stops above the banner
+        scene.glyphs.extend(glyphs);
+        scene.clip = Some(frame);
+        scene.overlays.extend(quads);
+
+        // Draw cursors. The primary one comes last, and is the one typed at.
+        for (row, column) in carets {
+            let x = bb.left + column as f32 * metrics.cell_width;
+            let y = bb.top + row as f32 * metrics.line_height;
+            scene.cursor = (row < visible_rows).then_some((x, y));
+            scene.overlays.push(Quad {
+                aabb: [x, y, x + scale * 0.1, y + metrics.line_height],
+                z_pos: 0.0,
+                color: ctx.theme.cursor.0,
+            });
+        }
+
+        if let Some((blame, left, top)) = blame {
+            scene.labels.push(
+                Label::new(
+                    (left, top),
+                    (bb.left + bb.width - left, metrics.line_height),
+                )
+                .add_span(
+                    Span::new(blame)
+                        .with_scale(scale)
+                        .with_color(ctx.theme.blame.0)
+                        .with_font_id(fonts.primary(FontStyle::Regular)),
+                ),
+            );
+        }
+
+        if let Some(banner) = banner {
+            let name = self
+                .path
+                .as_deref()
+                .and_then(Path::file_name)
+                .map_or("The file".into(), |name| name.to_string_lossy());
+            let question = match self.recovered {
+                true => format!(
+                    "Recovered unsaved changes to {}. y: load the file instead, n: keep them, d: compare",
+                    name
+                ),
+                false => format!(
+                    "{} changed on disk. y: load it, n: keep your changes, d: compare",
+                    name
+                ),
+            };
+
+            scene.overlays.push(Quad {
+                aabb: [
+                    banner.left,
+                    banner.top,
+                    banner.left + banner.width,
+                    banner.top + ctx.scale_factor.max(1.0),
+                ],
+                z_pos: 0.0,
+                color: ctx.theme.split_border.0,
+            });
+            scene.labels.push(
+                Label::new((banner.left, banner.top), (banner.width, banner.height)).add_span(
+                    Span::new(question)
+                        .with_scale(scale)
+                        .with_color(color)
+                        .with_font_id(fonts.primary(FontStyle::Regular)),
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Cursors and their selections: adding, merging and moving them around the text.
+
+use super::{position_offset, TextBuffer, GUTTER_COLUMNS};
+use crate::brackets;
+use crate::buffer::{EventHandlerOutcome, Motion};
+use crate::grid::{self, TextGrid};
+use std::ops::Range;
+use unicode_segmentation::GraphemeCursor;
+
+/// A cursor and the other end of its selection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Selection {
+    pub(super) cursor: usize,
+    pub(super) anchor: Option<usize>,
+}
+
+/// Whether selections over `a` and then `b`, which doesn't start before it, should merge.
+/// Carets merge with whatever they touch, selections only with what they overlap.
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    b.start < a.end || b.start == a.end && (a.is_empty() || b.is_empty())
+}
+
+impl Selection {
+    pub(super) fn range(&self) -> Range<usize> {
+        let anchor = self.anchor.unwrap_or(self.cursor);
+
+        anchor.min(self.cursor)..anchor.max(self.cursor)
+    }
+}
+
+impl TextBuffer {
+    /// Selected range, if the selection isn't empty.
+    pub(super) fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.anchor.filter(|&anchor| anchor != self.cursor)?;
+
+        Some(anchor.min(self.cursor)..anchor.max(self.cursor))
+    }
+
+    pub(super) fn primary(&self) -> Selection {
+        Selection {
+            cursor: self.cursor,
+            anchor: self.anchor,
+        }
+    }
+
+    pub(super) fn set_primary(&mut self, selection: Selection) {
+        self.cursor = selection.cursor;
+        self.anchor = selection.anchor;
+    }
+
+    /// Applies `op` at every cursor in turn as if it were the only one, in text order or
+    /// `backward` from the last. Edits made at one cursor move the others along.
+    pub(super) fn each_selection(
+        &mut self,
+        backward: bool,
+        op: impl Fn(&mut Self) -> EventHandlerOutcome,
+    ) -> EventHandlerOutcome {
+        if self.secondary.is_empty() {
+            return op(self);
+        }
+
+        // The primary cursor is the last of `all`
+        let mut all = std::mem::take(&mut self.secondary);
+        all.push(self.primary());
+        let mut order: Vec<_> = (0..all.len()).collect();
+        order.sort_by_key(|&i| all[i].cursor);
+        if backward {
+            order.reverse();
+        }
+
+        let mut outcome = EventHandlerOutcome::None;
+        self.history.begin_group();
+        for i in order {
+            self.set_primary(all[i]);
+            self.secondary = all
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, selection)| *selection)
+                .collect();
+
+            if let EventHandlerOutcome::Redraw = op(self) {
+                outcome = EventHandlerOutcome::Redraw;
+            }
+
+            let mut others = std::mem::take(&mut self.secondary).into_iter();
+            all = (0..all.len())
+                .map(|j| match j == i {
+                    true => self.primary(),
+                    false => others.next().expect("one selection per cursor"),
+                })
+                .collect();
+        }
+        self.history.end_group();
+
+        self.set_primary(all.pop().expect("primary selection"));
+        self.secondary = all;
+        self.merge_selections();
+        outcome
+    }
+
+    /// Merges cursors that ran into each other, and selections that overlap.
+    pub(super) fn merge_selections(&mut self) {
+        // The second element marks the primary selection
+        let mut all: Vec<_> = self.secondary.drain(..).map(|s| (s, false)).collect();
+        all.push((self.primary(), true));
+        all.sort_by_key(|(selection, _)| selection.range().start);
+
+        let mut merged: Vec<(Selection, bool)> = Vec::new();
+        for (selection, primary) in all {
+            let range = selection.range();
+
+            match merged.last_mut() {
+                Some((last, last_primary)) if overlaps(&last.range(), &range) => {
+                    let union = last.range().start..last.range().end.max(range.end);
+                    if range == union {
+                        *last = selection;
+                    } else if last.range() != union {
+                        *last = Selection {
+                            cursor: union.end,
+                            anchor: Some(union.start),
+                        };
+                    }
+                    *last_primary |= primary;
+                }
+                _ => merged.push((selection, primary)),
+            }
+        }
+
+        let index = merged
+            .iter()
+            .position(|&(_, primary)| primary)
+            .expect("primary selection");
+        self.set_primary(merged.remove(index).0);
+        self.secondary = merged.into_iter().map(|(selection, _)| selection).collect();
+    }
+
+    /// Adds a cursor a row above the first cursor, or below the last one.
+    pub(super) fn add_cursor(&mut self, below: bool) -> EventHandlerOutcome {
+        let cursors = self.secondary.iter().map(|selection| selection.cursor);
+        let cursors = cursors.chain([self.cursor]);
+        let edge = match below {
+            true => cursors.max(),
+            false => cursors.min(),
+        }
+        .expect("primary cursor");
+
+        let target = self.vertical_target(edge, if below { 1 } else { -1 });
+        let Some(target) = target.filter(|&target| target != edge) else {
+            return EventHandlerOutcome::None;
+        };
+
+        self.secondary.push(Selection {
+            cursor: target,
+            anchor: None,
+        });
+        self.merge_selections();
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Leaves only the primary cursor.
+    pub(super) fn single_cursor(&mut self) -> EventHandlerOutcome {
+        if self.secondary.is_empty() {
+            return EventHandlerOutcome::None;
+        }
+
+        self.secondary.clear();
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn jump_to_match(&mut self) -> EventHandlerOutcome {
+        let cursor = self.cursor;
+        let (text, syntax) = self.syntax();
+
+        match brackets::pair_at_caret(text, cursor, syntax) {
+            Some((_, target)) => {
+                self.anchor = None;
+                self.place_cursor(target)
+            }
+            None => EventHandlerOutcome::None,
+        }
+    }
+
+    /// Moves the cursor, extending the selection with `select` and dropping it otherwise.
+    pub(super) fn move_cursor(&mut self, motion: Motion, select: bool) -> EventHandlerOutcome {
+        let start = self.line_start(self.cursor);
+        let end = start + grid::line_at(&self.text, start).len();
+        let anchor = self.anchor;
+
+        self.anchor = match select {
+            true => Some(anchor.unwrap_or(self.cursor)),
+            false => None,
+        };
+
+        let target = match motion {
+            Motion::Left => self.previous_boundary(self.cursor),
+            Motion::Right => self.next_boundary(self.cursor),
+            Motion::LineStart => Some(start),
+            Motion::LineEnd => Some(end),
+            Motion::Up => self.vertical_target(self.cursor, -1),
+            Motion::Down => self.vertical_target(self.cursor, 1),
+        };
+        let forward = matches!(motion, Motion::Right | Motion::Down | Motion::LineEnd);
+        let target = target.map(|target| self.skip_folds(target, forward));
+
+        match target.map(|target| self.place_cursor(target)) {
+            Some(EventHandlerOutcome::Redraw) => EventHandlerOutcome::Redraw,
+            _ if self.anchor != anchor => EventHandlerOutcome::Redraw,
+            _ => EventHandlerOutcome::None,
+        }
+    }
+
+    /// Offset `rows` visual rows away from `offset`, in the same column.
+    fn vertical_target(&mut self, offset: usize, rows: isize) -> Option<usize> {
+        let wrap_columns = self.grid.as_ref().and_then(TextGrid::wrap_columns);
+        let (text, _, grid) = self.text_grid(wrap_columns);
+        let (row, column) = grid.caret(offset);
+        let target = row.checked_add_signed(rows)?;
+
+        (target < grid.row_count()).then(|| grid.offset_at(text, target, column as f32))
+    }
+
+    /// Moves the cursor to the caret closest to `x`, `y`, in pixels from the top left
+    /// corner of the frame. Clicks in the gutter toggle the fold of their line.
+    pub(super) fn click(&mut self, x: f32, y: f32) -> EventHandlerOutcome {
+        let Some(metrics) = self.metrics else {
+            return EventHandlerOutcome::None;
+        };
+
+        let wrap_columns = self.grid.as_ref().and_then(TextGrid::wrap_columns);
+        let gutter_width = GUTTER_COLUMNS as f32 * metrics.cell_width;
+        let (text, _, grid) = self.text_grid(wrap_columns);
+        let row = (y.max(0.0) / metrics.line_height) as usize;
+        let row = row.min(grid.row_count().saturating_sub(1));
+
+        if x < gutter_width {
+            let offset = grid.offset_at(text, row, 0.0);
+            let start = self.line_start(offset);
+            return match self.toggle_fold_at(start) {
+                true => EventHandlerOutcome::Redraw,
+                false => EventHandlerOutcome::None,
+            };
+        }
+
+        let target = grid.offset_at(text, row, (x - gutter_width) / metrics.cell_width);
+
+        let had_selection = self.anchor.take().is_some();
+        match self.place_cursor(target) {
+            EventHandlerOutcome::None if had_selection => EventHandlerOutcome::Redraw,
+            outcome => outcome,
+        }
+    }
+
+    /// Moves the cursor to `offset`, unfolding the lines hiding it.
+    pub(super) fn place_cursor(&mut self, offset: usize) -> EventHandlerOutcome {
+        if offset == self.cursor {
+            return EventHandlerOutcome::None;
+        }
+
+        if self.folds.reveal(&self.text, offset) {
+            self.grid = None;
+        }
+        self.cursor = offset;
+        EventHandlerOutcome::Redraw
+    }
+
+    /// Drops the selection and the other cursors, and moves the cursor to `line` and
+    /// `column`.
+    pub(super) fn go_to(&mut self, line: usize, column: usize) -> EventHandlerOutcome {
+        self.secondary.clear();
+        self.anchor = None;
+        self.place_cursor(position_offset(&self.text, line, column));
+
+        EventHandlerOutcome::Redraw
+    }
+
+    pub(super) fn previous_boundary(&self, offset: usize) -> Option<usize> {
+        GraphemeCursor::new(offset, self.text.len(), true)
+            .prev_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+    }
+
+    pub(super) fn next_boundary(&self, offset: usize) -> Option<usize> {
+        GraphemeCursor::new(offset, self.text.len(), true)
+            .next_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+    }
+
+    /// Start offset of the line containing `offset`.
+    pub(super) fn line_start(&self, offset: usize) -> usize {
+        self.text[..offset].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// Offset of the line break ending the line containing `offset`, or the text's end.
+    pub(super) fn line_end(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |i| offset + i)
+    }
+}
@@ -0,0 +1,1255 @@
+use crate::app_state::AppState;
+use crate::buffer::explorer::Explorer;
+use crate::buffer::{diff, terminal};
+use crate::buffer::{BufferEvent, EventHandlerOutcome, FileOperation, Motion};
+use crate::git;
+use crate::indent::IndentKind;
+use crate::macros;
+use crate::scripting::{self, Hook, ScriptContext};
+use crate::shutdown;
+use crate::tasks;
+use crate::theme;
+use crate::watcher;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub type CommandResult = anyhow::Result<EventHandlerOutcome>;
+
+pub type CommandHandler = Arc<
+    dyn for<'a> Fn(&'a mut AppState, Vec<CommandArg>) -> BoxFuture<'a, CommandResult> + Send + Sync,
+>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Text,
+    Char,
+    Integer,
+    Number,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArgSpec {
+    pub name: String,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CommandArg {
+    Text(String),
+    Char(char),
+    Integer(i64),
+    Number(f64),
+}
+
+/// A request to run a command, as produced by keybindings or other callers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Invocation {
+    pub id: String,
+    pub args: Vec<CommandArg>,
+}
+
+#[derive(Clone)]
+pub struct Command {
+    pub id: String,
+    pub description: String,
+    pub args: Vec<ArgSpec>,
+    handler: CommandHandler,
+}
+
+#[derive(Clone)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+}
+
+impl ArgSpec {
+    pub fn required(name: &str, kind: ArgKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            optional: false,
+        }
+    }
+
+    pub fn optional(name: &str, kind: ArgKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            optional: true,
+        }
+    }
+}
+
+impl CommandArg {
+    pub fn kind(&self) -> ArgKind {
+        match self {
+            CommandArg::Text(_) => ArgKind::Text,
+            CommandArg::Char(_) => ArgKind::Char,
+            CommandArg::Integer(_) => ArgKind::Integer,
+            CommandArg::Number(_) => ArgKind::Number,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            CommandArg::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            CommandArg::Char(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            CommandArg::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            CommandArg::Number(n) => Some(*n),
+            CommandArg::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+}
+
+impl Invocation {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, arg: CommandArg) -> Self {
+        self.args.push(arg);
+        self
+    }
+}
+
+impl Command {
+    pub fn new<F>(id: &str, description: &str, handler: F) -> Self
+    where
+        F: for<'a> Fn(&'a mut AppState, Vec<CommandArg>) -> BoxFuture<'a, CommandResult>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            id: id.to_string(),
+            description: description.to_string(),
+            args: Vec::new(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn with_arg(mut self, spec: ArgSpec) -> Self {
+        self.args.push(spec);
+        self
+    }
+
+    fn check_args(&self, args: &[CommandArg]) -> anyhow::Result<()> {
+        if args.len() > self.args.len() {
+            bail!(
+                "{} takes at most {} arguments, got {}",
+                self.id,
+                self.args.len(),
+                args.len()
+            );
+        }
+
+        for (i, spec) in self.args.iter().enumerate() {
+            match args.get(i) {
+                // Integers are accepted wherever a number is expected
+                Some(CommandArg::Integer(_)) if spec.kind == ArgKind::Number => {}
+                Some(arg) if arg.kind() != spec.kind => {
+                    bail!(
+                        "{}: argument `{}` expects {:?}, got {:?}",
+                        self.id,
+                        spec.name,
+                        spec.kind,
+                        arg.kind()
+                    );
+                }
+                Some(_) => {}
+                None if spec.optional => {}
+                None => bail!("{}: missing argument `{}`", self.id, spec.name),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("id", &self.id)
+            .field("description", &self.description)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+
+        register_builtins(&mut registry);
+        macros::register_commands(&mut registry);
+        theme::register_commands(&mut registry);
+        watcher::register_commands(&mut registry);
+        git::register_commands(&mut registry);
+        diff::register_commands(&mut registry);
+        terminal::register_commands(&mut registry);
+        tasks::register_commands(&mut registry);
+        shutdown::register_commands(&mut registry);
+
+        registry
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a command, replacing any previous command with the same id.
+    pub fn register(&mut self, command: Command) {
+        self.commands.insert(command.id.clone(), command);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Command> {
+        self.commands.get(id)
+    }
+
+    /// All registered commands, sorted by id.
+    pub fn list(&self) -> impl Iterator<Item = &Command> {
+        self.commands.values()
+    }
+
+    pub async fn execute(&self, state: &mut AppState, invocation: &Invocation) -> CommandResult {
+        let command = self
+            .get(&invocation.id)
+            .ok_or_else(|| anyhow!("Unknown command `{}`", invocation.id))?;
+
+        command.check_args(&invocation.args)?;
+
+        (command.handler)(state, invocation.args.clone()).await
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const ZOOM_FACTOR: f32 = 1.1;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 8.0;
+
+#[derive(Copy, Clone)]
+enum ZoomStep {
+    In,
+    Out,
+    Reset,
+}
+
+impl ZoomStep {
+    fn apply(self, zoom: f32) -> f32 {
+        match self {
+            ZoomStep::In => (zoom * ZOOM_FACTOR).min(MAX_ZOOM),
+            ZoomStep::Out => (zoom / ZOOM_FACTOR).max(MIN_ZOOM),
+            ZoomStep::Reset => 1.0,
+        }
+    }
+}
+
+fn register_builtins(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "buffer.insert_char",
+            "Insert a character at the cursor",
+            insert_char,
+        )
+        .with_arg(ArgSpec::required("char", ArgKind::Char)),
+    );
+    registry.register(
+        Command::new("buffer.insert", "Insert text at the cursor", insert_text)
+            .with_arg(ArgSpec::required("text", ArgKind::Text)),
+    );
+    registry.register(Command::new(
+        "buffer.delete_backward",
+        "Delete the grapheme before the cursor",
+        delete_backward,
+    ));
+    registry.register(Command::new(
+        "buffer.delete_forward",
+        "Delete the grapheme after the cursor",
+        delete_forward,
+    ));
+    registry.register(Command::new(
+        "buffer.newline",
+        "Break the line at the cursor, keeping its indentation",
+        |state, _| Box::pin(async move { state.send_to_active_buffer(BufferEvent::Newline).await }),
+    ));
+    registry.register(Command::new(
+        "buffer.indent",
+        "Indent the selected lines, or the cursor's line",
+        |state, _| Box::pin(async move { state.send_to_active_buffer(BufferEvent::Indent).await }),
+    ));
+    registry.register(Command::new(
+        "buffer.outdent",
+        "Outdent the selected lines, or the cursor's line",
+        |state, _| Box::pin(async move { state.send_to_active_buffer(BufferEvent::Outdent).await }),
+    ));
+    registry.register(Command::new(
+        "cursor.jump_to_bracket",
+        "Move the cursor to the bracket matching the one at the caret",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::JumpToMatch).await })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.toggle_line_comment",
+        "Comment out the selected lines, or uncomment them if they all are",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::ToggleLineComment)
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.toggle_block_comment",
+        "Wrap the selection in a block comment, or unwrap it",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::ToggleBlockComment)
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.move_lines_up",
+        "Swap the selected lines with the line above",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::MoveLines { down: false })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.move_lines_down",
+        "Swap the selected lines with the line below",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::MoveLines { down: true })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.duplicate_lines",
+        "Copy the selected lines below themselves",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::DuplicateLines)
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.join_lines",
+        "Join the selected lines, or the cursor's line with the next",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::JoinLines).await })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.delete_lines",
+        "Delete the selected lines",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::DeleteLines).await })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.sort_lines",
+        "Sort the selected lines",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::SortLines).await })
+        },
+    ));
+    registry.register(Command::new(
+        "buffer.transpose",
+        "Swap the characters around the cursor",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::Transpose).await })
+        },
+    ));
+    registry.register(Command::new(
+        "cursor.add_above",
+        "Add a cursor on the line above the first cursor",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::AddCursor { below: false })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "cursor.add_below",
+        "Add a cursor on the line below the last cursor",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::AddCursor { below: true })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "cursor.single",
+        "Drop all cursors but the primary one",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::SingleCursor).await })
+        },
+    ));
+    registry.register(Command::new(
+        "fold.toggle",
+        "Fold or unfold the region at the cursor",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::ToggleFold).await })
+        },
+    ));
+    registry.register(Command::new(
+        "fold.fold_all",
+        "Fold every foldable region of the active buffer",
+        |state, _| Box::pin(async move { state.send_to_active_buffer(BufferEvent::FoldAll).await }),
+    ));
+    registry.register(Command::new(
+        "fold.unfold_all",
+        "Unfold every region of the active buffer",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::UnfoldAll).await })
+        },
+    ));
+    registry.register(
+        Command::new(
+            "buffer.set_indentation",
+            "Indent the active buffer with `tabs` or `spaces`, optionally of a given width",
+            set_indentation,
+        )
+        .with_arg(ArgSpec::required("kind", ArgKind::Text))
+        .with_arg(ArgSpec::optional("width", ArgKind::Integer)),
+    );
+    for (name, description, motion) in [
+        ("left", "one grapheme left", Motion::Left),
+        ("right", "one grapheme right", Motion::Right),
+        ("up", "to the line above", Motion::Up),
+        ("down", "to the line below", Motion::Down),
+        ("line_start", "to the start of the line", Motion::LineStart),
+        ("line_end", "to the end of the line", Motion::LineEnd),
+    ] {
+        for select in [false, true] {
+            let (id, description) = match select {
+                false => (
+                    format!("cursor.{}", name),
+                    format!("Move the cursor {}", description),
+                ),
+                true => (
+                    format!("cursor.select_{}", name),
+                    format!("Extend the selection {}", description),
+                ),
+            };
+
+            registry.register(Command::new(&id, &description, move |state, _| {
+                Box::pin(async move {
+                    state
+                        .send_to_active_buffer(BufferEvent::MoveCursor { motion, select })
+                        .await
+                })
+            }));
+        }
+    }
+    registry.register(Command::new("edit.undo", "Undo the last edit", undo));
+    registry.register(Command::new("edit.redo", "Redo the last undone edit", redo));
+    registry.register(Command::new(
+        "history.begin_group",
+        "Group following edits in every buffer into one undo step",
+        begin_undo_group,
+    ));
+    registry.register(Command::new(
+        "history.end_group",
+        "Close the undo group opened by history.begin_group",
+        end_undo_group,
+    ));
+    registry.register(
+        Command::new("file.open", "Open a file in the active frame", open_file)
+            .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "file.go_to",
+            "Move the cursor to a line and column, from 1",
+            go_to,
+        )
+        .with_arg(ArgSpec::required("line", ArgKind::Integer))
+        .with_arg(ArgSpec::optional("column", ArgKind::Integer)),
+    );
+    registry.register(
+        Command::new(
+            "file.save",
+            "Save the active buffer, optionally to a new path",
+            save_file,
+        )
+        .with_arg(ArgSpec::optional("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "explorer.open",
+            "Browse a folder, the working directory by default, in the active frame",
+            open_explorer,
+        )
+        .with_arg(ArgSpec::optional("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "explorer.open_file",
+            "Open a file in the frame focused before the explorer",
+            open_from_explorer,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "explorer.watch_folder",
+            "Refresh the explorers when entries of a folder change",
+            watch_folder,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "explorer.unwatch_folder",
+            "Stop refreshing the explorers on changes to a folder none of them lists",
+            unwatch_folder,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "explorer.folder_changed",
+            "Read a folder the explorers show again",
+            folder_changed,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    for (id, description, operation) in [
+        (
+            "explorer.new_file",
+            "Create a file in the selected folder",
+            FileOperation::NewFile,
+        ),
+        (
+            "explorer.new_folder",
+            "Create a folder in the selected folder",
+            FileOperation::NewFolder,
+        ),
+        (
+            "explorer.rename",
+            "Rename the selected file or folder",
+            FileOperation::Rename,
+        ),
+        (
+            "explorer.move",
+            "Move the selected file or folder to another folder",
+            FileOperation::Move,
+        ),
+        (
+            "explorer.delete",
+            "Delete the selected file or folder",
+            FileOperation::Delete,
+        ),
+    ] {
+        registry.register(Command::new(id, description, move |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::FileOperation(operation))
+                    .await
+            })
+        }));
+    }
+    registry.register(Command::new(
+        "layout.split_horizontal",
+        "Split the active frame into left and right halves",
+        split_horizontal,
+    ));
+    registry.register(Command::new(
+        "layout.split_vertical",
+        "Split the active frame into top and bottom halves",
+        split_vertical,
+    ));
+    registry.register(Command::new(
+        "layout.close_frame",
+        "Close the active frame",
+        close_frame,
+    ));
+    for (id, description, step) in [
+        (
+            "view.zoom_in",
+            "Make text in every buffer larger",
+            ZoomStep::In,
+        ),
+        (
+            "view.zoom_out",
+            "Make text in every buffer smaller",
+            ZoomStep::Out,
+        ),
+        (
+            "view.zoom_reset",
+            "Reset the zoom of every buffer",
+            ZoomStep::Reset,
+        ),
+    ] {
+        registry.register(Command::new(id, description, move |state, _| {
+            Box::pin(async move {
+                state.zoom = step.apply(state.zoom);
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        }));
+    }
+    for (id, description, step) in [
+        (
+            "buffer.zoom_in",
+            "Make text in the active buffer larger",
+            ZoomStep::In,
+        ),
+        (
+            "buffer.zoom_out",
+            "Make text in the active buffer smaller",
+            ZoomStep::Out,
+        ),
+        (
+            "buffer.zoom_reset",
+            "Reset the zoom of the active buffer",
+            ZoomStep::Reset,
+        ),
+    ] {
+        registry.register(Command::new(id, description, move |state, _| {
+            Box::pin(async move {
+                let mut buffer = state.active_buffer().lock().await;
+                let zoom = step.apply(buffer.zoom());
+                buffer.set_zoom(zoom);
+
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        }));
+    }
+    registry.register(Command::new(
+        "view.toggle_whitespace",
+        "Show or hide markers for spaces, tabs and trailing whitespace",
+        |state, _| {
+            Box::pin(async move {
+                state.view.whitespace = !state.view.whitespace;
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "view.toggle_indent_guides",
+        "Show or hide indentation guides",
+        |state, _| {
+            Box::pin(async move {
+                state.view.indent_guides = !state.view.indent_guides;
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "view.toggle_blame",
+        "Show or hide the commit that last changed the cursor line",
+        |state, _| {
+            Box::pin(async move {
+                state.view.blame = !state.view.blame;
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        },
+    ));
+    for (id, description, up) in [
+        (
+            "view.page_up",
+            "Show the page above, such as terminal scrollback",
+            true,
+        ),
+        ("view.page_down", "Show the page below", false),
+    ] {
+        registry.register(Command::new(id, description, move |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::ScrollPage { up })
+                    .await
+            })
+        }));
+    }
+    registry.register(Command::new(
+        "frame.focus_next",
+        "Focus the next frame in the layout",
+        focus_next,
+    ));
+    registry.register(Command::new(
+        "frame.focus_previous",
+        "Focus the previous frame in the layout",
+        focus_previous,
+    ));
+}
+
+fn insert_char(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let c = args[0].as_char().expect("checked by registry");
+
+        let outcome = state
+            .send_to_active_buffer(BufferEvent::InsertChar(c))
+            .await?;
+
+        // Plugins see every character typed, and aren't waited for
+        let context = ScriptContext::capture(state).await;
+        state
+            .scripts
+            .post(Hook::CharInput(c), context, state.state_tx.clone());
+
+        Ok(outcome)
+    })
+}
+
+fn set_indentation(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let kind: IndentKind = args[0].as_text().expect("checked by registry").parse()?;
+        let mut buffer = state.active_buffer().lock().await;
+        let mut style = buffer
+            .indent_style()
+            .context("Buffer has no indentation to set")?;
+
+        style.kind = kind;
+        if let Some(width) = args.get(1).and_then(CommandArg::as_integer) {
+            style.width = usize::try_from(width)
+                .ok()
+                .filter(|&width| width > 0)
+                .context("Indentation width must be positive")?;
+        }
+        buffer.set_indent_style(style);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn insert_text(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let text = args[0].as_text().expect("checked by registry").to_string();
+
+        state.send_to_active_buffer(BufferEvent::Insert(text)).await
+    })
+}
+
+fn delete_backward(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state
+            .send_to_active_buffer(BufferEvent::DeleteBackward)
+            .await
+    })
+}
+
+fn delete_forward(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state
+            .send_to_active_buffer(BufferEvent::DeleteForward)
+            .await
+    })
+}
+
+fn undo(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move { state.send_to_active_buffer(BufferEvent::Undo).await })
+}
+
+fn redo(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move { state.send_to_active_buffer(BufferEvent::Redo).await })
+}
+
+fn begin_undo_group(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        for buffer in &state.buffers {
+            buffer.lock().await.begin_undo_group();
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn end_undo_group(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        for buffer in &state.buffers {
+            buffer.lock().await.end_undo_group();
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn open_file(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = PathBuf::from(args[0].as_text().expect("checked by registry"));
+
+        state.open_file(path.clone()).await?;
+
+        let context = ScriptContext::capture(state).await;
+        let actions = state.scripts.fire(Hook::Open(path), context).await;
+        scripting::apply_actions(state, actions);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn open_explorer(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let root = match args.first().and_then(CommandArg::as_text) {
+            Some(path) => PathBuf::from(path),
+            None => std::env::current_dir().context("Can't read the working directory")?,
+        };
+        // Canonical paths so the same folder is browsed by a single explorer
+        let root = tokio::fs::canonicalize(&root)
+            .await
+            .with_context(|| format!("Can't browse {}", root.display()))?;
+
+        for (index, buffer) in state.buffers.iter().enumerate() {
+            if buffer.lock().await.path() == Some(root.as_path()) {
+                state.show_buffer(index);
+                return Ok(EventHandlerOutcome::Redraw);
+            }
+        }
+
+        let explorer = Explorer::new(state.font.clone(), root, state.state_tx.clone());
+        state.buffers.push(Arc::new(Mutex::new(explorer)));
+        state.show_buffer(state.buffers.len() - 1);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn open_from_explorer(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state.focus_other_frame();
+        state.pending.push_back(Invocation {
+            id: "file.open".to_string(),
+            args,
+        });
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn watch_folder(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let Some(watcher) = &mut state.watcher else {
+            return Ok(EventHandlerOutcome::None);
+        };
+
+        let dir = PathBuf::from(args[0].as_text().expect("checked by registry"));
+        let changed = Invocation::new("explorer.folder_changed").with_arg(args[0].clone());
+        watcher.notify_folder(&dir, changed)?;
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn unwatch_folder(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let dir = PathBuf::from(args[0].as_text().expect("checked by registry"));
+        state.unwatch_folders(vec![dir]).await;
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn folder_changed(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let dir = PathBuf::from(args[0].as_text().expect("checked by registry"));
+
+        let mut changed = false;
+        for buffer in &state.buffers {
+            changed |= buffer.lock().await.folder_changed(&dir);
+        }
+
+        Ok(match changed {
+            true => EventHandlerOutcome::Redraw,
+            false => EventHandlerOutcome::None,
+        })
+    })
+}
+
+fn go_to(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let position = |arg: Option<&CommandArg>| {
+            arg.and_then(CommandArg::as_integer)
+                .map_or(0, |n| n.max(1) as usize - 1)
+        };
+        let event = BufferEvent::GoTo {
+            line: position(args.first()),
+            column: position(args.get(1)),
+        };
+
+        state.send_to_active_buffer(event).await
+    })
+}
+
+fn save_file(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = args
+            .first()
+            .and_then(CommandArg::as_text)
+            .map(PathBuf::from);
+        let path = state.save_active_buffer(path).await?;
+
+        let context = ScriptContext::capture(state).await;
+        let actions = state.scripts.fire(Hook::Save(path), context).await;
+        scripting::apply_actions(state, actions);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn split_horizontal(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state.layout.split_frame(state.active_frame, false);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn split_vertical(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state.layout.split_frame(state.active_frame, true);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn close_frame(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        if !state.close_frame(state.active_frame).await {
+            bail!("Can't close the last frame");
+        }
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn focus_next(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move { Ok(state.cycle_focus(1)) })
+}
+
+fn focus_previous(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move { Ok(state.cycle_focus(-1)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::text_buffer::{FontConfig, TextBuffer};
+    use crate::buffer::{BoundingBox, Buffer, RenderContext};
+    use crate::fonts::FontCollection;
+    use crate::grid::GridSettings;
+    use crate::indent::IndentStyle;
+    use crate::input::{Key, Modifiers};
+    use crate::keymap::{KeyBinding, Keymap};
+    use crate::layout::Layout;
+    use crate::scene::Scene;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::mpsc;
+
+    /// State with a single empty text buffer in a single frame.
+    fn state() -> AppState {
+        let font = FontConfig {
+            scale: 16.0,
+            fonts: FontCollection::embedded(),
+            grid: GridSettings::default(),
+            indent: IndentStyle::default(),
+        };
+        let (state_tx, _) = mpsc::channel(1);
+        let mut state = AppState::new(font.clone(), state_tx);
+
+        state
+            .buffers
+            .push(Arc::new(Mutex::new(TextBuffer::new(font))));
+        state.set_layout(Layout::single(0));
+        state
+    }
+
+    fn noop(_: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+        Box::pin(async { Ok(EventHandlerOutcome::None) })
+    }
+
+    fn with_args() -> Command {
+        Command::new("test.args", "", noop)
+            .with_arg(ArgSpec::required("text", ArgKind::Text))
+            .with_arg(ArgSpec::optional("scale", ArgKind::Number))
+    }
+
+    #[test]
+    fn lists_commands_sorted_by_id() {
+        let registry = CommandRegistry::new();
+        let ids: Vec<_> = registry.list().map(|command| command.id.as_str()).collect();
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.contains(&"buffer.insert"));
+        assert!(ids.contains(&"frame.focus_next"));
+        assert!(CommandRegistry::empty().list().next().is_none());
+    }
+
+    #[test]
+    fn register_replaces_commands_with_the_same_id() {
+        let mut registry = CommandRegistry::empty();
+
+        registry.register(Command::new("test.args", "first", noop));
+        registry.register(with_args());
+
+        assert_eq!(registry.list().count(), 1);
+        assert_eq!(registry.get("test.args").unwrap().args.len(), 2);
+    }
+
+    #[test]
+    fn checks_arguments() {
+        let command = with_args();
+        let text = || CommandArg::Text("a".to_string());
+
+        assert!(command.check_args(&[text()]).is_ok());
+        assert!(command
+            .check_args(&[text(), CommandArg::Number(1.5)])
+            .is_ok());
+        // Integers are taken for numbers
+        assert!(command
+            .check_args(&[text(), CommandArg::Integer(2)])
+            .is_ok());
+
+        let missing = command.check_args(&[]).unwrap_err();
+        assert_eq!(missing.to_string(), "test.args: missing argument `text`");
+
+        let wrong = command.check_args(&[CommandArg::Char('a')]).unwrap_err();
+        assert_eq!(
+            wrong.to_string(),
+            "test.args: argument `text` expects Text, got Char"
+        );
+
+        let extra = [text(), CommandArg::Number(1.0), text()];
+        assert!(command.check_args(&extra).is_err());
+    }
+
+    /// Buffer that goes with its frame, as terminals do, noting when it's shut down.
+    struct Closing(Arc<AtomicBool>);
+
+    impl Buffer for Closing {
+        fn draw(&mut self, _: BoundingBox, _: &RenderContext, _: &mut Scene) {}
+
+        fn handle_events(&mut self, _: BufferEvent) -> EventHandlerOutcome {
+            EventHandlerOutcome::None
+        }
+
+        fn closes_with_frame(&self) -> bool {
+            true
+        }
+
+        fn shutdown(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn releases_buffers_that_close_with_their_last_frame() {
+        let registry = CommandRegistry::new();
+        let mut state = state();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let close = Invocation::new("layout.close_frame");
+
+        state
+            .buffers
+            .push(Arc::new(Mutex::new(Closing(stopped.clone()))));
+        state
+            .buffers
+            .push(Arc::new(Mutex::new(TextBuffer::new(state.font.clone()))));
+        state.show_buffer(1);
+        let split = Invocation::new("layout.split_vertical");
+        registry.execute(&mut state, &split).await.unwrap();
+        registry.execute(&mut state, &split).await.unwrap();
+        state.show_buffer(2);
+        assert_eq!(state.layout.buffer_indices(), [2, 1, 1]);
+
+        // Another frame still shows it
+        state.show_buffer(1);
+        registry.execute(&mut state, &close).await.unwrap();
+        assert_eq!(state.buffers.len(), 3);
+        assert!(!stopped.load(Ordering::Relaxed));
+
+        state.show_buffer(1);
+        registry.execute(&mut state, &close).await.unwrap();
+        assert_eq!(state.buffers.len(), 2);
+        assert!(stopped.load(Ordering::Relaxed));
+        assert_eq!(state.layout.buffer_indices(), [1]);
+        assert_eq!(state.active_buffer, 1);
+    }
+
+    #[tokio::test]
+    async fn reuses_diff_buffers_no_frame_shows() {
+        let registry = CommandRegistry::new();
+        let mut state = state();
+        let dir = crate::paths::scratch_dir("diff-buffers");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        std::fs::write(&old, "a\n").unwrap();
+        std::fs::write(&new, "b\n").unwrap();
+        let path = |path: &std::path::Path| CommandArg::Text(path.display().to_string());
+        let compare = Invocation::new("diff.files")
+            .with_arg(path(&old))
+            .with_arg(path(&new));
+        let close = Invocation::new("layout.close_frame");
+
+        registry.execute(&mut state, &compare).await.unwrap();
+        assert_eq!(state.layout.frames().len(), 3);
+        let buffers = state.buffers.len();
+
+        // Diffs on screen get views of their own
+        registry.execute(&mut state, &compare).await.unwrap();
+        assert_eq!(state.buffers.len(), buffers + 2);
+
+        for _ in 0..4 {
+            registry.execute(&mut state, &close).await.unwrap();
+        }
+        assert_eq!(state.layout.frames().len(), 1);
+        registry.execute(&mut state, &compare).await.unwrap();
+        assert_eq!(state.buffers.len(), buffers + 2);
+        assert_eq!(state.layout.frames().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn executes_commands_on_the_state() {
+        let registry = CommandRegistry::new();
+        let mut state = state();
+        let insert = Invocation::new("buffer.insert").with_arg(CommandArg::Text("hi".into()));
+
+        let outcome = registry.execute(&mut state, &insert).await.unwrap();
+        assert!(matches!(outcome, EventHandlerOutcome::Redraw));
+        assert_eq!(state.active_buffer().lock().await.text(), Some("hi"));
+
+        registry
+            .execute(&mut state, &Invocation::new("edit.undo"))
+            .await
+            .unwrap();
+        assert_eq!(state.active_buffer().lock().await.text(), Some(""));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_commands_and_bad_arguments() {
+        let registry = CommandRegistry::new();
+        let mut state = state();
+
+        let unknown = registry
+            .execute(&mut state, &Invocation::new("no.such_command"))
+            .await
+            .unwrap_err();
+        assert_eq!(unknown.to_string(), "Unknown command `no.such_command`");
+
+        // Arguments are checked before the handler runs
+        let wrong = Invocation::new("buffer.insert").with_arg(CommandArg::Integer(1));
+        assert!(registry.execute(&mut state, &wrong).await.is_err());
+        assert!(registry
+            .execute(&mut state, &Invocation::new("buffer.insert"))
+            .await
+            .is_err());
+        assert_eq!(state.active_buffer().lock().await.text(), Some(""));
+    }
+
+    #[tokio::test]
+    async fn focus_moves_between_frames_of_the_same_buffer() {
+        let registry = CommandRegistry::new();
+        let mut state = state();
+        let run = |id: &'static str| Invocation::new(id);
+
+        registry
+            .execute(&mut state, &run("layout.split_horizontal"))
+            .await
+            .unwrap();
+        let frames = state.layout.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(state.layout.buffer_indices(), [0, 0]);
+        assert_eq!(state.active_frame, frames[0].0);
+
+        registry
+            .execute(&mut state, &run("frame.focus_next"))
+            .await
+            .unwrap();
+        assert_eq!(state.active_frame, frames[1].0);
+        assert_eq!(state.previous_frame, frames[0].0);
+
+        registry
+            .execute(&mut state, &run("frame.focus_previous"))
+            .await
+            .unwrap();
+        assert_eq!(state.active_frame, frames[0].0);
+
+        // Closing the active frame focuses the one left
+        registry
+            .execute(&mut state, &run("layout.close_frame"))
+            .await
+            .unwrap();
+        assert_eq!(state.layout.frames(), [frames[1]]);
+        assert_eq!(state.active_frame, frames[1].0);
+        assert!(registry
+            .execute(&mut state, &run("layout.close_frame"))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn default_bindings_resolve_to_commands() {
+        let registry = CommandRegistry::new();
+        let keymap = Keymap::new();
+
+        let binding = KeyBinding::new(Modifiers::CTRL | Modifiers::SHIFT, Key::Tab);
+        assert_eq!(
+            keymap.lookup(&binding),
+            Some(&Invocation::new("frame.focus_previous"))
+        );
+        assert_eq!("ctrl+shift+tab".parse::<KeyBinding>().unwrap(), binding);
+        assert!(keymap
+            .lookup(&KeyBinding::new(Modifiers::LOGO, Key::F(12)))
+            .is_none());
+
+        for (binding, invocation) in keymap.bindings() {
+            let command = registry
+                .get(&invocation.id)
+                .unwrap_or_else(|| panic!("{:?} is bound to unknown {}", binding, invocation.id));
+            command.check_args(&invocation.args).unwrap();
+        }
+    }
+}
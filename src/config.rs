@@ -0,0 +1,77 @@
+use crate::grid::GridSettings;
+use crate::indent::IndentStyle;
+use crate::paths;
+use crate::tasks::TaskSettings;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Editor settings from `config.toml` in the configuration directory.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub font: FontSettings,
+    pub layout: GridSettings,
+    pub indent: IndentStyle,
+    pub tasks: Vec<TaskSettings>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FontSettings {
+    /// Family of the main font. The embedded FiraCode is used when unset or not installed.
+    pub family: Option<String>,
+    /// Font files overriding the faces found for `family`.
+    pub regular: Option<PathBuf>,
+    pub bold: Option<PathBuf>,
+    pub italic: Option<PathBuf>,
+    pub bold_italic: Option<PathBuf>,
+    /// Families or font files tried in order for characters the main font lacks.
+    pub fallback: Vec<String>,
+    pub size: f32,
+}
+
+impl Config {
+    pub async fn load() -> anyhow::Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(source) => toml::from_str(&source)
+                .with_context(|| format!("Malformed config {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Can't read {}", path.display())),
+        }
+    }
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        Self {
+            family: None,
+            regular: None,
+            bold: None,
+            italic: None,
+            bold_italic: None,
+            fallback: [
+                "Noto Sans Mono CJK JP",
+                "Noto Sans CJK JP",
+                "Noto Emoji",
+                "Noto Sans Symbols",
+                "Noto Sans Symbols 2",
+                "Symbola",
+                "DejaVu Sans",
+            ]
+            .map(String::from)
+            .to_vec(),
+            size: 20.0,
+        }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(paths::config_dir()?.join(CONFIG_FILE))
+}
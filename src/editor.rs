@@ -0,0 +1,351 @@
+use crate::app_state::{AppState, SharedState};
+use crate::buffer::text_buffer::{FontConfig, TextBuffer};
+use crate::buffer::BoundingBox;
+use crate::command::{BoxFuture, CommandResult, Invocation};
+use crate::config::Config;
+use crate::fonts::FontCollection;
+use crate::layout::Layout;
+use crate::macros::MacroRegisters;
+use crate::remote::server::{self, Notifier};
+use crate::remote::socket_path;
+use crate::scene::{self, Drawing, Surface};
+use crate::scripting::{self, ScriptHost};
+use crate::session::Session;
+use crate::state::{self, StateEvent};
+use crate::tasks::Tasks;
+use crate::theme::{self, Theme};
+use crate::watcher::FileWatcher;
+use crate::{paths, swap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// Events waiting for the state loop, such as keys typed faster than they're handled
+pub(crate) const EVENT_CAPACITY: usize = 1024;
+
+/// Shows the editor and takes the user's input, such as a window or a terminal.
+pub trait Frontend: Send + Sync + 'static {
+    /// What's on screen changed, so it should be drawn again.
+    fn request_redraw(&self);
+    /// The editor shut down, so the frontend can go as well.
+    fn exit(&self);
+}
+
+/// Nothing shows the editor, as in a server with no clients attached.
+impl Frontend for () {
+    fn request_redraw(&self) {}
+
+    fn exit(&self) {}
+}
+
+impl<A: Frontend, B: Frontend> Frontend for (A, B) {
+    fn request_redraw(&self) {
+        self.0.request_redraw();
+        self.1.request_redraw();
+    }
+
+    fn exit(&self) {
+        self.0.exit();
+        self.1.exit();
+    }
+}
+
+/// Editor a frontend shows: one in this process, or one in a server the frontend is
+/// attached to.
+pub trait Host: Send + Sync + 'static {
+    /// Sender of input and other events for the editor.
+    fn events(&self) -> Sender<StateEvent>;
+    /// Starts handling events, telling `frontend` when to redraw and when it should go.
+    fn start(&mut self, frontend: impl Frontend)
+    where
+        Self: Sized;
+    /// Fonts text is laid out with, and their size before zoom.
+    fn font(&self) -> BoxFuture<'_, (FontCollection, f32)>;
+    /// Lays out what each frame shows in `window`, on `surface`.
+    fn draw(&self, window: BoundingBox, surface: Surface)
+        -> BoxFuture<'_, anyhow::Result<Drawing>>;
+}
+
+/// The editor without anything showing it: buffers, layout, commands and what runs in the
+/// background. Frontends send it input through `events` and draw what `draw` lays out.
+pub struct Editor {
+    state: SharedState,
+    events: Sender<StateEvent>,
+    /// Taken by the state loop once started
+    receiver: Option<Receiver<StateEvent>>,
+    /// Set while serving clients, for the state loop to tell them when to redraw
+    notifier: Option<Notifier>,
+}
+
+impl Editor {
+    /// Editor with one empty buffer, set up from `config` alone. It reads no files and
+    /// runs nothing in the background, which suits tests and embedding.
+    pub fn new(config: &Config, fonts: FontCollection) -> Self {
+        let (events, receiver) = mpsc::channel(EVENT_CAPACITY);
+        let font = FontConfig {
+            scale: config.font.size,
+            fonts,
+            grid: config.layout,
+            indent: config.indent,
+        };
+
+        let mut state = AppState::new(font.clone(), events.clone());
+        state.tasks = Tasks::new(config.tasks.clone());
+        state
+            .buffers
+            .push(Arc::new(Mutex::new(TextBuffer::new(font))));
+        state.set_layout(Layout::single(0));
+
+        Self {
+            state: Arc::new(RwLock::new(state)),
+            events,
+            receiver: Some(receiver),
+            notifier: None,
+        }
+    }
+
+    /// Editor set up from the user's configuration, fonts, macros, plugins and theme,
+    /// with the session of the working directory and the changes a crash left unsaved.
+    /// What can't be loaded is logged and left at its default.
+    pub async fn load() -> anyhow::Result<Self> {
+        let (config, fonts) = load_settings().await?;
+        let editor = Self::new(&config, fonts.clone());
+        let mut app_state = editor.state.write().await;
+
+        match FileWatcher::start(editor.events.clone()) {
+            Ok(watcher) => app_state.watcher = Some(watcher),
+            Err(err) => tracing::warn!("Failed to watch files: {:#}", err),
+        }
+
+        match MacroRegisters::load().await {
+            Ok(registers) => app_state.macros = registers,
+            Err(err) => tracing::warn!("Failed to load macros: {:#}", err),
+        }
+
+        if let Some(config_dir) = paths::config_dir() {
+            match ScriptHost::start(&config_dir.join("plugins")).await {
+                Ok((scripts, actions)) => {
+                    app_state.scripts = scripts;
+                    scripting::apply_actions(&mut app_state, actions);
+                }
+                Err(err) => tracing::warn!("Failed to start plugins: {:#}", err),
+            }
+        }
+
+        // TODO: Make it init just basic buffer
+        app_state
+            .buffers
+            .push(Arc::new(Mutex::new(TextBuffer::new(FontConfig {
+                scale: config.font.size * 2.0,
+                fonts,
+                grid: config.layout,
+                indent: config.indent,
+            }))));
+        app_state.set_layout(Layout::new());
+        app_state.show_buffer(1);
+
+        match Session::load().await {
+            Ok(Some(session)) => session.restore(&mut app_state).await,
+            Ok(None) => {}
+            Err(err) => tracing::warn!("Failed to load session: {:#}", err),
+        }
+
+        // Files a crash left unsaved changes to are opened to recover them
+        if let Ok(workspace) = std::env::current_dir() {
+            for path in swap::leftovers(&workspace).await {
+                if let Err(err) = app_state.open_file(path).await {
+                    tracing::warn!("{:#}", err);
+                }
+            }
+        }
+
+        if let Some(path) = theme::theme_path() {
+            if path.exists() {
+                match Theme::load(&path).await {
+                    Ok(theme) => app_state.theme = Arc::new(theme),
+                    Err(err) => tracing::warn!("Failed to load theme: {:#}", err),
+                }
+            }
+
+            // Edits to the theme show as soon as they're saved
+            if let Some(watcher) = &mut app_state.watcher {
+                if let Err(err) = watcher.notify(&path, Invocation::new("theme.reload")) {
+                    tracing::debug!("Not watching the theme: {:#}", err);
+                }
+            }
+        }
+
+        app_state.swap_writer = Some(swap::start(editor.state.clone()));
+        drop(app_state);
+
+        Ok(editor)
+    }
+
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    /// Sender of input and other events for the state loop.
+    pub fn events(&self) -> Sender<StateEvent> {
+        self.events.clone()
+    }
+
+    /// Takes requests from clients on the socket of the user's session, such as windows
+    /// attached to this editor and `kami file.rs` run from a shell. Called before `start`,
+    /// which tells the clients when to redraw.
+    pub fn serve(&mut self) -> anyhow::Result<()> {
+        self.serve_at(socket_path()?)
+    }
+
+    /// Takes requests from clients on the socket at `path`, as `serve` does.
+    pub fn serve_at(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        let notifier = server::listen(path, self.state.clone(), self.events.clone())?;
+        self.notifier = Some(notifier);
+
+        Ok(())
+    }
+
+    /// Starts handling events, telling `frontend` when to redraw and when the editor is
+    /// done. The loop ends once the editor shuts down.
+    pub fn start(&mut self, frontend: impl Frontend) -> JoinHandle<()> {
+        let receiver = self.receiver.take().expect("editor already started");
+        let state = self.state.clone();
+
+        match self.notifier.take() {
+            Some(notifier) => {
+                tokio::spawn(state::state_loop((frontend, notifier), receiver, state))
+            }
+            None => tokio::spawn(state::state_loop(frontend, receiver, state)),
+        }
+    }
+
+    /// Runs a command the way a key bound to it would, then the commands it queued.
+    /// Returns the outcome of the first.
+    pub async fn execute(&self, invocation: &Invocation) -> CommandResult {
+        let outcome = state::execute(&self.state, invocation, false).await;
+
+        loop {
+            let Some(invocation) = self.state.write().await.pending.pop_front() else {
+                break;
+            };
+
+            if let Err(err) = state::execute(&self.state, &invocation, false).await {
+                tracing::warn!("Command `{}` failed: {:#}", invocation.id, err);
+            }
+        }
+
+        outcome
+    }
+
+    /// Lays out what each frame shows in `window`, on `surface`.
+    pub async fn draw(&self, window: BoundingBox, surface: Surface) -> Drawing {
+        scene::draw(&self.state, window, surface).await
+    }
+}
+
+impl Host for Editor {
+    fn events(&self) -> Sender<StateEvent> {
+        self.events.clone()
+    }
+
+    fn start(&mut self, frontend: impl Frontend) {
+        Editor::start(self, frontend);
+    }
+
+    fn font(&self) -> BoxFuture<'_, (FontCollection, f32)> {
+        Box::pin(async move {
+            let state = self.state.read().await;
+            (state.font.fonts.clone(), state.font.scale)
+        })
+    }
+
+    fn draw(
+        &self,
+        window: BoundingBox,
+        surface: Surface,
+    ) -> BoxFuture<'_, anyhow::Result<Drawing>> {
+        Box::pin(async move { Ok(Editor::draw(self, window, surface).await) })
+    }
+}
+
+/// The user's configuration and the fonts it asks for. What can't be loaded is logged and
+/// left at its default.
+pub(crate) async fn load_settings() -> anyhow::Result<(Config, FontCollection)> {
+    let config = match Config::load().await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::warn!("Failed to load config: {:#}", err);
+            Config::default()
+        }
+    };
+
+    let font_settings = config.font.clone();
+    let fonts =
+        match tokio::task::spawn_blocking(move || FontCollection::load(&font_settings)).await? {
+            Ok(fonts) => fonts,
+            Err(err) => {
+                tracing::warn!("Failed to load fonts: {:#}", err);
+                FontCollection::embedded()
+            }
+        };
+
+    Ok((config, fonts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandArg;
+
+    // Needs neither a window nor a terminal, so it runs with `--no-default-features` too
+    #[tokio::test]
+    async fn runs_commands_and_draws_without_a_frontend() {
+        let editor = Editor::new(&Config::default(), FontCollection::embedded());
+        let insert = Invocation::new("buffer.insert").with_arg(CommandArg::Text("hi".into()));
+
+        editor.execute(&insert).await.unwrap();
+        editor
+            .execute(&Invocation::new("layout.split_horizontal"))
+            .await
+            .unwrap();
+        assert_eq!(editor.state().read().await.layout.buffer_indices(), [0, 0]);
+        assert!(editor.execute(&Invocation::new("no.such")).await.is_err());
+
+        let window = BoundingBox {
+            left: 0.0,
+            top: 0.0,
+            width: 800.0,
+            height: 600.0,
+        };
+        let drawing = editor
+            .draw(window, Surface::Window { scale_factor: 1.0 })
+            .await;
+        assert_eq!(drawing.active_frame, 0);
+        let frames: Vec<_> = drawing
+            .frames
+            .iter()
+            .map(|(bb, index, _)| (bb.left, bb.width, *index))
+            .collect();
+        assert_eq!(frames, [(0.0, 400.0, 0), (400.0, 400.0, 0)]);
+
+        // Both frames show the text, and the cursor after it
+        for (bb, _, scene) in &drawing.frames {
+            let text: String = scene
+                .glyphs
+                .iter()
+                .map(|glyph| glyph.text.as_str())
+                .collect();
+            assert_eq!(text, "hi");
+            let (x, y) = scene.cursor.expect("cursor on screen");
+            assert!(x > bb.left && y == bb.top);
+        }
+
+        // Terminals lay frames out on whole cells
+        let drawing = editor.draw(window, Surface::Cells { scale: 16.0 }).await;
+        let metrics = crate::grid::GridMetrics::new(&FontCollection::embedded(), 16.0);
+        let (right, ..) = drawing.frames[1];
+        assert_eq!(right.left % metrics.cell_width, 0.0);
+    }
+}
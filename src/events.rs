@@ -1,4 +0,0 @@
-#[derive(Debug)]
-pub enum KamiEvent {
-    RequestRedraw,
-}
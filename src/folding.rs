@@ -0,0 +1,201 @@
+use crate::brackets;
+use crate::grid;
+use crate::syntax::{SpanKind, SyntaxSpans};
+use std::cmp::Reverse;
+use std::ops::Range;
+
+/// Lines that can be folded under a header line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FoldRegion {
+    /// Start of the line that stays visible
+    pub header: usize,
+    /// Whole lines hidden by the fold, from the start of the first to the start of the
+    /// line after the last
+    pub hidden: Range<usize>,
+}
+
+/// Foldable regions of `text`, sorted by header. Blocks between brackets and comments
+/// spanning lines are found from the syntax, other lines fold by indentation.
+pub fn regions(text: &str, syntax: &SyntaxSpans, tab_width: usize) -> Vec<FoldRegion> {
+    let mut starts = vec![0];
+    starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    let line_of = |offset: usize| starts.partition_point(|&start| start <= offset) - 1;
+    let end_of = |line: usize| starts.get(line + 1).copied().unwrap_or(text.len());
+    let is_line_comment = |start: usize| {
+        let line = grid::line_at(text, start);
+        let code = start + line.len() - line.trim_start().len();
+        line.trim_start().starts_with("//") && syntax.kind_at(code) == Some(SpanKind::Comment)
+    };
+
+    let mut regions = Vec::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let line = grid::line_at(text, start);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let first = start + line.len() - line.trim_start().len();
+        let last = start + line.trim_end().len() - 1;
+
+        // Lines of a block end at the one with its closing bracket, which stays visible
+        let opens = matches!(text.as_bytes()[last], b'{' | b'(' | b'[');
+        let block = (opens && syntax.kind_at(last).is_none())
+            .then(|| brackets::find_match(text, last, syntax))
+            .flatten()
+            .map(|close| line_of(close).saturating_sub(1));
+
+        let comment = match syntax.span_at(first) {
+            // Runs of line comments fold under their first line
+            Some(_) if is_line_comment(start) => match i > 0 && is_line_comment(starts[i - 1]) {
+                true => Some(i),
+                false => (i + 1..starts.len())
+                    .take_while(|&line| is_line_comment(starts[line]))
+                    .last()
+                    .or(Some(i)),
+            },
+            Some((span, SpanKind::Comment)) if span.start == first => Some(line_of(span.end - 1)),
+            Some((_, SpanKind::Comment)) => Some(i),
+            _ => None,
+        };
+
+        let last_line = block.or(comment).unwrap_or_else(|| {
+            let indent = grid::indent_width(line, tab_width);
+            let mut last = i;
+            for (line, &next_start) in starts.iter().enumerate().skip(i + 1) {
+                let next = grid::line_at(text, next_start);
+                if next.trim().is_empty() {
+                    continue;
+                }
+                if grid::indent_width(next, tab_width) <= indent {
+                    break;
+                }
+                last = line;
+            }
+            last
+        });
+
+        if last_line > i {
+            regions.push(FoldRegion {
+                header: start,
+                hidden: starts[i + 1]..end_of(last_line),
+            });
+        }
+    }
+
+    regions
+}
+
+/// Folded ranges of a text, each made of the whole lines a `FoldRegion` hides.
+#[derive(Clone, Debug, Default)]
+pub struct Folds {
+    /// Sorted by start. Folds can nest, but never partially overlap.
+    folded: Vec<Range<usize>>,
+}
+
+impl Folds {
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.folded
+    }
+
+    pub fn is_folded(&self, hidden: &Range<usize>) -> bool {
+        self.folded.contains(hidden)
+    }
+
+    /// Folds `hidden`, or unfolds it if it already is. Returns whether it's now folded.
+    pub fn toggle(&mut self, hidden: Range<usize>) -> bool {
+        match self.folded.iter().position(|fold| *fold == hidden) {
+            Some(index) => {
+                self.folded.remove(index);
+                false
+            }
+            None => {
+                let index = self.folded.partition_point(|fold| {
+                    (fold.start, Reverse(fold.end)) < (hidden.start, Reverse(hidden.end))
+                });
+                self.folded.insert(index, hidden);
+                true
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.folded.clear();
+    }
+
+    /// Whether the line starting at `start` is hidden.
+    pub fn hides_line(&self, start: usize) -> bool {
+        self.folded.iter().any(|fold| fold.contains(&start))
+    }
+
+    /// Outermost fold hiding a caret at `offset` of `text`. The end of a fold is visible,
+    /// as the start of the line after it, unless the fold runs to the end of the text.
+    pub fn hiding(&self, text: &str, offset: usize) -> Option<Range<usize>> {
+        self.folded
+            .iter()
+            .find(|fold| {
+                fold.contains(&offset)
+                    || offset == fold.end && fold.end == text.len() && !text.ends_with('\n')
+            })
+            .cloned()
+    }
+
+    /// Unfolds everything hiding a caret at `offset`. Returns whether anything changed.
+    pub fn reveal(&mut self, text: &str, offset: usize) -> bool {
+        let mut changed = false;
+
+        while let Some(fold) = self.hiding(text, offset) {
+            self.folded.retain(|other| *other != fold);
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Moves folds along with the replacement of `range` by `inserted` bytes, which
+    /// `text` already contains. Edits inside a fold unfold it.
+    pub fn edit(&mut self, text: &str, range: Range<usize>, inserted: usize) {
+        let shift = |offset: usize| offset - (range.end - range.start) + inserted;
+
+        self.folded = self
+            .folded
+            .drain(..)
+            .filter_map(|fold| {
+                if range.end <= fold.start && !(range.is_empty() && range.start == fold.start) {
+                    Some(shift(fold.start)..shift(fold.end))
+                } else if range.start >= fold.end {
+                    Some(fold)
+                } else {
+                    None
+                }
+            })
+            // Folds keep covering whole lines
+            .filter(|fold| fold.start > 0 && text.as_bytes()[fold.start - 1] == b'\n')
+            .filter(|fold| fold.end == text.len() || text.as_bytes()[fold.end - 1] == b'\n')
+            .collect();
+    }
+}
+
+/// Caret at the end of the header line of `fold`.
+pub fn header_end(text: &str, fold: &Range<usize>) -> usize {
+    let start = text[..fold.start - 1].rfind('\n').map_or(0, |i| i + 1);
+
+    start + grid::line_at(text, start).len()
+}
+
+/// Smallest edit turning `old` into `new`: the replaced range of `old` and the length of
+/// its replacement.
+pub fn changed_range(old: &str, new: &str) -> (Range<usize>, usize) {
+    let prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old.as_bytes()[prefix..]
+        .iter()
+        .rev()
+        .zip(new.as_bytes()[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    (prefix..old.len() - suffix, new.len() - suffix - prefix)
+}
@@ -0,0 +1,195 @@
+use crate::config::FontSettings;
+use ab_glyph::{Font, FontArc, FontVec};
+use anyhow::Context;
+use fontdb::{Database, Family, Query, Style, Weight};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+const EMBEDDED_FONT: &[u8] = include_bytes!("../resources/FiraCode-Regular.ttf");
+
+/// Face of a `FontCollection`, by its index.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontId(pub usize);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    #[default]
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+/// All faces a buffer draws with. Frontends load `faces()` in order, so a `FontId` is
+/// also the index of its face there.
+#[derive(Clone)]
+pub struct FontCollection {
+    faces: Arc<Vec<FontArc>>,
+    /// Index of each face within its font file, for font collections such as `.ttc`
+    face_indices: Arc<Vec<u32>>,
+    /// Main face of every `FontStyle`, in declaration order
+    styles: [FontId; 4],
+    fallbacks: Arc<Vec<FontId>>,
+}
+
+impl FontStyle {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn weight(self) -> Weight {
+        match self {
+            FontStyle::Bold | FontStyle::BoldItalic => Weight::BOLD,
+            FontStyle::Regular | FontStyle::Italic => Weight::NORMAL,
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            FontStyle::Italic | FontStyle::BoldItalic => Style::Italic,
+            FontStyle::Regular | FontStyle::Bold => Style::Normal,
+        }
+    }
+}
+
+impl FontCollection {
+    /// Collection with only the embedded FiraCode.
+    pub fn embedded() -> Self {
+        let font = FontArc::try_from_slice(EMBEDDED_FONT).expect("embedded font is valid");
+
+        Self {
+            faces: Arc::new(vec![font]),
+            face_indices: Arc::new(vec![0]),
+            styles: [FontId(0); 4],
+            fallbacks: Arc::default(),
+        }
+    }
+
+    /// Resolves the configured faces against the system font directories. This reads
+    /// font files, so call it off the async runtime.
+    pub fn load(settings: &FontSettings) -> anyhow::Result<Self> {
+        let mut db = Database::new();
+        db.load_system_fonts();
+
+        let mut faces = Vec::new();
+        let mut face_indices = Vec::new();
+        let mut push = |(font, index): (FontArc, u32)| {
+            faces.push(font);
+            face_indices.push(index);
+            FontId(faces.len() - 1)
+        };
+
+        let family = settings.family.as_deref();
+        let regular = match (&settings.regular, family) {
+            (Some(path), _) => push(load_file(path)?),
+            (None, Some(family)) => match query(&db, family, FontStyle::Regular) {
+                Some(font) => push(font),
+                None => {
+                    tracing::warn!("Font family `{}` not found, using FiraCode", family);
+                    push((FontArc::try_from_slice(EMBEDDED_FONT)?, 0))
+                }
+            },
+            (None, None) => push((FontArc::try_from_slice(EMBEDDED_FONT)?, 0)),
+        };
+
+        let mut styles = [regular; 4];
+        for (style, path) in [
+            (FontStyle::Bold, &settings.bold),
+            (FontStyle::Italic, &settings.italic),
+            (FontStyle::BoldItalic, &settings.bold_italic),
+        ] {
+            // The embedded font is FiraCode, so look for its other faces when no family is set
+            let face = match path {
+                Some(path) => Some(load_file(path)?),
+                None => query(&db, family.unwrap_or("Fira Code"), style),
+            };
+
+            if let Some(face) = face {
+                styles[style.index()] = push(face);
+            }
+        }
+
+        let mut fallbacks = Vec::new();
+        for entry in &settings.fallback {
+            let path = Path::new(entry);
+            let face = if path.is_file() {
+                Some(load_file(path)?)
+            } else {
+                query(&db, entry, FontStyle::Regular)
+            };
+
+            match face {
+                Some(face) => fallbacks.push(push(face)),
+                None => tracing::debug!("Fallback font `{}` not found", entry),
+            }
+        }
+
+        Ok(Self {
+            faces: Arc::new(faces),
+            face_indices: Arc::new(face_indices),
+            styles,
+            fallbacks: Arc::new(fallbacks),
+        })
+    }
+
+    pub fn faces(&self) -> &[FontArc] {
+        &self.faces
+    }
+
+    pub fn font(&self, id: FontId) -> &FontArc {
+        &self.faces[id.0]
+    }
+
+    pub fn face_index(&self, id: FontId) -> u32 {
+        self.face_indices[id.0]
+    }
+
+    pub fn primary(&self, style: FontStyle) -> FontId {
+        self.styles[style.index()]
+    }
+
+    /// First face able to draw `c`: the face of `style`, then the regular face, then the
+    /// fallback chain. Characters no face has are drawn with `style`'s missing glyph.
+    pub fn resolve(&self, c: char, style: FontStyle) -> FontId {
+        let primary = self.primary(style);
+
+        [primary, self.primary(FontStyle::Regular)]
+            .into_iter()
+            .chain(self.fallbacks.iter().copied())
+            .find(|&id| self.font(id).glyph_id(c).0 != 0)
+            .unwrap_or(primary)
+    }
+}
+
+fn load_file(path: &Path) -> anyhow::Result<(FontArc, u32)> {
+    let data =
+        std::fs::read(path).with_context(|| format!("Can't read font {}", path.display()))?;
+    let font = FontVec::try_from_vec(data)
+        .with_context(|| format!("Invalid font file {}", path.display()))?;
+
+    Ok((FontArc::new(font), 0))
+}
+
+/// Face of `family` in `style`. Unlike fontdb's nearest match this refuses substitutes,
+/// e.g. a regular face when bold was asked for.
+fn query(db: &Database, family: &str, style: FontStyle) -> Option<(FontArc, u32)> {
+    let id = db.query(&Query {
+        families: &[Family::Name(family)],
+        weight: style.weight(),
+        style: style.style(),
+        ..Query::default()
+    })?;
+
+    let info = db.face(id)?;
+    let bold_enough = (info.weight >= Weight::SEMIBOLD) == (style.weight() == Weight::BOLD);
+    if !bold_enough || (info.style == Style::Normal) != (style.style() == Style::Normal) {
+        return None;
+    }
+
+    db.with_face_data(id, |data, index| {
+        let font = FontVec::try_from_vec_and_index(data.to_vec(), index).ok()?;
+        Some((FontArc::new(font), index))
+    })
+    .flatten()
+}
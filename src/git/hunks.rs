@@ -0,0 +1,115 @@
+use similar::{Algorithm, DiffTag, TextDiff};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Longest a diff may take before settling for a coarser result, for huge files.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Lines of the base replaced by lines of the text, as line index ranges.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    /// Lines of the base removed before line `new.start` of the text
+    Deleted,
+}
+
+impl Hunk {
+    pub fn kind(&self) -> ChangeKind {
+        match (self.old.is_empty(), self.new.is_empty()) {
+            (true, _) => ChangeKind::Added,
+            (false, true) => ChangeKind::Deleted,
+            (false, false) => ChangeKind::Modified,
+        }
+    }
+
+    /// Whether the hunk touches line `line` of the text. Deletions touch the line after them.
+    pub fn contains(&self, line: usize) -> bool {
+        self.new.contains(&line) || self.new.is_empty() && self.new.start == line
+    }
+}
+
+/// Changes from `base` to `text`, in order.
+pub fn hunks(base: &str, text: &str) -> Vec<Hunk> {
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Myers)
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(base, text);
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut previous = DiffTag::Equal;
+
+    // Deletions next to insertions are a single modification
+    for (tag, old, new) in diff.ops().iter().map(|op| op.as_tag_tuple()) {
+        match (tag, previous, hunks.last_mut()) {
+            (DiffTag::Equal, _, _) => {}
+            (_, DiffTag::Delete | DiffTag::Insert | DiffTag::Replace, Some(last)) => {
+                last.old.end = old.end;
+                last.new.end = new.end;
+            }
+            _ => hunks.push(Hunk { old, new }),
+        }
+        previous = tag;
+    }
+
+    hunks
+}
+
+/// For each line of `text`, the line of `base` it was left unchanged from.
+pub fn origins(base: &str, text: &str) -> Vec<Option<usize>> {
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Myers)
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(base, text);
+    let mut origins = vec![None; text.split('\n').count()];
+
+    for op in diff.ops() {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            for (line, origin) in new.zip(old) {
+                origins[line] = Some(origin);
+            }
+        }
+    }
+
+    origins
+}
+
+/// `base` with `hunk` applied, taking its lines from `text`.
+pub fn apply(base: &str, text: &str, hunk: &Hunk) -> String {
+    let replaced = line_bytes(base, hunk.old.clone());
+
+    let mut applied = String::with_capacity(base.len());
+    applied.push_str(&base[..replaced.start]);
+    applied.push_str(&text[line_bytes(text, hunk.new.clone())]);
+    applied.push_str(&base[replaced.end..]);
+    applied
+}
+
+/// Byte range of `text` to replace, and what with, to undo `hunk` in it.
+pub fn revert<'a>(base: &'a str, text: &str, hunk: &Hunk) -> (Range<usize>, &'a str) {
+    (
+        line_bytes(text, hunk.new.clone()),
+        &base[line_bytes(base, hunk.old.clone())],
+    )
+}
+
+/// Byte range of `lines`, line breaks included.
+pub fn line_bytes(text: &str, lines: Range<usize>) -> Range<usize> {
+    let offset = |line: usize| {
+        if line == 0 {
+            return 0;
+        }
+
+        text.match_indices('\n')
+            .nth(line - 1)
+            .map_or(text.len(), |(index, _)| index + 1)
+    };
+
+    offset(lines.start)..offset(lines.end)
+}
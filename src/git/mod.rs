@@ -0,0 +1,465 @@
+use crate::app_state::{AppState, SharedBuffer};
+use crate::buffer::diff::{self, Version};
+use crate::buffer::{BufferEvent, EventHandlerOutcome};
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult, Invocation,
+};
+use crate::state::StateEvent;
+use anyhow::{bail, Context};
+use git2::{IndexEntry, IndexTime, Oid, Repository};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+
+pub mod hunks;
+
+/// Files in the git folder that change when commits are made or checked out.
+const REPOSITORY_FILES: [&str; 3] = ["index", "HEAD", "logs/HEAD"];
+
+/// What the repository knows about an open file, read off the state loop.
+#[derive(Debug)]
+pub struct GitFile {
+    pub git_dir: PathBuf,
+    /// Path of the file in the repository
+    pub relative: PathBuf,
+    /// Staged contents, which change markers compare the text with. Untracked files have
+    /// none, so all their lines are added.
+    pub index: Option<String>,
+    /// Committed contents, with the commit that last changed each of their lines
+    pub head: Option<String>,
+    pub blame: Vec<Arc<Commit>>,
+}
+
+#[derive(Debug)]
+pub struct Commit {
+    pub id: String,
+    pub author: String,
+    /// Seconds since the Unix epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+impl Commit {
+    /// One line description, such as `Ann, 3 days ago · Fix the parser`.
+    pub fn describe(&self, now: SystemTime) -> String {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64);
+
+        format!(
+            "{}, {} · {}",
+            self.author,
+            ago(now - self.time),
+            self.summary
+        )
+    }
+}
+
+fn ago(seconds: i64) -> String {
+    let (count, unit) = match seconds.max(0) {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s if s < 86400 * 30 => (s / 86400, "day"),
+        s if s < 86400 * 365 => (s / (86400 * 30), "month"),
+        s => (s / (86400 * 365), "year"),
+    };
+
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+/// Reads what the repository around `path` knows about it. Files outside of repositories
+/// give `None`.
+pub fn read(path: &Path) -> anyhow::Result<Option<GitFile>> {
+    let Ok(repo) = Repository::discover(path.parent().unwrap_or(path)) else {
+        return Ok(None);
+    };
+    let Some(workdir) = repo.workdir() else {
+        return Ok(None);
+    };
+
+    let workdir = workdir.canonicalize()?;
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        // New files are read relative to their folder, which exists
+        Err(_) => match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => dir.canonicalize()?.join(name),
+            _ => return Ok(None),
+        },
+    };
+    let Ok(relative) = path.strip_prefix(&workdir).map(Path::to_path_buf) else {
+        return Ok(None);
+    };
+
+    let index = repo
+        .index()?
+        .get_path(&relative, 0)
+        .map(|entry| blob_text(&repo, entry.id))
+        .transpose()?;
+
+    let head = match repo.head().and_then(|head| head.peel_to_tree()) {
+        Ok(tree) => match tree.get_path(&relative) {
+            Ok(entry) => Some(blob_text(&repo, entry.id())?),
+            Err(_) => None,
+        },
+        // Repositories without commits yet
+        Err(_) => None,
+    };
+
+    let blame = match head {
+        Some(_) => blame(&repo, &relative)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(GitFile {
+        git_dir: repo.path().to_path_buf(),
+        relative,
+        index,
+        head,
+        blame,
+    }))
+}
+
+fn blob_text(repo: &Repository, id: Oid) -> anyhow::Result<String> {
+    let blob = repo.find_blob(id)?;
+
+    Ok(String::from_utf8_lossy(blob.content()).into_owned())
+}
+
+/// Commit that last changed each line of the committed file.
+fn blame(repo: &Repository, relative: &Path) -> anyhow::Result<Vec<Arc<Commit>>> {
+    let blame = repo.blame_file(relative, None)?;
+    let mut commits: HashMap<Oid, Arc<Commit>> = HashMap::new();
+    let mut lines = Vec::new();
+
+    for hunk in blame.iter() {
+        let id = hunk.final_commit_id();
+        let commit = match commits.get(&id) {
+            Some(commit) => commit.clone(),
+            None => {
+                let found = repo.find_commit(id)?;
+                let commit = Arc::new(Commit {
+                    id: id.to_string()[..8].to_string(),
+                    author: found.author().name().unwrap_or("Unknown").to_string(),
+                    time: found.time().seconds(),
+                    summary: found.summary().unwrap_or("").to_string(),
+                });
+                commits.insert(id, commit.clone());
+                commit
+            }
+        };
+
+        lines.extend(std::iter::repeat_n(commit, hunk.lines_in_hunk()));
+    }
+
+    Ok(lines)
+}
+
+/// Replaces the staged contents of `file` with `contents`.
+pub fn stage(file: &GitFile, contents: &str) -> anyhow::Result<()> {
+    let repo = Repository::open(&file.git_dir)?;
+    let mut index = repo.index()?;
+
+    let mut entry = index
+        .get_path(&file.relative, 0)
+        .unwrap_or_else(|| IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: Oid::zero(),
+            flags: 0,
+            flags_extended: 0,
+            path: file.relative.to_string_lossy().into_owned().into_bytes(),
+        });
+    // The staged contents aren't the file's, so the entry can't keep the file's stat data,
+    // or git would take the file for unchanged since staging
+    entry.ctime = IndexTime::new(0, 0);
+    entry.mtime = IndexTime::new(0, 0);
+    entry.dev = 0;
+    entry.ino = 0;
+    entry.uid = 0;
+    entry.gid = 0;
+    entry.file_size = 0;
+
+    index.add_frombuffer(&entry, contents.as_bytes())?;
+    index.write()?;
+
+    Ok(())
+}
+
+/// Reads what the repository knows about the file of `buffer` off the state loop, then
+/// hands it to the buffer and asks for a redraw.
+pub fn refresh(buffer: SharedBuffer, state_tx: Sender<StateEvent>) {
+    tokio::spawn(async move {
+        let Some(path) = buffer.lock().await.path().map(Path::to_path_buf) else {
+            return;
+        };
+
+        let file = match tokio::task::spawn_blocking(move || read(&path)).await {
+            Ok(Ok(file)) => file,
+            Ok(Err(err)) => {
+                tracing::debug!("Can't read the repository: {:#}", err);
+                None
+            }
+            Err(_) => return,
+        };
+
+        // Commits and checkouts made elsewhere refresh the buffer again
+        if let Some(file) = &file {
+            let watch = Invocation::new("git.watch_repository").with_arg(CommandArg::Text(
+                file.git_dir.to_string_lossy().into_owned(),
+            ));
+            if state_tx.send(StateEvent::Command(watch)).await.is_err() {
+                return;
+            }
+        }
+
+        buffer.lock().await.set_git(file.map(Arc::new));
+        let _ = state_tx.send(StateEvent::Redraw).await;
+    });
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "git.refresh",
+        "Read the repository state of every open file again",
+        |state, _| {
+            Box::pin(async move {
+                for buffer in &state.buffers {
+                    refresh(buffer.clone(), state.state_tx.clone());
+                }
+
+                Ok(EventHandlerOutcome::None)
+            })
+        },
+    ));
+    registry.register(
+        Command::new(
+            "git.watch_repository",
+            "Refresh open files whenever the repository at a git folder changes",
+            watch_repository,
+        )
+        .with_arg(ArgSpec::required("git_dir", ArgKind::Text)),
+    );
+    registry.register(Command::new(
+        "git.next_hunk",
+        "Move the cursor to the next changed block of lines",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::JumpToHunk { forward: true })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "git.previous_hunk",
+        "Move the cursor to the previous changed block of lines",
+        |state, _| {
+            Box::pin(async move {
+                state
+                    .send_to_active_buffer(BufferEvent::JumpToHunk { forward: false })
+                    .await
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "git.revert_hunk",
+        "Undo the changed block of lines at the cursor, back to its staged contents",
+        |state, _| {
+            Box::pin(async move { state.send_to_active_buffer(BufferEvent::RevertHunk).await })
+        },
+    ));
+    registry.register(Command::new(
+        "git.stage_hunk",
+        "Stage the changed block of lines at the cursor",
+        stage_hunk,
+    ));
+    registry.register(Command::new(
+        "git.diff",
+        "Compare the active buffer with its staged contents, in a new frame",
+        |state, _| Box::pin(diff_with(state, Base::Index)),
+    ));
+    registry.register(Command::new(
+        "git.diff_head",
+        "Compare the active buffer with its last committed contents, in a new frame",
+        |state, _| Box::pin(diff_with(state, Base::Head)),
+    ));
+}
+
+fn watch_repository(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let git_dir = PathBuf::from(args[0].as_text().expect("checked by registry"));
+
+        if let Some(watcher) = &mut state.watcher {
+            for file in REPOSITORY_FILES {
+                watcher.notify(&git_dir.join(file), Invocation::new("git.refresh"))?;
+            }
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn stage_hunk(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let buffer = state.active_buffer().clone();
+        let (file, staged) = {
+            let mut buffer = buffer.lock().await;
+            let file = buffer
+                .git_file()
+                .context("Buffer isn't in a git repository")?;
+            let Some(hunk) = buffer.hunk_at_cursor() else {
+                bail!("No change at the cursor");
+            };
+            let text = buffer.text().context("Buffer has no text to stage")?;
+
+            let staged = hunks::apply(file.index.as_deref().unwrap_or(""), text, &hunk);
+            (file, staged)
+        };
+
+        tokio::task::spawn_blocking(move || stage(&file, &staged))
+            .await?
+            .context("Can't stage the change")?;
+        refresh(buffer, state.state_tx.clone());
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+/// Contents of the repository to compare buffers with.
+#[derive(Copy, Clone)]
+enum Base {
+    Index,
+    Head,
+}
+
+async fn diff_with(state: &mut AppState, base: Base) -> CommandResult {
+    let (old, new) = {
+        let buffer = state.active_buffer().lock().await;
+        let file = buffer
+            .git_file()
+            .context("Buffer isn't in a git repository")?;
+        let text = buffer.text().context("Buffer has no text to compare")?;
+
+        let name = file.relative.display().to_string();
+        let (contents, label) = match base {
+            Base::Index => (&file.index, "staged"),
+            Base::Head => (&file.head, "HEAD"),
+        };
+        let old = Version {
+            title: format!("{} ({})", name, label),
+            text: contents.clone().unwrap_or_default(),
+        };
+        let new = Version {
+            title: format!("{} (working)", name),
+            text: text.to_string(),
+        };
+        (old, new)
+    };
+
+    diff::open(state, old, new).await;
+
+    Ok(EventHandlerOutcome::Redraw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+    use git2::{Signature, Status};
+
+    /// Repository in a scratch folder with `name` committed as `text`.
+    fn repository(name: &str, text: &str) -> (Repository, PathBuf) {
+        let dir = paths::scratch_dir(&format!("git-{}", name));
+        let repo = Repository::init(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+
+        {
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new(name)).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let author = Signature::now("Ann", "ann@example.com").unwrap();
+            repo.commit(Some("HEAD"), &author, &author, "Add", &tree, &[])
+                .unwrap();
+        }
+
+        (repo, path)
+    }
+
+    fn staged(repo: &Repository, name: &str) -> String {
+        // The repository keeps its index in memory, and `stage` writes another one
+        let mut index = repo.index().unwrap();
+        index.read(true).unwrap();
+
+        let entry = index.get_path(Path::new(name), 0).unwrap();
+        blob_text(repo, entry.id).unwrap()
+    }
+
+    #[test]
+    fn stages_part_of_a_change() {
+        let (repo, path) = repository("a.txt", "a\nb\nc\n");
+        let text = "a\nB\nc\nd\n";
+        std::fs::write(&path, text).unwrap();
+
+        let file = read(&path).unwrap().unwrap();
+        let base = file.index.clone().unwrap();
+        let hunk = hunks::hunks(&base, text)[0].clone();
+        stage(&file, &hunks::apply(&base, text, &hunk)).unwrap();
+
+        assert_eq!(staged(&repo, "a.txt"), "a\nB\nc\n");
+        let status = repo.status_file(Path::new("a.txt")).unwrap();
+        assert_eq!(status, Status::INDEX_MODIFIED | Status::WT_MODIFIED);
+        assert_eq!(
+            read(&path).unwrap().unwrap().index.as_deref(),
+            Some("a\nB\nc\n")
+        );
+    }
+
+    #[test]
+    fn staging_everything_leaves_the_file_unchanged() {
+        let (repo, path) = repository("b.txt", "a\n");
+        std::fs::write(&path, "b\n").unwrap();
+
+        let file = read(&path).unwrap().unwrap();
+        stage(&file, "b\n").unwrap();
+
+        let statuses = repo.statuses(None).unwrap();
+        let statuses: Vec<_> = statuses
+            .iter()
+            .map(|entry| (entry.path().unwrap().to_string(), entry.status()))
+            .collect();
+        assert_eq!(statuses, [("b.txt".to_string(), Status::INDEX_MODIFIED)]);
+    }
+
+    #[test]
+    fn stages_untracked_files() {
+        let (repo, path) = repository("c.txt", "");
+        let new = path.with_file_name("d.txt");
+        std::fs::write(&new, "d\n").unwrap();
+
+        let file = read(&new).unwrap().unwrap();
+        assert_eq!(file.index, None);
+        stage(&file, "d\n").unwrap();
+
+        assert_eq!(staged(&repo, "d.txt"), "d\n");
+        assert_eq!(
+            repo.status_file(Path::new("d.txt")).unwrap(),
+            Status::INDEX_NEW
+        );
+    }
+}
@@ -0,0 +1,6 @@
+#[derive(Debug)]
+pub enum KamiEvent {
+    RequestRedraw,
+    /// The state loop is done and the window can close
+    Exit,
+}
@@ -0,0 +1,247 @@
+use crate::buffer::BoundingBox;
+use crate::editor::{Frontend, Host};
+use crate::input::{Key, Modifiers};
+use crate::state::StateEvent;
+use events::KamiEvent;
+use quad_brush::QuadBrush;
+use render::RenderEvent;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use viewport::Viewport;
+use wgpu_glyph::GlyphBrush;
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopProxy};
+use winit::window::Window;
+
+pub mod events;
+mod quad_brush;
+mod render;
+mod viewport;
+
+pub struct WindowData {
+    viewport: Viewport,
+    host: Box<dyn Host>,
+    /// Draws the text of all frames, set up again when glyphs need rerasterizing
+    glyph_brush: Option<GlyphBrush<()>>,
+    /// Draw under and over the text of all frames
+    backgrounds: QuadBrush,
+    overlays: QuadBrush,
+    scale_factor: f32,
+    /// Scale factor and zoom of the last frame, to notice when glyphs need rerasterizing
+    rendered_scale: Option<(f32, f32)>,
+}
+
+impl Frontend for EventLoopProxy<KamiEvent> {
+    fn request_redraw(&self) {
+        let _ = self.send_event(KamiEvent::RequestRedraw);
+    }
+
+    fn exit(&self) {
+        let _ = self.send_event(KamiEvent::Exit);
+    }
+}
+
+/// Shows the editor of `host` in `window`, drawn with wgpu.
+pub async fn run(
+    event_loop: EventLoop<KamiEvent>,
+    window: Window,
+    mut host: impl Host,
+) -> anyhow::Result<!> {
+    let (render_tx, render_rx) = mpsc::channel(1024);
+
+    let state_tx = host.events();
+    // Clicks are mapped to frames in window coordinates
+    let mut window_size = window.inner_size();
+    let mut mouse_position = (0.0, 0.0);
+
+    host.start(event_loop.create_proxy());
+    let exit_tx = state_tx.clone();
+    tokio::spawn(async move {
+        // Without a window nothing can ask about unsaved changes, so they're kept aside
+        if let Err(err) = render::render_loop(window, render_rx, Box::new(host)).await {
+            tracing::error!("Render loop failed: {:#}", err);
+            let _ = exit_tx.send(StateEvent::Exit).await;
+        }
+    });
+
+    event_loop.run(move |event, _, control_flow| {
+        let handle = Handle::current();
+
+        *control_flow = ControlFlow::Wait;
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::Resized(new_size),
+                ..
+            } => {
+                window_size = new_size;
+                forward(&handle, &render_tx, RenderEvent::Resize(new_size));
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    },
+                ..
+            } => {
+                let new_size = *new_inner_size;
+                window_size = new_size;
+                forward(
+                    &handle,
+                    &render_tx,
+                    RenderEvent::ScaleFactorChange(scale_factor, new_size),
+                );
+            }
+            Event::RedrawRequested(_) => forward(&handle, &render_tx, RenderEvent::Redraw),
+            // The state loop asks about unsaved changes first, then asks for the exit
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => forward(&handle, &state_tx, StateEvent::Quit),
+            Event::WindowEvent {
+                // Handle text input
+                event: WindowEvent::ReceivedCharacter(c),
+                ..
+            } => forward(&handle, &state_tx, StateEvent::CharInput(c)),
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(code),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(key) = key(code) {
+                    forward(&handle, &state_tx, StateEvent::KeyPress(key));
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => forward(
+                &handle,
+                &state_tx,
+                StateEvent::ModifiersChange(modifiers(state)),
+            ),
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => mouse_position = (position.x as f32, position.y as f32),
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                ..
+            } => {
+                let click = StateEvent::Click {
+                    position: mouse_position,
+                    window: BoundingBox {
+                        left: 0.0,
+                        top: 0.0,
+                        width: window_size.width as f32,
+                        height: window_size.height as f32,
+                    },
+                };
+
+                forward(&handle, &state_tx, click);
+            }
+            Event::UserEvent(event) => match event {
+                KamiEvent::RequestRedraw => forward(&handle, &render_tx, RenderEvent::Redraw),
+                KamiEvent::Exit => *control_flow = ControlFlow::Exit,
+            },
+            _ => {}
+        }
+    });
+}
+
+/// Sends `event` to the state or render loop. The window's event loop can't wait, so the
+/// event is sent from a task. A loop that stopped only loses the event.
+fn forward<T: Send + 'static>(handle: &Handle, tx: &mpsc::Sender<T>, event: T) {
+    let tx = tx.clone();
+
+    handle.spawn(async move {
+        if tx.send(event).await.is_err() {
+            tracing::warn!("Dropped an event for a stopped loop");
+        }
+    });
+}
+
+fn modifiers(state: ModifiersState) -> Modifiers {
+    let mut modifiers = Modifiers::empty();
+
+    for (held, modifier) in [
+        (state.ctrl(), Modifiers::CTRL),
+        (state.shift(), Modifiers::SHIFT),
+        (state.alt(), Modifiers::ALT),
+        (state.logo(), Modifiers::LOGO),
+    ] {
+        if held {
+            modifiers |= modifier;
+        }
+    }
+
+    modifiers
+}
+
+/// Key of a key code, for the keys bindings can name.
+fn key(code: VirtualKeyCode) -> Option<Key> {
+    use VirtualKeyCode::*;
+
+    let letters = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    if let Some(index) = letters.iter().position(|&letter| letter == code) {
+        return Some(Key::Char((b'a' + index as u8) as char));
+    }
+
+    let digits = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    if let Some(index) = digits.iter().position(|&digit| digit == code) {
+        return Some(Key::Char((b'0' + index as u8) as char));
+    }
+
+    let functions = [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12];
+    if let Some(index) = functions.iter().position(|&function| function == code) {
+        return Some(Key::F(index as u8 + 1));
+    }
+
+    Some(match code {
+        Minus => Key::Char('-'),
+        Equals => Key::Char('='),
+        Plus => Key::Char('+'),
+        LBracket => Key::Char('['),
+        RBracket => Key::Char(']'),
+        Semicolon => Key::Char(';'),
+        Apostrophe => Key::Char('\''),
+        Comma => Key::Char(','),
+        Period => Key::Char('.'),
+        Slash => Key::Char('/'),
+        Backslash => Key::Char('\\'),
+        Grave => Key::Char('`'),
+        Tab => Key::Tab,
+        Return => Key::Enter,
+        Space => Key::Space,
+        Back => Key::Backspace,
+        Delete => Key::Delete,
+        Escape => Key::Escape,
+        Insert => Key::Insert,
+        Home => Key::Home,
+        End => Key::End,
+        PageUp => Key::PageUp,
+        PageDown => Key::PageDown,
+        Left => Key::Left,
+        Right => Key::Right,
+        Up => Key::Up,
+        Down => Key::Down,
+        _ => return None,
+    })
+}
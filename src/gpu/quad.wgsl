@@ -1,12 +1,8 @@
-struct Globals {
-    transform: mat4x4<f32>;
-};
-
 struct VertexInput {
     [[builtin(vertex_index)]] vertex_index: u32;
     [[location(0)]] aabb: vec4<f32>; // left top right bottom
     [[location(1)]] z_pos: f32;
-    [[location(2)]] color: vec3<f32>;
+    [[location(2)]] color: vec4<f32>;
 };
 
 struct VertexOutput {
@@ -36,7 +32,7 @@ fn vs_main(input: VertexInput) -> VertexOutput {
         default: {}
     }
 
-    out.f_color = vec4<f32>(input.color, 1.0);
+    out.f_color = input.color;
     out.position = vec4<f32>(pos, input.z_pos, 1.0);
 
     return out;
@@ -1,8 +1,9 @@
+use crate::scene::Quad;
+use bytemuck::{Pod, Zeroable};
 use std::borrow::Cow;
 use std::default::default;
 use std::mem::size_of;
 use std::num::NonZeroU64;
-use std::slice::from_raw_parts;
 use wgpu::util::StagingBelt;
 use wgpu::{
     vertex_attr_array, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer,
@@ -12,29 +13,24 @@ use wgpu::{
     ShaderSource, TextureView, VertexBufferLayout, VertexState, VertexStepMode,
 };
 
-pub struct CursorBrush {
+/// Draws solid rectangles: cursors, selections, borders.
+pub struct QuadBrush {
     raw: RenderPipeline,
     buffer: Buffer,
+    capacity: usize,
 
-    cursor: Cursor,
+    queued: Vec<Quad>,
 }
 
-#[derive(Default)]
-#[repr(C)]
-pub struct Cursor {
-    pub aabb: [f32; 4],
-    pub z_pos: f32,
-    pub color: [f32; 3],
-}
+// Safety: `Quad` is `repr(C)` and made of `f32`s only, so it has no padding
+unsafe impl Zeroable for Quad {}
+unsafe impl Pod for Quad {}
+
+const INITIAL_CAPACITY: usize = 64;
 
-impl CursorBrush {
+impl QuadBrush {
     pub fn new(device: &wgpu::Device, render_format: wgpu::TextureFormat) -> Self {
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("kami::pipeline buffer"),
-            size: size_of::<Cursor>() as u64,
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let buffer = create_instance_buffer(device, INITIAL_CAPACITY);
 
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
@@ -44,7 +40,7 @@ impl CursorBrush {
 
         let shader = device.create_shader_module(&ShaderModuleDescriptor {
             label: None,
-            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("./additions.wgsl"))),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("./quad.wgsl"))),
         });
 
         let raw = device.create_render_pipeline(&RenderPipelineDescriptor {
@@ -54,12 +50,12 @@ impl CursorBrush {
                 module: &shader,
                 entry_point: "vs_main",
                 buffers: &[VertexBufferLayout {
-                    array_stride: 32,
+                    array_stride: size_of::<Quad>() as u64,
                     step_mode: VertexStepMode::Instance,
                     attributes: &vertex_attr_array![
                         0 => Float32x4,
                         1 => Float32,
-                        2 => Float32x3,
+                        2 => Float32x4,
                     ],
                 }],
             },
@@ -97,41 +93,64 @@ impl CursorBrush {
         Self {
             raw,
             buffer,
-            cursor: default(),
+            capacity: INITIAL_CAPACITY,
+            queued: Vec::new(),
         }
     }
 
-    pub fn update(&mut self, cursor: Cursor) {
-        self.cursor = cursor;
+    pub fn queue(&mut self, quad: Quad) {
+        self.queued.push(quad);
     }
 
+    /// Draws and clears everything queued since the last draw.
     pub fn draw(
         &mut self,
         encoder: &mut CommandEncoder,
         view: &TextureView,
         device: &Device,
         staging_belt: &mut StagingBelt,
+        target_width: u32,
+        target_height: u32,
     ) {
-        let cursor_bytes = unsafe {
-            // This reinterpret works when cursor is repr(C)
-            from_raw_parts(
-                &self.cursor as *const Cursor as *const u8,
-                size_of::<Cursor>(),
-            )
-        };
+        if self.queued.is_empty() {
+            return;
+        }
+
+        if self.queued.len() > self.capacity {
+            self.capacity = self.queued.len().next_power_of_two();
+            self.buffer = create_instance_buffer(device, self.capacity);
+        }
+
+        // Convert from pixels to normalized device coordinates
+        let instances: Vec<Quad> = self
+            .queued
+            .drain(..)
+            .map(|quad| {
+                let [left, top, right, bottom] = quad.aabb;
+                let x = |px: f32| px / target_width as f32 * 2.0 - 1.0;
+                let y = |px: f32| 1.0 - px / target_height as f32 * 2.0;
+
+                Quad {
+                    aabb: [x(left), y(top), x(right), y(bottom)],
+                    ..quad
+                }
+            })
+            .collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&instances);
 
         let mut buffer_view = staging_belt.write_buffer(
             encoder,
             &self.buffer,
             0,
-            NonZeroU64::new(size_of::<Cursor>() as u64).unwrap(),
+            NonZeroU64::new(bytes.len() as u64).unwrap(),
             device,
         );
 
-        buffer_view.copy_from_slice(cursor_bytes);
+        buffer_view.copy_from_slice(bytes);
+        drop(buffer_view);
 
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("kami::pipeline render pass"),
+            label: Some("kami::quad render pass"),
             color_attachments: &[wgpu::RenderPassColorAttachment {
                 view,
                 resolve_target: None,
@@ -144,18 +163,17 @@ impl CursorBrush {
         });
 
         render_pass.set_pipeline(&self.raw);
-        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..bytes.len() as u64));
 
-        render_pass.draw(0..4, 0..1);
+        render_pass.draw(0..4, 0..instances.len() as u32);
     }
 }
 
-pub fn orthographic_projection(width: u32, height: u32) -> [f32; 16] {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    [
-        2.0 / width as f32, 0.0, 0.0, 0.0,
-        0.0, -2.0 / height as f32, 0.0, 0.0,
-        0.0, 0.0, 1.0, 0.0,
-        -1.0, 1.0, 0.0, 1.0,
-    ]
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("kami::quad instance buffer"),
+        size: (size_of::<Quad>() * capacity) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
@@ -0,0 +1,291 @@
+use crate::buffer::BoundingBox;
+use crate::editor::Host;
+use crate::gpu::quad_brush::QuadBrush;
+use crate::gpu::viewport::ViewportDescriptor;
+use crate::gpu::WindowData;
+use crate::scene::{Quad, Scene, Surface};
+use crate::theme::Rgba;
+use anyhow::Context;
+use std::default::default;
+use tokio::sync::mpsc::Receiver;
+use wgpu::util::StagingBelt;
+use wgpu::{
+    Backends, Color, CommandEncoderDescriptor, Device, DeviceDescriptor, Features, Instance,
+    Limits, LoadOp, Operations, Queue, RenderPassColorAttachment, RenderPassDescriptor,
+    RequestAdapterOptions, TextureFormat, TextureViewDescriptor,
+};
+use wgpu_glyph::ab_glyph::{point, Rect};
+use wgpu_glyph::{Extra, FontId, GlyphBrushBuilder, Section, SectionGlyph, Text};
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+const SPLIT_BORDER_WIDTH: f32 = 1.0;
+
+#[derive(Debug)]
+pub enum RenderEvent {
+    Resize(PhysicalSize<u32>),
+    ScaleFactorChange(f64, PhysicalSize<u32>),
+    Redraw,
+}
+
+pub async fn render_loop(
+    window: Window,
+    mut rx: Receiver<RenderEvent>,
+    host: Box<dyn Host>,
+) -> anyhow::Result<()> {
+    let instance = Instance::new(Backends::all());
+    let viewport_desc = ViewportDescriptor::new(window, &instance);
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            compatible_surface: Some(&viewport_desc.surface),
+            ..default()
+        })
+        .await
+        .context("Failed to find an appropriate adapter")?;
+
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: None,
+                features: Features::empty(),
+                limits: Limits::downlevel_defaults(),
+            },
+            None,
+        )
+        .await
+        .context("Failed to create device")?;
+
+    let viewport = viewport_desc
+        .build(&adapter, &device)
+        .context("Failed to build viewport")?;
+    let format = viewport.config.format;
+    let scale_factor = viewport.descriptor.window.scale_factor() as f32;
+
+    let mut window_data = WindowData {
+        viewport,
+        host,
+        glyph_brush: None,
+        backgrounds: QuadBrush::new(&device, format),
+        overlays: QuadBrush::new(&device, format),
+        scale_factor,
+        rendered_scale: None,
+    };
+
+    let mut staging_belt = StagingBelt::new(1024);
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            RenderEvent::Resize(new_size) => resize_window(&mut window_data, &device, new_size),
+            RenderEvent::ScaleFactorChange(scale_factor, new_size) => {
+                window_data.scale_factor = scale_factor as f32;
+                resize_window(&mut window_data, &device, new_size);
+            }
+            RenderEvent::Redraw => {
+                redraw_window(&mut window_data, format, &device, &queue, &mut staging_belt).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn resize_window(window_data: &mut WindowData, device: &Device, new_size: PhysicalSize<u32>) {
+    window_data.viewport.resize(device, new_size);
+    window_data.viewport.descriptor.window.request_redraw();
+}
+
+async fn redraw_window(
+    window_data: &mut WindowData,
+    format: TextureFormat,
+    device: &Device,
+    queue: &Queue,
+    staging_belt: &mut StagingBelt,
+) {
+    let size = window_data.viewport.descriptor.window.inner_size();
+    let window = BoundingBox {
+        left: 0.0,
+        top: 0.0,
+        width: size.width as f32,
+        height: size.height as f32,
+    };
+
+    let surface = Surface::Window {
+        scale_factor: window_data.scale_factor,
+    };
+    let drawing = match window_data.host.draw(window, surface).await {
+        Ok(drawing) => drawing,
+        Err(err) => {
+            tracing::warn!("Skipped a frame: {:#}", err);
+            return;
+        }
+    };
+
+    // Glyphs of every buffer were rasterized for another size
+    let scale = (window_data.scale_factor, drawing.zoom);
+    if window_data.rendered_scale.replace(scale) != Some(scale) {
+        window_data.glyph_brush = None;
+    }
+    if window_data.glyph_brush.is_none() {
+        let (fonts, _) = window_data.host.font().await;
+        window_data.glyph_brush =
+            Some(GlyphBrushBuilder::using_fonts(fonts.faces().to_vec()).build(device, format));
+    }
+
+    let frame = match window_data.viewport.current_texture() {
+        Ok(frame) => frame,
+        // Lost and outdated surfaces work again once configured anew
+        Err(err) => {
+            tracing::warn!("Skipped a frame: {:#}", err);
+            window_data.viewport.resize(device, size);
+            return;
+        }
+    };
+    let view = frame.texture.create_view(&TextureViewDescriptor::default());
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    let _ = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: None,
+        color_attachments: &[RenderPassColorAttachment {
+            view: &view,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(clear_color(drawing.theme.background)),
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: None,
+    });
+
+    for (bounding_box, _, scene) in &drawing.frames {
+        queue_scene(window_data, *bounding_box, scene);
+    }
+
+    // Borders go on the right and bottom edges of frames that don't touch the window edge
+    let border_width = SPLIT_BORDER_WIDTH * window_data.scale_factor;
+    for (bb, ..) in &drawing.frames {
+        let right = bb.left + bb.width;
+        let bottom = bb.top + bb.height;
+
+        if right < size.width as f32 - 1.0 {
+            window_data.overlays.queue(Quad {
+                aabb: [right - border_width, bb.top, right, bottom],
+                z_pos: 0.0,
+                color: drawing.theme.split_border.0,
+            });
+        }
+        if bottom < size.height as f32 - 1.0 {
+            window_data.overlays.queue(Quad {
+                aabb: [bb.left, bottom - border_width, right, bottom],
+                z_pos: 0.0,
+                color: drawing.theme.split_border.0,
+            });
+        }
+    }
+
+    // Frames don't overlap, so each layer is drawn for all of them at once
+    window_data.backgrounds.draw(
+        &mut encoder,
+        &view,
+        device,
+        staging_belt,
+        size.width,
+        size.height,
+    );
+    window_data
+        .glyph_brush
+        .as_mut()
+        .expect("set up above")
+        .draw_queued(
+            device,
+            staging_belt,
+            &mut encoder,
+            &view,
+            size.width,
+            size.height,
+        )
+        .expect(".draw_queued can't return Err(_)");
+    window_data.overlays.draw(
+        &mut encoder,
+        &view,
+        device,
+        staging_belt,
+        size.width,
+        size.height,
+    );
+
+    staging_belt.finish();
+    queue.submit(Some(encoder.finish()));
+    frame.present();
+
+    tokio::spawn(staging_belt.recall());
+}
+
+fn queue_scene(window_data: &mut WindowData, bb: BoundingBox, scene: &Scene) {
+    for quad in &scene.backgrounds {
+        window_data.backgrounds.queue(*quad);
+    }
+    for quad in &scene.overlays {
+        window_data.overlays.queue(*quad);
+    }
+
+    let glyph_brush = window_data.glyph_brush.as_mut().expect("set up before");
+
+    // Each glyph is its own section, to give it its own color
+    let clip = scene.clip.unwrap_or(bb);
+    let bounds = Rect {
+        min: point(clip.left, clip.top),
+        max: point(clip.left + clip.width, clip.top + clip.height),
+    };
+    let (glyphs, extra) = scene
+        .glyphs
+        .iter()
+        .enumerate()
+        .map(|(section_index, glyph)| {
+            let section_glyph = SectionGlyph {
+                section_index,
+                byte_index: 0,
+                glyph: glyph.glyph.clone(),
+                font_id: FontId(glyph.font_id.0),
+            };
+
+            (
+                section_glyph,
+                Extra {
+                    color: glyph.color,
+                    z: 0.0,
+                },
+            )
+        })
+        .unzip();
+    glyph_brush.queue_pre_positioned(glyphs, extra, bounds);
+
+    for label in &scene.labels {
+        let text = label
+            .spans
+            .iter()
+            .map(|span| {
+                Text::new(&span.text)
+                    .with_scale(span.scale)
+                    .with_color(span.color)
+                    .with_font_id(FontId(span.font_id.0))
+            })
+            .collect();
+
+        glyph_brush.queue(
+            Section::default()
+                .with_screen_position(label.position)
+                .with_bounds(label.bounds)
+                .with_text(text),
+        );
+    }
+}
+
+fn clear_color(color: Rgba) -> Color {
+    let [r, g, b, a] = color.0;
+
+    Color {
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: a as f64,
+    }
+}
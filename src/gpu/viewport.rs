@@ -1,13 +1,14 @@
-use crate::{Color, PresentMode, SurfaceConfiguration, TextureUsages};
 use anyhow::Context;
-use wgpu::{Adapter, Device, Instance, Surface, SurfaceTexture};
+use wgpu::{
+    Adapter, Device, Instance, PresentMode, Surface, SurfaceConfiguration, SurfaceTexture,
+    TextureUsages,
+};
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
 pub struct ViewportDescriptor {
     pub window: Window,
     pub surface: Surface,
-    pub bg: Color,
 }
 
 pub struct Viewport {
@@ -16,14 +17,10 @@ pub struct Viewport {
 }
 
 impl ViewportDescriptor {
-    pub fn new(window: Window, bg: Color, instance: &Instance) -> Self {
+    pub fn new(window: Window, instance: &Instance) -> Self {
         let surface = unsafe { instance.create_surface(&window) };
 
-        Self {
-            window,
-            surface,
-            bg,
-        }
+        Self { window, surface }
     }
 
     pub fn build(self, adapter: &Adapter, device: &Device) -> anyhow::Result<Viewport> {
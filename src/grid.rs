@@ -0,0 +1,450 @@
+use crate::folding::Folds;
+use crate::fonts::{FontCollection, FontStyle};
+use crate::shaping::ShapedLine;
+use ab_glyph::{Font, ScaleFont};
+use serde::Deserialize;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// How lines are laid out on the cell grid, from the `[layout]` table of `config.toml`.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GridSettings {
+    /// Columns between tab stops
+    pub tab_width: usize,
+    /// Wrap lines at the frame's edge instead of letting them run past it
+    pub soft_wrap: bool,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            soft_wrap: false,
+        }
+    }
+}
+
+/// Size of a grid cell in pixels, from the primary font at the current scale.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GridMetrics {
+    pub cell_width: f32,
+    pub line_height: f32,
+    /// Distance from the top of a row to the baseline
+    pub ascent: f32,
+}
+
+impl GridMetrics {
+    /// Cells sized after the digits of the regular primary font, at `scale`.
+    pub fn new(fonts: &FontCollection, scale: f32) -> Self {
+        let primary = fonts
+            .font(fonts.primary(FontStyle::Regular))
+            .as_scaled(scale);
+
+        Self {
+            cell_width: primary.h_advance(primary.glyph_id('0')),
+            line_height: primary.height() + primary.line_gap(),
+            ascent: primary.ascent(),
+        }
+    }
+}
+
+/// Position of a grapheme on the grid, relative to the first row of its line.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Cell {
+    pub row: usize,
+    pub column: usize,
+    /// 2 for wide characters, the distance to the next tab stop for tabs
+    pub width: usize,
+    pub rtl: bool,
+}
+
+struct Row {
+    /// Byte offset of the grapheme drawn in each column
+    offsets: Vec<usize>,
+    /// Caret offset for positions past the last column
+    end: usize,
+}
+
+/// One line of text laid out on the grid. Lookups by byte offset and by cell are O(1),
+/// so drawing, cursor motion and hit testing all agree on where everything is.
+pub struct LineGrid {
+    /// Cell of the grapheme at each byte offset of the line, plus the end of the line
+    cells: Vec<Cell>,
+    rows: Vec<Row>,
+}
+
+impl LineGrid {
+    /// Lays out `line` in the visual order of its shaped glyphs. With `wrap_columns`,
+    /// rows are broken at that width and continue at the line's indentation.
+    pub fn new(
+        line: &str,
+        shaped: &ShapedLine,
+        settings: &GridSettings,
+        wrap_columns: Option<usize>,
+    ) -> Self {
+        let tab_width = settings.tab_width.max(1);
+        let mut cells = vec![Cell::default(); line.len() + 1];
+        let mut rows = vec![Row {
+            offsets: Vec::new(),
+            end: line.len(),
+        }];
+
+        // Deeply indented lines would leave no room for text
+        let indent =
+            wrap_columns.map_or(0, |columns| indent_width(line, tab_width).min(columns / 2));
+
+        let mut column = 0;
+        for (range, rtl) in visual_graphemes(line, shaped) {
+            let grapheme = &line[range.clone()];
+            let width = match grapheme {
+                "\t" => tab_width - column % tab_width,
+                _ => grapheme.width().max(1),
+            };
+
+            if let Some(columns) = wrap_columns {
+                if column > indent && column + width > columns {
+                    rows.last_mut().unwrap().end = range.start;
+                    rows.push(Row {
+                        offsets: vec![range.start; indent],
+                        end: line.len(),
+                    });
+                    column = indent;
+                }
+            }
+
+            let cell = Cell {
+                row: rows.len() - 1,
+                column,
+                width,
+                rtl,
+            };
+            cells[range.clone()].fill(cell);
+            rows.last_mut()
+                .unwrap()
+                .offsets
+                .extend(std::iter::repeat_n(range.start, width));
+            column += width;
+        }
+
+        cells[line.len()] = Cell {
+            row: rows.len() - 1,
+            column,
+            width: 0,
+            rtl: false,
+        };
+
+        Self { cells, rows }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Cell of the grapheme containing byte `offset`.
+    pub fn cell(&self, offset: usize) -> Cell {
+        self.cells[offset]
+    }
+
+    /// Row and column of the caret before the grapheme at `offset`.
+    pub fn caret(&self, offset: usize) -> (usize, usize) {
+        let cell = self.cells[offset];
+
+        // Carets of right-to-left text sit on the right edge of the grapheme
+        if cell.rtl {
+            (cell.row, cell.column + cell.width)
+        } else {
+            (cell.row, cell.column)
+        }
+    }
+
+    /// Leftmost cell of the graphemes in `range`, where a ligature or a cluster of
+    /// combining marks covering them starts.
+    pub fn span_start(&self, range: Range<usize>) -> Cell {
+        self.cells[range]
+            .iter()
+            .copied()
+            .min_by_key(|cell| (cell.row, cell.column))
+            .unwrap_or_default()
+    }
+
+    /// Caret offset closest to a fractional `column` of `row`, for hit testing and
+    /// vertical cursor motion.
+    pub fn offset_at(&self, line: &str, row: usize, column: f32) -> usize {
+        let row = &self.rows[row.min(self.rows.len() - 1)];
+        let index = column.max(0.0) as usize;
+
+        let Some(&offset) = row.offsets.get(index) else {
+            return row.end;
+        };
+
+        let cell = self.cells[offset];
+
+        // Indentation cells of a continuation row belong to no grapheme
+        if index < cell.column {
+            return offset;
+        }
+
+        let end = offset + line[offset..].graphemes(true).next().map_or(0, str::len);
+        let right_half = column - cell.column as f32 > cell.width as f32 / 2.0;
+
+        if right_half != cell.rtl {
+            end
+        } else {
+            offset
+        }
+    }
+}
+
+/// Columns covered by the leading whitespace of `line`.
+pub fn indent_width(line: &str, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+
+    line.chars()
+        .take_while(|c| matches!(c, ' ' | '\t'))
+        .fold(0, |column, c| match c {
+            '\t' => column + tab_width - column % tab_width,
+            _ => column + 1,
+        })
+}
+
+/// Graphemes of `line` in the order the shaped glyphs draw them, with their direction.
+/// Graphemes no glyph covers, e.g. when shaping failed, follow in logical order.
+fn visual_graphemes(line: &str, shaped: &ShapedLine) -> Vec<(Range<usize>, bool)> {
+    let mut graphemes = Vec::new();
+    let mut seen = vec![false; line.len()];
+    let mut previous: Option<&Range<usize>> = None;
+
+    for glyph in &shaped.glyphs {
+        if previous == Some(&glyph.cluster) || seen[glyph.cluster.start] {
+            continue;
+        }
+        previous = Some(&glyph.cluster);
+
+        let start = glyph.cluster.start;
+        let mut cluster: Vec<_> = line[glyph.cluster.clone()]
+            .grapheme_indices(true)
+            .map(|(i, g)| (start + i..start + i + g.len(), glyph.rtl))
+            .collect();
+
+        if glyph.rtl {
+            cluster.reverse();
+        }
+
+        for (range, _) in &cluster {
+            seen[range.start] = true;
+        }
+        graphemes.extend(cluster);
+    }
+
+    for (i, g) in line.grapheme_indices(true) {
+        if !seen[i] {
+            graphemes.push((i..i + g.len(), false));
+        }
+    }
+
+    graphemes
+}
+
+/// Whole text laid out on the grid, line by line. Folded lines take no rows.
+pub struct TextGrid {
+    lines: Vec<LineGrid>,
+    /// Byte offset where each line starts
+    starts: Vec<usize>,
+    /// Visual row where each line starts. Hidden lines share the row of the next line.
+    first_rows: Vec<usize>,
+    hidden: Vec<bool>,
+    wrap_columns: Option<usize>,
+}
+
+impl TextGrid {
+    /// `shaped` holds the shaped lines of `text`, split on `\n`.
+    pub fn new(
+        text: &str,
+        shaped: &[ShapedLine],
+        settings: &GridSettings,
+        wrap_columns: Option<usize>,
+        folds: &Folds,
+    ) -> Self {
+        let mut grid = Self {
+            lines: Vec::with_capacity(shaped.len()),
+            starts: Vec::with_capacity(shaped.len()),
+            first_rows: Vec::with_capacity(shaped.len()),
+            hidden: Vec::with_capacity(shaped.len()),
+            wrap_columns,
+        };
+        let mut start = 0;
+        let mut row = 0;
+
+        for shaped in shaped {
+            let line = line_at(text, start);
+            let line_grid = LineGrid::new(line, shaped, settings, wrap_columns);
+            let hidden = folds.hides_line(start);
+
+            grid.starts.push(start);
+            grid.first_rows.push(row);
+            grid.hidden.push(hidden);
+            if !hidden {
+                row += line_grid.row_count();
+            }
+            grid.lines.push(line_grid);
+
+            start += text[start..].find('\n').map_or(0, |end| end + 1);
+        }
+
+        grid
+    }
+
+    pub fn wrap_columns(&self) -> Option<usize> {
+        self.wrap_columns
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.lines()
+            .last()
+            .map_or(0, |(_, first_row, line)| first_row + line.row_count())
+    }
+
+    /// Visible lines with their start offset and first visual row, in order.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, usize, &LineGrid)> {
+        self.starts
+            .iter()
+            .zip(&self.first_rows)
+            .zip(&self.lines)
+            .zip(&self.hidden)
+            .filter(|(_, &hidden)| !hidden)
+            .map(|(((&start, &row), grid), _)| (start, row, grid))
+    }
+
+    /// Visual rows of line `index`, empty if it's folded away. Lines past the end start
+    /// after the last row.
+    pub fn line_rows(&self, index: usize) -> Range<usize> {
+        let Some(&first) = self.first_rows.get(index) else {
+            let end = self.row_count();
+            return end..end;
+        };
+
+        match self.hidden[index] {
+            true => first..first,
+            false => first..first + self.lines[index].row_count(),
+        }
+    }
+
+    /// Whether the line containing byte `offset` is folded away.
+    pub fn is_hidden(&self, offset: usize) -> bool {
+        self.hidden[self.line_index(offset)]
+    }
+
+    /// Index of the line containing byte `offset`.
+    pub fn line_index(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset).max(1) - 1
+    }
+
+    /// Visual row of the grapheme at byte `offset`, with its cell.
+    pub fn cell(&self, offset: usize) -> (usize, Cell) {
+        let line = self.line_index(offset);
+        let cell = self.lines[line].cell(offset - self.starts[line]);
+
+        (self.first_rows[line] + cell.row, cell)
+    }
+
+    /// Visual row and column of the caret at byte `offset`.
+    pub fn caret(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_index(offset);
+        let (row, column) = self.lines[line].caret(offset - self.starts[line]);
+
+        (self.first_rows[line] + row, column)
+    }
+
+    /// Caret offset closest to a fractional `column` of visual `row`.
+    pub fn offset_at(&self, text: &str, row: usize, column: f32) -> usize {
+        // Hidden lines come before the visible line sharing their first row
+        let line = self
+            .first_rows
+            .partition_point(|&first| first <= row)
+            .max(1)
+            - 1;
+        let start = self.starts[line];
+
+        start
+            + self.lines[line].offset_at(line_at(text, start), row - self.first_rows[line], column)
+    }
+}
+
+/// Text of the line starting at `start`, without its line break: `\n` or `\r\n`. A `\r`
+/// ending the text breaks no line, so it's part of the last one.
+pub fn line_at(text: &str, start: usize) -> &str {
+    let rest = &text[start..];
+
+    match rest.find('\n') {
+        Some(end) => rest[..end].strip_suffix('\r').unwrap_or(&rest[..end]),
+        None => rest,
+    }
+}
+
+/// Lines of `text` without their line breaks, as `line_at` cuts them.
+pub fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut lines = text.split('\n').peekable();
+
+    std::iter::from_fn(move || {
+        let line = lines.next()?;
+
+        Some(match lines.peek() {
+            Some(_) => line.strip_suffix('\r').unwrap_or(line),
+            None => line,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::{FontCollection, FontStyle};
+    use crate::shaping;
+
+    fn grid(text: &str) -> TextGrid {
+        let fonts = FontCollection::embedded();
+        let shaped: Vec<_> = lines(text)
+            .map(|line| shaping::shape_line(&fonts, line, FontStyle::Regular))
+            .collect();
+
+        TextGrid::new(
+            text,
+            &shaped,
+            &GridSettings::default(),
+            None,
+            &Folds::default(),
+        )
+    }
+
+    #[test]
+    fn cuts_lines_at_line_breaks() {
+        assert_eq!(lines("a\r\nb\nc\r").collect::<Vec<_>>(), ["a", "b", "c\r"]);
+        assert_eq!(lines("a\n").collect::<Vec<_>>(), ["a", ""]);
+        assert_eq!(line_at("a\r\nb\r", 0), "a");
+        assert_eq!(line_at("a\r\nb\r", 3), "b\r");
+    }
+
+    #[test]
+    fn places_carets_around_a_trailing_carriage_return() {
+        let text = "ab\r";
+        let grid = grid(text);
+
+        assert_eq!(grid.row_count(), 1);
+        assert_eq!(grid.caret(2), (0, 2));
+        assert_eq!(grid.caret(text.len()), (0, 3));
+    }
+
+    #[test]
+    fn places_carets_around_crlf_line_breaks() {
+        let text = "ab\r\ncd\r\n";
+        let grid = grid(text);
+
+        assert_eq!(grid.row_count(), 3);
+        assert_eq!(grid.caret(2), (0, 2));
+        assert_eq!(grid.caret(4), (1, 0));
+        assert_eq!(grid.caret(6), (1, 2));
+        assert_eq!(grid.caret(text.len()), (2, 0));
+    }
+}
@@ -0,0 +1,148 @@
+use serde::Deserialize;
+
+/// Brackets that indent the lines after them, with the closer that ends the block.
+const OPENERS: [(char, Option<char>); 4] = [
+    ('{', Some('}')),
+    ('(', Some(')')),
+    ('[', Some(']')),
+    (':', None),
+];
+const CLOSERS: [char; 3] = ['}', ')', ']'];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentKind {
+    Tabs,
+    Spaces,
+}
+
+/// How a buffer indents, from the `[indent]` table of `config.toml` unless detected
+/// from the file being opened.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct IndentStyle {
+    pub kind: IndentKind,
+    /// Spaces per level, or columns per tab
+    pub width: usize,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        Self {
+            kind: IndentKind::Spaces,
+            width: 4,
+        }
+    }
+}
+
+impl std::str::FromStr for IndentKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tabs" => Ok(Self::Tabs),
+            "spaces" => Ok(Self::Spaces),
+            _ => anyhow::bail!("Expected `tabs` or `spaces`, got `{}`", s),
+        }
+    }
+}
+
+impl IndentStyle {
+    /// Text of one indentation level.
+    pub fn unit(&self) -> String {
+        match self.kind {
+            IndentKind::Tabs => "\t".to_string(),
+            IndentKind::Spaces => " ".repeat(self.width.max(1)),
+        }
+    }
+
+    /// Guesses the style of `text` from its indented lines. Space widths come from the
+    /// most common step between the indentation of consecutive lines.
+    pub fn detect(text: &str) -> Option<Self> {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut steps = [0usize; 9];
+        let mut previous = 0;
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let indent = leading_whitespace(line);
+
+            if indent.starts_with('\t') {
+                tabs += 1;
+            } else if !indent.is_empty() {
+                spaces += 1;
+            }
+
+            if !indent.contains('\t') {
+                let step = indent.len().abs_diff(previous);
+                if let Some(count) = steps.get_mut(step).filter(|_| step > 1) {
+                    *count += 1;
+                }
+                previous = indent.len();
+            }
+        }
+
+        if tabs == 0 && spaces == 0 {
+            return None;
+        }
+
+        if tabs > spaces {
+            return Some(Self {
+                kind: IndentKind::Tabs,
+                ..Self::default()
+            });
+        }
+
+        // Files with no clear step get the usual four spaces
+        let width = (2..steps.len()).max_by_key(|&step| (steps[step], step == 4))?;
+
+        Some(Self {
+            kind: IndentKind::Spaces,
+            width,
+        })
+    }
+
+    /// Removes one level from the start of `indent`.
+    pub fn dedent<'a>(&self, indent: &'a str) -> &'a str {
+        if let Some(rest) = indent.strip_suffix('\t') {
+            return rest;
+        }
+
+        let spaces = indent.len() - indent.trim_end_matches(' ').len();
+        &indent[..indent.len() - spaces.min(self.width.max(1))]
+    }
+}
+
+pub fn leading_whitespace(line: &str) -> &str {
+    let trimmed = line.trim_start_matches([' ', '\t']);
+
+    &line[..line.len() - trimmed.len()]
+}
+
+/// What pressing Enter inserts between `before` and `after`, the parts of the line around
+/// the cursor: a newline with the line's indentation, one level deeper after an opener.
+/// Between a bracket pair the closer moves to its own line, and the second element is the
+/// text going after the cursor.
+pub fn newline(before: &str, after: &str, style: &IndentStyle) -> (String, String) {
+    let indent = leading_whitespace(before);
+    let opener = before.trim_end().chars().last();
+    let closer = OPENERS
+        .iter()
+        .find(|(open, _)| Some(*open) == opener)
+        .map(|(_, close)| *close);
+
+    match closer {
+        Some(Some(close)) if after.trim_start().starts_with(close) => (
+            format!("\n{}{}", indent, style.unit()),
+            format!("\n{}", indent),
+        ),
+        Some(_) => (format!("\n{}{}", indent, style.unit()), String::new()),
+        None => (format!("\n{}", indent), String::new()),
+    }
+}
+
+/// Whether typing `c` after `before`, the start of the line up to the cursor, should
+/// remove one level of indentation first.
+pub fn dedents(c: char, before: &str) -> bool {
+    CLOSERS.contains(&c) && !before.is_empty() && before.trim().is_empty()
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{BitOr, BitOrAssign};
+
+/// Modifier keys held down, as a set.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Modifiers(u8);
+
+/// Key pressed, by where it is rather than the text it types. Keys typing characters are
+/// named by their unshifted character, lowercase for letters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Key {
+    Char(char),
+    /// Function key, from 1
+    F(u8),
+    Tab,
+    Enter,
+    Space,
+    Backspace,
+    Delete,
+    Escape,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Modifiers {
+    pub const CTRL: Self = Self(1);
+    pub const SHIFT: Self = Self(1 << 1);
+    pub const ALT: Self = Self(1 << 2);
+    pub const LOGO: Self = Self(1 << 3);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn ctrl(self) -> bool {
+        self.contains(Self::CTRL)
+    }
+
+    pub fn shift(self) -> bool {
+        self.contains(Self::SHIFT)
+    }
+
+    pub fn alt(self) -> bool {
+        self.contains(Self::ALT)
+    }
+
+    pub fn logo(self) -> bool {
+        self.contains(Self::LOGO)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
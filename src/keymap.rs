@@ -0,0 +1,239 @@
+use crate::command::{CommandArg, Invocation};
+use crate::input::{Key, Modifiers};
+use anyhow::{anyhow, bail};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+pub struct Keymap {
+    bindings: HashMap<KeyBinding, Invocation>,
+}
+
+impl KeyBinding {
+    pub fn new(modifiers: Modifiers, key: Key) -> Self {
+        Self { modifiers, key }
+    }
+}
+
+/// Parses bindings written as `ctrl+shift+tab`, modifiers first and the key last.
+impl FromStr for KeyBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifiers::empty();
+        let mut parts = s.split('+').map(str::trim).peekable();
+
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                let key = parse_key(part).ok_or_else(|| anyhow!("Unknown key `{}`", part))?;
+
+                return Ok(Self { modifiers, key });
+            }
+
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => Modifiers::CTRL,
+                "shift" => Modifiers::SHIFT,
+                "alt" => Modifiers::ALT,
+                "super" | "logo" | "cmd" => Modifiers::LOGO,
+                _ => bail!("Unknown modifier `{}`", part),
+            };
+        }
+
+        bail!("Empty key binding")
+    }
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut keymap = Self::empty();
+
+        keymap.bind_default("left", Invocation::new("cursor.left"));
+        keymap.bind_default("right", Invocation::new("cursor.right"));
+        keymap.bind_default("up", Invocation::new("cursor.up"));
+        keymap.bind_default("down", Invocation::new("cursor.down"));
+        keymap.bind_default("home", Invocation::new("cursor.line_start"));
+        keymap.bind_default("end", Invocation::new("cursor.line_end"));
+        keymap.bind_default("shift+left", Invocation::new("cursor.select_left"));
+        keymap.bind_default("shift+right", Invocation::new("cursor.select_right"));
+        keymap.bind_default("shift+up", Invocation::new("cursor.select_up"));
+        keymap.bind_default("shift+down", Invocation::new("cursor.select_down"));
+        keymap.bind_default("shift+home", Invocation::new("cursor.select_line_start"));
+        keymap.bind_default("shift+end", Invocation::new("cursor.select_line_end"));
+        keymap.bind_default("ctrl+m", Invocation::new("cursor.jump_to_bracket"));
+        keymap.bind_default("shift+tab", Invocation::new("buffer.outdent"));
+        keymap.bind_default("ctrl+]", Invocation::new("buffer.indent"));
+        keymap.bind_default("ctrl+[", Invocation::new("buffer.outdent"));
+        keymap.bind_default("ctrl+shift+[", Invocation::new("fold.toggle"));
+        keymap.bind_default("ctrl+shift+]", Invocation::new("fold.unfold_all"));
+        keymap.bind_default("ctrl+/", Invocation::new("buffer.toggle_line_comment"));
+        keymap.bind_default(
+            "ctrl+shift+/",
+            Invocation::new("buffer.toggle_block_comment"),
+        );
+        keymap.bind_default("alt+up", Invocation::new("buffer.move_lines_up"));
+        keymap.bind_default("alt+down", Invocation::new("buffer.move_lines_down"));
+        keymap.bind_default("ctrl+shift+d", Invocation::new("buffer.duplicate_lines"));
+        keymap.bind_default("ctrl+j", Invocation::new("buffer.join_lines"));
+        keymap.bind_default("ctrl+shift+k", Invocation::new("buffer.delete_lines"));
+        keymap.bind_default("f9", Invocation::new("buffer.sort_lines"));
+        keymap.bind_default("ctrl+t", Invocation::new("buffer.transpose"));
+        keymap.bind_default("ctrl+alt+up", Invocation::new("cursor.add_above"));
+        keymap.bind_default("ctrl+alt+down", Invocation::new("cursor.add_below"));
+        keymap.bind_default("escape", Invocation::new("cursor.single"));
+        keymap.bind_default("alt+]", Invocation::new("git.next_hunk"));
+        keymap.bind_default("alt+[", Invocation::new("git.previous_hunk"));
+        keymap.bind_default("ctrl+alt+s", Invocation::new("git.stage_hunk"));
+        keymap.bind_default("ctrl+alt+r", Invocation::new("git.revert_hunk"));
+        keymap.bind_default("ctrl+alt+d", Invocation::new("git.diff"));
+        keymap.bind_default("ctrl+alt+shift+d", Invocation::new("git.diff_head"));
+        keymap.bind_default("ctrl+alt+i", Invocation::new("diff.toggle_inline"));
+        keymap.bind_default("ctrl+tab", Invocation::new("frame.focus_next"));
+        keymap.bind_default("ctrl+shift+tab", Invocation::new("frame.focus_previous"));
+        keymap.bind_default("ctrl+z", Invocation::new("edit.undo"));
+        keymap.bind_default("ctrl+shift+z", Invocation::new("edit.redo"));
+        keymap.bind_default("ctrl+s", Invocation::new("file.save"));
+        keymap.bind_default("ctrl+q", Invocation::new("app.quit"));
+        keymap.bind_default("ctrl+e", Invocation::new("explorer.open"));
+        keymap.bind_default("f2", Invocation::new("explorer.rename"));
+        keymap.bind_default("ctrl+alt+n", Invocation::new("explorer.new_file"));
+        keymap.bind_default("ctrl+alt+shift+n", Invocation::new("explorer.new_folder"));
+        keymap.bind_default("ctrl+alt+m", Invocation::new("explorer.move"));
+        keymap.bind_default("ctrl+\\", Invocation::new("layout.split_horizontal"));
+        keymap.bind_default("ctrl+shift+\\", Invocation::new("layout.split_vertical"));
+        keymap.bind_default("ctrl+w", Invocation::new("layout.close_frame"));
+        keymap.bind_default("ctrl+=", Invocation::new("view.zoom_in"));
+        keymap.bind_default("ctrl+plus", Invocation::new("view.zoom_in"));
+        keymap.bind_default("ctrl+-", Invocation::new("view.zoom_out"));
+        keymap.bind_default("ctrl+0", Invocation::new("view.zoom_reset"));
+        keymap.bind_default("ctrl+alt+=", Invocation::new("buffer.zoom_in"));
+        keymap.bind_default("ctrl+alt+-", Invocation::new("buffer.zoom_out"));
+        keymap.bind_default("ctrl+alt+0", Invocation::new("buffer.zoom_reset"));
+        keymap.bind_default("ctrl+`", Invocation::new("terminal.open"));
+        keymap.bind_default("shift+pageup", Invocation::new("view.page_up"));
+        keymap.bind_default("shift+pagedown", Invocation::new("view.page_down"));
+        keymap.bind_default(
+            "ctrl+b",
+            Invocation::new("task.run").with_arg(CommandArg::Text("build".to_string())),
+        );
+        keymap.bind_default(
+            "ctrl+shift+b",
+            Invocation::new("task.run").with_arg(CommandArg::Text("test".to_string())),
+        );
+        keymap.bind_default("ctrl+alt+b", Invocation::new("task.cancel"));
+        keymap.bind_default("f8", Invocation::new("problems.next"));
+        keymap.bind_default("shift+f8", Invocation::new("problems.previous"));
+        keymap.bind_default("ctrl+shift+m", Invocation::new("problems.show"));
+        keymap.bind_default("f3", Invocation::new("macro.toggle_recording"));
+        keymap.bind_default("f4", Invocation::new("macro.play"));
+
+        keymap
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, binding: KeyBinding, invocation: Invocation) {
+        self.bindings.insert(binding, invocation);
+    }
+
+    pub fn unbind(&mut self, binding: &KeyBinding) -> Option<Invocation> {
+        self.bindings.remove(binding)
+    }
+
+    pub fn lookup(&self, binding: &KeyBinding) -> Option<&Invocation> {
+        self.bindings.get(binding)
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&KeyBinding, &Invocation)> {
+        self.bindings.iter()
+    }
+
+    fn bind_default(&mut self, binding: &str, invocation: Invocation) {
+        let binding = binding.parse().expect("valid default key binding");
+
+        self.bind(binding, invocation);
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Name of a key that doesn't type a character, as written in bindings. Delete isn't one,
+/// as it comes as a character.
+pub fn special_key_name(key: Key) -> Option<&'static str> {
+    Some(match key {
+        Key::Up => "up",
+        Key::Down => "down",
+        Key::Left => "left",
+        Key::Right => "right",
+        Key::Home => "home",
+        Key::End => "end",
+        Key::PageUp => "pageup",
+        Key::PageDown => "pagedown",
+        Key::Insert => "insert",
+        Key::F(n) => [
+            "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+        ]
+        .get(usize::from(n).checked_sub(1)?)?,
+        _ => return None,
+    })
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let lower = name.to_ascii_lowercase();
+
+    if let [c] = lower.as_bytes() {
+        return match c {
+            b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'='
+            | b'['
+            | b']'
+            | b';'
+            | b'\''
+            | b','
+            | b'.'
+            | b'/'
+            | b'\\'
+            | b'`' => Some(Key::Char(*c as char)),
+            _ => None,
+        };
+    }
+
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=12).contains(&n).then_some(Key::F(n));
+    }
+
+    Some(match lower.as_str() {
+        "tab" => Key::Tab,
+        "enter" | "return" => Key::Enter,
+        "space" => Key::Space,
+        "backspace" => Key::Backspace,
+        "delete" => Key::Delete,
+        "escape" | "esc" => Key::Escape,
+        "insert" => Key::Insert,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "plus" => Key::Char('+'),
+        "minus" => Key::Char('-'),
+        _ => return None,
+    })
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+/// What buffers need to know about the language of a file, picked from its extension.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    pub line_comment: Option<&'static str>,
+    pub block_comment: Option<(&'static str, &'static str)>,
+}
+
+static PLAIN_TEXT: Language = Language {
+    line_comment: None,
+    block_comment: None,
+};
+
+static LANGUAGES: [(&[&str], Language); 7] = [
+    (
+        &[
+            "rs", "c", "h", "cc", "cpp", "hpp", "js", "ts", "go", "java", "kt", "swift", "wgsl",
+        ],
+        Language {
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+        },
+    ),
+    (
+        &["py", "sh", "bash", "toml", "yaml", "yml", "rb", "nix"],
+        Language {
+            line_comment: Some("#"),
+            block_comment: None,
+        },
+    ),
+    (
+        &["lua"],
+        Language {
+            line_comment: Some("--"),
+            block_comment: Some(("--[[", "]]")),
+        },
+    ),
+    (
+        &["sql"],
+        Language {
+            line_comment: Some("--"),
+            block_comment: Some(("/*", "*/")),
+        },
+    ),
+    (
+        &["css"],
+        Language {
+            line_comment: None,
+            block_comment: Some(("/*", "*/")),
+        },
+    ),
+    (
+        &["html", "xml", "svg", "md"],
+        Language {
+            line_comment: None,
+            block_comment: Some(("<!--", "-->")),
+        },
+    ),
+    (
+        &["lisp", "el", "clj", "scm"],
+        Language {
+            line_comment: Some(";"),
+            block_comment: None,
+        },
+    ),
+];
+
+impl Language {
+    /// Language of the file at `path`. Buffers without a file are plain text.
+    pub fn from_path(path: Option<&Path>) -> &'static Language {
+        let extension = path
+            .and_then(Path::extension)
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        LANGUAGES
+            .iter()
+            .find(|(extensions, _)| {
+                extension
+                    .as_deref()
+                    .is_some_and(|extension| extensions.contains(&extension))
+            })
+            .map_or(&PLAIN_TEXT, |(_, language)| language)
+    }
+}
@@ -1,18 +1,38 @@
-use crate::BoundingBox;
+use crate::buffer::BoundingBox;
+use serde::{Deserialize, Serialize};
 
 pub type Percentage = f32;
 
+/// Identity of a frame, which stays the same while frames around it open and close.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FrameId(usize);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "SavedLayout")]
 pub struct Layout {
     pub root: Split,
+    /// Id the next frame opened gets
+    #[serde(skip)]
+    next_id: usize,
+}
+
+/// Layout as saved with sessions, without frame ids.
+#[derive(Deserialize)]
+struct SavedLayout {
+    root: Split,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Split {
     Singleton(Frame),
     Vertical(Vec<(Split, Percentage)>),
     Horizontal(Vec<(Split, Percentage)>),
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Frame {
+    #[serde(skip)]
+    pub id: FrameId,
     pub buffer_index: usize,
 }
 
@@ -21,30 +41,227 @@ impl Layout {
     pub fn new() -> Self {
         Self {
             root: Split::Horizontal(vec![
-                (Split::Singleton(Frame { buffer_index: 0 }), 0.3),
-                (Split::Singleton(Frame { buffer_index: 1 }), 0.7),
+                (Split::frame(FrameId(0), 0), 0.3),
+                (Split::frame(FrameId(1), 1), 0.7),
             ]),
+            next_id: 2,
+        }
+    }
+
+    /// One frame showing `buffer_index`.
+    pub fn single(buffer_index: usize) -> Self {
+        Self {
+            root: Split::frame(FrameId(0), buffer_index),
+            next_id: 1,
         }
     }
 
-    pub fn build_bounding_boxes(&self, container: BoundingBox) -> Vec<(BoundingBox, usize)> {
+    pub fn build_bounding_boxes(
+        &self,
+        container: BoundingBox,
+    ) -> Vec<(BoundingBox, FrameId, usize)> {
         let mut accumulator = Vec::new();
 
         self.root.build_bounding_boxes(container, &mut accumulator);
 
         accumulator
     }
+
+    /// Every frame with the index of the buffer it shows, in the same order as
+    /// `build_bounding_boxes`.
+    pub fn frames(&self) -> Vec<(FrameId, usize)> {
+        let mut accumulator = Vec::new();
+
+        self.root.frames(&mut accumulator);
+
+        accumulator
+    }
+
+    /// Buffer indices of all frames, in the same order as `build_bounding_boxes`.
+    pub fn buffer_indices(&self) -> Vec<usize> {
+        self.frames().into_iter().map(|(_, index)| index).collect()
+    }
+
+    /// Index of the buffer `frame` shows, if it's still open.
+    pub fn buffer(&self, frame: FrameId) -> Option<usize> {
+        self.frames()
+            .into_iter()
+            .find_map(|(id, index)| (id == frame).then_some(index))
+    }
+
+    /// First frame showing `buffer_index`.
+    pub fn frame_showing(&self, buffer_index: usize) -> Option<FrameId> {
+        self.frames()
+            .into_iter()
+            .find_map(|(id, index)| (index == buffer_index).then_some(id))
+    }
+
+    /// Splits `frame` in two, both showing the same buffer. Returns the new frame, right
+    /// of or below `frame`.
+    pub fn split_frame(&mut self, frame: FrameId, vertical: bool) -> Option<FrameId> {
+        let id = FrameId(self.next_id);
+
+        self.root.split_frame(frame, id, vertical).then(|| {
+            self.next_id += 1;
+            id
+        })
+    }
+
+    /// Removes `frame`. The last frame can't be closed.
+    pub fn close_frame(&mut self, frame: FrameId) -> bool {
+        if matches!(self.root, Split::Singleton(_)) {
+            return false;
+        }
+
+        self.root.close_frame(frame)
+    }
+
+    /// Removes every frame showing `buffer_index`, but the last frame. Returns whether
+    /// none shows it anymore.
+    pub fn close_frames_showing(&mut self, buffer_index: usize) -> bool {
+        while let Some(frame) = self.frame_showing(buffer_index) {
+            if !self.close_frame(frame) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Moves frames showing buffers after `buffer_index` back by one, once it's removed.
+    /// No frame may show it.
+    pub fn buffer_removed(&mut self, buffer_index: usize) {
+        for (frame, index) in self.frames() {
+            if index > buffer_index {
+                self.set_buffer(frame, index - 1);
+            }
+        }
+    }
+
+    /// Makes `frame` show `buffer_index` instead.
+    pub fn set_buffer(&mut self, frame: FrameId, buffer_index: usize) -> bool {
+        match self.root.frame_mut(frame) {
+            Some(frame) => {
+                frame.buffer_index = buffer_index;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl From<SavedLayout> for Layout {
+    fn from(saved: SavedLayout) -> Self {
+        let mut layout = Self {
+            root: saved.root,
+            next_id: 0,
+        };
+
+        layout.root.number_frames(&mut layout.next_id);
+        layout
+    }
 }
 
 impl Split {
+    fn frame(id: FrameId, buffer_index: usize) -> Self {
+        Split::Singleton(Frame { id, buffer_index })
+    }
+
+    fn frame_mut(&mut self, id: FrameId) -> Option<&mut Frame> {
+        match self {
+            Split::Singleton(frame) if frame.id == id => Some(frame),
+            Split::Singleton(_) => None,
+            Split::Vertical(sub) | Split::Horizontal(sub) => {
+                sub.iter_mut().find_map(|(item, _)| item.frame_mut(id))
+            }
+        }
+    }
+
+    /// Gives every frame an id, counting from `next_id`.
+    fn number_frames(&mut self, next_id: &mut usize) {
+        match self {
+            Split::Singleton(frame) => {
+                frame.id = FrameId(*next_id);
+                *next_id += 1;
+            }
+            Split::Vertical(sub) | Split::Horizontal(sub) => {
+                for (item, _) in sub {
+                    item.number_frames(next_id);
+                }
+            }
+        }
+    }
+
+    fn split_frame(&mut self, id: FrameId, new_id: FrameId, vertical: bool) -> bool {
+        match self {
+            Split::Singleton(frame) if frame.id == id => {
+                let buffer_index = frame.buffer_index;
+                let halves = vec![
+                    (Split::frame(id, buffer_index), 0.5),
+                    (Split::frame(new_id, buffer_index), 0.5),
+                ];
+
+                *self = if vertical {
+                    Split::Vertical(halves)
+                } else {
+                    Split::Horizontal(halves)
+                };
+                true
+            }
+            Split::Singleton(_) => false,
+            Split::Vertical(sub) | Split::Horizontal(sub) => sub
+                .iter_mut()
+                .any(|(item, _)| item.split_frame(id, new_id, vertical)),
+        }
+    }
+
+    fn close_frame(&mut self, id: FrameId) -> bool {
+        let (Split::Vertical(sub) | Split::Horizontal(sub)) = self else {
+            return false;
+        };
+
+        let position = sub
+            .iter()
+            .position(|(item, _)| matches!(item, Split::Singleton(frame) if frame.id == id));
+
+        let Some(position) = position else {
+            return sub.iter_mut().any(|(item, _)| item.close_frame(id));
+        };
+
+        let (_, freed) = sub.remove(position);
+        let remaining: Percentage = 1.0 - freed;
+
+        // Give the freed space to the remaining siblings proportionally
+        for (_, percentage) in sub.iter_mut() {
+            *percentage /= remaining;
+        }
+
+        if sub.len() == 1 {
+            let (only, _) = sub.pop().expect("one remaining split");
+            *self = only;
+        }
+        true
+    }
+
+    fn frames(&self, accumulator: &mut Vec<(FrameId, usize)>) {
+        match self {
+            Split::Singleton(frame) => accumulator.push((frame.id, frame.buffer_index)),
+            Split::Vertical(sub) | Split::Horizontal(sub) => {
+                for (item, _) in sub {
+                    item.frames(accumulator);
+                }
+            }
+        }
+    }
+
     fn build_bounding_boxes(
         &self,
         container: BoundingBox,
-        accumulator: &mut Vec<(BoundingBox, usize)>,
+        accumulator: &mut Vec<(BoundingBox, FrameId, usize)>,
     ) {
         match self {
             Split::Singleton(frame) => {
-                accumulator.push((container, frame.buffer_index));
+                accumulator.push((container, frame.id, frame.buffer_index));
             }
             Split::Vertical(sub) => {
                 let mut accumulated_top = container.top;
@@ -93,3 +310,61 @@ impl Default for Layout {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_keep_their_ids_as_others_open_and_close() {
+        let mut layout = Layout::single(0);
+        let [(first, 0)] = layout.frames()[..] else {
+            panic!("one frame");
+        };
+
+        let second = layout.split_frame(first, false).unwrap();
+        let third = layout.split_frame(first, true).unwrap();
+        assert_eq!(layout.frames(), [(first, 0), (third, 0), (second, 0)]);
+
+        assert!(layout.set_buffer(third, 1));
+        assert_eq!(layout.frame_showing(1), Some(third));
+        assert_eq!(layout.buffer(second), Some(0));
+
+        assert!(layout.close_frame(first));
+        assert_eq!(layout.frames(), [(third, 1), (second, 0)]);
+        assert_eq!(layout.buffer(first), None);
+        assert!(!layout.set_buffer(first, 2));
+        assert!(layout.split_frame(first, false).is_none());
+    }
+
+    #[test]
+    fn keeps_the_last_frame_open() {
+        let mut layout = Layout::single(0);
+        let first = layout.frames()[0].0;
+        let second = layout.split_frame(first, false).unwrap();
+        layout.set_buffer(second, 1);
+        layout.split_frame(second, true);
+
+        assert!(layout.close_frames_showing(1));
+        assert_eq!(layout.frames(), [(first, 0)]);
+        assert!(!layout.close_frames_showing(0));
+        assert!(!layout.close_frame(first));
+    }
+
+    #[test]
+    fn saved_layouts_get_new_ids() {
+        let mut layout = Layout::new();
+        let right = layout.frames()[1].0;
+        layout.split_frame(right, true);
+
+        let saved = serde_json::to_string(&layout).unwrap();
+        let mut loaded: Layout = serde_json::from_str(&saved).unwrap();
+        let frames = loaded.frames();
+        assert_eq!(loaded.buffer_indices(), [0, 1, 1]);
+        assert_ne!(frames[1].0, frames[2].0);
+
+        // Frames opened later don't reuse them
+        let new = loaded.split_frame(frames[0].0, false).unwrap();
+        assert!(frames.iter().all(|(frame, _)| *frame != new));
+    }
+}
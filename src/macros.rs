@@ -0,0 +1,166 @@
+use crate::app_state::AppState;
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult, Invocation,
+};
+use crate::paths;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const DEFAULT_REGISTER: &str = "default";
+
+const MACROS_FILE: &str = "macros.json";
+
+/// Upper bound on queued invocations, so a macro that replays itself can't run forever.
+pub const MAX_PENDING: usize = 100_000;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct MacroRegisters {
+    registers: BTreeMap<String, Vec<Invocation>>,
+
+    #[serde(skip)]
+    recording: Option<(String, Vec<Invocation>)>,
+}
+
+impl MacroRegisters {
+    pub fn get(&self, register: &str) -> Option<&[Invocation]> {
+        self.registers.get(register).map(Vec::as_slice)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start_recording(&mut self, register: &str) {
+        self.recording = Some((register.to_string(), Vec::new()));
+    }
+
+    /// Stores the recorded steps into their register and returns its name.
+    pub fn stop_recording(&mut self) -> Option<String> {
+        let (register, steps) = self.recording.take()?;
+
+        self.registers.insert(register.clone(), steps);
+
+        Some(register)
+    }
+
+    /// Records a dispatched invocation. Recording controls are never recorded,
+    /// but playing another macro is.
+    pub fn record(&mut self, invocation: &Invocation) {
+        let Some((_, steps)) = &mut self.recording else {
+            return;
+        };
+
+        if invocation.id.starts_with("macro.") && invocation.id != "macro.play" {
+            return;
+        }
+
+        steps.push(invocation.clone());
+    }
+
+    pub async fn load() -> anyhow::Result<Self> {
+        let path = macros_path()?;
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Malformed macros file {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Can't read {}", path.display())),
+        }
+    }
+
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let path = macros_path()?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .await
+            .with_context(|| format!("Can't write {}", path.display()))
+    }
+}
+
+fn macros_path() -> anyhow::Result<PathBuf> {
+    Ok(paths::data_dir()
+        .context("No data directory on this platform")?
+        .join(MACROS_FILE))
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "macro.toggle_recording",
+            "Start recording a macro into a register, or stop the current recording",
+            toggle_recording,
+        )
+        .with_arg(ArgSpec::optional("register", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "macro.play",
+            "Replay the macro in a register, optionally several times",
+            play,
+        )
+        .with_arg(ArgSpec::optional("register", ArgKind::Text))
+        .with_arg(ArgSpec::optional("count", ArgKind::Integer)),
+    );
+}
+
+fn register_arg(args: &[CommandArg]) -> &str {
+    args.first()
+        .and_then(CommandArg::as_text)
+        .unwrap_or(DEFAULT_REGISTER)
+}
+
+fn toggle_recording(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        if state.macros.is_recording() {
+            let register = state.macros.stop_recording().expect("recording");
+            tracing::info!("Recorded macro into `{}`", register);
+
+            state.macros.save().await?;
+        } else {
+            state.macros.start_recording(register_arg(&args));
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn play(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let register = register_arg(&args);
+        let count = args.get(1).and_then(CommandArg::as_integer).unwrap_or(1);
+
+        if count < 0 {
+            bail!("Can't play a macro {} times", count);
+        }
+
+        let steps = state
+            .macros
+            .get(register)
+            .ok_or_else(|| anyhow!("Register `{}` is empty", register))?;
+
+        if state.pending.len() + steps.len() * count as usize > MAX_PENDING {
+            bail!("Macro `{}` expands to too many steps", register);
+        }
+
+        let mut queued = vec![Invocation::new("history.begin_group")];
+        for _ in 0..count {
+            queued.extend(steps.iter().cloned());
+        }
+        queued.push(Invocation::new("history.end_group"));
+
+        // Steps are dispatched by the state loop exactly like live input. They go to the
+        // front of the queue so a macro played from another macro runs in place.
+        for invocation in queued.into_iter().rev() {
+            state.pending.push_front(invocation);
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
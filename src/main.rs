@@ -1,13 +1,153 @@
-use kami::run;
-use winit::event_loop::EventLoop;
-use winit::window::WindowBuilder;
+use kami::command::{CommandArg, Invocation};
+use kami::editor::{Editor, Host};
+use kami::remote::client::{Client, RemoteEditor};
+use std::path::PathBuf;
+
+#[cfg(not(any(feature = "gpu", feature = "tui")))]
+compile_error!("kami needs the `gpu` or the `tui` feature to show anything");
+
+/// Command line: `kami [--tui] [--server] [file...]`
+struct Args {
+    /// Show the editor in the terminal rather than a window
+    tui: bool,
+    /// Run the editor without showing it, for clients to attach to. Without it the first
+    /// instance shows the editor and serves it too, so closing its window quits the
+    /// editor, telling attached clients to exit as well. Only a server outlives them all.
+    server: bool,
+    files: Vec<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+    let terminal = !args.server && use_terminal(&args);
+    init_logging(terminal)?;
+
+    // A running instance opens the files, or shows its editor here as well
+    if let Ok(client) = Client::connect().await {
+        if args.server {
+            anyhow::bail!("A server is running already");
+        }
+
+        if !args.files.is_empty() {
+            for path in args.files {
+                client.open(std::path::absolute(path)?).await?;
+            }
+
+            return Ok(());
+        }
+
+        return show(RemoteEditor::attach(client).await?, terminal).await;
+    }
+
+    let mut editor = Editor::load().await?;
+    // Without the socket this instance works alone
+    if let Err(err) = editor.serve() {
+        if args.server {
+            return Err(err);
+        }
+
+        tracing::warn!("Failed to serve clients: {:#}", err);
+    }
+
+    for path in args.files {
+        let path = std::path::absolute(path)?.to_string_lossy().into_owned();
+        let invocation = Invocation::new("file.open").with_arg(CommandArg::Text(path));
+        if let Err(err) = editor.execute(&invocation).await {
+            tracing::warn!("{:#}", err);
+        }
+    }
+
+    if args.server {
+        // Runs until a client quits it
+        return Ok(editor.start(()).await?);
+    }
+
+    // Quitting here quits the editor every client shows
+    show(editor, terminal).await
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self {
+            tui: false,
+            server: false,
+            files: Vec::new(),
+        };
+
+        for arg in std::env::args_os().skip(1) {
+            match arg.to_str() {
+                Some("--tui") => args.tui = true,
+                Some("--server") => args.server = true,
+                Some(option) if option.starts_with("--") => {
+                    anyhow::bail!("Unknown option `{}`", option)
+                }
+                _ => args.files.push(arg.into()),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+/// Whether to show the editor in the terminal: when asked to with `--tui`, or when
+/// there's no display to open a window on, such as over SSH.
+fn use_terminal(args: &Args) -> bool {
+    let no_display = cfg!(target_os = "linux")
+        && std::env::var_os("DISPLAY").is_none()
+        && std::env::var_os("WAYLAND_DISPLAY").is_none();
+
+    args.tui || no_display || !cfg!(feature = "gpu")
+}
+
+#[cfg_attr(not(feature = "tui"), allow(unused_variables))]
+fn init_logging(terminal: bool) -> anyhow::Result<()> {
+    // Logs would end up on screen, so they go to a file
+    #[cfg(feature = "tui")]
+    if terminal {
+        let log = kami::tui::log_file()?;
+        tracing_subscriber::fmt()
+            .with_writer(std::sync::Mutex::new(log))
+            .with_ansi(false)
+            .init();
+
+        return Ok(());
+    }
+
     tracing_subscriber::fmt::init();
+    Ok(())
+}
+
+async fn show(host: impl Host, terminal: bool) -> anyhow::Result<()> {
+    if terminal {
+        show_in_terminal(host).await
+    } else {
+        show_in_window(host).await
+    }
+}
+
+#[cfg(feature = "gpu")]
+async fn show_in_window(host: impl Host) -> anyhow::Result<()> {
+    use winit::event_loop::EventLoop;
+    use winit::window::WindowBuilder;
 
     let ev_loop = EventLoop::with_user_event();
     let window = WindowBuilder::new().with_title("紙").build(&ev_loop)?;
 
-    run(ev_loop, window).await?;
+    kami::gpu::run(ev_loop, window, host).await?;
+}
+
+#[cfg(not(feature = "gpu"))]
+async fn show_in_window(_: impl Host) -> anyhow::Result<()> {
+    anyhow::bail!("kami was built without the `gpu` feature, so it can't open a window")
+}
+
+#[cfg(feature = "tui")]
+async fn show_in_terminal(host: impl Host) -> anyhow::Result<()> {
+    kami::tui::run(host).await
+}
+
+#[cfg(not(feature = "tui"))]
+async fn show_in_terminal(_: impl Host) -> anyhow::Result<()> {
+    anyhow::bail!("kami was built without the `tui` feature, so it can't run in a terminal")
 }
@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+/// Directory for user configuration such as plugins and themes.
+pub fn config_dir() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("kami"))
+}
+
+/// Directory for state Kami persists between sessions.
+pub fn data_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("kami"))
+}
+
+/// Directory for files that only live as long as the user's login, such as sockets. Falls
+/// back to the data directory where there's no such thing.
+pub fn runtime_dir() -> Option<PathBuf> {
+    Some(dirs::runtime_dir().or_else(dirs::data_dir)?.join("kami"))
+}
+
+/// File name standing for `path`, which stays unique to it: the path with its separators
+/// and `%` signs percent-encoded, so `/a%b` and `/a/b` don't meet.
+pub fn flattened(path: &Path) -> String {
+    let separator = std::path::MAIN_SEPARATOR;
+
+    path.to_string_lossy()
+        .replace('%', "%25")
+        .replace(separator, &format!("%{:02X}", separator as u8))
+}
+
+/// Empty directory of its own for a test, under the system's temporary directory.
+#[cfg(test)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("kami-test-{}", std::process::id()))
+        .join(name);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create scratch directory");
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattens_paths_apart() {
+        let flat = |path: &str| flattened(Path::new(path));
+
+        assert_eq!(flat("/home/ann/a.rs"), "%2Fhome%2Fann%2Fa.rs");
+        assert_ne!(flat("/a%b"), flat("/a/b"));
+        assert_ne!(flat("/a%2Fb"), flat("/a/b"));
+        assert_ne!(flat("/a%/b"), flat("/a/%b"));
+    }
+}
@@ -0,0 +1,321 @@
+use super::{lines, receive, send, socket_path, Input, Message, Request, PROTOCOL_VERSION};
+use crate::buffer::BoundingBox;
+use crate::command::BoxFuture;
+use crate::editor::{self, Frontend, Host, EVENT_CAPACITY};
+use crate::fonts::FontCollection;
+use crate::scene::{Drawing, Surface};
+use crate::state::StateEvent;
+use anyhow::{anyhow, bail, Context};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+/// Connection to the server of the user's session.
+pub struct Client {
+    /// Requests for the connection, with where to send their reply
+    requests: UnboundedSender<(Request, oneshot::Sender<Message>)>,
+    /// `redraw` and `exit`, once subscribed
+    notifications: Option<UnboundedReceiver<Message>>,
+}
+
+impl Client {
+    /// Connects to the server of the user's session, failing when none is running.
+    pub async fn connect() -> anyhow::Result<Self> {
+        Self::connect_to(&socket_path()?).await
+    }
+
+    /// Connects to the server listening on the socket at `path`.
+    pub async fn connect_to(path: &Path) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("Can't connect to {}", path.display()))?;
+        let (reader, writer) = stream.into_split();
+        let mut lines = lines(reader);
+
+        match receive(&mut lines).await? {
+            Some(Message::Hello { version }) if version == PROTOCOL_VERSION => {}
+            Some(Message::Hello { version }) => bail!(
+                "The server speaks version {} of the protocol, not {}",
+                version,
+                PROTOCOL_VERSION
+            ),
+            _ => bail!("The server didn't say hello"),
+        }
+
+        let (requests, queued) = mpsc::unbounded_channel();
+        let (notify, notifications) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(err) = connection(writer, lines, queued, notify).await {
+                tracing::warn!("Lost the connection to the server: {:#}", err);
+            }
+        });
+
+        Ok(Self {
+            requests,
+            notifications: Some(notifications),
+        })
+    }
+
+    /// Sends `request` and waits for its reply. Errors the server replies with are
+    /// returned as such.
+    pub async fn request(&self, request: Request) -> anyhow::Result<Message> {
+        let (reply_tx, reply) = oneshot::channel();
+        self.requests
+            .send((request, reply_tx))
+            .map_err(|_| anyhow!("Lost the connection to the server"))?;
+
+        match reply.await.context("Lost the connection to the server")? {
+            Message::Error { message } => Err(anyhow!(message)),
+            reply => Ok(reply),
+        }
+    }
+
+    /// Sends `request` without waiting for its reply, as for input.
+    fn post(&self, request: Request) {
+        let (reply_tx, _) = oneshot::channel();
+        let _ = self.requests.send((request, reply_tx));
+    }
+
+    /// Opens `path` in the server's active frame.
+    pub async fn open(&self, path: PathBuf) -> anyhow::Result<()> {
+        self.request(Request::Open { path }).await.map(drop)
+    }
+}
+
+/// Writes requests and reads what the server sends, handing replies out in the order
+/// the requests were sent. Ends when the client is dropped or the server hangs up.
+async fn connection(
+    mut writer: OwnedWriteHalf,
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    mut requests: UnboundedReceiver<(Request, oneshot::Sender<Message>)>,
+    notify: UnboundedSender<Message>,
+) -> anyhow::Result<()> {
+    let mut waiting = VecDeque::new();
+
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some((request, reply)) = request else {
+                    return Ok(());
+                };
+
+                send(&mut writer, &request).await?;
+                waiting.push_back(reply);
+            }
+            message = receive(&mut lines) => match message? {
+                Some(message @ (Message::Redraw | Message::Exit)) => {
+                    let _ = notify.send(message);
+                }
+                Some(reply) => {
+                    if let Some(waiting) = waiting.pop_front() {
+                        let _ = waiting.send(reply);
+                    }
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Editor of a server, shown by a frontend of this process. Several can show the same
+/// one, and it keeps running once they're gone.
+pub struct RemoteEditor {
+    client: Arc<Client>,
+    events: Sender<StateEvent>,
+    /// Taken once started, with the notifications
+    receiver: Option<Receiver<StateEvent>>,
+    notifications: Option<UnboundedReceiver<Message>>,
+    fonts: FontCollection,
+    scale: f32,
+}
+
+impl RemoteEditor {
+    /// Attaches to the editor `client` is connected to. Fonts are loaded here, from the
+    /// same configuration the server loads them from.
+    pub async fn attach(mut client: Client) -> anyhow::Result<Self> {
+        let Message::Font { scale } = client.request(Request::Font).await? else {
+            bail!("The server replied to `font` with something else");
+        };
+        let (_, fonts) = editor::load_settings().await?;
+        let (events, receiver) = mpsc::channel(EVENT_CAPACITY);
+
+        Ok(Self {
+            notifications: client.notifications.take(),
+            client: Arc::new(client),
+            events,
+            receiver: Some(receiver),
+            fonts,
+            scale,
+        })
+    }
+}
+
+impl Host for RemoteEditor {
+    fn events(&self) -> Sender<StateEvent> {
+        self.events.clone()
+    }
+
+    /// Sends input to the server and tells `frontend` what the server says. Closing the
+    /// frontend detaches it, leaving the server running.
+    fn start(&mut self, frontend: impl Frontend) {
+        let mut receiver = self.receiver.take().expect("editor already started");
+        let mut notifications = self.notifications.take().expect("editor already started");
+        let frontend = Arc::new(frontend);
+
+        self.client.post(Request::Subscribe);
+
+        let client = self.client.clone();
+        let input_frontend = frontend.clone();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                match event {
+                    StateEvent::Quit | StateEvent::Exit => break,
+                    StateEvent::Redraw => input_frontend.request_redraw(),
+                    StateEvent::Command(invocation) => client.post(Request::Command(invocation)),
+                    event => {
+                        if let Some(event) = Input::from_event(event) {
+                            client.post(Request::Input { event });
+                        }
+                    }
+                }
+            }
+
+            input_frontend.exit();
+        });
+
+        tokio::spawn(async move {
+            while let Some(message) = notifications.recv().await {
+                match message {
+                    Message::Exit => break,
+                    _ => frontend.request_redraw(),
+                }
+            }
+
+            // The server shut down, or can't be reached anymore
+            frontend.exit();
+        });
+    }
+
+    fn font(&self) -> BoxFuture<'_, (FontCollection, f32)> {
+        Box::pin(async move { (self.fonts.clone(), self.scale) })
+    }
+
+    fn draw(
+        &self,
+        window: BoundingBox,
+        surface: Surface,
+    ) -> BoxFuture<'_, anyhow::Result<Drawing>> {
+        Box::pin(async move {
+            match self
+                .client
+                .request(Request::Draw { window, surface })
+                .await?
+            {
+                Message::Drawing { drawing } => Ok(drawing),
+                _ => bail!("The server replied to `draw` with something else"),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::editor::Editor;
+    use crate::paths;
+    use crate::remote::server;
+    use std::time::Duration;
+
+    /// Next `redraw` or `exit` from the server, if one comes soon.
+    async fn notification(notifications: &mut UnboundedReceiver<Message>) -> Option<Message> {
+        tokio::time::timeout(Duration::from_secs(5), notifications.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn window() -> BoundingBox {
+        BoundingBox {
+            left: 0.0,
+            top: 0.0,
+            width: 800.0,
+            height: 600.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_and_draws_files_on_a_server() {
+        let dir = paths::scratch_dir("remote-open");
+        let socket = dir.join("kami.sock");
+        let file = dir.join("notes.md");
+        std::fs::write(&file, "hello").unwrap();
+
+        let mut editor = Editor::new(&Config::default(), FontCollection::embedded());
+        editor.serve_at(socket.clone()).unwrap();
+        editor.start(());
+
+        let mut client = Client::connect_to(&socket).await.unwrap();
+        let mut notifications = client.notifications.take().unwrap();
+        assert!(matches!(
+            client.request(Request::Subscribe).await.unwrap(),
+            Message::Ok
+        ));
+        let relative = client.open(PathBuf::from("notes.md")).await.unwrap_err();
+        assert_eq!(relative.to_string(), "Paths must be absolute, got notes.md");
+
+        // The file opens once the editor gets to it, which tells subscribers to redraw
+        client.open(file).await.unwrap();
+        assert!(matches!(
+            notification(&mut notifications).await,
+            Some(Message::Redraw)
+        ));
+
+        let surface = Surface::Window { scale_factor: 1.0 };
+        let request = Request::Draw {
+            window: window(),
+            surface,
+        };
+        let Message::Drawing { drawing } = client.request(request).await.unwrap() else {
+            panic!("`draw` replied with something else");
+        };
+        let [(bb, _, scene)] = &drawing.frames[..] else {
+            panic!("one frame");
+        };
+        assert_eq!((bb.width, bb.height), (800.0, 600.0));
+        let text: String = scene
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.text.as_str())
+            .collect();
+        assert_eq!(text, "hello");
+    }
+
+    #[tokio::test]
+    async fn clients_are_told_when_the_editor_they_show_exits() {
+        let dir = paths::scratch_dir("remote-exit");
+        let socket = dir.join("kami.sock");
+        let editor = Editor::new(&Config::default(), FontCollection::embedded());
+        let notifier =
+            server::listen(socket.clone(), editor.state().clone(), editor.events()).unwrap();
+
+        let mut client = Client::connect_to(&socket).await.unwrap();
+        let mut notifications = client.notifications.take().unwrap();
+        client.request(Request::Subscribe).await.unwrap();
+
+        // As when the window of the instance serving the editor closes
+        notifier.exit();
+        assert!(matches!(
+            notification(&mut notifications).await,
+            Some(Message::Exit)
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!socket.exists());
+        assert!(Client::connect_to(&socket).await.is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Protocol between a Kami server, the process owning the buffers and state, and the
+//! clients attached to it over a Unix socket: windows, terminals, or `kami file.rs` run
+//! from a shell.
+//!
+//! Messages are JSON objects, one per line, with their kind in `type`. The server says
+//! `hello` with the protocol version when a client connects. Then every request gets one
+//! reply, in the order they were sent: `ok`, `error`, or the data asked for. After
+//! `subscribe`, the server also sends `redraw` and `exit` as they happen, between replies.
+//!
+//! ```text
+//! <- {"type":"hello","version":1}
+//! -> {"type":"open","path":"/home/me/kami/src/main.rs"}
+//! <- {"type":"ok"}
+//! -> {"type":"input","event":{"key":"enter"}}
+//! <- {"type":"ok"}
+//! ```
+//!
+//! Requests are handled in order with the input of every client, as if typed in one
+//! window. All clients share the layout and the buffer it shows.
+
+use crate::buffer::BoundingBox;
+use crate::command::Invocation;
+use crate::input::{Key, Modifiers};
+use crate::paths;
+use crate::scene::{Drawing, Surface};
+use crate::state::StateEvent;
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::OwnedReadHalf;
+
+pub mod client;
+pub mod server;
+
+/// Changes whenever messages change in a way older clients or servers can't follow
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const SOCKET: &str = "kami.sock";
+
+/// Message from a client to the server.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Opens `path`, absolute, in the active frame.
+    ///
+    /// `{"type":"open","path":"/tmp/notes.md"}`
+    Open { path: PathBuf },
+    /// Runs a command, as a key bound to it would.
+    ///
+    /// `{"type":"command","id":"layout.split_horizontal","args":[]}`
+    Command(Invocation),
+    /// Input from the user of the client.
+    ///
+    /// `{"type":"input","event":{"char":"a"}}`
+    Input { event: Input },
+    /// Asks for the size fonts are laid out at before zoom, replied to with `font`.
+    ///
+    /// `{"type":"font"}`
+    Font,
+    /// Asks for what each frame shows in `window`, on `surface`, replied to with `drawing`.
+    ///
+    /// `{"type":"draw","window":{"left":0,"top":0,"width":800,"height":600},
+    /// "surface":{"window":{"scale_factor":1.0}}}`
+    Draw {
+        window: BoundingBox,
+        surface: Surface,
+    },
+    /// Asks to be sent `redraw` and `exit` from now on.
+    ///
+    /// `{"type":"subscribe"}`
+    Subscribe,
+}
+
+/// What a user does in a client, the way a window reports it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Input {
+    /// Modifier keys held down since, as bits: 1 for Control, 2 for Shift, 4 for Alt and 8
+    /// for Logo.
+    ///
+    /// `{"modifiers":3}`
+    Modifiers(Modifiers),
+    /// Character typed, including control characters such as `"\r"` for Enter.
+    ///
+    /// `{"char":"a"}`
+    Char(char),
+    /// Key pressed, by its unshifted character or its name: `{"key":{"char":"a"}}`,
+    /// `{"key":{"f":5}}` or `{"key":"page_down"}`.
+    Key(Key),
+    /// Left click at `position`, in pixels of the window covered by `window`.
+    ///
+    /// `{"click":{"position":[10.0,20.0],"window":{"left":0,"top":0,"width":800,"height":600}}}`
+    Click {
+        position: (f32, f32),
+        window: BoundingBox,
+    },
+}
+
+/// Message from the server to a client.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// First message on every connection.
+    ///
+    /// `{"type":"hello","version":1}`
+    Hello { version: u32 },
+    /// The request was taken. Commands and input are queued with everyone else's, so
+    /// their outcome shows in the next drawing rather than here.
+    ///
+    /// `{"type":"ok"}`
+    Ok,
+    /// The request couldn't be handled.
+    ///
+    /// `{"type":"error","message":"Malformed request: ..."}`
+    Error { message: String },
+    /// Reply to `font`.
+    ///
+    /// `{"type":"font","scale":16.0}`
+    Font { scale: f32 },
+    /// Reply to `draw`: the theme, the zoom and every frame with its scene.
+    Drawing { drawing: Drawing },
+    /// What's on screen changed, so subscribers should draw again.
+    ///
+    /// `{"type":"redraw"}`
+    Redraw,
+    /// The server is shutting down.
+    ///
+    /// `{"type":"exit"}`
+    Exit,
+}
+
+impl Input {
+    pub fn from_event(event: StateEvent) -> Option<Self> {
+        Some(match event {
+            StateEvent::ModifiersChange(modifiers) => Self::Modifiers(modifiers),
+            StateEvent::CharInput(c) => Self::Char(c),
+            StateEvent::KeyPress(key) => Self::Key(key),
+            StateEvent::Click { position, window } => Self::Click { position, window },
+            _ => return None,
+        })
+    }
+
+    pub fn into_event(self) -> StateEvent {
+        match self {
+            Self::Modifiers(modifiers) => StateEvent::ModifiersChange(modifiers),
+            Self::Char(c) => StateEvent::CharInput(c),
+            Self::Key(key) => StateEvent::KeyPress(key),
+            Self::Click { position, window } => StateEvent::Click { position, window },
+        }
+    }
+}
+
+/// Socket the server of the user's session listens on.
+pub fn socket_path() -> anyhow::Result<PathBuf> {
+    Ok(paths::runtime_dir()
+        .context("No runtime directory on this platform")?
+        .join(SOCKET))
+}
+
+/// Writes `message` as a line of JSON.
+async fn send<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &T,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+
+    writer.write_all(&line).await?;
+    Ok(writer.flush().await?)
+}
+
+/// Reads the next message, or `None` once the other side hung up.
+async fn receive<T: DeserializeOwned>(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+) -> anyhow::Result<Option<T>> {
+    let Some(line) = lines.next_line().await? else {
+        return Ok(None);
+    };
+
+    serde_json::from_str(&line)
+        .map(Some)
+        .context("Malformed message")
+}
+
+fn lines(reader: OwnedReadHalf) -> Lines<BufReader<OwnedReadHalf>> {
+    BufReader::new(reader).lines()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandRegistry;
+    use crate::config::Config;
+    use crate::editor::Editor;
+    use crate::fonts::FontCollection;
+    use serde_json::Value;
+
+    /// Reads `json` as a `T` and writes it back, expecting the same JSON.
+    fn round_trip<T: Serialize + DeserializeOwned>(json: &str) -> T {
+        let message: T = serde_json::from_str(json).unwrap();
+        let written = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(written, serde_json::from_str::<Value>(json).unwrap());
+        message
+    }
+
+    #[test]
+    fn requests_read_and_write_as_documented() {
+        let registry = CommandRegistry::new();
+
+        for json in [
+            r#"{"type":"open","path":"/tmp/notes.md"}"#,
+            r#"{"type":"command","id":"layout.split_horizontal","args":[]}"#,
+            r#"{"type":"command","id":"buffer.insert","args":[{"Text":"a"}]}"#,
+            r#"{"type":"input","event":{"char":"a"}}"#,
+            r#"{"type":"input","event":{"modifiers":3}}"#,
+            r#"{"type":"input","event":{"key":{"char":"a"}}}"#,
+            r#"{"type":"input","event":{"key":{"f":5}}}"#,
+            r#"{"type":"input","event":{"key":"page_down"}}"#,
+            r#"{"type":"input","event":{"click":{"position":[10.0,20.0],
+                "window":{"left":0.0,"top":0.0,"width":800.0,"height":600.0}}}}"#,
+            r#"{"type":"font"}"#,
+            r#"{"type":"draw","window":{"left":0.0,"top":0.0,"width":800.0,"height":600.0},
+                "surface":{"window":{"scale_factor":1.0}}}"#,
+            r#"{"type":"draw","window":{"left":0.0,"top":0.0,"width":80.0,"height":24.0},
+                "surface":{"cells":{"scale":16.0}}}"#,
+            r#"{"type":"subscribe"}"#,
+        ] {
+            // Commands in the examples have to run
+            if let Request::Command(invocation) = round_trip::<Request>(json) {
+                assert!(registry.get(&invocation.id).is_some(), "{}", invocation.id);
+            }
+        }
+
+        assert!(serde_json::from_str::<Request>(r#"{"type":"open"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"type":"fly"}"#).is_err());
+    }
+
+    #[test]
+    fn messages_read_and_write_as_documented() {
+        for json in [
+            r#"{"type":"hello","version":1}"#,
+            r#"{"type":"ok"}"#,
+            r#"{"type":"error","message":"Malformed request: ..."}"#,
+            r#"{"type":"font","scale":16.0}"#,
+            r#"{"type":"redraw"}"#,
+            r#"{"type":"exit"}"#,
+        ] {
+            round_trip::<Message>(json);
+        }
+    }
+
+    #[tokio::test]
+    async fn drawings_read_and_write_back_the_same() {
+        let editor = Editor::new(&Config::default(), FontCollection::embedded());
+        let window = BoundingBox {
+            left: 0.0,
+            top: 0.0,
+            width: 800.0,
+            height: 600.0,
+        };
+        let drawing = editor
+            .draw(window, Surface::Window { scale_factor: 1.0 })
+            .await;
+
+        // Compared as text, which keeps floats as they were written
+        let json = serde_json::to_string(&Message::Drawing { drawing }).unwrap();
+        let read: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+    }
+}
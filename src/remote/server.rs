@@ -0,0 +1,210 @@
+use super::{lines, send, Message, Request, PROTOCOL_VERSION};
+use crate::app_state::SharedState;
+use crate::command::{CommandArg, Invocation};
+use crate::editor::Frontend;
+use crate::scene;
+use crate::state::StateEvent;
+use anyhow::{bail, Context};
+use std::path::PathBuf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::Sender;
+
+/// Notifications a slow client can fall behind on before it's only told to redraw
+const NOTIFICATION_CAPACITY: usize = 64;
+
+#[derive(Copy, Clone, Debug)]
+enum Notification {
+    Redraw,
+    Exit,
+}
+
+/// Tells the clients subscribed to the server when to redraw and when it shuts down.
+pub struct Notifier(broadcast::Sender<Notification>);
+
+impl Frontend for Notifier {
+    fn request_redraw(&self) {
+        let _ = self.0.send(Notification::Redraw);
+    }
+
+    fn exit(&self) {
+        let _ = self.0.send(Notification::Exit);
+    }
+}
+
+/// Listens on the socket at `path`, handing what clients ask for to the editor of
+/// `state`. The socket is removed once the editor shuts down.
+pub fn listen(
+    path: PathBuf,
+    state: SharedState,
+    events: Sender<StateEvent>,
+) -> anyhow::Result<Notifier> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Can't create {}", parent.display()))?;
+    }
+
+    // A socket nothing answers on was left by an instance that crashed
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            bail!("Another instance is listening on {}", path.display());
+        }
+
+        std::fs::remove_file(&path).with_context(|| format!("Can't remove {}", path.display()))?;
+    }
+
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("Can't listen on {}", path.display()))?;
+    let (notifications, _) = broadcast::channel(NOTIFICATION_CAPACITY);
+    tokio::spawn(accept(listener, path, state, events, notifications.clone()));
+
+    Ok(Notifier(notifications))
+}
+
+async fn accept(
+    listener: UnixListener,
+    path: PathBuf,
+    state: SharedState,
+    events: Sender<StateEvent>,
+    notifications: broadcast::Sender<Notification>,
+) {
+    let mut exit = notifications.subscribe();
+
+    loop {
+        tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok((stream, _)) => {
+                    let (state, events) = (state.clone(), events.clone());
+                    let notifications = notifications.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(err) = serve(stream, state, events, notifications).await {
+                            tracing::warn!("Client failed: {:#}", err);
+                        }
+                    });
+                }
+                Err(err) => tracing::warn!("Failed to accept a client: {}", err),
+            },
+            notification = exit.recv() => {
+                if matches!(notification, Ok(Notification::Exit) | Err(RecvError::Closed)) {
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Err(err) = std::fs::remove_file(&path) {
+        tracing::warn!("Can't remove {}: {}", path.display(), err);
+    }
+}
+
+/// What the server keeps for a client while it's connected.
+struct Connection {
+    state: SharedState,
+    events: Sender<StateEvent>,
+    notifications: broadcast::Sender<Notification>,
+    /// Set once the client subscribed to notifications
+    subscription: Option<broadcast::Receiver<Notification>>,
+}
+
+/// Answers the requests of one client until it hangs up, or the editor shuts down.
+async fn serve(
+    stream: UnixStream,
+    state: SharedState,
+    events: Sender<StateEvent>,
+    notifications: broadcast::Sender<Notification>,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = lines(reader);
+    let mut connection = Connection {
+        state,
+        events,
+        notifications,
+        subscription: None,
+    };
+
+    send(
+        &mut writer,
+        &Message::Hello {
+            version: PROTOCOL_VERSION,
+        },
+    )
+    .await?;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+
+                let reply = match serde_json::from_str(&line) {
+                    Ok(request) => connection.handle(request).await.unwrap_or_else(|err| {
+                        Message::Error {
+                            message: format!("{:#}", err),
+                        }
+                    }),
+                    Err(err) => Message::Error {
+                        message: format!("Malformed request: {}", err),
+                    },
+                };
+                send(&mut writer, &reply).await?;
+            }
+            notification = connection.next() => match notification {
+                Notification::Redraw => send(&mut writer, &Message::Redraw).await?,
+                Notification::Exit => return send(&mut writer, &Message::Exit).await,
+            },
+        }
+    }
+}
+
+impl Connection {
+    async fn handle(&mut self, request: Request) -> anyhow::Result<Message> {
+        let event = match request {
+            Request::Open { path } => {
+                if !path.is_absolute() {
+                    bail!("Paths must be absolute, got {}", path.display());
+                }
+
+                let path = path.to_string_lossy().into_owned();
+                StateEvent::Command(Invocation::new("file.open").with_arg(CommandArg::Text(path)))
+            }
+            Request::Command(invocation) => StateEvent::Command(invocation),
+            Request::Input { event } => event.into_event(),
+            Request::Font => {
+                let scale = self.state.read().await.font.scale;
+                return Ok(Message::Font { scale });
+            }
+            Request::Draw { window, surface } => {
+                let drawing = scene::draw(&self.state, window, surface).await;
+                return Ok(Message::Drawing { drawing });
+            }
+            Request::Subscribe => {
+                self.subscription = Some(self.notifications.subscribe());
+                return Ok(Message::Ok);
+            }
+        };
+
+        self.events
+            .send(event)
+            .await
+            .ok()
+            .context("The editor is shutting down")?;
+
+        Ok(Message::Ok)
+    }
+
+    /// Next notification for the client, if it subscribed. Others never get one.
+    async fn next(&mut self) -> Notification {
+        let Some(receiver) = &mut self.subscription else {
+            return std::future::pending().await;
+        };
+
+        match receiver.recv().await {
+            Ok(notification) => notification,
+            // Redraws were dropped, and one does for them all
+            Err(RecvError::Lagged(_)) => Notification::Redraw,
+            Err(RecvError::Closed) => Notification::Exit,
+        }
+    }
+}
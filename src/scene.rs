@@ -0,0 +1,232 @@
+use crate::app_state::SharedState;
+use crate::buffer::{BoundingBox, RenderContext};
+use crate::fonts::FontId;
+use crate::grid::GridMetrics;
+use crate::theme::Theme;
+use ab_glyph::{GlyphId, Point, PxScale};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Rectangle in window pixel coordinates.
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
+#[repr(C)]
+pub struct Quad {
+    /// Left, top, right, bottom
+    pub aabb: [f32; 4],
+    pub z_pos: f32,
+    pub color: [f32; 4],
+}
+
+/// Glyph shaped and placed by the buffer, drawn as is.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Glyph {
+    /// Position of the glyph's baseline origin, in window pixels
+    #[serde(with = "GlyphDef")]
+    pub glyph: ab_glyph::Glyph,
+    pub font_id: FontId,
+    pub color: [f32; 4],
+    /// Characters the glyph draws, for frontends that draw text rather than glyphs. The
+    /// first glyph of a cluster, such as a ligature, has them all and the others none.
+    pub text: String,
+}
+
+// ab_glyph's types don't implement serde's traits, so these stand in for them
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ab_glyph::Glyph")]
+struct GlyphDef {
+    #[serde(with = "GlyphIdDef")]
+    id: GlyphId,
+    #[serde(with = "PxScaleDef")]
+    scale: PxScale,
+    #[serde(with = "PointDef")]
+    position: Point,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "GlyphId")]
+struct GlyphIdDef(u16);
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "PxScale")]
+struct PxScaleDef {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Point")]
+struct PointDef {
+    x: f32,
+    y: f32,
+}
+
+/// Text laid out by the frontend, on one line from `position` and cut to `bounds`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Label {
+    /// Top left corner, in window pixels
+    pub position: (f32, f32),
+    /// Width and height
+    pub bounds: (f32, f32),
+    pub spans: Vec<Span>,
+}
+
+/// Run of a label's text in one color and face.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Span {
+    pub text: String,
+    pub scale: f32,
+    pub color: [f32; 4],
+    pub font_id: FontId,
+}
+
+/// What a buffer shows in its frame, in the order it's drawn: backgrounds, then text,
+/// then overlays such as cursors.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Scene {
+    pub backgrounds: Vec<Quad>,
+    pub glyphs: Vec<Glyph>,
+    pub labels: Vec<Label>,
+    pub overlays: Vec<Quad>,
+    /// Area `glyphs` are cut to, when smaller than the frame
+    pub clip: Option<BoundingBox>,
+    /// Top left corner of the caret the user types at, for frontends with a cursor of
+    /// their own
+    pub cursor: Option<(f32, f32)>,
+}
+
+/// Everything on screen: the frames of the layout with what their buffers show.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Drawing {
+    pub theme: Arc<Theme>,
+    /// Index in `frames` of the frame the user types in
+    pub active_frame: usize,
+    /// Global font zoom the scenes were laid out with
+    pub zoom: f32,
+    /// Frame, index of the buffer shown in it, and the buffer's scene
+    pub frames: Vec<(BoundingBox, usize, Scene)>,
+}
+
+impl Label {
+    pub fn new(position: (f32, f32), bounds: (f32, f32)) -> Self {
+        Self {
+            position,
+            bounds,
+            spans: Vec::new(),
+        }
+    }
+
+    pub fn add_span(mut self, span: Span) -> Self {
+        self.spans.push(span);
+        self
+    }
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            scale: 16.0,
+            color: [0.0, 0.0, 0.0, 1.0],
+            font_id: FontId::default(),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_font_id(mut self, font_id: FontId) -> Self {
+        self.font_id = font_id;
+        self
+    }
+}
+
+/// What frames are drawn on.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Surface {
+    /// Pixels of a window, `scale_factor` of them per logical pixel
+    Window { scale_factor: f32 },
+    /// Cells of a terminal, each the size of a cell of text at `scale`. All text is laid
+    /// out at that size and frames cover whole cells.
+    Cells { scale: f32 },
+}
+
+/// Lays out every frame of `window` on `surface`. A buffer shown in several frames is
+/// drawn in each.
+pub async fn draw(state: &SharedState, window: BoundingBox, surface: Surface) -> Drawing {
+    let (buffers, mut bounding_boxes, theme, zoom, view, active_frame, fonts) = {
+        let state = state.read().await;
+        (
+            state.buffers.clone(),
+            state.layout.build_bounding_boxes(window),
+            state.theme.clone(),
+            state.zoom,
+            state.view,
+            state.active_frame,
+            state.font.fonts.clone(),
+        )
+    };
+
+    let (scale_factor, fixed_scale) = match surface {
+        Surface::Window { scale_factor } => (scale_factor, None),
+        Surface::Cells { scale } => {
+            let metrics = GridMetrics::new(&fonts, scale);
+            for (bounding_box, ..) in &mut bounding_boxes {
+                *bounding_box = snap(*bounding_box, metrics);
+            }
+
+            (1.0, Some(scale))
+        }
+    };
+
+    let ctx = RenderContext {
+        theme: &theme,
+        scale_factor,
+        zoom,
+        view,
+        fixed_scale,
+    };
+
+    let mut frames = Vec::with_capacity(bounding_boxes.len());
+    let mut active = 0;
+    for (bounding_box, frame, buffer_id) in bounding_boxes {
+        if frame == active_frame {
+            active = frames.len();
+        }
+
+        let mut scene = Scene::default();
+        buffers[buffer_id]
+            .lock()
+            .await
+            .draw(bounding_box, &ctx, &mut scene);
+        frames.push((bounding_box, buffer_id, scene));
+    }
+
+    Drawing {
+        theme,
+        active_frame: active,
+        zoom,
+        frames,
+    }
+}
+
+/// `bb` with its edges moved to the nearest cell boundaries.
+fn snap(bb: BoundingBox, metrics: GridMetrics) -> BoundingBox {
+    let column = |x: f32| (x / metrics.cell_width).round() * metrics.cell_width;
+    let row = |y: f32| (y / metrics.line_height).round() * metrics.line_height;
+    let (left, top) = (column(bb.left), row(bb.top));
+
+    BoundingBox {
+        left,
+        top,
+        width: column(bb.left + bb.width) - left,
+        height: row(bb.top + bb.height) - top,
+    }
+}
@@ -0,0 +1,567 @@
+use crate::app_state::AppState;
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{Command, CommandArg, Invocation};
+use crate::keymap::KeyBinding;
+use crate::state::StateEvent;
+use anyhow::{anyhow, Context};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::oneshot;
+
+/// Wall clock budget of a single script call before it is aborted.
+const SCRIPT_TIME_LIMIT: Duration = Duration::from_millis(250);
+
+/// Hard limit on evaluated operations, so runaway loops die even without a clock check.
+const SCRIPT_MAX_OPERATIONS: u64 = 10_000_000;
+
+const PLUGIN_EXTENSION: &str = "rhai";
+
+/// Editor events plugins can react to by defining a function with the hook's name.
+#[derive(Clone, Debug)]
+pub enum Hook {
+    Open(PathBuf),
+    Save(PathBuf),
+    CharInput(char),
+}
+
+/// Snapshot of editor state handed to scripts. Scripts never see the live buffers,
+/// so a misbehaving script can't hold a buffer lock.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptContext {
+    pub buffer_text: String,
+    pub buffer_path: Option<PathBuf>,
+}
+
+/// Requests a script made, applied by the editor once the script returns.
+#[derive(Clone, Debug)]
+pub enum ScriptAction {
+    Invoke(Invocation),
+    BindKey(KeyBinding, Invocation),
+    RegisterCommand {
+        id: String,
+        description: String,
+        plugin: String,
+        function: String,
+    },
+}
+
+/// Handle to the plugin thread. All script evaluation happens there.
+#[derive(Clone, Default)]
+pub struct ScriptHost {
+    tx: Option<mpsc::Sender<Request>>,
+    hooks: HashSet<&'static str>,
+}
+
+struct Request {
+    call: Call,
+    context: ScriptContext,
+    reply: oneshot::Sender<anyhow::Result<Vec<ScriptAction>>>,
+}
+
+enum Call {
+    Hook(Hook),
+    Function {
+        plugin: String,
+        function: String,
+        args: Vec<CommandArg>,
+    },
+}
+
+struct Plugin {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+/// State shared between the plugin thread and the functions it exposes to scripts.
+#[derive(Default)]
+struct CallState {
+    plugin: String,
+    context: ScriptContext,
+    actions: Vec<ScriptAction>,
+    deadline: Option<Instant>,
+}
+
+type SharedCallState = Arc<Mutex<CallState>>;
+
+impl Hook {
+    fn function_name(&self) -> &'static str {
+        match self {
+            Hook::Open(_) => "on_open",
+            Hook::Save(_) => "on_save",
+            Hook::CharInput(_) => "on_char_input",
+        }
+    }
+
+    fn args(&self) -> Vec<Dynamic> {
+        match self {
+            Hook::Open(path) | Hook::Save(path) => {
+                vec![Dynamic::from(path.display().to_string())]
+            }
+            Hook::CharInput(c) => vec![Dynamic::from(*c)],
+        }
+    }
+}
+
+impl ScriptContext {
+    pub async fn capture(state: &AppState) -> Self {
+        let Some(buffer) = state.buffers.get(state.active_buffer) else {
+            return Self::default();
+        };
+        let buffer = buffer.lock().await;
+
+        Self {
+            buffer_text: buffer.text().unwrap_or_default().to_string(),
+            buffer_path: buffer.path().map(Path::to_path_buf),
+        }
+    }
+}
+
+impl ScriptHost {
+    /// Loads every `*.rhai` file in `dir` on a dedicated thread. Returns the actions
+    /// requested by the plugins' top level code, e.g. key bindings.
+    pub async fn start(dir: &Path) -> anyhow::Result<(Self, Vec<ScriptAction>)> {
+        let state = SharedCallState::default();
+        let mut plugins = Vec::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Self::default(), Vec::new()))
+            }
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", dir.display())),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(PLUGIN_EXTENSION) {
+                continue;
+            }
+
+            match Plugin::load(&path, &state) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(err) => tracing::warn!("Failed to load plugin {}: {:#}", path.display(), err),
+            }
+        }
+
+        let hooks = ["on_open", "on_save", "on_char_input"]
+            .into_iter()
+            .filter(|hook| plugins.iter().any(|plugin| plugin.defines(hook)))
+            .collect();
+
+        let (tx, rx) = mpsc::channel(64);
+        let (init_tx, init_rx) = oneshot::channel();
+
+        thread::Builder::new()
+            .name("kami-scripts".to_string())
+            .spawn(move || plugin_thread(plugins, state, init_tx, rx))?;
+
+        let actions = init_rx.await.context("Plugin thread died while loading")?;
+
+        Ok((
+            Self {
+                tx: Some(tx),
+                hooks,
+            },
+            actions,
+        ))
+    }
+
+    /// Runs the hook in every plugin defining it.
+    pub async fn fire(&self, hook: Hook, context: ScriptContext) -> Vec<ScriptAction> {
+        if !self.hooks.contains(hook.function_name()) {
+            return Vec::new();
+        }
+
+        self.request(Call::Hook(hook), context)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::warn!("Plugin hook failed: {:#}", err);
+                Vec::new()
+            })
+    }
+
+    /// Runs the hook in every plugin defining it without waiting for them, so the state
+    /// isn't held while they run. The actions they request are sent to the state loop.
+    pub fn post(&self, hook: Hook, context: ScriptContext, state_tx: Sender<StateEvent>) {
+        if !self.hooks.contains(hook.function_name()) {
+            return;
+        }
+
+        let host = self.clone();
+        tokio::spawn(async move {
+            let actions = host.fire(hook, context).await;
+            if !actions.is_empty() && state_tx.send(StateEvent::Script(actions)).await.is_err() {
+                tracing::warn!("Dropped plugin actions for a stopped loop");
+            }
+        });
+    }
+
+    pub async fn call(
+        &self,
+        plugin: &str,
+        function: &str,
+        args: Vec<CommandArg>,
+        context: ScriptContext,
+    ) -> anyhow::Result<Vec<ScriptAction>> {
+        let call = Call::Function {
+            plugin: plugin.to_string(),
+            function: function.to_string(),
+            args,
+        };
+
+        self.request(call, context).await
+    }
+
+    async fn request(
+        &self,
+        call: Call,
+        context: ScriptContext,
+    ) -> anyhow::Result<Vec<ScriptAction>> {
+        let tx = self.tx.as_ref().context("No plugins loaded")?;
+        let (reply, rx) = oneshot::channel();
+
+        tx.send(Request {
+            call,
+            context,
+            reply,
+        })
+        .await
+        .map_err(|_| anyhow!("Plugin thread stopped"))?;
+
+        // Scripts abort themselves at the deadline, this only guards against a stuck thread
+        tokio::time::timeout(SCRIPT_TIME_LIMIT * 4, rx)
+            .await
+            .context("Plugin thread didn't respond in time")?
+            .context("Plugin thread stopped")?
+    }
+}
+
+impl Plugin {
+    fn load(path: &Path, state: &SharedCallState) -> anyhow::Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .context("Plugin file name is not valid unicode")?
+            .to_string();
+        let source = fs::read_to_string(path)?;
+        let engine = build_engine(state.clone());
+        let ast = engine.compile(&source)?;
+
+        Ok(Self {
+            name,
+            engine,
+            ast,
+            scope: Scope::new(),
+        })
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == function)
+    }
+
+    fn run(&mut self) -> Result<(), Box<EvalAltResult>> {
+        self.engine.run_ast_with_scope(&mut self.scope, &self.ast)
+    }
+
+    fn call(&mut self, function: &str, args: Vec<Dynamic>) -> Result<(), Box<EvalAltResult>> {
+        self.engine
+            .call_fn_with_options::<Dynamic>(
+                CallFnOptions::new().eval_ast(false),
+                &mut self.scope,
+                &self.ast,
+                function,
+                args,
+            )
+            .map(|_| ())
+    }
+}
+
+fn plugin_thread(
+    mut plugins: Vec<Plugin>,
+    state: SharedCallState,
+    init: oneshot::Sender<Vec<ScriptAction>>,
+    mut rx: mpsc::Receiver<Request>,
+) {
+    for plugin in &mut plugins {
+        begin_call(&state, &plugin.name, ScriptContext::default());
+
+        if let Err(err) = plugin.run() {
+            tracing::warn!("Plugin `{}` failed to initialize: {}", plugin.name, err);
+        }
+    }
+    let _ = init.send(take_actions(&state));
+
+    while let Some(request) = rx.blocking_recv() {
+        match request.call {
+            Call::Hook(hook) => {
+                let function = hook.function_name();
+
+                for plugin in plugins.iter_mut().filter(|p| p.defines(function)) {
+                    begin_call(&state, &plugin.name, request.context.clone());
+
+                    if let Err(err) = plugin.call(function, hook.args()) {
+                        tracing::warn!("Plugin `{}` failed in {}: {}", plugin.name, function, err);
+                    }
+                }
+            }
+            Call::Function {
+                plugin,
+                function,
+                args,
+            } => {
+                let Some(plugin) = plugins.iter_mut().find(|p| p.name == plugin) else {
+                    let _ = request
+                        .reply
+                        .send(Err(anyhow!("No plugin named `{}`", plugin)));
+                    continue;
+                };
+
+                begin_call(&state, &plugin.name, request.context);
+
+                let args = args.into_iter().map(arg_to_dynamic).collect();
+                if let Err(err) = plugin.call(&function, args) {
+                    tracing::warn!("Plugin `{}` failed in {}: {}", plugin.name, function, err);
+                }
+            }
+        }
+
+        let _ = request.reply.send(Ok(take_actions(&state)));
+    }
+}
+
+fn begin_call(state: &SharedCallState, plugin: &str, context: ScriptContext) {
+    let mut state = state.lock().unwrap();
+
+    state.plugin = plugin.to_string();
+    state.context = context;
+    state.deadline = Some(Instant::now() + SCRIPT_TIME_LIMIT);
+}
+
+fn take_actions(state: &SharedCallState) -> Vec<ScriptAction> {
+    let mut state = state.lock().unwrap();
+
+    state.deadline = None;
+    std::mem::take(&mut state.actions)
+}
+
+fn build_engine(state: SharedCallState) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+
+    let s = state.clone();
+    engine.on_progress(move |_| {
+        let deadline = s.lock().unwrap().deadline?;
+
+        (Instant::now() > deadline).then(|| Dynamic::from("time limit exceeded"))
+    });
+
+    let s = state.clone();
+    engine.on_print(move |message| {
+        tracing::info!("[{}] {}", s.lock().unwrap().plugin, message);
+    });
+
+    let s = state.clone();
+    engine.register_fn("buffer_text", move || -> String {
+        s.lock().unwrap().context.buffer_text.clone()
+    });
+
+    let s = state.clone();
+    engine.register_fn("buffer_path", move || -> String {
+        let state = s.lock().unwrap();
+
+        state
+            .context
+            .buffer_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    });
+
+    let s = state.clone();
+    engine.register_fn("insert", move |text: &str| {
+        push_invocation(
+            &s,
+            Invocation::new("buffer.insert").with_arg(CommandArg::Text(text.to_string())),
+        );
+    });
+
+    for (name, command) in [
+        ("delete_backward", "buffer.delete_backward"),
+        ("undo", "edit.undo"),
+        ("redo", "edit.redo"),
+        ("split_horizontal", "layout.split_horizontal"),
+        ("split_vertical", "layout.split_vertical"),
+        ("close_frame", "layout.close_frame"),
+        ("focus_next", "frame.focus_next"),
+        ("focus_previous", "frame.focus_previous"),
+    ] {
+        let s = state.clone();
+        engine.register_fn(name, move || push_invocation(&s, Invocation::new(command)));
+    }
+
+    let s = state.clone();
+    engine.register_fn("run_command", move |id: &str| {
+        push_invocation(&s, Invocation::new(id));
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "run_command",
+        move |id: &str, args: Array| -> Result<(), Box<EvalAltResult>> {
+            let mut invocation = Invocation::new(id);
+            for arg in args {
+                invocation = invocation.with_arg(dynamic_to_arg(arg)?);
+            }
+
+            push_invocation(&s, invocation);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "bind_key",
+        move |binding: &str, command: &str| -> Result<(), Box<EvalAltResult>> {
+            let binding = binding
+                .parse::<KeyBinding>()
+                .map_err(|err| err.to_string())?;
+
+            s.lock()
+                .unwrap()
+                .actions
+                .push(ScriptAction::BindKey(binding, Invocation::new(command)));
+            Ok(())
+        },
+    );
+
+    let s = state;
+    engine.register_fn(
+        "register_command",
+        move |id: &str, description: &str, function: &str| {
+            let mut state = s.lock().unwrap();
+            let plugin = state.plugin.clone();
+
+            state.actions.push(ScriptAction::RegisterCommand {
+                id: id.to_string(),
+                description: description.to_string(),
+                plugin,
+                function: function.to_string(),
+            });
+        },
+    );
+
+    engine
+}
+
+fn push_invocation(state: &SharedCallState, invocation: Invocation) {
+    state
+        .lock()
+        .unwrap()
+        .actions
+        .push(ScriptAction::Invoke(invocation));
+}
+
+fn dynamic_to_arg(value: Dynamic) -> Result<CommandArg, Box<EvalAltResult>> {
+    if value.is_string() {
+        Ok(CommandArg::Text(value.into_string()?))
+    } else if value.is_char() {
+        Ok(CommandArg::Char(value.as_char()?))
+    } else if value.is_int() {
+        Ok(CommandArg::Integer(value.as_int()?))
+    } else if value.is_float() {
+        Ok(CommandArg::Number(value.as_float()?))
+    } else {
+        Err(format!("Unsupported command argument type `{}`", value.type_name()).into())
+    }
+}
+
+fn arg_to_dynamic(arg: CommandArg) -> Dynamic {
+    match arg {
+        CommandArg::Text(text) => Dynamic::from(text),
+        CommandArg::Char(c) => Dynamic::from(c),
+        CommandArg::Integer(i) => Dynamic::from(i),
+        CommandArg::Number(n) => Dynamic::from(n),
+    }
+}
+
+/// Applies what a script asked for. Invocations run through the state loop like any
+/// other input.
+pub fn apply_actions(state: &mut AppState, actions: Vec<ScriptAction>) {
+    for action in actions {
+        match action {
+            ScriptAction::Invoke(invocation) => state.pending.push_back(invocation),
+            ScriptAction::BindKey(binding, invocation) => state.keymap.bind(binding, invocation),
+            ScriptAction::RegisterCommand {
+                id,
+                description,
+                plugin,
+                function,
+            } => Arc::make_mut(&mut state.commands).register(script_command(
+                &id,
+                &description,
+                plugin,
+                function,
+            )),
+        }
+    }
+}
+
+fn script_command(id: &str, description: &str, plugin: String, function: String) -> Command {
+    Command::new(id, description, move |state, args| {
+        let plugin = plugin.clone();
+        let function = function.clone();
+
+        Box::pin(async move {
+            let context = ScriptContext::capture(state).await;
+            let actions = state
+                .scripts
+                .call(&plugin, &function, args, context)
+                .await?;
+
+            apply_actions(state, actions);
+
+            Ok(EventHandlerOutcome::Redraw)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+
+    #[tokio::test]
+    async fn calls_to_unknown_plugins_fail() {
+        let dir = paths::scratch_dir("unknown-plugin");
+        fs::write(
+            dir.join("greet.rhai"),
+            "fn hello() { run_command(\"edit.undo\"); }",
+        )
+        .unwrap();
+        let (host, _) = ScriptHost::start(&dir).await.unwrap();
+        let context = ScriptContext::default();
+
+        let actions = host
+            .call("greet", "hello", Vec::new(), context.clone())
+            .await
+            .unwrap();
+        assert!(
+            matches!(&actions[..], [ScriptAction::Invoke(invocation)] if invocation.id == "edit.undo")
+        );
+
+        let err = host
+            .call("missing", "hello", Vec::new(), context)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No plugin named `missing`");
+    }
+}
@@ -0,0 +1,222 @@
+use crate::app_state::AppState;
+use crate::buffer::explorer::Explorer;
+use crate::buffer::text_buffer::TextBuffer;
+use crate::buffer::BufferEvent;
+use crate::layout::Layout;
+use crate::paths;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const SESSIONS_DIR: &str = "sessions";
+
+/// What was open in the working directory when Kami last quit, restored when it starts
+/// there again.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// Buffers by index, with `None` for those that can't be opened again, such as
+    /// terminals and diffs. They come back empty, so the layout's indices stay valid.
+    buffers: Vec<Option<SavedBuffer>>,
+    layout: Layout,
+    active_buffer: usize,
+    previous_buffer: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SavedBuffer {
+    File {
+        path: PathBuf,
+        /// Line and column of the cursor, both from 0
+        cursor: Option<(usize, usize)>,
+        zoom: f32,
+    },
+    Explorer {
+        root: PathBuf,
+    },
+}
+
+impl Session {
+    /// The layout of `state`, its open files and where their cursors are.
+    pub async fn capture(state: &AppState) -> Self {
+        let mut buffers = Vec::with_capacity(state.buffers.len());
+        for buffer in &state.buffers {
+            let buffer = buffer.lock().await;
+
+            // Of the buffers with a path, only files have text, while explorers browse a folder
+            buffers.push(match (buffer.path(), buffer.text()) {
+                (Some(path), Some(_)) => Some(SavedBuffer::File {
+                    path: path.to_path_buf(),
+                    cursor: buffer.cursor_position(),
+                    zoom: buffer.zoom(),
+                }),
+                (Some(root), None) => Some(SavedBuffer::Explorer {
+                    root: root.to_path_buf(),
+                }),
+                (None, _) => None,
+            });
+        }
+
+        Self {
+            buffers,
+            layout: state.layout.clone(),
+            active_buffer: state.active_buffer,
+            previous_buffer: state
+                .layout
+                .buffer(state.previous_frame)
+                .unwrap_or(state.active_buffer),
+        }
+    }
+
+    /// Replaces the buffers and layout of `state` with the session's. Files and folders
+    /// that are gone or can't be read leave an empty buffer in their place.
+    pub async fn restore(self, state: &mut AppState) {
+        let count = self.buffers.len();
+        let frames = self.layout.buffer_indices();
+        if frames.is_empty() || frames.iter().any(|index| *index >= count) {
+            tracing::warn!("Session layout doesn't match its buffers");
+            return;
+        }
+
+        // The session's buffers take the place of those shown while it loaded
+        for buffer in std::mem::take(&mut state.buffers) {
+            buffer.lock().await.shutdown();
+        }
+
+        for saved in self.buffers {
+            match saved {
+                Some(SavedBuffer::File { path, cursor, zoom }) if path.is_file() => {
+                    let index = match state.load_file(path).await {
+                        Ok(index) => index,
+                        Err(err) => {
+                            tracing::warn!("{:#}", err);
+                            push_empty(state);
+                            continue;
+                        }
+                    };
+
+                    let mut buffer = state.buffers[index].lock().await;
+                    buffer.set_zoom(zoom);
+                    if let Some((line, column)) = cursor {
+                        buffer.handle_events(BufferEvent::GoTo { line, column });
+                    }
+                }
+                Some(SavedBuffer::Explorer { root }) if root.is_dir() => {
+                    let explorer = Explorer::new(state.font.clone(), root, state.state_tx.clone());
+                    state.buffers.push(Arc::new(Mutex::new(explorer)));
+                }
+                _ => push_empty(state),
+            }
+        }
+
+        state.set_layout(self.layout);
+        if let Some(previous) = state.layout.frame_showing(self.previous_buffer) {
+            state.focus_frame(previous);
+        }
+        if frames.contains(&self.active_buffer) {
+            state.show_buffer(self.active_buffer);
+        }
+    }
+
+    /// Session last saved for the working directory, if any.
+    pub async fn load() -> anyhow::Result<Option<Self>> {
+        let path = session_path()?;
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .with_context(|| format!("Malformed session file {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("Can't read {}", path.display())),
+        }
+    }
+
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let path = session_path()?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .await
+            .with_context(|| format!("Can't write {}", path.display()))
+    }
+}
+
+/// Stands in for a buffer that can't be opened again.
+fn push_empty(state: &mut AppState) {
+    let empty = TextBuffer::new(state.font.clone());
+    state.buffers.push(Arc::new(Mutex::new(empty)));
+}
+
+/// File of the session of the working directory, as sessions are kept per workspace.
+fn session_path() -> anyhow::Result<PathBuf> {
+    let workspace = std::env::current_dir().context("No working directory")?;
+
+    Ok(paths::data_dir()
+        .context("No data directory on this platform")?
+        .join(SESSIONS_DIR)
+        .join(format!("{}.json", paths::flattened(&workspace))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::text_buffer::FontConfig;
+    use crate::fonts::FontCollection;
+    use crate::grid::GridSettings;
+    use crate::indent::IndentStyle;
+    use tokio::sync::mpsc;
+
+    fn state() -> AppState {
+        let font = FontConfig {
+            scale: 16.0,
+            fonts: FontCollection::embedded(),
+            grid: GridSettings::default(),
+            indent: IndentStyle::default(),
+        };
+        let (state_tx, _) = mpsc::channel(16);
+
+        AppState::new(font, state_tx)
+    }
+
+    #[tokio::test]
+    async fn restores_files_and_explorers() {
+        let dir = paths::scratch_dir("session");
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "one\ntwo").unwrap();
+
+        let mut saved = state();
+        let index = saved.load_file(file.clone()).await.unwrap();
+        saved.buffers[index]
+            .lock()
+            .await
+            .handle_events(BufferEvent::GoTo { line: 1, column: 2 });
+        let explorer = Explorer::new(saved.font.clone(), dir.clone(), saved.state_tx.clone());
+        saved.buffers.push(Arc::new(Mutex::new(explorer)));
+        push_empty(&mut saved);
+        saved.layout = Layout::single(1);
+
+        let session = Session::capture(&saved).await;
+        let json = serde_json::to_string(&session).unwrap();
+        let session: Session = serde_json::from_str(&json).unwrap();
+
+        let mut restored = state();
+        push_empty(&mut restored);
+        session.restore(&mut restored).await;
+
+        let buffers = &restored.buffers;
+        assert_eq!(buffers.len(), 3);
+        let file_buffer = buffers[0].lock().await;
+        assert_eq!(file_buffer.path(), Some(file.as_path()));
+        assert_eq!(file_buffer.cursor_position(), Some((1, 2)));
+        let explorer = buffers[1].lock().await;
+        assert_eq!(explorer.path(), Some(dir.as_path()));
+        assert!(explorer.text().is_none());
+        assert!(buffers[2].lock().await.path().is_none());
+        assert_eq!(restored.layout.buffer_indices(), [1]);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::fonts::{FontCollection, FontId, FontStyle};
+use ab_glyph::{Font, GlyphId};
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+use std::ops::Range;
+use unicode_bidi::ParagraphBidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Glyph of a shaped line. Horizontal metrics are in units of the face's height, so
+/// multiplying by the `PxScale` of the text gives pixels, the same way ab_glyph scales.
+#[derive(Clone, Debug)]
+pub struct ShapedGlyph {
+    pub font_id: FontId,
+    pub glyph_id: GlyphId,
+    /// Bytes of the line this glyph was shaped from. A ligature covers several characters.
+    pub cluster: Range<usize>,
+    pub rtl: bool,
+    /// Pen position, from the start of the line
+    pub x: f32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+}
+
+/// Glyphs of a single line, in visual order.
+#[derive(Clone, Debug, Default)]
+pub struct ShapedLine {
+    pub glyphs: Vec<ShapedGlyph>,
+    pub width: f32,
+}
+
+/// Shapes one line of text, which must not contain line breaks. Text is split into
+/// bidi runs in visual order, then into runs of the same face of the fallback chain.
+pub fn shape_line(fonts: &FontCollection, line: &str, style: FontStyle) -> ShapedLine {
+    let mut shaped = ShapedLine::default();
+
+    if line.is_empty() {
+        return shaped;
+    }
+
+    let bidi = ParagraphBidiInfo::new(line, None);
+    let (levels, runs) = bidi.visual_runs(0..line.len());
+
+    for run in runs {
+        let rtl = levels[run.start].is_rtl();
+        let mut font_runs: Vec<_> = font_runs(fonts, &line[run.clone()], style)
+            .map(|(range, font_id)| (range.start + run.start..range.end + run.start, font_id))
+            .collect();
+
+        // Within an RTL run the logically first font run is visually last
+        if rtl {
+            font_runs.reverse();
+        }
+
+        for (range, font_id) in font_runs {
+            shape_run(fonts, line, range, font_id, rtl, &mut shaped);
+        }
+    }
+
+    shaped
+}
+
+fn shape_run(
+    fonts: &FontCollection,
+    line: &str,
+    range: Range<usize>,
+    font_id: FontId,
+    rtl: bool,
+    shaped: &mut ShapedLine,
+) {
+    let font = fonts.font(font_id);
+    let Some(face) = Face::from_slice(font.font_data(), fonts.face_index(font_id)) else {
+        return;
+    };
+    let units = font.height_unscaled();
+
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(&line[range.clone()]);
+    buffer.set_direction(if rtl {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    });
+
+    let output = rustybuzz::shape(&face, &[], buffer);
+
+    // Clusters are byte offsets into the run. A cluster ends where the next larger one starts.
+    let mut starts: Vec<usize> = output
+        .glyph_infos()
+        .iter()
+        .map(|info| info.cluster as usize)
+        .collect();
+    starts.sort_unstable();
+    starts.dedup();
+    let cluster_end = |start: usize| {
+        starts
+            .iter()
+            .find(|&&next| next > start)
+            .copied()
+            .unwrap_or(range.len())
+    };
+
+    for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        let start = info.cluster as usize;
+        let advance = position.x_advance as f32 / units;
+
+        shaped.glyphs.push(ShapedGlyph {
+            font_id,
+            glyph_id: GlyphId(info.glyph_id as u16),
+            cluster: range.start + start..range.start + cluster_end(start),
+            rtl,
+            x: shaped.width,
+            x_offset: position.x_offset as f32 / units,
+            y_offset: position.y_offset as f32 / units,
+        });
+
+        shaped.width += advance;
+    }
+}
+
+/// Splits `text` into runs drawn with the same face of the fallback chain.
+pub fn font_runs<'a>(
+    fonts: &'a FontCollection,
+    text: &'a str,
+    style: FontStyle,
+) -> impl Iterator<Item = (Range<usize>, FontId)> + 'a {
+    let mut start = 0;
+
+    std::iter::from_fn(move || {
+        let rest = &text[start..];
+        let mut graphemes = rest
+            .grapheme_indices(true)
+            .map(|(i, grapheme)| (i, grapheme.chars().next().expect("graphemes aren't empty")));
+        let (_, first) = graphemes.next()?;
+        let font_id = fonts.resolve(first, style);
+
+        // Whitespace and combining marks never switch faces
+        let end = graphemes
+            .find(|&(_, c)| !c.is_whitespace() && fonts.resolve(c, style) != font_id)
+            .map_or(rest.len(), |(i, _)| i);
+
+        let range = start..start + end;
+        start += end;
+        Some((range, font_id))
+    })
+}
@@ -0,0 +1,189 @@
+use crate::app_state::AppState;
+use crate::buffer::output::{Log, OutputView};
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{BoxFuture, Command, CommandArg, CommandRegistry, CommandResult};
+use crate::scripting::{self, Hook, ScriptContext, ScriptHost};
+use crate::session::Session;
+use crate::swap;
+use anyhow::bail;
+use std::sync::{Arc, Mutex};
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "app.quit",
+        "Quit, asking first what to do with unsaved changes",
+        quit,
+    ));
+    registry.register(Command::new(
+        "app.quit_saving",
+        "Save every file with unsaved changes, then quit",
+        quit_saving,
+    ));
+    registry.register(Command::new(
+        "app.quit_discarding",
+        "Quit, dropping unsaved changes",
+        quit_discarding,
+    ));
+    registry.register(Command::new(
+        "app.cancel_quit",
+        "Close the question about unsaved changes and keep working",
+        cancel_quit,
+    ));
+}
+
+fn quit(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let unsaved = unsaved(state).await;
+        if unsaved.is_empty() {
+            state.exiting = true;
+            return Ok(EventHandlerOutcome::None);
+        }
+
+        let log = match &state.quit_dialog {
+            Some((_, log)) => log.clone(),
+            None => {
+                let log = Arc::new(Mutex::new(Log::new(String::new())));
+                let view = OutputView::new(state.font.clone(), log.clone(), state.state_tx.clone());
+                state.buffers.push(Arc::new(tokio::sync::Mutex::new(view)));
+                state.quit_dialog = Some((state.buffers.len() - 1, log.clone()));
+                log
+            }
+        };
+
+        {
+            let mut log = log.lock().unwrap();
+            log.clear("Quit with unsaved changes?".to_string());
+            log.status = Some("s: save all, d: discard, c: cancel".to_string());
+            for (_, name) in &unsaved {
+                log.push(name, None);
+            }
+        }
+
+        // The question goes below the active frame, and takes the keys
+        let index = state.quit_dialog.as_ref().expect("created above").0;
+        state.show_below(index);
+        state.show_buffer(index);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn quit_saving(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let mut untitled = 0;
+
+        for (index, _) in unsaved(state).await {
+            if state.buffers[index].lock().await.path().is_none() {
+                untitled += 1;
+                continue;
+            }
+
+            let path = state.save_buffer(index, None).await?;
+
+            let context = ScriptContext::capture(state).await;
+            let actions = state.scripts.fire(Hook::Save(path), context).await;
+            scripting::apply_actions(state, actions);
+        }
+
+        // Buffers without a file need one from `file.save` first
+        if untitled > 0 {
+            bail!("{} untitled buffers have no file to save to", untitled);
+        }
+
+        state.exiting = true;
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn quit_discarding(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        // Dropped changes aren't kept in swap files for recovery either
+        for (index, _) in unsaved(state).await {
+            state.buffers[index].lock().await.mark_saved();
+        }
+
+        state.exiting = true;
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+fn cancel_quit(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        close_dialog(state);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+/// Whether the question about unsaved changes is focused, so it takes the keys answering
+/// it.
+pub fn is_asking(state: &AppState) -> bool {
+    matches!(&state.quit_dialog, Some((index, _)) if *index == state.active_buffer)
+}
+
+/// Buffers with unsaved changes, and how the question names them.
+async fn unsaved(state: &AppState) -> Vec<(usize, String)> {
+    let mut unsaved = Vec::new();
+
+    for (index, buffer) in state.buffers.iter().enumerate() {
+        let buffer = buffer.lock().await;
+        if !buffer.is_dirty() {
+            continue;
+        }
+
+        let name = match buffer.path() {
+            Some(path) => path.display().to_string(),
+            None => "Untitled buffer, which can't be saved without a file".to_string(),
+        };
+        unsaved.push((index, name));
+    }
+
+    unsaved
+}
+
+fn close_dialog(state: &mut AppState) {
+    let Some((index, _)) = &state.quit_dialog else {
+        return;
+    };
+    let index = *index;
+
+    if state.active_buffer == index {
+        state.focus_other_frame();
+    }
+    state.layout.close_frames_showing(index);
+}
+
+/// Stops everything running in the background, keeping what's needed to start again
+/// where the user left off. Unsaved changes left at this point, when the window is gone,
+/// stay in their swap files to be recovered.
+pub async fn shutdown(state: &mut AppState) {
+    close_dialog(state);
+
+    // The swap writer could otherwise bring back the files removed below
+    if let Some(writer) = state.swap_writer.take() {
+        writer.abort();
+        let _ = writer.await;
+    }
+    for buffer in &state.buffers {
+        let buffer = buffer.lock().await;
+        let (Some(path), false) = (buffer.path(), buffer.is_dirty()) else {
+            continue;
+        };
+
+        if let Err(err) = swap::remove(path).await {
+            tracing::warn!("{:#}", err);
+        }
+    }
+
+    let session = Session::capture(state).await;
+    if let Err(err) = session.save().await {
+        tracing::warn!("Failed to save session: {:#}", err);
+    }
+
+    state.tasks.stop_all().await;
+    for buffer in &state.buffers {
+        buffer.lock().await.shutdown();
+    }
+    state.watcher = None;
+    state.scripts = ScriptHost::default();
+}
@@ -1,33 +1,241 @@
 use crate::app_state::SharedState;
-use crate::buffer::EventHandlerOutcome;
-use crate::KamiEvent;
+use crate::buffer::{BoundingBox, EventHandlerOutcome};
+use crate::command::{CommandArg, CommandResult, Invocation};
+use crate::editor::Frontend;
+use crate::input::{Key, Modifiers};
+use crate::keymap::{special_key_name, KeyBinding};
+use crate::scripting::{self, ScriptAction};
+use crate::shutdown;
 use tokio::sync::mpsc::Receiver;
-use winit::event::ModifiersState;
-use winit::event_loop::EventLoopProxy;
+
+const BACKSPACE_CHAR: char = '\u{08}';
+const DELETE_CHAR: char = '\u{7f}';
+const RETURN_CHAR: char = '\r';
+const ESCAPE_CHAR: char = '\u{1b}';
 
 #[derive(Debug)]
 pub enum StateEvent {
-    ModifiersChange(ModifiersState),
+    ModifiersChange(Modifiers),
     CharInput(char),
+    KeyPress(Key),
+    /// Left click at `position`, in pixels of the window covered by `window`
+    Click {
+        position: (f32, f32),
+        window: BoundingBox,
+    },
+    /// Commands issued by background tasks rather than the user. These are not recorded
+    /// into macros.
+    Command(Invocation),
+    /// Background tasks changed what's on screen
+    Redraw,
+    /// Actions plugins requested from a hook that wasn't waited for
+    Script(Vec<ScriptAction>),
+    /// The user asked to quit, e.g. by closing the window
+    Quit,
+    /// Exit without asking, leaving unsaved changes in their swap files
+    Exit,
 }
 
 pub async fn state_loop(
-    proxy: EventLoopProxy<KamiEvent>,
+    frontend: impl Frontend,
     mut state_rx: Receiver<StateEvent>,
     app_state: SharedState,
 ) {
+    let mut modifiers = Modifiers::empty();
+
     while let Some(event) = state_rx.recv().await {
-        match event {
-            StateEvent::ModifiersChange(_ms) => {
-                // TODO: handle keyboard control
+        let (invocation, record) = match event {
+            StateEvent::ModifiersChange(ms) => {
+                modifiers = ms;
+                continue;
             }
             // All input, translated to unicode, including backspace and delete comes here
-            StateEvent::CharInput(c) => match app_state.read().await.handle_character(c).await {
-                EventHandlerOutcome::Redraw => {
-                    proxy.send_event(KamiEvent::RequestRedraw).unwrap();
+            StateEvent::CharInput(c) => {
+                if modifiers.logo() {
+                    continue;
+                }
+
+                // Terminals take what's typed as is, control characters included
+                if takes_raw_input(&app_state).await {
+                    let text = match c {
+                        '\u{1}'..='\u{1a}' if modifiers.ctrl() && c != '\t' => c.to_string(),
+                        _ if modifiers.ctrl() => continue,
+                        BACKSPACE_CHAR => "\x7f".to_string(),
+                        DELETE_CHAR => "\x1b[3~".to_string(),
+                        '\t' if modifiers.shift() => continue,
+                        // Alt sends an escape before the character
+                        c if modifiers.alt() => format!("\x1b{}", c),
+                        c => c.to_string(),
+                    };
+
+                    let invocation =
+                        Invocation::new("buffer.insert").with_arg(CommandArg::Text(text));
+                    dispatch(&frontend, &app_state, &invocation, true).await;
+                    continue;
+                }
+
+                // Chords are handled through the keymap
+                if modifiers.ctrl() {
+                    continue;
                 }
-                EventHandlerOutcome::None => {}
-            },
+
+                // Quitting with unsaved changes takes an answer first
+                if shutdown::is_asking(&*app_state.read().await) {
+                    let invocation = match c.to_ascii_lowercase() {
+                        's' => Invocation::new("app.quit_saving"),
+                        'd' => Invocation::new("app.quit_discarding"),
+                        'c' => Invocation::new("app.cancel_quit"),
+                        _ => continue,
+                    };
+
+                    (invocation, false)
+                } else if has_conflict(&app_state).await {
+                    // A file changed on disk under unsaved changes takes an answer first
+                    let invocation = match c.to_ascii_lowercase() {
+                        'y' => Invocation::new("file.reload"),
+                        'n' => Invocation::new("file.keep_changes"),
+                        'd' => Invocation::new("file.diff_on_disk"),
+                        _ => continue,
+                    };
+
+                    (invocation, false)
+                } else {
+                    let invocation = match c {
+                        BACKSPACE_CHAR => Invocation::new("buffer.delete_backward"),
+                        DELETE_CHAR => Invocation::new("buffer.delete_forward"),
+                        RETURN_CHAR => Invocation::new("buffer.newline"),
+                        // Shift+Tab outdents through the keymap
+                        '\t' if modifiers.shift() => continue,
+                        // Escape works through the keymap
+                        ESCAPE_CHAR => continue,
+                        c => Invocation::new("buffer.insert_char").with_arg(CommandArg::Char(c)),
+                    };
+
+                    (invocation, true)
+                }
+            }
+            StateEvent::KeyPress(key) => {
+                if takes_raw_input(&app_state).await {
+                    if let (true, Some(name)) = (modifiers.is_empty(), special_key_name(key)) {
+                        let invocation = Invocation::new("terminal.key")
+                            .with_arg(CommandArg::Text(name.to_string()));
+                        dispatch(&frontend, &app_state, &invocation, true).await;
+                        continue;
+                    }
+
+                    // Control or Alt with a letter types a character for the terminal
+                    let letter = matches!(key, Key::Char(c) if c.is_ascii_lowercase());
+                    if letter && (modifiers == Modifiers::CTRL || modifiers == Modifiers::ALT) {
+                        continue;
+                    }
+                }
+
+                let binding = KeyBinding::new(modifiers, key);
+
+                // Escape answers the question about unsaved changes by staying
+                if key == Key::Escape && shutdown::is_asking(&*app_state.read().await) {
+                    (Invocation::new("app.cancel_quit"), false)
+                } else {
+                    match app_state.read().await.keymap.lookup(&binding) {
+                        Some(invocation) => (invocation.clone(), true),
+                        None => continue,
+                    }
+                }
+            }
+            StateEvent::Click { position, window } => {
+                let outcome = app_state.write().await.click(position, window).await;
+                if let EventHandlerOutcome::Redraw = outcome {
+                    frontend.request_redraw();
+                }
+                continue;
+            }
+            StateEvent::Command(invocation) => (invocation, false),
+            StateEvent::Redraw => {
+                frontend.request_redraw();
+                continue;
+            }
+            StateEvent::Script(actions) => {
+                let mut state = app_state.write().await;
+                scripting::apply_actions(&mut state, actions);
+
+                match state.pending.pop_front() {
+                    Some(invocation) => (invocation, false),
+                    None => continue,
+                }
+            }
+            StateEvent::Quit => (Invocation::new("app.quit"), false),
+            StateEvent::Exit => {
+                exit(&frontend, &app_state).await;
+                break;
+            }
+        };
+
+        dispatch(&frontend, &app_state, &invocation, record).await;
+
+        // Commands such as macro playback queue follow-up invocations
+        loop {
+            let Some(invocation) = app_state.write().await.pending.pop_front() else {
+                break;
+            };
+
+            dispatch(&frontend, &app_state, &invocation, false).await;
+        }
+
+        if app_state.read().await.exiting {
+            exit(&frontend, &app_state).await;
+            break;
         }
     }
 }
+
+/// Shuts down what runs in the background, then lets the frontend go.
+async fn exit(frontend: &impl Frontend, app_state: &SharedState) {
+    shutdown::shutdown(&mut *app_state.write().await).await;
+
+    frontend.exit();
+}
+
+async fn has_conflict(app_state: &SharedState) -> bool {
+    let state = app_state.read().await;
+    let conflict = state.active_buffer().lock().await.conflict().is_some();
+
+    conflict
+}
+
+async fn takes_raw_input(app_state: &SharedState) -> bool {
+    let state = app_state.read().await;
+    let raw = state.active_buffer().lock().await.takes_raw_input();
+
+    raw
+}
+
+async fn dispatch(
+    frontend: &impl Frontend,
+    app_state: &SharedState,
+    invocation: &Invocation,
+    record: bool,
+) {
+    match execute(app_state, invocation, record).await {
+        Ok(EventHandlerOutcome::Redraw) => frontend.request_redraw(),
+        Ok(EventHandlerOutcome::None) => {}
+        Err(err) => tracing::warn!("Command `{}` failed: {:#}", invocation.id, err),
+    }
+}
+
+/// Runs `invocation`, recording it into the macro being recorded if `record` and it
+/// succeeds.
+pub async fn execute(
+    app_state: &SharedState,
+    invocation: &Invocation,
+    record: bool,
+) -> CommandResult {
+    let mut state = app_state.write().await;
+    let commands = state.commands.clone();
+    let outcome = commands.execute(&mut state, invocation).await;
+
+    if record && outcome.is_ok() {
+        state.macros.record(invocation);
+    }
+
+    outcome
+}
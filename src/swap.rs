@@ -0,0 +1,196 @@
+use crate::app_state::SharedState;
+use crate::paths;
+use crate::watcher;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const SWAP_DIR: &str = "swap";
+
+/// How often unsaved changes are written aside
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Unsaved text of a file, kept aside so it survives a crash.
+#[derive(Serialize, Deserialize)]
+struct SwapFile {
+    /// Process that wrote it, so swap files of instances still running are left alone
+    pid: u32,
+    path: PathBuf,
+    text: String,
+}
+
+/// Starts a task writing the text of files with unsaved changes to swap files, and
+/// removing them once the changes are saved or dropped.
+pub fn start(state: SharedState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Hash of the text last written for each file
+        let mut written = HashMap::new();
+        let mut interval = tokio::time::interval(SWAP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            for (path, change) in changes(&state, &written).await {
+                let result = match change {
+                    Some((hash, text)) => write(&path, text).await.map(|_| {
+                        written.insert(path.clone(), hash);
+                    }),
+                    None => {
+                        written.remove(&path);
+                        remove(&path).await
+                    }
+                };
+
+                if let Err(err) = result {
+                    tracing::warn!("{:#}", err);
+                }
+            }
+        }
+    })
+}
+
+/// Files whose swap file is out of date: with their new text and its hash, or `None` for
+/// swap files no longer needed.
+async fn changes(
+    state: &SharedState,
+    written: &HashMap<PathBuf, u64>,
+) -> Vec<(PathBuf, Option<(u64, String)>)> {
+    let state = state.read().await;
+    let mut changes = Vec::new();
+    let mut unsaved = Vec::new();
+
+    for buffer in &state.buffers {
+        let buffer = buffer.lock().await;
+        let (Some(path), Some(text), true) = (buffer.path(), buffer.text(), buffer.is_dirty())
+        else {
+            continue;
+        };
+        unsaved.push(path.to_path_buf());
+
+        let hash = watcher::hash(text);
+        if written.get(path) != Some(&hash) {
+            changes.push((path.to_path_buf(), Some((hash, text.to_string()))));
+        }
+    }
+
+    for path in written.keys() {
+        if !unsaved.contains(path) {
+            changes.push((path.clone(), None));
+        }
+    }
+
+    changes
+}
+
+/// Takes the text of `path` out of a swap file left by an instance that's no longer
+/// running, such as one that crashed.
+pub async fn take_leftover(path: &Path) -> Option<String> {
+    let swap = read(&swap_path(path).ok()?).await?;
+    if is_running(swap.pid) || swap.path != path {
+        return None;
+    }
+
+    if let Err(err) = remove(path).await {
+        tracing::warn!("{:#}", err);
+    }
+
+    Some(swap.text)
+}
+
+/// Files under `dir` with changes left in swap files by instances no longer running.
+pub async fn leftovers(dir: &Path) -> Vec<PathBuf> {
+    let mut leftovers = Vec::new();
+    let Ok(swap_dir) = swap_dir() else {
+        return leftovers;
+    };
+    let Ok(mut entries) = tokio::fs::read_dir(swap_dir).await else {
+        return leftovers;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let swap_path = entry.path();
+        if swap_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            != Some("swp")
+        {
+            continue;
+        }
+        let Some(swap) = read(&swap_path).await else {
+            continue;
+        };
+
+        if swap.path.starts_with(dir) && !is_running(swap.pid) {
+            leftovers.push(swap.path);
+        }
+    }
+
+    leftovers
+}
+
+async fn read(swap_path: &Path) -> Option<SwapFile> {
+    let bytes = tokio::fs::read(swap_path).await.ok()?;
+
+    match serde_json::from_slice(&bytes) {
+        Ok(swap) => Some(swap),
+        Err(err) => {
+            tracing::warn!("Malformed swap file {}: {}", swap_path.display(), err);
+            None
+        }
+    }
+}
+
+/// Writes the swap file of `path` through a temporary file, so a crash while writing
+/// leaves the previous one whole.
+async fn write(path: &Path, text: String) -> anyhow::Result<()> {
+    let swap_path = swap_path(path)?;
+    let swap = SwapFile {
+        pid: std::process::id(),
+        path: path.to_path_buf(),
+        text,
+    };
+
+    if let Some(parent) = swap_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let temporary = swap_path.with_extension("tmp");
+    tokio::fs::write(&temporary, serde_json::to_vec(&swap)?)
+        .await
+        .with_context(|| format!("Can't write {}", temporary.display()))?;
+    tokio::fs::rename(&temporary, &swap_path)
+        .await
+        .with_context(|| format!("Can't write {}", swap_path.display()))
+}
+
+/// Removes the swap file of `path`, if there's one.
+pub async fn remove(path: &Path) -> anyhow::Result<()> {
+    let swap_path = swap_path(path)?;
+
+    match tokio::fs::remove_file(&swap_path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Can't remove {}", swap_path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists
+    let found = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+
+    found || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn swap_dir() -> anyhow::Result<PathBuf> {
+    Ok(paths::data_dir()
+        .context("No data directory on this platform")?
+        .join(SWAP_DIR))
+}
+
+fn swap_path(path: &Path) -> anyhow::Result<PathBuf> {
+    Ok(swap_dir()?.join(format!("{}.swp", paths::flattened(path))))
+}
@@ -0,0 +1,234 @@
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    String,
+    Comment,
+}
+
+/// Strings and comments of a text, where brackets and quotes aren't code.
+///
+/// This is a lexical scan of C-like syntax: `//` and nestable `/* */` comments,
+/// double-quoted strings with escapes, Rust's raw strings and char literals. It
+/// stands in until buffers get real syntax trees, which can fill the same spans.
+#[derive(Clone, Debug, Default)]
+pub struct SyntaxSpans {
+    /// Sorted and disjoint
+    spans: Vec<Span>,
+}
+
+#[derive(Clone, Debug)]
+struct Span {
+    range: Range<usize>,
+    kind: SpanKind,
+    /// Whether the span ends with a delimiter. A caret at the end of a line comment or
+    /// of an unterminated string is still inside it.
+    closed: bool,
+}
+
+impl SyntaxSpans {
+    pub fn scan(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let mut spans = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let start = i;
+            let (kind, (end, closed)) = match (bytes[i], bytes.get(i + 1)) {
+                (b'/', Some(b'/')) => {
+                    let end = text[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                    (SpanKind::Comment, (end, false))
+                }
+                (b'/', Some(b'*')) => (SpanKind::Comment, block_comment_end(bytes, i)),
+                (b'"', _) => (SpanKind::String, string_end(bytes, i + 1)),
+                // Lifetimes and labels start with a quote too, but don't end with one
+                (b'\'', _) => match char_end(text, i + 1) {
+                    Some(end) => (SpanKind::String, (end, true)),
+                    None => {
+                        i += 1;
+                        continue;
+                    }
+                },
+                (b'r', Some(b'"' | b'#')) if !is_ident_byte(bytes, i.wrapping_sub(1)) => {
+                    match raw_string_end(text, i + 1) {
+                        Some(end) => (SpanKind::String, end),
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            i = end;
+            spans.push(Span {
+                range: start..end,
+                kind,
+                closed,
+            });
+        }
+
+        Self { spans }
+    }
+
+    /// Kind of the string or comment containing the byte at `offset`, delimiters included.
+    pub fn kind_at(&self, offset: usize) -> Option<SpanKind> {
+        self.span_at(offset).map(|(_, kind)| kind)
+    }
+
+    /// Range and kind of the string or comment containing the byte at `offset`.
+    pub fn span_at(&self, offset: usize) -> Option<(Range<usize>, SpanKind)> {
+        let index = self.spans.partition_point(|span| span.range.end <= offset);
+
+        self.spans
+            .get(index)
+            .filter(|span| span.range.contains(&offset))
+            .map(|span| (span.range.clone(), span.kind))
+    }
+
+    /// Kind of the string or comment a caret at `offset` is typing into. A caret before
+    /// an opening delimiter or after a closing one is in code.
+    pub fn kind_at_caret(&self, offset: usize) -> Option<SpanKind> {
+        let index = self.spans.partition_point(|span| span.range.end < offset);
+
+        self.spans
+            .get(index)
+            .filter(|span| {
+                span.range.start < offset
+                    && (offset < span.range.end || offset == span.range.end && !span.closed)
+            })
+            .map(|span| span.kind)
+    }
+}
+
+fn is_ident_byte(bytes: &[u8], index: usize) -> bool {
+    bytes
+        .get(index)
+        .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+}
+
+/// End of the block comment starting at `start`, and whether it was closed.
+fn block_comment_end(bytes: &[u8], start: usize) -> (usize, bool) {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return (i, true);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    (bytes.len(), false)
+}
+
+/// End of a string whose contents start at `i`, past the closing quote.
+fn string_end(bytes: &[u8], mut i: usize) -> (usize, bool) {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return (i + 1, true),
+            _ => i += 1,
+        }
+    }
+
+    (bytes.len(), false)
+}
+
+/// End of a char literal whose contents start at `i`, past the closing quote. `None`
+/// when there's no such literal, as after the quote of a lifetime.
+fn char_end(text: &str, i: usize) -> Option<usize> {
+    let rest = &text[i..];
+    let contents = match rest.strip_prefix('\\') {
+        // Escapes are short: `\n`, `\x7f` or `\u{10FFFF}` at most
+        Some(escape) => escape
+            .char_indices()
+            .skip(1)
+            .take(9)
+            .take_while(|&(_, c)| c != '\n')
+            .find(|&(_, c)| c == '\'')
+            .map(|(end, _)| end + 1)?,
+        None => rest
+            .chars()
+            .next()
+            .filter(|&c| c != '\'' && c != '\n')?
+            .len_utf8(),
+    };
+
+    rest[contents..]
+        .starts_with('\'')
+        .then_some(i + contents + 1)
+}
+
+/// End of a raw string `r#"..."#` whose hashes start at `i`.
+fn raw_string_end(text: &str, i: usize) -> Option<(usize, bool)> {
+    let hashes = text[i..].bytes().take_while(|&b| b == b'#').count();
+    let open = i + hashes;
+
+    if text.as_bytes().get(open) != Some(&b'"') {
+        return None;
+    }
+
+    let close = format!("\"{}", "#".repeat(hashes));
+    Some(
+        text[open + 1..]
+            .find(&close)
+            .map_or((text.len(), false), |end| {
+                (open + 1 + end + close.len(), true)
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(text: &str) -> Vec<&str> {
+        SyntaxSpans::scan(text)
+            .spans
+            .iter()
+            .map(|span| &text[span.range.clone()])
+            .collect()
+    }
+
+    #[test]
+    fn scans_char_literals() {
+        assert_eq!(spans("['(', '\"', 'é']"), ["'('", "'\"'", "'é'"]);
+        assert_eq!(
+            spans(r"['\'', '\\', '\x7f', '\u{10FFFF}', b'\n']"),
+            [r"'\''", r"'\\'", r"'\x7f'", r"'\u{10FFFF}'", r"'\n'"]
+        );
+        assert_eq!(SyntaxSpans::scan("'{'").kind_at(1), Some(SpanKind::String));
+    }
+
+    #[test]
+    fn leaves_lifetimes_alone() {
+        assert!(spans("fn f<'a, 'b>(x: &'a str) -> &'b str {}").is_empty());
+        assert!(spans("'outer: loop { break 'outer; }").is_empty());
+        assert_eq!(spans("impl<'a> X<'a> { \"s\" }"), ["\"s\""]);
+        assert_eq!(spans("fn f<'a>() -> char { 'a' }"), ["'a'"]);
+    }
+
+    #[test]
+    fn scans_strings_and_comments() {
+        assert_eq!(
+            spans("a /* b /* c */ */ \"d\\\"\" r#\"e\"# // f\ng"),
+            ["/* b /* c */ */", "\"d\\\"\"", "r#\"e\"#", "// f"]
+        );
+        assert_eq!(spans("\"open"), ["\"open"]);
+    }
+}
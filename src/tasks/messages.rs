@@ -0,0 +1,269 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Notes and help attached to nothing else
+    Note,
+}
+
+/// Compiler message about a span of a file.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub path: PathBuf,
+    /// Line and column the span starts at, from 0. Columns count characters.
+    pub start: (usize, usize),
+    /// Line and column right after the span
+    pub end: (usize, usize),
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What a line of task output says.
+#[derive(Debug)]
+pub enum Message {
+    /// Text shown for a compiler message, several lines for cargo's, and the problem it
+    /// points at if it points at a file
+    Problem {
+        problem: Option<Problem>,
+        text: String,
+    },
+    /// Line shown as it is
+    Text(String),
+    /// Cargo bookkeeping such as built artifacts, which isn't shown
+    Hidden,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum CargoMessage {
+    CompilerMessage {
+        message: Diagnostic,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Diagnostic of `cargo --message-format=json`.
+#[derive(Deserialize)]
+struct Diagnostic {
+    message: String,
+    level: String,
+    spans: Vec<Span>,
+    /// What the compiler prints for the diagnostic without JSON output
+    rendered: Option<String>,
+}
+
+/// Lines and columns of spans count from 1.
+#[derive(Deserialize)]
+struct Span {
+    file_name: PathBuf,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+impl Problem {
+    /// One line description, such as `src/main.rs:3:5: error: expected ;`, with the path
+    /// relative to `cwd`.
+    pub fn describe(&self, cwd: &Path) -> String {
+        format!(
+            "{}:{}:{}: {}: {}",
+            self.path.strip_prefix(cwd).unwrap_or(&self.path).display(),
+            self.start.0 + 1,
+            self.start.1 + 1,
+            self.severity.name(),
+            self.message
+        )
+    }
+}
+
+/// Reads a line printed by a task running in `cwd`. Cargo's JSON messages are read as
+/// such, and other lines that start with `path:line:column:` as problems found by other
+/// tools.
+pub fn parse(line: &str, cwd: &Path) -> Message {
+    if line.starts_with('{') {
+        if let Ok(message) = serde_json::from_str::<CargoMessage>(line) {
+            return match message {
+                CargoMessage::CompilerMessage { message } => compiler_message(message, cwd),
+                CargoMessage::Other => Message::Hidden,
+            };
+        }
+    }
+
+    match located(line, cwd) {
+        Some(problem) => Message::Problem {
+            problem: Some(problem),
+            text: line.to_string(),
+        },
+        None => Message::Text(line.to_string()),
+    }
+}
+
+fn compiler_message(diagnostic: Diagnostic, cwd: &Path) -> Message {
+    let severity = match diagnostic.level.as_str() {
+        level if level.starts_with("error") => Severity::Error,
+        "warning" => Severity::Warning,
+        _ => Severity::Note,
+    };
+    let problem = diagnostic
+        .spans
+        .iter()
+        .find(|span| span.is_primary)
+        .map(|span| Problem {
+            path: cwd.join(&span.file_name),
+            start: (
+                span.line_start.saturating_sub(1),
+                span.column_start.saturating_sub(1),
+            ),
+            end: (
+                span.line_end.saturating_sub(1),
+                span.column_end.saturating_sub(1),
+            ),
+            severity,
+            message: diagnostic.message.clone(),
+        });
+    let text = diagnostic
+        .rendered
+        .unwrap_or_else(|| format!("{}: {}", diagnostic.level, diagnostic.message));
+
+    Message::Problem {
+        problem,
+        text: text.trim_end().to_string(),
+    }
+}
+
+/// Problem of a line such as `src/app.c:12:5: warning: unused variable`, if it names a
+/// file that exists.
+fn located(line: &str, cwd: &Path) -> Option<Problem> {
+    let mut parts = line.splitn(4, ':');
+    let (path, line, column, message) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let line = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let column = column.trim().parse::<usize>().ok()?.saturating_sub(1);
+
+    let path = cwd.join(path.trim());
+    if !path.is_file() {
+        return None;
+    }
+
+    let message = message.trim();
+    let (severity, message) = match message.split_once(':') {
+        Some(("warning", rest)) => (Severity::Warning, rest.trim()),
+        Some(("note" | "info" | "help", rest)) => (Severity::Note, rest.trim()),
+        Some(("error", rest)) => (Severity::Error, rest.trim()),
+        _ => (Severity::Error, message),
+    };
+
+    Some(Problem {
+        path,
+        start: (line, column),
+        end: (line, column + 1),
+        severity,
+        message: message.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+
+    const WARNING: &str = r#"{"reason":"compiler-message","package_id":"path+file:///home/ann/app#0.1.0","manifest_path":"/home/ann/app/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"app","src_path":"/home/ann/app/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"warning: unused variable: `x`\n --> src/main.rs:2:9\n  |\n2 |     let x = 1;\n  |         ^ help: if this is intentional, prefix it with an underscore: `_x`\n\n","$message_type":"diagnostic","children":[],"code":{"code":"unused_variables","explanation":null},"level":"warning","message":"unused variable: `x`","spans":[{"byte_end":26,"byte_start":25,"column_end":10,"column_start":9,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":null,"line_end":2,"line_start":2,"suggested_replacement":null,"suggestion_applicability":null,"text":[{"highlight_end":10,"highlight_start":9,"text":"    let x = 1;"}]}]}}"#;
+
+    fn problem_of(message: Message) -> (Option<Problem>, String) {
+        match message {
+            Message::Problem { problem, text } => (problem, text),
+            other => panic!("expected a problem, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_cargo_messages() {
+        let cwd = Path::new("/home/ann/app");
+        let (problem, text) = problem_of(parse(WARNING, cwd));
+        let problem = problem.unwrap();
+
+        assert_eq!(problem.path, cwd.join("src/main.rs"));
+        assert_eq!((problem.start, problem.end), ((1, 8), (1, 9)));
+        assert_eq!(problem.severity, Severity::Warning);
+        assert_eq!(
+            problem.describe(cwd),
+            "src/main.rs:2:9: warning: unused variable: `x`"
+        );
+        assert!(text.starts_with("warning: unused variable: `x`\n --> src/main.rs:2:9"));
+        assert!(!text.ends_with('\n'));
+
+        let artifact = r#"{"reason":"compiler-artifact","package_id":"app 0.1.0","fresh":true}"#;
+        assert!(matches!(parse(artifact, cwd), Message::Hidden));
+    }
+
+    #[test]
+    fn reads_cargo_messages_without_a_primary_span() {
+        let line = r#"{"reason":"compiler-message","message":{"message":"aborting due to 2 previous errors","level":"error","spans":[],"rendered":null}}"#;
+        let (problem, text) = problem_of(parse(line, Path::new("/")));
+
+        assert!(problem.is_none());
+        assert_eq!(text, "error: aborting due to 2 previous errors");
+
+        // Spans counting from 0 don't point before the file
+        let line = r#"{"reason":"compiler-message","message":{"message":"ice","level":"error: internal compiler error","spans":[{"file_name":"lib.rs","line_start":0,"line_end":0,"column_start":0,"column_end":0,"is_primary":true}]}}"#;
+        let problem = problem_of(parse(line, Path::new("/"))).0.unwrap();
+        assert_eq!((problem.start, problem.end), ((0, 0), (0, 0)));
+        assert_eq!(problem.severity, Severity::Error);
+    }
+
+    #[test]
+    fn reads_lines_of_other_compilers() {
+        let cwd = paths::scratch_dir("messages");
+        std::fs::create_dir(cwd.join("src")).unwrap();
+        std::fs::write(cwd.join("src/app.c"), "int main() {}\n").unwrap();
+
+        let line = "src/app.c:12:5: warning: unused variable 'x' [-Wunused-variable]";
+        let (problem, text) = problem_of(parse(line, &cwd));
+        let problem = problem.unwrap();
+
+        assert_eq!(text, line);
+        assert_eq!(problem.path, cwd.join("src/app.c"));
+        assert_eq!((problem.start, problem.end), ((11, 4), (11, 5)));
+        assert_eq!(problem.severity, Severity::Warning);
+        assert_eq!(problem.message, "unused variable 'x' [-Wunused-variable]");
+
+        let line = "src/app.c:3:1: expected ';' before '}' token";
+        let problem = problem_of(parse(line, &cwd)).0.unwrap();
+        assert_eq!(problem.severity, Severity::Error);
+        assert_eq!(problem.message, "expected ';' before '}' token");
+    }
+
+    #[test]
+    fn shows_other_lines_as_they_are() {
+        let cwd = paths::scratch_dir("messages-other");
+
+        for line in [
+            r#"{"reason":"compiler-message","message":"#,
+            "src/missing.c:1:1: error: no such file",
+            "src/app.c:0:1: error: lines count from 1",
+            "Compiling app v0.1.0 (/home/ann/app)",
+        ] {
+            assert!(
+                matches!(parse(line, &cwd), Message::Text(text) if text == line),
+                "{}",
+                line
+            );
+        }
+    }
+}
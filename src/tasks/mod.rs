@@ -0,0 +1,526 @@
+use crate::app_state::AppState;
+use crate::buffer::output::{self, Log, OutputView, SharedLog};
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult, Invocation,
+};
+use crate::state::StateEvent;
+use crate::tasks::messages::{Message, Problem, Severity};
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+pub mod messages;
+
+/// Command run by `task.run`, from the `tasks` list of `config.toml`. Tasks named like a
+/// built-in one replace it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TaskSettings {
+    pub name: String,
+    /// Shell command, run with `sh -c`
+    pub command: String,
+    /// Folder the command runs in, the working directory by default
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+}
+
+/// Tasks that can be run, the ones running and what they found.
+pub struct Tasks {
+    settings: Vec<TaskSettings>,
+    /// Running tasks, by name
+    running: HashMap<String, Running>,
+    /// Output of each task run so far, and the buffer showing it
+    outputs: HashMap<String, (usize, SharedLog)>,
+    /// Problems found by the last run
+    pub problems: SharedLog,
+    /// Buffer showing `problems`, once shown
+    problems_buffer: Option<usize>,
+}
+
+impl Tasks {
+    pub fn new(configured: Vec<TaskSettings>) -> Self {
+        let mut settings = built_in();
+        for task in configured {
+            settings.retain(|built_in| built_in.name != task.name);
+            settings.push(task);
+        }
+
+        Self {
+            settings,
+            running: HashMap::new(),
+            outputs: HashMap::new(),
+            problems: Arc::new(Mutex::new(Log::new("Problems".to_string()))),
+            problems_buffer: None,
+        }
+    }
+
+    /// Problems found in the file at `path`.
+    pub fn problems_at(&self, path: &Path) -> Vec<Arc<Problem>> {
+        let problems = self.problems.lock().unwrap();
+
+        problems
+            .problems()
+            .filter(|problem| problem.path == path)
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets buffer `index`, which was removed, and moves the buffers after it back.
+    pub fn buffer_removed(&mut self, index: usize) {
+        self.outputs.retain(|_, (output, _)| *output != index);
+        for (output, _) in self.outputs.values_mut() {
+            *output -= usize::from(*output > index);
+        }
+
+        self.problems_buffer = self
+            .problems_buffer
+            .filter(|&problems| problems != index)
+            .map(|problems| problems - usize::from(problems > index));
+    }
+
+    /// Stops every running task, waiting until they're gone.
+    pub async fn stop_all(&mut self) {
+        for (_, running) in self.running.drain() {
+            let _ = running.cancel.send(());
+            let _ = running.handle.await;
+        }
+    }
+}
+
+/// Task running in the background.
+struct Running {
+    /// Stops the task
+    cancel: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Default for Tasks {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+fn built_in() -> Vec<TaskSettings> {
+    [
+        ("build", "cargo build --message-format=json"),
+        ("check", "cargo check --message-format=json"),
+        ("test", "cargo test --message-format=json"),
+        ("clippy", "cargo clippy --message-format=json"),
+    ]
+    .into_iter()
+    .map(|(name, command)| TaskSettings {
+        name: name.to_string(),
+        command: command.to_string(),
+        cwd: None,
+    })
+    .collect()
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "task.run",
+            "Run a task, such as `build` or `test`, showing its output below the active frame",
+            run,
+        )
+        .with_arg(ArgSpec::required("name", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "task.run_command",
+            "Run a shell command as a task",
+            |state, args| {
+                Box::pin(async move {
+                    let command = args[0].as_text().expect("checked by registry");
+                    let task = TaskSettings {
+                        name: command.to_string(),
+                        command: command.to_string(),
+                        cwd: None,
+                    };
+
+                    start(state, task).await
+                })
+            },
+        )
+        .with_arg(ArgSpec::required("command", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "task.cancel",
+            "Stop a running task, or every running task",
+            cancel,
+        )
+        .with_arg(ArgSpec::optional("name", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "task.finished",
+            "Show the problems a task found in the files they're in",
+            finished,
+        )
+        .with_arg(ArgSpec::required("name", ArgKind::Text)),
+    );
+    registry.register(Command::new(
+        "problems.show",
+        "Show the problems found by the last task in the active frame",
+        |state, _| {
+            Box::pin(async move {
+                let index = problems_buffer(state);
+                state.show_buffer(index);
+
+                Ok(EventHandlerOutcome::Redraw)
+            })
+        },
+    ));
+    registry.register(Command::new(
+        "problems.next",
+        "Go to the next problem found by the last task",
+        |state, _| Box::pin(jump_to_problem(state, true)),
+    ));
+    registry.register(Command::new(
+        "problems.previous",
+        "Go to the previous problem found by the last task",
+        |state, _| Box::pin(jump_to_problem(state, false)),
+    ));
+    registry.register(
+        Command::new(
+            "problems.open_location",
+            "Open a file at a line and column, from 1, in an editor frame",
+            open_location,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text))
+        .with_arg(ArgSpec::required("line", ArgKind::Integer))
+        .with_arg(ArgSpec::required("column", ArgKind::Integer)),
+    );
+}
+
+fn run(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let name = args[0].as_text().expect("checked by registry");
+        let Some(task) = state
+            .tasks
+            .settings
+            .iter()
+            .find(|task| task.name == name)
+            .cloned()
+        else {
+            bail!("No task named `{}`", name);
+        };
+
+        start(state, task).await
+    })
+}
+
+/// Runs `task` in the background, stopping its previous run first.
+async fn start(state: &mut AppState, task: TaskSettings) -> CommandResult {
+    if let Some(running) = state.tasks.running.remove(&task.name) {
+        let _ = running.cancel.send(());
+    }
+
+    let cwd = match &task.cwd {
+        Some(cwd) => cwd.clone(),
+        None => std::env::current_dir().context("Can't read the working directory")?,
+    };
+
+    let output = output_buffer(state, &task);
+    let problems = state.tasks.problems.clone();
+    problems.lock().unwrap().clear("Problems".to_string());
+
+    let (cancel, cancelled) = oneshot::channel();
+    let name = task.name.clone();
+    let handle = tokio::spawn(run_in_background(
+        task,
+        cwd,
+        output,
+        problems,
+        cancelled,
+        state.state_tx.clone(),
+    ));
+    state.tasks.running.insert(name, Running { cancel, handle });
+
+    Ok(EventHandlerOutcome::Redraw)
+}
+
+/// Log of `task`, cleared for a new run and shown below the active frame unless it's
+/// already on screen.
+fn output_buffer(state: &mut AppState, task: &TaskSettings) -> SharedLog {
+    let (index, log) = match state.tasks.outputs.get(&task.name) {
+        Some((index, log)) => {
+            log.lock().unwrap().clear(task.command.clone());
+            (*index, log.clone())
+        }
+        None => {
+            let log = Arc::new(Mutex::new(Log::new(task.command.clone())));
+            let view = OutputView::new(state.font.clone(), log.clone(), state.state_tx.clone());
+            state.buffers.push(Arc::new(tokio::sync::Mutex::new(view)));
+
+            let index = state.buffers.len() - 1;
+            state
+                .tasks
+                .outputs
+                .insert(task.name.clone(), (index, log.clone()));
+            (index, log)
+        }
+    };
+    log.lock().unwrap().status = Some("running".to_string());
+
+    state.show_below(index);
+
+    log
+}
+
+fn problems_buffer(state: &mut AppState) -> usize {
+    if let Some(index) = state.tasks.problems_buffer {
+        return index;
+    }
+
+    let view = OutputView::new(
+        state.font.clone(),
+        state.tasks.problems.clone(),
+        state.state_tx.clone(),
+    );
+    state.buffers.push(Arc::new(tokio::sync::Mutex::new(view)));
+
+    let index = state.buffers.len() - 1;
+    state.tasks.problems_buffer = Some(index);
+    index
+}
+
+async fn run_in_background(
+    task: TaskSettings,
+    cwd: PathBuf,
+    output: SharedLog,
+    problems: SharedLog,
+    cancelled: oneshot::Receiver<()>,
+    state_tx: Sender<StateEvent>,
+) {
+    let reader = Reader {
+        cwd: &cwd,
+        output: (&output, output.lock().unwrap().run()),
+        problems: (&problems, problems.lock().unwrap().run()),
+        state_tx: &state_tx,
+    };
+
+    let status = match execute(&task, &reader, cancelled).await {
+        Ok(Some(status)) => status.to_string(),
+        Ok(None) => "cancelled".to_string(),
+        Err(err) => format!("{:#}", err),
+    };
+    if let Some(mut output) = reader.lock(reader.output) {
+        output.status = Some(status);
+    }
+    if let Some(mut problems) = reader.lock(reader.problems) {
+        problems.status = Some(summary(&problems));
+    }
+
+    let finished = Invocation::new("task.finished").with_arg(CommandArg::Text(task.name));
+    let _ = state_tx.send(StateEvent::Command(finished)).await;
+}
+
+/// Runs the command of `task` until it exits, or until `cancelled`. Cancelled tasks give
+/// no exit status.
+async fn execute(
+    task: &TaskSettings,
+    reader: &Reader<'_>,
+    cancelled: oneshot::Receiver<()>,
+) -> anyhow::Result<Option<ExitStatus>> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(&task.command)
+        .current_dir(reader.cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // A group of its own, to stop what the shell started along with it
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Can't run `{}`", task.command))?;
+    let pid = child.id();
+    let stdout = child.stdout.take().context("No output to read")?;
+    let stderr = child.stderr.take().context("No output to read")?;
+
+    let exited = async {
+        tokio::join!(reader.read(stdout), reader.read(stderr));
+        child.wait().await
+    };
+
+    tokio::select! {
+        status = exited => Ok(Some(status?)),
+        // A dropped sender stops the task too, as nothing could stop it anymore
+        _ = cancelled => {
+            if let Some(pid) = pid {
+                // SAFETY: `kill` has no memory safety requirements
+                unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) };
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Feeds what a task prints into its output and problems, as long as they aren't cleared
+/// for another run.
+struct Reader<'a> {
+    cwd: &'a Path,
+    /// Logs, with the run they were cleared for
+    output: (&'a SharedLog, usize),
+    problems: (&'a SharedLog, usize),
+    state_tx: &'a Sender<StateEvent>,
+}
+
+impl Reader<'_> {
+    fn lock<'a>(&self, (log, run): (&'a SharedLog, usize)) -> Option<MutexGuard<'a, Log>> {
+        let log = log.lock().unwrap();
+
+        (log.run() == run).then_some(log)
+    }
+
+    async fn read(&self, stream: impl AsyncRead + Unpin) {
+        let mut stream = BufReader::new(stream);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            match stream.read_until(b'\n', &mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+
+            let (text, problem) = match messages::parse(text, self.cwd) {
+                Message::Problem { problem, text } => (text, problem.map(Arc::new)),
+                Message::Text(text) => (text, None),
+                Message::Hidden => continue,
+            };
+
+            let mut redraw = false;
+            if let (Some(problem), Some(mut problems)) = (&problem, self.lock(self.problems)) {
+                redraw |= problems.push(&problem.describe(self.cwd), Some(problem.clone()));
+            }
+            match self.lock(self.output) {
+                Some(mut output) => redraw |= output.push(&text, problem),
+                // Started again
+                None => break,
+            }
+
+            if redraw && self.state_tx.send(StateEvent::Redraw).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Counts of the problems, such as `2 errors, 1 warning`.
+fn summary(problems: &Log) -> String {
+    let count = |severity: Severity| {
+        problems
+            .problems()
+            .filter(|problem| problem.severity == severity)
+            .count()
+    };
+    let plural = |count: usize, noun: &str| match count {
+        1 => format!("1 {}", noun),
+        count => format!("{} {}s", count, noun),
+    };
+
+    format!(
+        "{}, {}",
+        plural(count(Severity::Error), "error"),
+        plural(count(Severity::Warning), "warning")
+    )
+}
+
+fn cancel(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let running = &mut state.tasks.running;
+        let stopped: Vec<_> = match args.first().and_then(CommandArg::as_text) {
+            Some(name) => running.remove(name).into_iter().collect(),
+            None => running.drain().map(|(_, running)| running).collect(),
+        };
+
+        if stopped.is_empty() {
+            bail!("No task to stop");
+        }
+        for running in stopped {
+            let _ = running.cancel.send(());
+        }
+
+        Ok(EventHandlerOutcome::None)
+    })
+}
+
+/// Forgets the finished task, and marks its problems in the open files.
+fn finished(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let name = args[0].as_text().expect("checked by registry");
+
+        // The task may have been started again since
+        if let Some(running) = state.tasks.running.get(name) {
+            if running.cancel.is_closed() {
+                state.tasks.running.remove(name);
+            }
+        }
+
+        for buffer in &state.buffers {
+            let mut buffer = buffer.lock().await;
+            let Some(path) = buffer.path() else {
+                continue;
+            };
+
+            let problems = state.tasks.problems_at(path);
+            buffer.set_problems(problems);
+        }
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+async fn jump_to_problem(state: &mut AppState, forward: bool) -> CommandResult {
+    let problem = state
+        .tasks
+        .problems
+        .lock()
+        .unwrap()
+        .jump_to_problem(forward);
+    let Some(problem) = problem else {
+        bail!("No problems");
+    };
+
+    output::open(&problem, &state.state_tx);
+
+    Ok(EventHandlerOutcome::Redraw)
+}
+
+/// Shows the file at `path` with the cursor on `line` and `column`. Files opened from a
+/// list go to the frame focused before it, so the list stays on screen.
+fn open_location(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = args[0].as_text().expect("checked by registry").to_string();
+        let line = args[1].as_integer().expect("checked by registry");
+        let column = args[2].as_integer().expect("checked by registry");
+
+        if state.active_buffer().lock().await.text().is_none() {
+            state.focus_other_frame();
+        }
+
+        state
+            .pending
+            .push_back(Invocation::new("file.open").with_arg(CommandArg::Text(path)));
+        state.pending.push_back(
+            Invocation::new("file.go_to")
+                .with_arg(CommandArg::Integer(line))
+                .with_arg(CommandArg::Integer(column)),
+        );
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
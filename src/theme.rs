@@ -0,0 +1,193 @@
+use crate::app_state::AppState;
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{BoxFuture, Command, CommandArg, CommandRegistry, CommandResult};
+use crate::paths;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const THEME_FILE: &str = "theme.toml";
+
+/// Linear RGBA color, written as `#rrggbb` or `#rrggbbaa` in theme files.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rgba(pub [f32; 4]);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub background: Rgba,
+    pub foreground: Rgba,
+    pub cursor: Rgba,
+    pub selection: Rgba,
+    /// Background of the bracket at the caret and of its match
+    pub bracket_match: Rgba,
+    pub gutter: Rgba,
+    /// Fold markers in the gutter and placeholders of folded lines
+    pub fold_marker: Rgba,
+    pub split_border: Rgba,
+    /// Markers drawn for spaces and tabs
+    pub whitespace: Rgba,
+    /// Background of whitespace at the end of lines
+    pub trailing_whitespace: Rgba,
+    pub indent_guide: Rgba,
+    /// Indent guide of the block the cursor is in
+    pub active_indent_guide: Rgba,
+    /// Gutter markers of lines added, changed and removed since staging
+    pub git_added: Rgba,
+    pub git_modified: Rgba,
+    pub git_deleted: Rgba,
+    /// Commit shown after the cursor line
+    pub blame: Rgba,
+    pub line_number: Rgba,
+    /// Background of lines removed and added in diffs
+    pub diff_deleted: Rgba,
+    pub diff_added: Rgba,
+    /// Background of the words that changed within changed lines
+    pub diff_deleted_word: Rgba,
+    pub diff_added_word: Rgba,
+    /// Squiggles under problems found by tasks, and their marks in task output
+    pub error: Rgba,
+    pub warning: Rgba,
+    pub note: Rgba,
+    /// The 16 standard colors of terminal programs, black to bright white
+    pub terminal_colors: Vec<Rgba>,
+    /// Colors of syntax scopes such as `keyword` or `string.quoted`.
+    pub syntax: HashMap<String, Rgba>,
+}
+
+impl Rgba {
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Self([r, g, b, 1.0])
+    }
+}
+
+impl std::str::FromStr for Rgba {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').context("Colors must start with `#`")?;
+
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            bail!("Expected `#rrggbb` or `#rrggbbaa`, got `{}`", s);
+        }
+
+        let mut color = [1.0; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)? as f32 / 255.0;
+        }
+
+        Ok(Self(color))
+    }
+}
+
+impl Serialize for Rgba {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let [r, g, b, a] = self.0.map(|channel| (channel * 255.0).round() as u8);
+
+        serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a))
+    }
+}
+
+impl<'de> Deserialize<'de> for Rgba {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Theme {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Can't read {}", path.display()))?;
+
+        toml::from_str(&source).with_context(|| format!("Malformed theme {}", path.display()))
+    }
+
+    /// Color of a syntax scope. Unknown scopes fall back to their parent scope
+    /// (`keyword.control` to `keyword`), and finally to the foreground.
+    pub fn scope_color(&self, scope: &str) -> Rgba {
+        let mut scope = scope;
+
+        loop {
+            if let Some(color) = self.syntax.get(scope) {
+                return *color;
+            }
+
+            match scope.rfind('.') {
+                Some(dot) => scope = &scope[..dot],
+                None => return self.foreground,
+            }
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: Rgba::rgb(0.4, 0.4, 0.4),
+            foreground: Rgba::rgb(0.0, 0.0, 0.0),
+            cursor: Rgba::rgb(0.0, 0.0, 0.0),
+            selection: Rgba([0.2, 0.4, 0.8, 0.4]),
+            bracket_match: Rgba([0.0, 0.0, 0.0, 0.2]),
+            gutter: Rgba::rgb(0.35, 0.35, 0.35),
+            fold_marker: Rgba([0.0, 0.0, 0.0, 0.55]),
+            split_border: Rgba::rgb(0.2, 0.2, 0.2),
+            whitespace: Rgba([0.0, 0.0, 0.0, 0.35]),
+            trailing_whitespace: Rgba([0.8, 0.2, 0.2, 0.35]),
+            indent_guide: Rgba([0.0, 0.0, 0.0, 0.15]),
+            active_indent_guide: Rgba([0.0, 0.0, 0.0, 0.45]),
+            git_added: Rgba::rgb(0.2, 0.6, 0.2),
+            git_modified: Rgba::rgb(0.2, 0.4, 0.8),
+            git_deleted: Rgba::rgb(0.8, 0.2, 0.2),
+            blame: Rgba([0.0, 0.0, 0.0, 0.45]),
+            line_number: Rgba([0.0, 0.0, 0.0, 0.55]),
+            diff_deleted: Rgba([0.8, 0.2, 0.2, 0.2]),
+            diff_added: Rgba([0.2, 0.6, 0.2, 0.2]),
+            diff_deleted_word: Rgba([0.8, 0.2, 0.2, 0.45]),
+            diff_added_word: Rgba([0.2, 0.6, 0.2, 0.45]),
+            error: Rgba::rgb(0.8, 0.1, 0.1),
+            warning: Rgba::rgb(0.8, 0.6, 0.1),
+            note: Rgba::rgb(0.2, 0.4, 0.8),
+            terminal_colors: [
+                "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd",
+                "#e5e5e5", "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff",
+                "#00ffff", "#ffffff",
+            ]
+            .iter()
+            .map(|color| color.parse().expect("valid default color"))
+            .collect(),
+            syntax: HashMap::new(),
+        }
+    }
+}
+
+pub fn theme_path() -> Option<PathBuf> {
+    Some(paths::config_dir()?.join(THEME_FILE))
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "theme.reload",
+        "Reload the theme from the configuration directory",
+        reload,
+    ));
+}
+
+fn reload(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = theme_path().context("No configuration directory on this platform")?;
+
+        // A deleted theme file means going back to the defaults
+        state.theme = if path.exists() {
+            Arc::new(Theme::load(&path).await?)
+        } else {
+            Arc::default()
+        };
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
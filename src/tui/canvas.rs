@@ -0,0 +1,337 @@
+use crate::buffer::BoundingBox;
+use crate::grid::GridMetrics;
+use crate::scene::{Drawing, Quad, Scene};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::queue;
+use crossterm::style::{Color, Colors, Print, SetColors};
+use crossterm::terminal::{BeginSynchronizedUpdate, Clear, ClearType, EndSynchronizedUpdate};
+use std::io::{self, Write};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Character cell of the terminal, with the colors it's printed in.
+#[derive(Clone, Debug, PartialEq)]
+struct Cell {
+    /// Grapheme shown, empty for the right half of a wide one
+    text: String,
+    fg: [f32; 3],
+    bg: [f32; 3],
+}
+
+/// What the terminal shows, cell by cell: a drawing with its pixels mapped to cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Canvas {
+    columns: u16,
+    rows: u16,
+    cells: Vec<Cell>,
+    metrics: GridMetrics,
+    /// Cell of the caret the user types at, if it's on screen
+    cursor: Option<(u16, u16)>,
+}
+
+impl Canvas {
+    /// Paints `drawing`, laid out in a window of `columns` by `rows` cells of `metrics`.
+    pub fn paint(drawing: &Drawing, metrics: GridMetrics, columns: u16, rows: u16) -> Self {
+        let theme = &drawing.theme;
+        let blank = Cell {
+            text: " ".to_string(),
+            fg: rgb(theme.foreground.0),
+            bg: rgb(theme.background.0),
+        };
+        let mut canvas = Self {
+            columns,
+            rows,
+            cells: vec![blank; columns as usize * rows as usize],
+            metrics,
+            cursor: None,
+        };
+
+        for (bb, _, scene) in &drawing.frames {
+            canvas.paint_scene(*bb, scene);
+        }
+
+        // Borders are a cell thick here, on the last column or row of frames next to another
+        let (width, height) = canvas.size();
+        for (bb, _, _) in &drawing.frames {
+            let (right, bottom) = (bb.left + bb.width, bb.top + bb.height);
+            let mut borders = Vec::new();
+
+            if right < width - 1.0 {
+                borders.push([right - metrics.cell_width, bb.top, right, bottom]);
+            }
+            if bottom < height - 1.0 {
+                borders.push([bb.left, bottom - metrics.line_height, right, bottom]);
+            }
+
+            for aabb in borders {
+                let border = Quad {
+                    aabb,
+                    z_pos: 0.0,
+                    color: theme.split_border.0,
+                };
+                canvas.fill(&border, *bb);
+            }
+        }
+
+        canvas.cursor = drawing
+            .frames
+            .get(drawing.active_frame)
+            .and_then(|(_, _, scene)| scene.cursor)
+            .and_then(|(x, y)| {
+                let column = (x / metrics.cell_width).round();
+                let row = (y / metrics.line_height).round();
+
+                canvas
+                    .contains(column, row)
+                    .then_some((column as u16, row as u16))
+            });
+
+        canvas
+    }
+
+    /// Size of the window the drawing was laid out in, in pixels.
+    pub fn size(&self) -> (f32, f32) {
+        (
+            self.columns as f32 * self.metrics.cell_width,
+            self.rows as f32 * self.metrics.line_height,
+        )
+    }
+
+    /// Center of a cell, in pixels of the window.
+    pub fn center(&self, column: u16, row: u16) -> (f32, f32) {
+        (
+            (column as f32 + 0.5) * self.metrics.cell_width,
+            (row as f32 + 0.5) * self.metrics.line_height,
+        )
+    }
+
+    fn paint_scene(&mut self, bb: BoundingBox, scene: &Scene) {
+        let metrics = self.metrics;
+
+        for quad in &scene.backgrounds {
+            self.fill(quad, bb);
+        }
+
+        let clip = scene.clip.unwrap_or(bb);
+        for glyph in &scene.glyphs {
+            let position = glyph.glyph.position;
+            // Glyphs start a little past their cell's edge and sit on its baseline
+            let column = (position.x / metrics.cell_width + 0.25).floor();
+            let row = ((position.y - metrics.ascent) / metrics.line_height).round();
+
+            self.print(column, row, &glyph.text, glyph.color, clip);
+        }
+
+        for label in &scene.labels {
+            let (x, y) = label.position;
+            let clip = intersection(
+                bb,
+                BoundingBox {
+                    left: x,
+                    top: y,
+                    width: label.bounds.0,
+                    height: label.bounds.1,
+                },
+            );
+            let mut column = (x / metrics.cell_width).round();
+            let row = (y / metrics.line_height).round();
+
+            for span in &label.spans {
+                column += self.print(column, row, &span.text, span.color, clip);
+            }
+        }
+
+        for quad in &scene.overlays {
+            self.fill(quad, bb);
+        }
+    }
+
+    /// Blends `quad`, cut to `clip`, into the background of the cells whose centers it
+    /// covers. Thin quads such as carets and underlines don't show.
+    fn fill(&mut self, quad: &Quad, clip: BoundingBox) {
+        let [left, top, right, bottom] = quad.aabb;
+        let (left, top) = (left.max(clip.left), top.max(clip.top));
+        let right = right.min(clip.left + clip.width);
+        let bottom = bottom.min(clip.top + clip.height);
+
+        let columns = covered(left, right, self.metrics.cell_width);
+        let rows = covered(top, bottom, self.metrics.line_height);
+        for row in rows {
+            for column in columns.clone() {
+                if let Some(cell) = self.cell(column as f32, row as f32) {
+                    cell.bg = blend(cell.bg, quad.color);
+                }
+            }
+        }
+    }
+
+    /// Prints `text` from a cell on, leaving out graphemes whose cells are outside `clip`.
+    /// Returns how many columns the text takes.
+    fn print(
+        &mut self,
+        column: f32,
+        row: f32,
+        text: &str,
+        color: [f32; 4],
+        clip: BoundingBox,
+    ) -> f32 {
+        let mut offset = 0.0;
+
+        for grapheme in text.graphemes(true) {
+            let width = grapheme.width();
+            if width == 0 || grapheme.chars().any(char::is_control) {
+                continue;
+            }
+
+            let column = column + offset;
+            offset += width as f32;
+            if !self.is_inside(column, row, width, clip) {
+                continue;
+            }
+
+            // Terminals can't show half of a wide grapheme, so one written over is blanked
+            self.split(column, row);
+            self.split(column + width as f32, row);
+
+            let cell = self.cell(column, row).expect("inside the canvas");
+            cell.text = grapheme.to_string();
+            cell.fg = blend(cell.bg, color);
+            let fg = cell.fg;
+
+            // The cells a wide grapheme covers past its first are left blank
+            for next in 1..width {
+                let cell = self
+                    .cell(column + next as f32, row)
+                    .expect("inside the canvas");
+                cell.text.clear();
+                cell.fg = fg;
+            }
+        }
+
+        offset
+    }
+
+    /// Blanks the wide grapheme across the left edge of a cell, if there's one.
+    fn split(&mut self, column: f32, row: f32) {
+        match self.cell(column, row) {
+            Some(cell) if cell.text.is_empty() => cell.text.push(' '),
+            _ => return,
+        }
+
+        if let Some(cell) = self.cell(column - 1.0, row) {
+            cell.text = " ".to_string();
+        }
+    }
+
+    /// Whether the cells from `column` on, `width` of them, are all in the canvas and
+    /// have their centers in `clip`.
+    fn is_inside(&self, column: f32, row: f32, width: usize, clip: BoundingBox) -> bool {
+        let (x, y) = (
+            column * self.metrics.cell_width,
+            (row + 0.5) * self.metrics.line_height,
+        );
+        let right = x + width as f32 * self.metrics.cell_width;
+        let half_cell = self.metrics.cell_width / 2.0;
+
+        self.contains(column, row)
+            && self.contains(column + width as f32 - 1.0, row)
+            && x + half_cell >= clip.left
+            && right - half_cell <= clip.left + clip.width
+            && y >= clip.top
+            && y <= clip.top + clip.height
+    }
+
+    fn contains(&self, column: f32, row: f32) -> bool {
+        column >= 0.0 && row >= 0.0 && column < self.columns as f32 && row < self.rows as f32
+    }
+
+    fn cell(&mut self, column: f32, row: f32) -> Option<&mut Cell> {
+        if !self.contains(column, row) {
+            return None;
+        }
+
+        let index = row as usize * self.columns as usize + column as usize;
+        self.cells.get_mut(index)
+    }
+
+    /// Writes the canvas to the terminal. Only cells that differ from `previous`, the
+    /// canvas last written, are printed; without it the screen is cleared first.
+    pub fn flush(&self, previous: Option<&Canvas>, out: &mut impl Write) -> io::Result<()> {
+        // A canvas of another size has its cells elsewhere
+        let previous = previous
+            .filter(|previous| (previous.columns, previous.rows) == (self.columns, self.rows));
+        // Where the terminal's cursor is after printing, and the colors it prints in
+        let mut position = None;
+        let mut colors = None;
+
+        queue!(out, BeginSynchronizedUpdate, Hide)?;
+        if previous.is_none() {
+            queue!(out, Clear(ClearType::All))?;
+        }
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let unchanged = previous.map(|previous| &previous.cells[index]) == Some(cell);
+            if unchanged || cell.text.is_empty() {
+                continue;
+            }
+
+            let column = (index % self.columns as usize) as u16;
+            let row = (index / self.columns as usize) as u16;
+            if position != Some((column, row)) {
+                queue!(out, MoveTo(column, row))?;
+            }
+            if colors != Some((cell.fg, cell.bg)) {
+                queue!(out, SetColors(Colors::new(color(cell.fg), color(cell.bg))))?;
+                colors = Some((cell.fg, cell.bg));
+            }
+
+            queue!(out, Print(&cell.text))?;
+            position = Some((column + cell.text.width() as u16, row));
+        }
+
+        if let Some((column, row)) = self.cursor {
+            queue!(out, MoveTo(column, row), Show)?;
+        }
+        queue!(out, EndSynchronizedUpdate)?;
+
+        out.flush()
+    }
+}
+
+/// Cells along one axis whose centers `start..end` covers.
+fn covered(start: f32, end: f32, size: f32) -> std::ops::Range<i64> {
+    let first = (start / size + 0.5).floor() as i64;
+    let last = (end / size + 0.5).floor() as i64;
+
+    first..last.max(first)
+}
+
+fn intersection(a: BoundingBox, b: BoundingBox) -> BoundingBox {
+    let (left, top) = (a.left.max(b.left), a.top.max(b.top));
+    let right = (a.left + a.width).min(b.left + b.width);
+    let bottom = (a.top + a.height).min(b.top + b.height);
+
+    BoundingBox {
+        left,
+        top,
+        width: (right - left).max(0.0),
+        height: (bottom - top).max(0.0),
+    }
+}
+
+/// `color` over `base`, by its alpha.
+fn blend(base: [f32; 3], color: [f32; 4]) -> [f32; 3] {
+    let alpha = color[3];
+
+    [0, 1, 2].map(|i| base[i] * (1.0 - alpha) + color[i] * alpha)
+}
+
+fn rgb([r, g, b, _]: [f32; 4]) -> [f32; 3] {
+    [r, g, b]
+}
+
+fn color(rgb: [f32; 3]) -> Color {
+    let [r, g, b] = rgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+    Color::Rgb { r, g, b }
+}
@@ -0,0 +1,291 @@
+use crate::buffer::BoundingBox;
+use crate::command::{CommandArg, Invocation};
+use crate::editor::{Frontend, Host};
+use crate::grid::GridMetrics;
+use crate::input::{Key, Modifiers};
+use crate::paths;
+use crate::scene::Surface;
+use crate::state::StateEvent;
+use anyhow::Context;
+use canvas::Canvas;
+use crossterm::cursor::{SetCursorStyle, Show};
+use crossterm::event::{
+    DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
+    EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures_util::StreamExt;
+use std::fs::File;
+use std::io;
+use tokio::sync::mpsc::{self, Sender, UnboundedSender};
+
+mod canvas;
+
+const LOG_FILE: &str = "kami.log";
+
+/// What the editor asks of the terminal frontend.
+enum Signal {
+    Redraw,
+    Exit,
+}
+
+struct Proxy(UnboundedSender<Signal>);
+
+impl Frontend for Proxy {
+    fn request_redraw(&self) {
+        let _ = self.0.send(Signal::Redraw);
+    }
+
+    fn exit(&self) {
+        let _ = self.0.send(Signal::Exit);
+    }
+}
+
+/// The terminal taken over by the editor: raw input, the alternate screen, mouse reports
+/// and bracketed paste. Dropping it gives the terminal back as it was.
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            io::stdout(),
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            EnableBracketedPaste,
+            SetCursorStyle::SteadyBar
+        )?;
+
+        Ok(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(
+            io::stdout(),
+            SetCursorStyle::DefaultUserShape,
+            DisableBracketedPaste,
+            DisableMouseCapture,
+            LeaveAlternateScreen,
+            Show
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Shows the editor of `host` in the terminal it was started from, a character cell for
+/// each cell of its grid.
+pub async fn run(mut host: impl Host) -> anyhow::Result<()> {
+    let state_tx = host.events();
+    let (signal_tx, mut signals) = mpsc::unbounded_channel();
+
+    let _terminal = RawTerminal::enter().context("Can't set up the terminal")?;
+    host.start(Proxy(signal_tx));
+
+    let mut input = EventStream::new();
+    let mut input_open = true;
+    // What the terminal shows, to print only what changed
+    let mut screen = None;
+    let mut modifiers = Modifiers::empty();
+
+    redraw(&host, &mut screen).await?;
+    loop {
+        tokio::select! {
+            signal = signals.recv() => {
+                let mut exit = !matches!(signal, Some(Signal::Redraw));
+                // Redraws asked for while drawing are done at once
+                while let Ok(signal) = signals.try_recv() {
+                    exit |= matches!(signal, Signal::Exit);
+                }
+
+                if exit {
+                    break;
+                }
+                redraw(&host, &mut screen).await?;
+            }
+            event = input.next(), if input_open => match event {
+                Some(Ok(Event::Resize(..))) => redraw(&host, &mut screen).await?,
+                Some(Ok(event)) => {
+                    handle(event, &mut modifiers, screen.as_ref(), &state_tx).await;
+                }
+                // Without input there's no way to quit, so unsaved changes are kept aside
+                Some(Err(_)) | None => {
+                    tracing::error!("Lost the terminal's input");
+                    input_open = false;
+                    let _ = state_tx.send(StateEvent::Exit).await;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Draws the editor at the terminal's size, printing the cells that changed since `screen`.
+async fn redraw(host: &impl Host, screen: &mut Option<Canvas>) -> anyhow::Result<()> {
+    let (columns, rows) = terminal::size()?;
+    let (fonts, scale) = host.font().await;
+
+    // Frames are laid out in the pixels of a window with a cell for each terminal cell
+    let metrics = GridMetrics::new(&fonts, scale);
+    let window = BoundingBox {
+        left: 0.0,
+        top: 0.0,
+        width: columns as f32 * metrics.cell_width,
+        height: rows as f32 * metrics.line_height,
+    };
+    let drawing = host.draw(window, Surface::Cells { scale }).await?;
+
+    let canvas = Canvas::paint(&drawing, metrics, columns, rows);
+    canvas.flush(screen.as_ref(), &mut io::stdout().lock())?;
+    *screen = Some(canvas);
+
+    Ok(())
+}
+
+/// Sends the state loop what the user did, the way a window reports it.
+async fn handle(
+    event: Event,
+    modifiers: &mut Modifiers,
+    screen: Option<&Canvas>,
+    state_tx: &Sender<StateEvent>,
+) {
+    let mut events = Vec::new();
+    let held = match &event {
+        // Shift+Tab comes as a key of its own, Shift not always reported with it
+        Event::Key(key) if key.code == KeyCode::BackTab => key.modifiers | KeyModifiers::SHIFT,
+        Event::Key(key) => key.modifiers,
+        Event::Mouse(mouse) => mouse.modifiers,
+        _ => KeyModifiers::NONE,
+    };
+
+    // Terminals report the modifiers held with each event rather than their changes
+    if matches!(event, Event::Key(_) | Event::Mouse(_)) && convert(held) != *modifiers {
+        *modifiers = convert(held);
+        events.push(StateEvent::ModifiersChange(*modifiers));
+    }
+
+    match event {
+        Event::Key(KeyEvent {
+            code,
+            modifiers: held,
+            kind: KeyEventKind::Press | KeyEventKind::Repeat,
+            ..
+        }) => {
+            if let Some(key) = key(code) {
+                events.push(StateEvent::KeyPress(key));
+            }
+            if let Some(c) = character(code, held) {
+                events.push(StateEvent::CharInput(c));
+            }
+        }
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            ..
+        }) => {
+            if let Some(screen) = screen {
+                let (width, height) = screen.size();
+                events.push(StateEvent::Click {
+                    position: screen.center(column, row),
+                    window: BoundingBox {
+                        left: 0.0,
+                        top: 0.0,
+                        width,
+                        height,
+                    },
+                });
+            }
+        }
+        Event::Paste(text) => events.push(StateEvent::Command(
+            Invocation::new("buffer.insert").with_arg(CommandArg::Text(text)),
+        )),
+        _ => {}
+    }
+
+    for event in events {
+        if state_tx.send(event).await.is_err() {
+            tracing::warn!("Dropped an event for a stopped loop");
+        }
+    }
+}
+
+fn convert(held: KeyModifiers) -> Modifiers {
+    let mut modifiers = Modifiers::empty();
+
+    for (key, modifier) in [
+        (KeyModifiers::CONTROL, Modifiers::CTRL),
+        (KeyModifiers::SHIFT, Modifiers::SHIFT),
+        (KeyModifiers::ALT, Modifiers::ALT),
+        (KeyModifiers::SUPER, Modifiers::LOGO),
+    ] {
+        if held.contains(key) {
+            modifiers |= modifier;
+        }
+    }
+
+    modifiers
+}
+
+/// Key of a key code, for the keys bindings can name. Terminals report the character a
+/// key types, so shifted symbols are named by it.
+fn key(code: KeyCode) -> Option<Key> {
+    Some(match code {
+        KeyCode::Char(' ') => Key::Space,
+        KeyCode::Char(c) => Key::Char(c.to_ascii_lowercase()),
+        KeyCode::F(n) => Key::F(n),
+        KeyCode::Tab | KeyCode::BackTab => Key::Tab,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Esc => Key::Escape,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        _ => return None,
+    })
+}
+
+/// Character a key types, as a window would send it: control characters for Control
+/// with a letter, and for keys such as Enter and Backspace.
+fn character(code: KeyCode, held: KeyModifiers) -> Option<char> {
+    Some(match code {
+        KeyCode::Char(c) if held.contains(KeyModifiers::CONTROL) => {
+            if !c.is_ascii_alphabetic() {
+                return None;
+            }
+
+            (c.to_ascii_lowercase() as u8 - b'a' + 1) as char
+        }
+        KeyCode::Char(c) => c,
+        KeyCode::Enter => '\r',
+        KeyCode::Tab | KeyCode::BackTab => '\t',
+        KeyCode::Backspace => '\u{8}',
+        KeyCode::Delete => '\u{7f}',
+        KeyCode::Esc => '\u{1b}',
+        _ => return None,
+    })
+}
+
+/// Log file of the terminal frontend, whose output would end up on screen otherwise.
+pub fn log_file() -> anyhow::Result<File> {
+    let dir = paths::data_dir().context("No data directory on this platform")?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("Can't create {}", dir.display()))?;
+
+    let path = dir.join(LOG_FILE);
+    File::options()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Can't open {}", path.display()))
+}
@@ -0,0 +1,479 @@
+use crate::app_state::AppState;
+use crate::buffer::diff::{self, Version};
+use crate::buffer::EventHandlerOutcome;
+use crate::command::{
+    ArgKind, ArgSpec, BoxFuture, Command, CommandArg, CommandRegistry, CommandResult, Invocation,
+};
+use crate::git;
+use crate::state::StateEvent;
+use anyhow::Context;
+use futures_util::StreamExt;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+
+const EVENT_BUFFER_SIZE: usize = 4096;
+
+/// Commands to run on changes in each watched folder, with the name of the file they're
+/// about, or `None` for changes to the folder's entries.
+type WatchedFiles = Arc<Mutex<HashMap<WatchDescriptor, Vec<(Option<OsString>, Invocation)>>>>;
+
+/// Notices files open in buffers, the theme and the folders the explorer shows changing on
+/// disk, through inotify.
+pub struct FileWatcher {
+    watches: Watches,
+    files: WatchedFiles,
+    /// Watch of each folder, to remove once nothing in it is watched anymore
+    dirs: HashMap<PathBuf, WatchDescriptor>,
+    /// Hash of the contents each file had when Kami last read or wrote it, to tell its own
+    /// saves apart from changes made by other programs
+    known: HashMap<PathBuf, u64>,
+}
+
+impl FileWatcher {
+    /// Starts a task feeding changes to watched files into the state loop as commands. It
+    /// runs until the state loop is gone.
+    pub fn start(state_tx: Sender<StateEvent>) -> anyhow::Result<Self> {
+        let inotify = Inotify::init().context("Can't initialize inotify")?;
+        let watches = inotify.watches();
+        let mut events = inotify
+            .into_event_stream([0; EVENT_BUFFER_SIZE])
+            .context("Can't read inotify events")?;
+        let files = WatchedFiles::default();
+
+        let watched = files.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        tracing::warn!("Stopped watching files: {}", err);
+                        break;
+                    }
+                };
+                // The folder was deleted or its watch removed
+                if event.mask.contains(EventMask::IGNORED) {
+                    watched.lock().unwrap().remove(&event.wd);
+                    continue;
+                }
+                let Some(name) = event.name else {
+                    continue;
+                };
+                // Writing to a file leaves the folder's entries as they are, and a file is
+                // only worth reading once written
+                let listing = !event.mask.contains(EventMask::CLOSE_WRITE);
+                let written = !event.mask.contains(EventMask::CREATE);
+
+                let changed: Vec<_> = watched
+                    .lock()
+                    .unwrap()
+                    .get(&event.wd)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(file, _)| match file {
+                        Some(file) => *file == name && written,
+                        None => listing,
+                    })
+                    .map(|(_, invocation)| invocation.clone())
+                    .collect();
+
+                for invocation in changed {
+                    if state_tx
+                        .send(StateEvent::Command(invocation))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            watches,
+            files,
+            dirs: HashMap::new(),
+            known: HashMap::new(),
+        })
+    }
+
+    /// Reports changes to `path` from now on. `text` is what Kami just read from or wrote
+    /// to it, which doesn't count as a change.
+    pub fn watch(&mut self, path: &Path, text: &str) -> anyhow::Result<()> {
+        self.known.insert(path.to_path_buf(), hash(text));
+        self.notify(path, changed_on_disk_invocation(path))
+    }
+
+    /// Stops reporting changes to `path`, as its buffer is gone or edits another file.
+    pub fn unwatch(&mut self, path: &Path) {
+        self.known.remove(path);
+
+        let changed = changed_on_disk_invocation(path);
+        let (dir, file) = split(path);
+        self.remove(dir, |name, invocation| {
+            name.as_deref() == file && *invocation == changed
+        });
+    }
+
+    /// Runs `invocation` whenever the file at `path` is written, replaced or deleted.
+    pub fn notify(&mut self, path: &Path, invocation: Invocation) -> anyhow::Result<()> {
+        let (dir, file) = split(path);
+        self.add(dir, file, invocation)
+    }
+
+    /// Runs `invocation` whenever an entry is added to or removed from the folder `dir`.
+    pub fn notify_folder(&mut self, dir: &Path, invocation: Invocation) -> anyhow::Result<()> {
+        self.add(dir, None, invocation)
+    }
+
+    /// Stops running the commands given to `notify_folder` for `dir`.
+    pub fn unwatch_folder(&mut self, dir: &Path) {
+        self.remove(dir, |name, _| name.is_none());
+    }
+
+    fn add(
+        &mut self,
+        dir: &Path,
+        file: Option<&OsStr>,
+        invocation: Invocation,
+    ) -> anyhow::Result<()> {
+        // A folder has a single mask, covering both its files and its entries
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::MOVED_FROM
+            | WatchMask::CREATE
+            | WatchMask::DELETE;
+        let wd = self
+            .watches
+            .add(dir, mask)
+            .with_context(|| format!("Can't watch {}", dir.display()))?;
+        self.dirs.insert(dir.to_path_buf(), wd.clone());
+
+        let watch = (file.map(OsStr::to_os_string), invocation);
+        let mut files = self.files.lock().unwrap();
+        let watched = files.entry(wd).or_default();
+        if !watched.contains(&watch) {
+            watched.push(watch);
+        }
+
+        Ok(())
+    }
+
+    /// Drops what `matches` of the commands run on changes in `dir`, and the folder's watch
+    /// once none are left.
+    fn remove(&mut self, dir: &Path, matches: impl Fn(&Option<OsString>, &Invocation) -> bool) {
+        let Some(wd) = self.dirs.get(dir).cloned() else {
+            return;
+        };
+
+        let mut files = self.files.lock().unwrap();
+        let left = files.get_mut(&wd).map_or(0, |watched| {
+            watched.retain(|(name, invocation)| !matches(name, invocation));
+            watched.len()
+        });
+        if left > 0 {
+            return;
+        }
+
+        files.remove(&wd);
+        self.dirs.remove(dir);
+        // Deleted folders lose their watch on their own
+        if let Err(err) = self.watches.remove(wd) {
+            tracing::debug!("Can't stop watching {}: {}", dir.display(), err);
+        }
+    }
+
+    /// Whether `text` is what Kami last read from or wrote to `path`.
+    fn is_known(&self, path: &Path, text: &str) -> bool {
+        self.known.get(path) == Some(&hash(text))
+    }
+}
+
+/// Folder to watch for changes to `path`, and the file's name in it. Files are watched
+/// through their folder, to notice them replaced by a rename as editors, formatters and
+/// git save them.
+fn split(path: &Path) -> (&Path, Option<&OsStr>) {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => (dir, path.file_name()),
+        _ => (Path::new("."), path.file_name()),
+    }
+}
+
+fn changed_on_disk_invocation(path: &Path) -> Invocation {
+    Invocation::new("file.changed_on_disk")
+        .with_arg(CommandArg::Text(path.to_string_lossy().into_owned()))
+}
+
+/// Hash of `text`, to tell whether it changed without keeping a copy.
+pub fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "file.changed_on_disk",
+            "Reload a file changed by another program, or ask what to do with unsaved changes",
+            changed_on_disk,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "file.moved",
+            "Point the buffers of a moved file, or of files in a moved folder, at the new path",
+            file_moved,
+        )
+        .with_arg(ArgSpec::required("from", ArgKind::Text))
+        .with_arg(ArgSpec::required("to", ArgKind::Text)),
+    );
+    registry.register(
+        Command::new(
+            "file.deleted",
+            "Mark the buffers of a deleted file, or of files in a deleted folder, as unsaved",
+            file_deleted,
+        )
+        .with_arg(ArgSpec::required("path", ArgKind::Text)),
+    );
+    registry.register(Command::new(
+        "file.reload",
+        "Replace the active buffer with its file, dropping unsaved changes",
+        reload,
+    ));
+    registry.register(Command::new(
+        "file.keep_changes",
+        "Keep the unsaved changes of the active buffer over its changed file",
+        keep_changes,
+    ));
+    registry.register(Command::new(
+        "file.diff_on_disk",
+        "Compare the active buffer with its file, in a new frame",
+        diff_on_disk,
+    ));
+}
+
+fn changed_on_disk(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = PathBuf::from(args[0].as_text().expect("checked by registry"));
+        let Some(index) = state.find_buffer(&path).await else {
+            return Ok(EventHandlerOutcome::None);
+        };
+
+        let text = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => text,
+            // Deleted or moved away files leave their buffer as it is, to be saved again
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(EventHandlerOutcome::None)
+            }
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", path.display())),
+        };
+
+        if let Some(watcher) = &state.watcher {
+            if watcher.is_known(&path, &text) {
+                return Ok(EventHandlerOutcome::None);
+            }
+        }
+        state.watch(&path, &text);
+
+        let mut buffer = state.buffers[index].lock().await;
+        if buffer.text() == Some(text.as_str()) {
+            buffer.mark_saved();
+            buffer.set_conflict(None);
+        } else if buffer.is_dirty() {
+            tracing::info!("{} changed on disk with unsaved changes", path.display());
+            buffer.set_conflict(Some(text));
+        } else {
+            buffer.reload(text);
+        }
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn file_moved(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let from = PathBuf::from(args[0].as_text().expect("checked by registry"));
+        let to = PathBuf::from(args[1].as_text().expect("checked by registry"));
+
+        let mut moved = Vec::new();
+        for buffer in &state.buffers {
+            let mut locked = buffer.lock().await;
+            let Some(rest) = locked.path().and_then(|path| path.strip_prefix(&from).ok()) else {
+                continue;
+            };
+
+            let path = match rest.as_os_str().is_empty() {
+                true => to.clone(),
+                false => to.join(rest),
+            };
+            let old = locked.path().map(Path::to_path_buf);
+            locked.set_path(path.clone());
+            moved.push((buffer.clone(), old, path));
+        }
+
+        // The new path may be in another repository, and is the one to watch
+        for (buffer, old, path) in moved {
+            git::refresh(buffer, state.state_tx.clone());
+            if let Some(old) = old {
+                state.unwatch(&old);
+            }
+            if let Ok(text) = tokio::fs::read_to_string(&path).await {
+                state.watch(&path, &text);
+            }
+        }
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn file_deleted(state: &mut AppState, args: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = PathBuf::from(args[0].as_text().expect("checked by registry"));
+
+        for buffer in &state.buffers {
+            let mut buffer = buffer.lock().await;
+            if buffer.path().is_some_and(|file| file.starts_with(&path)) {
+                buffer.mark_unsaved();
+            }
+        }
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn reload(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let path = state
+            .active_buffer()
+            .lock()
+            .await
+            .path()
+            .context("Buffer has no file to reload")?
+            .to_path_buf();
+        let text = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Can't read {}", path.display()))?;
+
+        state.watch(&path, &text);
+        state.active_buffer().lock().await.reload(text);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn keep_changes(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        state.active_buffer().lock().await.set_conflict(None);
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+fn diff_on_disk(state: &mut AppState, _: Vec<CommandArg>) -> BoxFuture<'_, CommandResult> {
+    Box::pin(async move {
+        let (old, new) = {
+            let buffer = state.active_buffer().lock().await;
+            let path = buffer
+                .path()
+                .context("Buffer has no file to compare with")?;
+            let text = buffer.text().context("Buffer has no text to compare")?;
+            let on_disk = match buffer.conflict() {
+                Some(on_disk) => on_disk.to_string(),
+                None => tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Can't read {}", path.display()))?,
+            };
+
+            let name = path.display().to_string();
+            let old = Version {
+                title: format!("{} (on disk)", name),
+                text: on_disk,
+            };
+            let new = Version {
+                title: format!("{} (unsaved)", name),
+                text: text.to_string(),
+            };
+            (old, new)
+        };
+
+        diff::open(state, old, new).await;
+
+        Ok(EventHandlerOutcome::Redraw)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{self, Receiver};
+
+    async fn next_command(rx: &mut Receiver<StateEvent>) -> Option<String> {
+        match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+            Ok(Some(StateEvent::Command(invocation))) => Some(invocation.id),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_written_files_and_changed_folders() {
+        let dir = paths::scratch_dir("watcher");
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut watcher = FileWatcher::start(tx).unwrap();
+
+        let file = dir.join("theme.toml");
+        watcher.notify(&file, Invocation::new("test.file")).unwrap();
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(next_command(&mut rx).await.as_deref(), Some("test.file"));
+
+        // Writing to a file doesn't change the folder's entries
+        let sub = dir.join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("a"), "").unwrap();
+        watcher
+            .notify_folder(&sub, Invocation::new("test.folder"))
+            .unwrap();
+        std::fs::write(sub.join("a"), "a").unwrap();
+        std::fs::remove_file(sub.join("a")).unwrap();
+        assert_eq!(next_command(&mut rx).await.as_deref(), Some("test.folder"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn stops_reporting_unwatched_files_and_folders() {
+        let dir = paths::scratch_dir("unwatch");
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut watcher = FileWatcher::start(tx).unwrap();
+
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        watcher.watch(&a, "").unwrap();
+        watcher.watch(&b, "").unwrap();
+        watcher
+            .notify_folder(&dir, Invocation::new("test.folder"))
+            .unwrap();
+
+        // The folder stays watched for what's left in it
+        watcher.unwatch(&a);
+        watcher.unwatch_folder(&dir);
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        assert_eq!(
+            next_command(&mut rx).await.as_deref(),
+            Some("file.changed_on_disk")
+        );
+        assert!(rx.try_recv().is_err());
+        assert!(!watcher.known.contains_key(&a));
+
+        watcher.unwatch(&b);
+        assert!(watcher.dirs.is_empty());
+        assert!(watcher.files.lock().unwrap().is_empty());
+    }
+}